@@ -0,0 +1,119 @@
+use alloc::collections::VecDeque;
+use core::mem;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::{Sink, Stream};
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Batch, Change, DynamicHoneyBadger, Error, FaultKind, Input, Message, Result, Step};
+use crate::fault_log::FaultLog;
+use crate::{Contribution, NodeIdT, TargetedMessage};
+
+pub type OutgoingMessage<N> = TargetedMessage<Message<N>, N>;
+
+#[derive(Debug)]
+pub enum AsyncInput<C, N: Ord> {
+    Contribution(C),
+    Change(Change<N>),
+    Message(N, Message<N>),
+}
+
+pub struct DhbStream<C, N: Ord, R> {
+    dhb: DynamicHoneyBadger<C, N>,
+    rng: R,
+    outputs: VecDeque<Batch<C, N>>,
+    fault_log: FaultLog<N, FaultKind>,
+    messages: UnboundedSender<OutgoingMessage<N>>,
+    waker: Option<Waker>,
+}
+
+impl<C, N: Ord, R> Unpin for DhbStream<C, N, R> {}
+
+impl<C, N, R> DhbStream<C, N, R> where C: Contribution + Serialize + DeserializeOwned, N: NodeIdT + Serialize + DeserializeOwned, R: Rng, {
+    pub fn new(dhb: DynamicHoneyBadger<C, N>, rng: R) -> (Self, UnboundedReceiver<OutgoingMessage<N>>) {
+        let (messages, receiver) = mpsc::unbounded();
+        let stream = DhbStream {
+            dhb, rng, outputs: VecDeque::new(), fault_log: FaultLog::default(), messages, waker: None,
+        };
+        (stream, receiver)
+    }
+
+    pub fn dhb(&self) -> &DynamicHoneyBadger<C, N> {
+        &self.dhb
+    }
+
+    pub fn take_faults(&mut self) -> FaultLog<N, FaultKind> {
+        mem::replace(&mut self.fault_log, FaultLog::default())
+    }
+
+    pub fn handle(&mut self, input: AsyncInput<C, N>) -> Result<()> {
+        let step = match input {
+            AsyncInput::Contribution(contrib) => self.dhb.propose(contrib, &mut self.rng)?,
+            AsyncInput::Change(change) => self.dhb.vote_for(change)?,
+            AsyncInput::Message(sender_id, msg) => self.dhb.handle_message(&sender_id, msg, &mut self.rng)?,
+        };
+        self.apply(step);
+        Ok(())
+    }
+
+    fn apply(&mut self, step: Step<C, N>) {
+        self.outputs.extend(step.output);
+        self.fault_log.extend(step.fault_log);
+        for msg in step.messages {
+            let _ = self.messages.unbounded_send(msg);
+        }
+        if !self.outputs.is_empty() {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<C, N, R> Stream for DhbStream<C, N, R> where C: Contribution + Serialize + DeserializeOwned, N: NodeIdT + Serialize + DeserializeOwned, R: Rng, {
+    type Item = Batch<C, N>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match this.outputs.pop_front() {
+            Some(batch) => Poll::Ready(Some(batch)),
+            None => {
+                this.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<C, N, R> Sink<AsyncInput<C, N>> for DhbStream<C, N, R> where C: Contribution + Serialize + DeserializeOwned, N: NodeIdT + Serialize + DeserializeOwned, R: Rng, {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, input: AsyncInput<C, N>) -> Result<()> {
+        self.get_mut().handle(input)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().messages.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C, N: Ord> From<Input<C, N>> for AsyncInput<C, N> {
+    fn from(input: Input<C, N>) -> Self {
+        match input {
+            Input::User(contrib) => AsyncInput::Contribution(contrib),
+            Input::Change(change) => AsyncInput::Change(change),
+        }
+    }
+}
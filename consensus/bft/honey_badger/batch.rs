@@ -1,7 +1,11 @@
-use std::collections::BTreeMap;
-use std::sync::Arc;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
 
-use super::{ChangeState, JoinPlan, Params};
+use serde::{Deserialize, Serialize};
+use tiny_keccak::sha3_256;
+
+use super::{wire, ChangeState, Digest, Error, JoinPlan, Params, Result};
+use crate::crypto::{PublicKeySet, Signature};
 use crate::{NetworkInfo, NodeIdT, PubKeyMap};
 
 #[derive(Clone, Debug)]
@@ -10,9 +14,12 @@ pub struct Batch<C, N: Ord> {
     pub(super) era: u64,
     pub(super) contributions: BTreeMap<N, C>,
     pub(super) change: ChangeState<N>,
+    pub(super) key_gen_running: bool,
     pub(super) pub_keys: PubKeyMap<N>,
     pub(super) netinfo: Arc<NetworkInfo<N>>,
     pub(super) params: Params,
+    pub(super) signature: Option<Signature>,
+    pub(super) stats: ProposerStats<N>,
 }
 
 impl<C, N: NodeIdT> Batch<C, N> {
@@ -36,6 +43,24 @@ impl<C, N: NodeIdT> Batch<C, N> {
         &self.netinfo
     }
 
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    pub fn proposer_stats(&self) -> &ProposerStats<N> {
+        &self.stats
+    }
+
+    pub fn digest(&self) -> Result<Digest> where C: Serialize, N: Serialize, {
+        let ser = wire::to_vec(&(self.epoch, self.era, &self.contributions, &self.change)).map_err(Error::SerializeBatch)?;
+        Ok(sha3_256(&ser))
+    }
+
+    pub fn verify_signature(&self, pk_set: &PublicKeySet) -> Result<bool> where C: Serialize, N: Serialize, {
+        let digest = self.digest()?;
+        Ok(self.signature.as_ref().map_or(false, |sig| pk_set.public_key().verify(sig, digest)))
+    }
+
     pub fn contributions(&self) -> impl Iterator<Item = (&N, &C)> {
         self.contributions.iter()
     }
@@ -57,11 +82,14 @@ impl<C, N: NodeIdT> Batch<C, N> {
     }
 
     pub fn join_plan(&self) -> Option<JoinPlan<N>> {
-        if self.change == ChangeState::None {
-            return None;
-        }
-        Some (JoinPlan {
-            era: self.epoch + 1,
+        let (era, epoch) = match self.change {
+            ChangeState::None if self.key_gen_running => return None,
+            ChangeState::None => (self.era, self.epoch + 1 - self.era),
+            _ => (self.epoch + 1, 0),
+        };
+        Some(JoinPlan {
+            era,
+            epoch,
             change: self.change.clone(),
             pub_keys: self.pub_keys.clone(),
             pub_key_set: self.netinfo.public_key_set().clone(),
@@ -69,12 +97,209 @@ impl<C, N: NodeIdT> Batch<C, N> {
         })
     }
 
+    pub fn summary(&self) -> BatchSummary<C, N> where C: Clone, {
+        BatchSummary {
+            epoch: self.epoch,
+            era: self.era,
+            contributions: self.contributions.clone(),
+            change: self.change.clone(),
+            key_gen_running: self.key_gen_running,
+            pub_keys: self.pub_keys.clone(),
+            pub_key_set: self.netinfo.public_key_set().clone(),
+            params: self.params.clone(),
+            signature: self.signature.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+
+    pub fn into_summary(self) -> BatchSummary<C, N> {
+        BatchSummary {
+            pub_key_set: self.netinfo.public_key_set().clone(),
+            epoch: self.epoch,
+            era: self.era,
+            contributions: self.contributions,
+            change: self.change,
+            key_gen_running: self.key_gen_running,
+            pub_keys: self.pub_keys,
+            params: self.params,
+            signature: self.signature,
+            stats: self.stats,
+        }
+    }
+
     pub fn public_eq(&self, other: &Self) -> bool where C: PartialEq, {
         self.epoch == other.epoch && self.era == other.era 
         && self.contributions == other.contributions
         && self.change == other.change
+        && self.key_gen_running == other.key_gen_running
         && self.pub_keys == other.pub_keys
         && self.netinfo.public_key_set() == other.netinfo.public_key_set()
         && self.params == other.params
     }
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchSummary<C, N: Ord> {
+    epoch: u64,
+    era: u64,
+    contributions: BTreeMap<N, C>,
+    change: ChangeState<N>,
+    key_gen_running: bool,
+    pub_keys: PubKeyMap<N>,
+    pub_key_set: PublicKeySet,
+    params: Params,
+    signature: Option<Signature>,
+    stats: ProposerStats<N>,
+}
+
+impl<C, N: NodeIdT> BatchSummary<C, N> {
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn era(&self) -> u64 {
+        self.era
+    }
+
+    pub fn change(&self) -> &ChangeState<N> {
+        &self.change
+    }
+
+    pub fn public_keys(&self) -> &PubKeyMap<N> {
+        &self.pub_keys
+    }
+
+    pub fn public_key_set(&self) -> &PublicKeySet {
+        &self.pub_key_set
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    pub fn contributions(&self) -> impl Iterator<Item = (&N, &C)> {
+        self.contributions.iter()
+    }
+
+    pub fn proposer_stats(&self) -> &ProposerStats<N> {
+        &self.stats
+    }
+
+    pub fn into_contributions(self) -> BTreeMap<N, C> {
+        self.contributions
+    }
+
+    pub fn join_plan(&self) -> Option<JoinPlan<N>> {
+        let (era, epoch) = match self.change {
+            ChangeState::None if self.key_gen_running => return None,
+            ChangeState::None => (self.era, self.epoch + 1 - self.era),
+            _ => (self.epoch + 1, 0),
+        };
+        Some(JoinPlan {
+            era,
+            epoch,
+            change: self.change.clone(),
+            pub_keys: self.pub_keys.clone(),
+            pub_key_set: self.pub_key_set.clone(),
+            params: self.params.clone(),
+        })
+    }
+
+    pub fn verify_signature(&self, pk_set: &PublicKeySet) -> Result<bool> where C: Serialize, N: Serialize, {
+        let ser = wire::to_vec(&(self.epoch, self.era, &self.contributions, &self.change)).map_err(Error::SerializeBatch)?;
+        let digest = sha3_256(&ser);
+        Ok(self.signature.as_ref().map_or(false, |sig| pk_set.public_key().verify(sig, digest)))
+    }
+}
+
+impl<C, N: NodeIdT> From<Batch<C, N>> for BatchSummary<C, N> {
+    fn from(batch: Batch<C, N>) -> Self {
+        batch.into_summary()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProposerStats<N: Ord> {
+    pub(super) sizes: BTreeMap<N, u64>,
+    pub(super) late: BTreeSet<N>,
+}
+
+impl<N: Ord> ProposerStats<N> {
+    pub(super) fn new() -> Self {
+        ProposerStats {
+            sizes: BTreeMap::new(), late: BTreeSet::new(),
+        }
+    }
+
+    pub fn included(&self) -> impl Iterator<Item = &N> {
+        self.sizes.keys()
+    }
+
+    pub fn is_included(&self, node_id: &N) -> bool {
+        self.sizes.contains_key(node_id)
+    }
+
+    pub fn size(&self, node_id: &N) -> Option<u64> {
+        self.sizes.get(node_id).cloned()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.sizes.values().sum()
+    }
+
+    pub fn late(&self) -> impl Iterator<Item = &N> {
+        self.late.iter()
+    }
+
+    pub fn is_late(&self, node_id: &N) -> bool {
+        self.late.contains(node_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use rand::{rngs, Rng};
+    use super::{Batch, ProposerStats};
+    use super::super::{ChangeState, DynamicHoneyBadger};
+    use crate::crypto::{SecretKey, SecretKeySet};
+    use crate::honey_badger::Params;
+    use crate::{to_pub_keys, NetworkInfo};
+
+    fn batch(rng: &mut rngs::OsRng, key_gen_running: bool) -> Batch<Vec<u8>, usize> {
+        let sec_keys: BTreeMap<usize, SecretKey> = (0..4).map(|id| (id, rng.gen())).collect();
+        let pub_keys = to_pub_keys(&sec_keys);
+        let sk_set = SecretKeySet::random(1, rng);
+        let netinfo = Arc::new(NetworkInfo::new(0, sk_set.secret_key_share(0), sk_set.public_keys(), pub_keys.keys()));
+        Batch {
+            epoch: 12, era: 10, contributions: BTreeMap::new(), change: ChangeState::None, key_gen_running, pub_keys, netinfo, params: Params::default(), signature: None, stats: ProposerStats::new(),
+        }
+    }
+
+    #[test]
+    fn test_join_plan_without_change() {
+        let mut rng = rngs::OsRng::new().expect("Couldn't initialize osrng");
+        let batch = batch(&mut rng, false);
+
+        let plan = batch.join_plan().expect("join plan");
+        assert_eq!(plan.next_epoch(), 13);
+        let (dhb, step) = DynamicHoneyBadger::<Vec<u8>, usize>::new_joining(4, rng.gen(), plan, &mut rng).expect("join as observer");
+        assert!(dhb.is_observer());
+        assert_eq!(dhb.next_epoch(), 13);
+        assert!(step.messages.is_empty());
+    }
+
+    #[test]
+    fn test_no_join_plan_during_key_gen() {
+        let mut rng = rngs::OsRng::new().expect("Couldn't initialize osrng");
+        let batch = batch(&mut rng, true);
+
+        assert!(batch.join_plan().is_none());
+        assert!(batch.into_summary().join_plan().is_none());
+    }
+}
@@ -1,12 +1,22 @@
-use std::default::Default;
-use std::iter::once;
-use std::marker::PhantomData;
-use std::sync::Arc;
+use alloc::sync::Arc;
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::default::Default;
+use core::iter::once;
+use core::marker::PhantomData;
+#[cfg(feature = "keystore")]
+use std::path::Path;
 
-use crate::crypto::{SecretKey, SecretKeySet};
+use crate::crypto::{PublicKeySet, SecretKey, SecretKeySet};
+#[cfg(feature = "keystore")]
+use crate::crypto::SecretKeyShare;
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
 use serde::{de::DeserializeOwned, Serialize};
 
-use super::{DynamicHoneyBadger, EncryptionSchedule, JoinPlan, Result, Step};
+#[cfg(feature = "keystore")]
+use super::keystore::Keystore;
+use super::votes::DEFAULT_VOTE_TTL;
+use super::{AdaptiveEncryption, BufferLimits, Chunking, Clock, EvictionPolicy, ConsensusObserver, ContributionLimits, DynamicHoneyBadger, EncryptionSchedule, Entropy, Error, JoinPlan, QuorumRule, RateLimits, Result, Retention, Signer, Step, Verifier};
 use crate::honey_badger::{Params, SubsetHandlingStrategy};
 use crate::{to_pub_keys, Contribution, NetworkInfo, NodeIdT, PubKeyMap};
 
@@ -14,6 +24,25 @@ pub struct DynamicHoneyBadgerBuilder<C, N> {
     era: u64,
     epoch: u64,
     params: Params,
+    rate_limits: RateLimits,
+    buffer_limits: BufferLimits,
+    contribution_limits: ContributionLimits,
+    observer: Option<Arc<dyn ConsensusObserver<N>>>,
+    sign_batches: bool,
+    retention: Retention,
+    vote_ttl: u64,
+    quorum: QuorumRule,
+    change_kinds: Vec<String>,
+    chunking: Chunking,
+    rng_seed: Option<u64>,
+    entropy: Option<Arc<dyn Entropy>>,
+    clock: Option<Arc<dyn Clock>>,
+    eviction: Option<EvictionPolicy>,
+    adaptive_encryption: Option<AdaptiveEncryption>,
+    signer: Option<Arc<dyn Signer>>,
+    verifier: Option<Arc<dyn Verifier>>,
+    #[cfg(feature = "keystore")]
+    keystore: Option<Keystore<N>>,
     _phantom: PhantomData<(C, N)>,
 }
 
@@ -23,6 +52,25 @@ impl<C, N: Ord> Default for DynamicHoneyBadgerBuilder<C, N> {
             era: 0,
             epoch: 0,
             params: Params::default(),
+            rate_limits: RateLimits::default(),
+            buffer_limits: BufferLimits::default(),
+            contribution_limits: ContributionLimits::default(),
+            observer: None,
+            sign_batches: false,
+            retention: Retention::default(),
+            vote_ttl: DEFAULT_VOTE_TTL,
+            quorum: QuorumRule::default(),
+            change_kinds: Vec::new(),
+            chunking: Chunking::default(),
+            rng_seed: None,
+            entropy: None,
+            clock: None,
+            eviction: None,
+            adaptive_encryption: None,
+            signer: None,
+            verifier: None,
+            #[cfg(feature = "keystore")]
+            keystore: None,
             _phantom: PhantomData,
         }
     }
@@ -63,8 +111,161 @@ impl<C, N> DynamicHoneyBadgerBuilder<C, N> where C: Contribution + Serialize + D
         self
     }
 
+    pub fn rate_limits(&mut self, rate_limits: RateLimits) -> &mut Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn buffer_limits(&mut self, buffer_limits: BufferLimits) -> &mut Self {
+        self.buffer_limits = buffer_limits;
+        self
+    }
+
+    pub fn contribution_limits(&mut self, contribution_limits: ContributionLimits) -> &mut Self {
+        self.contribution_limits = contribution_limits;
+        self
+    }
+
+    pub fn observer(&mut self, observer: Arc<dyn ConsensusObserver<N>>) -> &mut Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    pub fn signer(&mut self, signer: Arc<dyn Signer>) -> &mut Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    pub fn verifier(&mut self, verifier: Arc<dyn Verifier>) -> &mut Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    pub fn sign_batches(&mut self, sign_batches: bool) -> &mut Self {
+        self.sign_batches = sign_batches;
+        self
+    }
+
+    pub fn retention(&mut self, retention: Retention) -> &mut Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn vote_ttl(&mut self, vote_ttl: u64) -> &mut Self {
+        self.vote_ttl = vote_ttl;
+        self
+    }
+
+    pub fn quorum_rule(&mut self, quorum: QuorumRule) -> &mut Self {
+        self.quorum = quorum;
+        self
+    }
+
+    pub fn change_kind(&mut self, kind: &str) -> &mut Self {
+        self.change_kinds.push(kind.to_owned());
+        self
+    }
+
+    pub fn chunking(&mut self, chunking: Chunking) -> &mut Self {
+        self.chunking = chunking;
+        self
+    }
+
+    pub fn eviction_policy(&mut self, eviction: EvictionPolicy) -> &mut Self {
+        self.eviction = Some(eviction);
+        self
+    }
+
+    pub fn adaptive_encryption(&mut self, adaptive: AdaptiveEncryption) -> &mut Self {
+        self.params.encryption_schedule = adaptive.schedule;
+        self.adaptive_encryption = Some(adaptive);
+        self
+    }
+
+    pub fn rng_seed(&mut self, seed: u64) -> &mut Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    pub fn entropy(&mut self, entropy: Arc<dyn Entropy>) -> &mut Self {
+        self.entropy = Some(entropy);
+        self
+    }
+
+    pub fn clock(&mut self, clock: Arc<dyn Clock>) -> &mut Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    pub fn seeded_rng(&self, stream: u64) -> Option<ChaChaRng> {
+        if let Some(seed) = self.rng_seed {
+            return Some(ChaChaRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+        }
+        self.entropy.as_ref().map(|entropy| {
+            let mut seed = <ChaChaRng as SeedableRng>::Seed::default();
+            entropy.fill_bytes(seed.as_mut());
+            ChaChaRng::from_seed(seed)
+        })
+    }
+
     pub fn build(&mut self, netinfo: NetworkInfo<N>, secret_key: SecretKey, pub_keys: PubKeyMap<N>,) -> DynamicHoneyBadger<C, N> {
-        DynamicHoneyBadger::new(secret_key, pub_keys, Arc::new(netinfo), self.params.clone(), self.era, self.epoch,)
+        let mut dhb = DynamicHoneyBadger::new(secret_key, pub_keys, Arc::new(netinfo), self.params.clone(), self.era, self.epoch,);
+        dhb.set_rate_limits(self.rate_limits.clone());
+        dhb.set_buffer_limits(self.buffer_limits);
+        dhb.set_contribution_limits(self.contribution_limits);
+        dhb.set_sign_batches(self.sign_batches);
+        dhb.set_retention(self.retention);
+        dhb.set_vote_ttl(self.vote_ttl);
+        dhb.set_quorum_rule(self.quorum.clone());
+        dhb.set_chunking(self.chunking);
+        dhb.set_eviction_policy(self.eviction.clone());
+        dhb.set_adaptive_encryption(self.adaptive_encryption.clone());
+        for kind in &self.change_kinds {
+            dhb.register_change_kind(kind);
+        }
+        if let Some(ref observer) = self.observer {
+            dhb.set_observer(observer.clone());
+        }
+        if let Some(ref signer) = self.signer {
+            dhb.set_signer(signer.clone());
+        }
+        if let Some(ref verifier) = self.verifier {
+            dhb.set_verifier(verifier.clone());
+        }
+        if let Some(ref clock) = self.clock {
+            dhb.set_clock(clock.clone());
+        }
+        dhb
+    }
+
+    #[cfg(feature = "keystore")]
+    pub fn from_keystore(&mut self, bytes: &[u8], passphrase: &[u8]) -> Result<&mut Self> {
+        self.keystore = Some(Keystore::decrypt(bytes, passphrase).map_err(Error::Keystore)?);
+        Ok(self)
+    }
+
+    #[cfg(feature = "keystore")]
+    pub fn from_keystore_file<P: AsRef<Path>>(&mut self, path: P, passphrase: &[u8]) -> Result<&mut Self> {
+        self.keystore = Some(Keystore::load(path, passphrase).map_err(Error::Keystore)?);
+        Ok(self)
+    }
+
+    #[cfg(feature = "keystore")]
+    pub fn build_from_keystore(&mut self, secret_key_share: Option<SecretKeyShare>, pub_key_set: PublicKeySet) -> Result<DynamicHoneyBadger<C, N>> {
+        let Keystore { our_id, secret_key, pub_keys } = self.keystore.take().ok_or(Error::MissingKeystore)?;
+        if secret_key_share.is_none() && !pub_keys.contains_key(&our_id) {
+            return Ok(self.build_observer(our_id, secret_key, pub_key_set, pub_keys));
+        }
+        let netinfo = NetworkInfo::new(our_id, secret_key_share, pub_key_set, pub_keys.keys());
+        Ok(self.build(netinfo, secret_key, pub_keys))
+    }
+
+    pub fn build_observer(&mut self, our_id: N, secret_key: SecretKey, pub_key_set: PublicKeySet, pub_keys: PubKeyMap<N>,) -> DynamicHoneyBadger<C, N> {
+        assert!(!pub_keys.contains_key(&our_id),
+        "An observer must not be a validator.");
+
+        let netinfo = NetworkInfo::new(our_id, None, pub_key_set, pub_keys.keys());
+        self.build(netinfo, secret_key, pub_keys)
     }
 
     pub fn build_first_node<R: rand::Rng>(&mut self, our_id: N, rng: &mut R,) -> Result<DynamicHoneyBadger<C, N>> {
@@ -76,6 +277,12 @@ impl<C, N> DynamicHoneyBadgerBuilder<C, N> where C: Contribution + Serialize + D
         let netinfo = NetworkInfo::new(our_id.clone(), sks, pk_set, once(our_id));
         Ok(self.build(netinfo, sk, pub_keys))
     }
+
+    pub fn build_first_node_seeded(&mut self, our_id: N) -> Result<(DynamicHoneyBadger<C, N>, ChaChaRng)> {
+        let mut rng = self.seeded_rng(0).ok_or(Error::MissingRngSeed)?;
+        let dhb = self.build_first_node(our_id, &mut rng)?;
+        Ok((dhb, rng))
+    }
 }
 
 #[deprecated]
@@ -0,0 +1,198 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+use tiny_keccak::sha3_256;
+
+use super::wire;
+use super::{Error, Params, Result, Signer};
+use crate::crypto::{PublicKeySet, Signature};
+use crate::{NodeIdT, PubKeyMap};
+
+pub const MAX_CATCH_UP_ERAS: usize = 64;
+pub const CATCH_UP_RETRY_MESSAGES: usize = 256;
+
+pub type Digest = [u8; 32];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EraSummary<N: Ord> {
+    era: u64,
+    pub_keys: PubKeyMap<N>,
+    pub_key_set: PublicKeySet,
+    params: Params,
+    batch_digests: Vec<(u64, Digest)>,
+}
+
+impl<N: Ord> EraSummary<N> {
+    pub(super) fn new(era: u64, pub_keys: PubKeyMap<N>, pub_key_set: PublicKeySet, params: Params) -> Self {
+        EraSummary {
+            era, pub_keys, pub_key_set, params, batch_digests: Vec::new(),
+        }
+    }
+
+    pub fn era(&self) -> u64 {
+        self.era
+    }
+
+    pub fn public_keys(&self) -> &PubKeyMap<N> {
+        &self.pub_keys
+    }
+
+    pub fn public_key_set(&self) -> &PublicKeySet {
+        &self.pub_key_set
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn batch_digests(&self) -> &[(u64, Digest)] {
+        &self.batch_digests
+    }
+
+    pub(super) fn push_digest(&mut self, epoch: u64, digest: Digest) {
+        self.batch_digests.push((epoch, digest));
+    }
+
+    pub(super) fn prune_digests(&mut self, min_epoch: u64) {
+        self.batch_digests.retain(|(epoch, _)| *epoch >= min_epoch);
+    }
+
+    pub(super) fn without_digests(&self) -> Self where N: Clone, {
+        EraSummary {
+            batch_digests: Vec::new(),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CatchUp<N: Ord> {
+    summaries: Vec<EraSummary<N>>,
+}
+
+impl<N: Ord + Serialize> CatchUp<N> {
+    pub(super) fn new(summaries: Vec<EraSummary<N>>) -> Self {
+        CatchUp { summaries }
+    }
+
+    pub fn eras(&self) -> &[EraSummary<N>] {
+        &self.summaries
+    }
+
+    pub fn target(&self) -> Option<&EraSummary<N>> {
+        self.summaries.last()
+    }
+
+    pub fn into_eras(self) -> Vec<EraSummary<N>> {
+        self.summaries
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        wire::to_vec(self).map_err(Error::SerializeCatchUp)
+    }
+
+    pub fn digest(&self) -> Result<Digest> {
+        Ok(sha3_256(&self.serialize()?))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedCatchUp<N: Ord> {
+    catch_up: CatchUp<N>,
+    signer: N,
+    sig: Signature,
+}
+
+impl<N: NodeIdT + Serialize> SignedCatchUp<N> {
+    pub(super) fn new(catch_up: CatchUp<N>, signer: N, key: &dyn Signer) -> Result<Self> {
+        let sig = key.sign(&catch_up.serialize()?);
+        Ok(SignedCatchUp { catch_up, signer, sig })
+    }
+
+    pub fn era(&self) -> u64 {
+        self.catch_up.target().map_or(0, EraSummary::era)
+    }
+
+    pub fn signer(&self) -> &N {
+        &self.signer
+    }
+
+    pub fn catch_up(&self) -> &CatchUp<N> {
+        &self.catch_up
+    }
+
+    pub fn verify(&self, pub_keys: &PubKeyMap<N>) -> Result<bool> {
+        let ser = self.catch_up.serialize()?;
+        Ok(pub_keys.get(&self.signer).map_or(false, |pk| pk.verify(&self.sig, ser)))
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct CatchUpState<N: Ord> {
+    pub(super) requested: Option<u64>,
+    waited: usize,
+    ahead: BTreeMap<N, u64>,
+    responses: BTreeMap<Digest, (CatchUp<N>, BTreeSet<N>)>,
+}
+
+impl<N: NodeIdT + Serialize> CatchUpState<N> {
+    pub(super) fn new() -> Self {
+        CatchUpState {
+            requested: None, waited: 0, ahead: BTreeMap::new(), responses: BTreeMap::new(),
+        }
+    }
+
+    pub(super) fn note_ahead(&mut self, sender_id: &N, era: u64) -> usize {
+        let entry = self.ahead.entry(sender_id.clone()).or_insert(era);
+        *entry = (*entry).max(era);
+        self.ahead.len()
+    }
+
+    pub(super) fn advance(&mut self, era: u64) {
+        self.ahead.retain(|_, ahead| *ahead > era);
+    }
+
+    pub(super) fn start(&mut self, era: u64) -> bool {
+        if self.requested == Some(era) && self.waited < CATCH_UP_RETRY_MESSAGES {
+            self.waited += 1;
+            return false;
+        }
+        self.requested = Some(era);
+        self.waited = 0;
+        self.responses.clear();
+        true
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.requested = None;
+        self.responses.clear();
+    }
+
+    pub(super) fn responders(&self) -> usize {
+        self.responses.values().map(|(_, ids)| ids.len()).sum()
+    }
+
+    pub(super) fn add_response(&mut self, signed: SignedCatchUp<N>) -> Result<usize> {
+        if self.responses.values().any(|(_, ids)| ids.contains(&signed.signer)) {
+            return Ok(0);
+        }
+        let digest = signed.catch_up.digest()?;
+        let SignedCatchUp { catch_up, signer, .. } = signed;
+        let entry = self.responses.entry(digest).or_insert_with(|| (catch_up, BTreeSet::new()));
+        entry.1.insert(signer);
+        Ok(entry.1.len())
+    }
+
+    pub(super) fn take(&mut self, signers: usize) -> Option<CatchUp<N>> {
+        let digest = *self.responses.iter().find(|(_, (_, ids))| ids.len() >= signers)?.0;
+        let catch_up = self.responses.remove(&digest).map(|(catch_up, _)| catch_up);
+        self.reset();
+        catch_up
+    }
+}
+
+pub fn batch_digest<C: Serialize, N: Ord + Serialize>(epoch: u64, contributions: &BTreeMap<N, C>) -> Result<Digest> {
+    let ser = wire::to_vec(&(epoch, contributions)).map_err(Error::SerializeCatchUp)?;
+    Ok(sha3_256(&ser))
+}
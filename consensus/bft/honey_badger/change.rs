@@ -0,0 +1,147 @@
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{wire, EncryptionSchedule, JoinPlan, WireError};
+use crate::crypto::PublicKeySet;
+use crate::crypto::PublicKey;
+use crate::{NodeIdT, PubKeyMap};
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+pub enum Change<N: Ord> {
+    NodeChange(PubKeyMap<N>),
+    EncryptionSchedule(EncryptionSchedule),
+    Add(N, PublicKey),
+    Remove(N),
+    Multiple(Vec<Change<N>>),
+    Atomic(Vec<Change<N>>),
+    Custom(CustomChange),
+}
+
+impl<N: NodeIdT> Change<N> {
+    pub fn is_diff(&self) -> bool {
+        match *self {
+            Change::Add(..) | Change::Remove(_) => true,
+            Change::Multiple(ref changes) => changes.iter().all(Change::is_diff),
+            Change::NodeChange(_) | Change::EncryptionSchedule(_) | Change::Atomic(_) | Change::Custom(_) => false,
+        }
+    }
+
+    pub fn node_id(&self) -> Option<&N> {
+        match *self {
+            Change::Add(ref node_id, _) | Change::Remove(ref node_id) => Some(node_id),
+            _ => None,
+        }
+    }
+
+    pub fn diffs(&self) -> Vec<&Change<N>> {
+        match *self {
+            Change::Add(..) | Change::Remove(_) => vec![self],
+            Change::Multiple(ref changes) => changes.iter().flat_map(Change::diffs).collect(),
+            Change::NodeChange(_) | Change::EncryptionSchedule(_) | Change::Atomic(_) | Change::Custom(_) => Vec::new(),
+        }
+    }
+
+    pub fn atomic<I, J>(add: I, remove: J) -> Self where I: IntoIterator<Item = (N, PublicKey)>, J: IntoIterator<Item = N>, {
+        let adds = add.into_iter().map(|(node_id, pub_key)| Change::Add(node_id, pub_key));
+        Change::Atomic(adds.chain(remove.into_iter().map(Change::Remove)).collect())
+    }
+
+    pub fn resolve(&self, pub_keys: &PubKeyMap<N>) -> Option<Change<N>> {
+        match *self {
+            Change::Atomic(ref changes) => {
+                let new_pub_keys = Change::apply_diffs(pub_keys, changes.iter().flat_map(Change::diffs));
+                if new_pub_keys == *pub_keys {
+                    return None;
+                }
+                Some(Change::NodeChange(new_pub_keys))
+            }
+            _ => Some(self.clone()),
+        }
+    }
+
+    pub fn apply_diffs<'a, I>(pub_keys: &PubKeyMap<N>, diffs: I) -> PubKeyMap<N> where I: IntoIterator<Item = &'a Change<N>>, N: 'a, {
+        let mut new_pub_keys = (**pub_keys).clone();
+        for diff in diffs {
+            match *diff {
+                Change::Add(ref node_id, ref pub_key) => {
+                    new_pub_keys.insert(node_id.clone(), *pub_key);
+                }
+                Change::Remove(ref node_id) => {
+                    new_pub_keys.remove(node_id);
+                }
+                _ => {}
+            }
+        }
+        Arc::new(new_pub_keys)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+pub struct CustomChange {
+    kind: String,
+    payload: Vec<u8>,
+}
+
+impl CustomChange {
+    pub fn new<T: Serialize>(kind: &str, value: &T) -> Result<Self, WireError> {
+        Ok(CustomChange {
+            kind: kind.to_owned(), payload: wire::to_vec(value)?,
+        })
+    }
+
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, WireError> {
+        wire::from_slice(&self.payload)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+pub enum ChangeState<N: Ord> {
+    None,
+    InProgress(Change<N>),
+    Complete(Change<N>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MembershipChange<N: Ord> {
+    pub(super) era: u64,
+    pub(super) old_pub_keys: PubKeyMap<N>,
+    pub(super) old_pub_key_set: PublicKeySet,
+    pub(super) join_plan: JoinPlan<N>,
+}
+
+impl<N: Ord> MembershipChange<N> {
+    pub fn era(&self) -> u64 {
+        self.era
+    }
+
+    pub fn old_public_keys(&self) -> &PubKeyMap<N> {
+        &self.old_pub_keys
+    }
+
+    pub fn old_public_key_set(&self) -> &PublicKeySet {
+        &self.old_pub_key_set
+    }
+
+    pub fn new_public_keys(&self) -> &PubKeyMap<N> {
+        &self.join_plan.pub_keys
+    }
+
+    pub fn new_public_key_set(&self) -> &PublicKeySet {
+        &self.join_plan.pub_key_set
+    }
+
+    pub fn join_plan(&self) -> &JoinPlan<N> {
+        &self.join_plan
+    }
+}
@@ -0,0 +1,119 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+use tiny_keccak::sha3_256;
+
+use super::{wire, Digest, Error, FaultKind, Result, Signer};
+use crate::crypto::{PublicKey, Signature};
+use crate::fault_log::FaultLog;
+use crate::NodeIdT;
+
+pub const MAX_CHUNKS: u32 = 1024;
+pub const MAX_PARTIALS_PER_SENDER: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    era: u64,
+    id: Digest,
+    index: u32,
+    total: u32,
+    data: Vec<u8>,
+    sig: Signature,
+}
+
+impl Chunk {
+    pub fn era(&self) -> u64 {
+        self.era
+    }
+
+    fn signed_bytes(era: u64, id: &Digest, index: u32, total: u32, data: &[u8]) -> Result<Vec<u8>> {
+        wire::to_vec(&(era, id, index, total, data)).map_err(Error::SerializeChunk)
+    }
+
+    pub fn verify(&self, pub_key: &PublicKey) -> Result<bool> {
+        let ser = Self::signed_bytes(self.era, &self.id, self.index, self.total, &self.data)?;
+        Ok(pub_key.verify(&self.sig, ser))
+    }
+}
+
+pub(super) fn split(era: u64, payload: &[u8], chunk_size: usize, signer: &dyn Signer) -> Result<Vec<Chunk>> {
+    let chunk_size = chunk_size.max(1);
+    let id = sha3_256(payload);
+    let total = ((payload.len() + chunk_size - 1) / chunk_size) as u32;
+    let mut chunks = Vec::with_capacity(total as usize);
+    for (index, data) in payload.chunks(chunk_size).enumerate() {
+        let index = index as u32;
+        let sig = signer.sign(&Chunk::signed_bytes(era, &id, index, total, data)?);
+        chunks.push(Chunk {
+            era, id, index, total, data: data.to_vec(), sig,
+        });
+    }
+    Ok(chunks)
+}
+
+#[derive(Debug)]
+struct Partial {
+    total: u32,
+    started: u64,
+    parts: BTreeMap<u32, Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub(super) struct Reassembler<N: Ord> {
+    partials: BTreeMap<(N, Digest), Partial>,
+    timeout_epochs: u64,
+}
+
+impl<N: NodeIdT> Reassembler<N> {
+    pub(super) fn new(timeout_epochs: u64) -> Self {
+        Reassembler {
+            partials: BTreeMap::new(), timeout_epochs,
+        }
+    }
+
+    pub(super) fn set_timeout(&mut self, timeout_epochs: u64) {
+        self.timeout_epochs = timeout_epochs;
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.partials.len()
+    }
+
+    pub(super) fn add(&mut self, sender_id: &N, chunk: Chunk, epoch: u64) -> (FaultLog<N, FaultKind>, Option<Vec<u8>>) {
+        let Chunk { id, index, total, data, .. } = chunk;
+        if total == 0 || total > MAX_CHUNKS || index >= total {
+            return (FaultLog::init(sender_id.clone(), FaultKind::InvalidChunk), None);
+        }
+        let key = (sender_id.clone(), id);
+        if !self.partials.contains_key(&key) {
+            let open = self.partials.keys().filter(|(id, _)| id == sender_id).count();
+            if open >= MAX_PARTIALS_PER_SENDER {
+                return (FaultLog::init(sender_id.clone(), FaultKind::TooManyPartialChunks), None);
+            }
+        }
+        let partial = self.partials.entry(key.clone()).or_insert_with(|| Partial {
+            total, started: epoch, parts: BTreeMap::new(),
+        });
+        if partial.total != total {
+            return (FaultLog::init(sender_id.clone(), FaultKind::InvalidChunk), None);
+        }
+        partial.parts.insert(index, data);
+        if partial.parts.len() < total as usize {
+            return (FaultLog::new(), None);
+        }
+        let payload: Vec<u8> = match self.partials.remove(&key) {
+            Some(partial) => partial.parts.into_iter().flat_map(|(_, data)| data).collect(),
+            None => return (FaultLog::new(), None),
+        };
+        if sha3_256(&payload) != id {
+            return (FaultLog::init(sender_id.clone(), FaultKind::InvalidChunk), None);
+        }
+        (FaultLog::new(), Some(payload))
+    }
+
+    pub(super) fn expire(&mut self, epoch: u64) {
+        let timeout_epochs = self.timeout_epochs;
+        self.partials.retain(|_, partial| partial.started.saturating_add(timeout_epochs) >= epoch);
+    }
+}
@@ -0,0 +1,128 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem;
+
+use serde::Serialize;
+
+use super::catch_up::Digest;
+use super::{Batch, FaultKind, Result};
+use crate::crypto::{SecretKeyShare, SignatureShare};
+use crate::fault_log::FaultLog;
+use crate::{NetworkInfo, NodeIdT};
+
+#[derive(Debug)]
+struct PendingBatch<C, N: Ord> {
+    batch: Option<(Batch<C, N>, Digest, Arc<NetworkInfo<N>>)>,
+    shares: BTreeMap<N, (u64, SignatureShare)>,
+}
+
+impl<C, N: Ord> Default for PendingBatch<C, N> {
+    fn default() -> Self {
+        PendingBatch {
+            batch: None, shares: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct BatchSigner<C, N: Ord> {
+    pending: BTreeMap<u64, PendingBatch<C, N>>,
+    released: Option<u64>,
+    max_future_epochs: u64,
+}
+
+impl<C: Serialize, N: NodeIdT + Serialize> BatchSigner<C, N> {
+    pub(super) fn new(max_future_epochs: u64) -> Self {
+        BatchSigner {
+            pending: BTreeMap::new(), released: None, max_future_epochs,
+        }
+    }
+
+    pub(super) fn add_batch(&mut self, batch: Batch<C, N>, netinfo: Arc<NetworkInfo<N>>, sk_share: Option<&SecretKeyShare>, fault_log: &mut FaultLog<N, FaultKind>) -> Result<(Option<SignatureShare>, Vec<Batch<C, N>>)> {
+        let digest = batch.digest()?;
+        let our_share = sk_share.map(|sks| sks.sign(&digest));
+        let epoch = batch.epoch();
+        let era = batch.era();
+        self.prune(epoch);
+        let entry = self.pending.entry(epoch).or_insert_with(PendingBatch::default);
+        let shares = mem::replace(&mut entry.shares, BTreeMap::new());
+        entry.batch = Some((batch, digest, netinfo.clone()));
+        if let Some(ref share) = our_share {
+            entry.shares.insert(netinfo.our_id().clone(), (era, share.clone()));
+        }
+        for (sender_id, (share_era, share)) in shares {
+            self.insert_share(epoch, sender_id, share_era, share, fault_log);
+        }
+        Ok((our_share, self.release()))
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub(super) fn has_era(&self, era: u64) -> bool {
+        self.pending.values().any(|pending| pending.batch.as_ref().map_or(false, |(batch, _, _)| batch.era() == era))
+    }
+
+    pub(super) fn prune(&mut self, min_epoch: u64) {
+        let stale: Vec<u64> = self.pending.range(..min_epoch).filter(|(_, pending)| pending.batch.is_none()).map(|(epoch, _)| *epoch).collect();
+        for epoch in stale {
+            self.pending.remove(&epoch);
+        }
+    }
+
+    pub(super) fn add_share(&mut self, sender_id: &N, era: u64, epoch: u64, share: SignatureShare, next_epoch: u64) -> (FaultLog<N, FaultKind>, Vec<Batch<C, N>>) {
+        let mut fault_log = FaultLog::default();
+        if self.released.map_or(false, |released| epoch <= released) || epoch > next_epoch.saturating_add(self.max_future_epochs) {
+            return (fault_log, Vec::new());
+        }
+        self.insert_share(epoch, sender_id.clone(), era, share, &mut fault_log);
+        (fault_log, self.release())
+    }
+
+    fn insert_share(&mut self, epoch: u64, sender_id: N, era: u64, share: SignatureShare, fault_log: &mut FaultLog<N, FaultKind>) {
+        let entry = self.pending.entry(epoch).or_insert_with(PendingBatch::default);
+        let valid = match entry.batch {
+            Some((ref batch, _, _)) if batch.era() != era => return,
+            Some((_, ref digest, ref netinfo)) => netinfo.public_key_share(&sender_id).map_or(false, |pks| pks.verify(&share, digest)),
+            None => {
+                entry.shares.insert(sender_id, (era, share));
+                return;
+            }
+        };
+        if valid {
+            entry.shares.insert(sender_id, (era, share));
+        } else {
+            fault_log.append(sender_id, FaultKind::InvalidBatchSignatureShare);
+        }
+    }
+
+    fn release(&mut self) -> Vec<Batch<C, N>> {
+        let mut ready = Vec::new();
+        loop {
+            let epoch = match self.pending.keys().next() {
+                Some(epoch) => *epoch,
+                None => break,
+            };
+            let entry = self.pending.get_mut(&epoch).unwrap();
+            let signature = match entry.batch {
+                Some((_, _, ref netinfo)) if entry.shares.len() > netinfo.public_key_set().threshold() => {
+                    let pk_set = netinfo.public_key_set();
+                    let shares = entry.shares.iter().filter_map(|(id, (_, share))| netinfo.node_index(id).map(|idx| (idx, share)));
+                    match pk_set.combine_signatures(shares) {
+                        Ok(signature) => signature,
+                        Err(_) => break,
+                    }
+                }
+                _ => break,
+            };
+            if let Some(PendingBatch { batch: Some((mut batch, _, _)), .. }) = self.pending.remove(&epoch) {
+                batch.signature = Some(signature);
+                ready.push(batch);
+            }
+            self.released = Some(epoch);
+        }
+        ready
+    }
+}
@@ -0,0 +1,15 @@
+use std::io;
+
+use zstd::bulk;
+
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 4096;
+
+const LEVEL: i32 = 3;
+
+pub(super) fn compress(payload: &[u8]) -> io::Result<Vec<u8>> {
+    bulk::compress(payload, LEVEL)
+}
+
+pub(super) fn decompress(data: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    bulk::decompress(data, max_len)
+}
@@ -1,26 +1,35 @@
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
-use std::sync::Arc;
-use std::{fmt, result};
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use alloc::{borrow::ToOwned, boxed::Box, string::String, vec::Vec};
+use core::cmp::Ordering;
+use core::{fmt, mem, result};
 
-use crate::crypto::{PublicKey, SecretKey, Signature};
-use bincode;
+use crate::crypto::{PublicKey, SecretKey, SecretKeyShare, Signature, SignatureShare};
 use derivative::Derivative;
 use log::debug;
 use rand::Rng;
 use serde::{de:DeserializeOwned, Serialize};
 
-use super::votes::{SignedVote, VoteCounter};
-use super::{Batch, Change, ChangeState, DynamicHoneyBadgerBuilder, EncryptionSchedule, Error, FaultKind, Input, InternalContrib, JoinPlan, KeyGenMessage, KeyGenState, Message, Params, Result, SignedKeyGenMsg, Step,};
+use super::chunk::{self, Chunk, Reassembler};
+use super::commitment::BatchSigner;
+use super::catch_up::{self, CatchUp, CatchUpState, EraSummary, SignedCatchUp};
+use super::evidence::Evidence;
+use super::observer::{ConsensusObserver, ObserverHandle};
+use super::rate_limit::{MessageKind, RateLimiter, RateLimits};
+use super::votes::{SignedVote, VoteCounter, DEFAULT_VOTE_TTL};
+use super::wire;
+use super::{AdaptiveEncryption, Batch, BufferLimits, ProposerStats, Change, Chunking, Clock, EvictionPolicy, FlowHint, Digest, MembershipChange, ContributionLimits, KeyGenProgress, ChangeState, DynamicHoneyBadgerBuilder, EncryptionSchedule, Error, FaultKind, Input, InternalContrib, JoinPlan, KeyGenMessage, KeyGenState, Message, Params, QuorumRule, Result, RetainedState, Retention, SavedState, SignedKeyGenMsg, Signer, Step, Verifier, DefaultVerifier,};
 use crate::fault_log::{Fault, FaultLog};
 use crate::honey_badger::{self, HoneyBadger, Message as HbMessage};
-use crate::sync_key_gen::{Ack, AckOutcome, Part, PartOutcome, PubKeyMap, SyncKeyGen};
+use crate::sync_key_gen::{Ack, AckOutcome, Part, PartFault, PartOutcome, PubKeyMap, SyncKeyGen};
 use crate::{util, ConsensusProtocol, Contribution, Epoched, NetworkInfo, NodeIdT, Target};
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct DynamicHoneyBadger<C, N: Ord> {
-    secret_key: SecretKey,
+    secret_key: Arc<SecretKey>,
+    signer: Arc<dyn Signer>,
+    verifier: Arc<dyn Verifier>,
     pub_keys: PubKeyMap<N>,
     max_future_epochs: u64,
     era: u64,
@@ -28,6 +37,33 @@ pub struct DynamicHoneyBadger<C, N: Ord> {
     key_gen_msg_buffer: Vec<SignedKeyGenMsg<N>>,
     honey_badger: HoneyBadger<InternalContrib<C, N>, N>,
     key_gen_state: Option<KeyGenState<N>>,
+    public_netinfo: Arc<NetworkInfo<N>>,
+    era_summary: EraSummary<N>,
+    era_history: VecDeque<EraSummary<N>>,
+    catch_up: CatchUpState<N>,
+    caught_up: Vec<EraSummary<N>>,
+    rate_limiter: RateLimiter<N>,
+    buffer_limits: BufferLimits,
+    contribution_limits: ContributionLimits,
+    observer: ObserverHandle<N>,
+    evidence: Vec<Evidence<N>>,
+    sign_batches: bool,
+    batch_signer: BatchSigner<C, N>,
+    proposals: VecDeque<C>,
+    retention: Retention,
+    vote_ttl: u64,
+    quorum: QuorumRule,
+    custom_kinds: BTreeSet<String>,
+    membership_changes: VecDeque<MembershipChange<N>>,
+    chunking: Chunking,
+    reassembler: Reassembler<N>,
+    flow_hints: BTreeMap<N, FlowHint>,
+    eviction: Option<EvictionPolicy>,
+    fault_epochs: BTreeMap<N, VecDeque<u64>>,
+    adaptive_encryption: Option<AdaptiveEncryption>,
+    censored_at: Option<u64>,
+    clock: Option<Arc<dyn Clock>>,
+    proposed_at: Option<(u64, u64)>,
 }
 
 impl<C, N> ConsensusProtocol for DynamicHoneyBadger<C, N> where C: Contribution + Serialize + DeserializeOwned, N: NodeIdT + Serialize + DeserializeOwned, {
@@ -69,24 +105,43 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
 
         let max_future_epochs = params.max_future_epochs;
         let our_id = netinfo.our_id().clone();
+        let era_summary = EraSummary::new(era, pub_keys.clone(), netinfo.public_key_set().clone(), params.clone());
+        let public_netinfo = without_share(&netinfo);
         let honey_badger = HoneyBadger::builder(netinfo).session_id(era).params(params).epoch(epoch).build();
-        let vote_counter = VoteCounter::new(our_id, secret_key.clone(), pub_keys.clone(), era);
+        let buffer_limits = BufferLimits::default();
+        let secret_key = Arc::new(secret_key);
+        let signer: Arc<dyn Signer> = secret_key.clone();
+        let mut vote_counter = VoteCounter::new(our_id, signer.clone(), pub_keys.clone(), era);
+        vote_counter.set_vote_window(DEFAULT_VOTE_TTL, max_future_epochs);
         DynamicHoneyBadger {
-            secret_key, pub_keys, max_future_epochs, era, vote_counter, key_gen_msg_buffer: Vec::new(), honey_badger, key_gen_state: None,
+            secret_key, signer, verifier: Arc::new(DefaultVerifier), pub_keys, max_future_epochs, era, vote_counter, key_gen_msg_buffer: Vec::new(), honey_badger, key_gen_state: None, public_netinfo,
+            era_summary, era_history: VecDeque::new(), catch_up: CatchUpState::new(), caught_up: Vec::new(),
+            rate_limiter: RateLimiter::new(RateLimits::default()), buffer_limits, contribution_limits: ContributionLimits::default(),
+            observer: ObserverHandle::default(), evidence: Vec::new(), sign_batches: false, batch_signer: BatchSigner::new(max_future_epochs),
+            proposals: VecDeque::new(), retention: Retention::default(), vote_ttl: DEFAULT_VOTE_TTL, quorum: QuorumRule::default(), custom_kinds: BTreeSet::new(), membership_changes: VecDeque::new(),
+            chunking: Chunking::default(), reassembler: Reassembler::new(Chunking::default().timeout_epochs), flow_hints: BTreeMap::new(),
+            eviction: None, fault_epochs: BTreeMap::new(), adaptive_encryption: None, censored_at: None, clock: None, proposed_at: None,
         }
     }
 
+    pub fn new_joining_verified<R: Rng>(our_id: N, secret_key: SecretKey, join_plan: JoinPlan<N>, expected: &Digest, rng: &mut R,) -> Result<(Self, Step<C, N>)> {
+        join_plan.verify_digest(expected)?;
+        Self::new_joining(our_id, secret_key, join_plan, rng)
+    }
+
     pub fn new_joining<R: Rng>(our_id: N, secret_key: SecretKey, join_plan: JoinPlan<N>, rng: &mut R,) -> Result<(Self, Step<C, N>)> {
         let JoinPlan {
-            era, change, pub_keys, pub_key_set, params
+            era, epoch, change, pub_keys, pub_key_set, params
         } = join_plan;
         let new_pub_keys_opt = match change {
-            ChangeState::InProgress(Change::EncryptionSchedule(..)) | ChangeState::None => None,
             ChangeState::InProgress(Change::NodeChange(pks)) => Some(pks),
+            ChangeState::InProgress(_) | ChangeState::None => None,
             ChangeState::Complete(change) => {
                 let valid = match change {
                     Change::EncryptionSchedule(schedule) => schedule == params.encryption_schedule,
                     Change::NodeChange(new_pub_keys) => new_pub_keys == pub_keys,
+                    Change::Add(..) | Change::Remove(_) | Change::Multiple(_) | Change::Atomic(_) => false,
+                    Change::Custom(_) => true,
                 };
                 if !valid {
                     return Err(Error::InvalidJoinPlan);
@@ -95,7 +150,7 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
             }
         };
         let netinfo = Arc::new(NetworkInfo::new(our_id, None, pub_key_set, pub_keys.keys()));
-        let mut dhb = DynamicHoneyBadger::new(secret_key, pub_keys, netinfo, params, era, 0);
+        let mut dhb = DynamicHoneyBadger::new(secret_key, pub_keys, netinfo, params, era, epoch);
         let step = match new_pub_keys_opt {
             Some(new_pub_keys) => dhb.update_key_gen(era, new_pub_keys, rng)?,
             None => Step::default(),
@@ -103,62 +158,612 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
         Ok((dhb, step))
     }
 
+    pub fn save_state(&self) -> SavedState<N> {
+        SavedState {
+            era: self.era,
+            epoch: self.honey_badger.epoch(),
+            pub_keys: self.pub_keys.clone(),
+            pub_key_set: self.netinfo().public_key_set().clone(),
+            params: self.honey_badger.params().clone(),
+            votes: self.vote_counter.state(),
+            key_gen_msg_buffer: self.key_gen_msg_buffer.clone(),
+            key_gen: self.key_gen_state.as_ref().map(|kgs| (kgs.public_keys().clone(), kgs.history.clone())),
+        }
+    }
+
+    pub fn restore_state<R: Rng>(our_id: N, secret_key: SecretKey, secret_key_share: Option<SecretKeyShare>, state: SavedState<N>, rng: &mut R,) -> Result<Self> {
+        let SavedState {
+            era, epoch, pub_keys, pub_key_set, params, votes, key_gen_msg_buffer, key_gen
+        } = state;
+        let netinfo = Arc::new(NetworkInfo::new(our_id.clone(), secret_key_share, pub_key_set, pub_keys.keys()));
+        let mut dhb = DynamicHoneyBadger::new(secret_key, pub_keys.clone(), netinfo, params, era, epoch);
+        dhb.vote_counter = VoteCounter::restore(our_id.clone(), dhb.signer.clone(), pub_keys, era, votes);
+        dhb.vote_counter.set_vote_window(dhb.vote_ttl, dhb.max_future_epochs);
+        dhb.vote_counter.set_quorum(dhb.quorum.clone());
+        dhb.vote_counter.set_custom_kinds(dhb.custom_kinds.clone());
+        dhb.vote_counter.set_epoch(era + epoch);
+        dhb.key_gen_msg_buffer = key_gen_msg_buffer;
+        if let Some((kg_pub_keys, history)) = key_gen {
+            let threshold = util::max_faulty(kg_pub_keys.len());
+            let (key_gen, _) = SyncKeyGen::new(our_id, SecretKey::clone(&dhb.secret_key), kg_pub_keys, threshold, rng).map_err(Error::SyncKeyGen)?;
+            let mut kgs = KeyGenState::new(key_gen);
+            for (sender_id, kg_msg) in history {
+                match kg_msg.clone() {
+                    KeyGenMessage::Part(part) => {
+                        kgs.key_gen.handle_part(&sender_id, part, rng).map_err(Error::SyncKeyGen)?;
+                    }
+                    KeyGenMessage::Ack(ack) => {
+                        kgs.key_gen.handle_ack(&sender_id, ack).map_err(Error::SyncKeyGen)?;
+                    }
+                }
+                kgs.history.push((sender_id, kg_msg));
+            }
+            dhb.key_gen_state = Some(kgs);
+        }
+        Ok(dhb)
+    }
+
     pub fn has_input(&self) -> bool {
         self.honey_badger.has_input()
     }
 
+    pub fn is_observer(&self) -> bool {
+        !self.netinfo().is_validator()
+    }
+
     pub fn propose<R: Rng>(&mut self, contrib: C, rng: &mut R) -> Result<Step<C, N>> {
+        if self.is_observer() {
+            return Ok(Step::default());
+        }
+        if !self.contribution_limits.allows_size(&contrib) {
+            return Err(Error::ContributionTooLarge);
+        }
+        if self.has_input() {
+            if self.proposals.len() >= self.buffer_limits.queued_proposals {
+                return Err(Error::TooManyProposals);
+            }
+            self.proposals.push_back(contrib);
+            return Ok(Step::default());
+        }
+        let era = self.era;
+        let mut step = self.propose_now(contrib, rng)?;
+        self.apply_eviction(&mut step)?;
+        self.apply_adaptive_encryption(&mut step)?;
+        self.chunk_messages(&mut step)?;
+        self.observe(era, &step);
+        Ok(step)
+    }
+
+    pub fn queued_proposals(&self) -> usize {
+        self.proposals.len()
+    }
+
+    fn propose_now<R: Rng>(&mut self, contrib: C, rng: &mut R) -> Result<Step<C, N>> {
         let key_gen_messages = self.key_gen_msg_buffer.iter().filter(|kg_msg| kg_msg.era() == self.era).cloned().collect();
 
         let contrib = InternalContrib {
             contrib, key_gen_messages, votes: self.vote_counter.pending_votes().cloned().collect(),
         };
+        if let Some(ref clock) = self.clock {
+            self.proposed_at = Some((self.era + self.honey_badger.epoch(), clock.now_millis()));
+        }
         let step = self.honey_badger.propose(&contrib, rng).map_err(Error::ProposeHoneyBadger)?;
-        
         self.process_output(step, rng)
     }
 
     pub fn vote_for(&mut self, change: Change<N>) -> Result<Step<C, N>> {
-        if !self.netinfo().is_validator() {
+        if self.is_observer() {
             return Ok(Step::default());
         }
+        let step = self.sign_vote(change)?;
+        self.observe(self.era, &step);
+        Ok(step)
+    }
+
+    fn sign_vote(&mut self, change: Change<N>) -> Result<Step<C, N>> {
         let signed_vote = self.vote_counter.sign_vote_for(change)?.clone();
         let msg = Message::SignedVote(signed_vote);
-        Ok(Traget::all().message(msg).into)
+        Ok(Target::all().message(msg).into())
     }
 
     pub fn vote_to_add(&mut self, node_id: N, pub_key: PublicKey) -> Result<Step<C, N>> {
-        let mut pub_keys = (*self.pub_keys).clone();
-        pub_keys.insert(node_id, pub_key);
-        self.vote_for(Change::NodeChange(Arc::new(pub_keys)))
+        self.vote_for_diff(Change::Add(node_id, pub_key))
     }
 
     pub fn vote_to_remove(&mut self, node_id: &N) -> Result<Step<C, N>> {
-        let mut pub_keys = (*self.pub_keys).clone();
-        pub_keys.remove(node_id);
-        self.vote_for(Change::NodeChange(Arc::new(pub_keys)))
+        self.vote_for_diff(Change::Remove(node_id.clone()))
+    }
+
+    pub fn vote_to_change_nodes<I, J>(&mut self, add: I, remove: J) -> Result<Step<C, N>> where I: IntoIterator<Item = (N, PublicKey)>, J: IntoIterator<Item = N>, {
+        self.vote_for(Change::atomic(add, remove))
+    }
+
+    pub fn vote_for_diff(&mut self, diff: Change<N>) -> Result<Step<C, N>> {
+        let change = self.merge_diff(diff);
+        self.vote_for(change)
+    }
+
+    fn merge_diff(&self, diff: Change<N>) -> Change<N> {
+        if !diff.is_diff() {
+            return diff;
+        }
+        let mut diffs = self.vote_counter.pending_diffs(self.our_id());
+        for new_diff in diff.diffs() {
+            diffs.retain(|old| old.node_id() != new_diff.node_id());
+            diffs.push(new_diff.clone());
+        }
+        if diffs.len() == 1 { diffs.remove(0) } else { Change::Multiple(diffs) }
+    }
+
+    pub fn eviction_policy(&self) -> Option<&EvictionPolicy> {
+        self.eviction.as_ref()
+    }
+
+    pub fn set_eviction_policy(&mut self, eviction: Option<EvictionPolicy>) {
+        self.eviction = eviction;
+        self.fault_epochs.clear();
+    }
+
+    fn apply_eviction(&mut self, step: &mut Step<C, N>) -> Result<()> {
+        let policy = match self.eviction {
+            Some(ref policy) if !self.is_observer() => policy.clone(),
+            _ => return Ok(()),
+        };
+        let epoch = self.next_epoch();
+        let mut evict = Vec::new();
+        for fault in step.fault_log.0.iter().filter(|fault| policy.counts(&fault.kind)) {
+            if fault.node_id == *self.our_id() || !self.pub_keys.contains_key(&fault.node_id) {
+                continue;
+            }
+            let epochs = self.fault_epochs.entry(fault.node_id.clone()).or_insert_with(VecDeque::new);
+            epochs.push_back(epoch);
+            while epochs.front().map_or(false, |first| first.saturating_add(policy.window) < epoch) {
+                epochs.pop_front();
+            }
+            if epochs.len() > policy.threshold && !evict.contains(&fault.node_id) {
+                evict.push(fault.node_id.clone());
+            }
+        }
+        for node_id in evict {
+            self.fault_epochs.remove(&node_id);
+            let already = self.vote_counter.pending_diffs(self.our_id()).contains(&Change::Remove(node_id.clone()));
+            if !already {
+                let change = self.merge_diff(Change::Remove(node_id));
+                step.extend(self.sign_vote(change)?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn adaptive_encryption(&self) -> Option<&AdaptiveEncryption> {
+        self.adaptive_encryption.as_ref()
+    }
+
+    pub fn set_adaptive_encryption(&mut self, adaptive: Option<AdaptiveEncryption>) {
+        self.adaptive_encryption = adaptive;
+        self.censored_at = None;
+    }
+
+    fn apply_adaptive_encryption(&mut self, step: &mut Step<C, N>) -> Result<()> {
+        let adaptive = match self.adaptive_encryption {
+            Some(ref adaptive) if !self.is_observer() => adaptive.clone(),
+            _ => return Ok(()),
+        };
+        let epoch = self.next_epoch();
+        let our_id = self.our_id().clone();
+        if step.fault_log.0.iter().any(|fault| fault.node_id != our_id && adaptive.counts(&fault.kind)) {
+            self.censored_at = Some(epoch);
+        }
+        let schedule = adaptive.schedule_at(self.censored_at, epoch);
+        if schedule == adaptive.schedule {
+            self.censored_at = None;
+        }
+        if schedule == self.honey_badger.params().encryption_schedule {
+            return Ok(());
+        }
+        let change = Change::EncryptionSchedule(schedule);
+        match self.vote_counter.pending_change(&our_id) {
+            Some(pending) if *pending == change => (),
+            None | Some(Change::EncryptionSchedule(_)) => step.extend(self.sign_vote(change)?),
+            Some(_) => (),
+        }
+        Ok(())
     }
 
     pub fn handle_message<R: Rng>(&mut self, sender_id: &N, message: Message<N>, rng: &mut R,) -> Result<Step<C, N>> {
+        self.observer.message_in(sender_id, &message);
+        let era = self.era;
+        let mut step = self.dispatch_message(sender_id, message, rng)?;
+        self.apply_eviction(&mut step)?;
+        self.apply_adaptive_encryption(&mut step)?;
+        self.chunk_messages(&mut step)?;
+        self.observe(era, &step);
+        Ok(step)
+    }
+
+    pub fn handle_messages<I, R: Rng>(&mut self, messages: I, rng: &mut R) -> Result<Step<C, N>> where I: IntoIterator<Item = (N, Message<N>)>, {
+        let mut messages: Vec<_> = messages.into_iter().collect();
+        messages.sort_by_key(|(_, msg)| MessageKind::from(msg).priority());
+        let mut step = Step::default();
+        for (sender_id, msg) in messages {
+            step.extend(self.handle_message(&sender_id, msg, rng)?);
+        }
+        Ok(step)
+    }
+
+    pub fn set_observer(&mut self, observer: Arc<dyn ConsensusObserver<N>>) {
+        self.observer = ObserverHandle::new(Some(observer));
+        self.vote_counter.set_observer(self.observer.clone());
+    }
+
+    fn observe(&mut self, old_era: u64, step: &Step<C, N>) {
+        let observer = match self.observer.get() {
+            Some(observer) => observer.clone(),
+            None => return,
+        };
+        for msg in &step.messages {
+            self.observer.message_out(&msg.target, &msg.message);
+        }
+        for fault in &step.fault_log.0 {
+            observer.on_fault(&fault.node_id, &fault.kind);
+        }
+        for batch in &step.output {
+            observer.on_epoch_output(batch.era(), batch.epoch(), batch.contributions.len());
+            if let Some(millis) = self.epoch_latency(batch.epoch()) {
+                observer.on_epoch_latency(batch.era(), batch.epoch(), millis);
+            }
+        }
+        if self.era != old_era {
+            observer.on_era_change(old_era, self.era);
+        }
+    }
+
+    fn dispatch_message<R: Rng>(&mut self, sender_id: &N, message: Message<N>, rng: &mut R,) -> Result<Step<C, N>> {
+        let epoch = self.epoch();
+        if !self.rate_limiter.allow(sender_id, &message, epoch) {
+            return Ok(Fault::new(sender_id.clone(), FaultKind::RateLimitExceeded).into());
+        }
+        let message = match message {
+            Message::CatchUpRequest(era) => return self.handle_catch_up_request(sender_id, era),
+            Message::CatchUpResponse(signed) => return self.handle_catch_up_response(sender_id, *signed),
+            Message::BatchSignatureShare(era, epoch, share) => return Ok(self.handle_batch_signature_share(sender_id, era, epoch, *share)),
+            message => message,
+        };
         match message.era().cmp(&self.era) {
             Ordering::Greater => {
-                Ok(Fault::new(sender_id.clone(), FaultKind::UnexpectedDhbMessageEra).into())
+                self.add_flow_hint(sender_id, FlowHint::PeerTooFarAhead);
+                let ahead = if self.pub_keys.contains_key(sender_id) {
+                    self.catch_up.note_ahead(sender_id, message.era())
+                } else {
+                    0
+                };
+                if ahead > self.netinfo().num_faulty() {
+                    Ok(self.request_catch_up())
+                } else if message.era() > self.next_epoch().saturating_add(self.max_future_epochs) {
+                    Ok(Fault::new(sender_id.clone(), FaultKind::UnexpectedDhbMessageEra).into())
+                } else {
+                    Ok(Step::default())
+                }
             }
             Ordering::Less => Ok(Step::default()),
             Ordering::Equal => match messsage {
                 Message::HoneyBadger(_, hb_msg) => {
+                    let next_epoch = self.honey_badger.next_epoch();
+                    if hb_msg.epoch() > next_epoch.saturating_add(self.max_future_epochs) {
+                        self.add_flow_hint(sender_id, FlowHint::PeerTooFarAhead);
+                    } else if hb_msg.epoch() > next_epoch {
+                        self.add_flow_hint(sender_id, FlowHint::MessagePostponed);
+                    }
                     self.handle_honey_badger_message(sender_id, hb_msg, rng)
                 }
                 Message::KeyGen(_, kg_msg, sig) => self.handle_key_gen_message(sender_id, kg_msg, *sig).map(FaultLog::into),
                 Message::SignedVote(signed_vote) => self.vote_counter.add_pending_vote(sender_id, signed_vote).map(FaultLog::into),
+                Message::Chunk(chunk) => self.handle_chunk(sender_id, *chunk, rng),
+                Message::CatchUpRequest(_) | Message::CatchUpResponse(_) | Message::BatchSignatureShare(..) => Ok(Step::default()),
             },
         }
     }
 
+    pub fn rate_limits(&self) -> &RateLimits {
+        self.rate_limiter.limits()
+    }
+
+    pub fn set_rate_limits(&mut self, limits: RateLimits) {
+        self.rate_limiter.set_limits(limits);
+    }
+
+    pub fn buffer_limits(&self) -> &BufferLimits {
+        &self.buffer_limits
+    }
+
+    pub fn set_buffer_limits(&mut self, limits: BufferLimits) {
+        self.buffer_limits = limits;
+        let limit = self.key_gen_sender_limit();
+        let our_id = self.our_id().clone();
+        let mut counts: BTreeMap<N, usize> = BTreeMap::new();
+        let mut kept: Vec<_> = mem::take(&mut self.key_gen_msg_buffer).into_iter().rev().filter(|msg| {
+            let count = counts.entry(msg.1.clone()).or_insert(0);
+            *count += 1;
+            msg.1 == our_id || *count <= limit
+        }).collect();
+        kept.reverse();
+        self.key_gen_msg_buffer = kept;
+    }
+
+    pub fn vote_ttl(&self) -> u64 {
+        self.vote_ttl
+    }
+
+    pub fn set_vote_ttl(&mut self, vote_ttl: u64) {
+        self.vote_ttl = vote_ttl;
+        self.vote_counter.set_vote_window(vote_ttl, self.max_future_epochs);
+    }
+
+    pub fn quorum_rule(&self) -> &QuorumRule {
+        &self.quorum
+    }
+
+    pub fn set_quorum_rule(&mut self, quorum: QuorumRule) {
+        self.vote_counter.set_quorum(quorum.clone());
+        self.quorum = quorum;
+    }
+
+    pub fn register_change_kind(&mut self, kind: &str) {
+        self.custom_kinds.insert(kind.to_owned());
+        self.vote_counter.set_custom_kinds(self.custom_kinds.clone());
+    }
+
+    pub fn chunking(&self) -> &Chunking {
+        &self.chunking
+    }
+
+    pub fn set_chunking(&mut self, chunking: Chunking) {
+        self.reassembler.set_timeout(chunking.timeout_epochs);
+        self.chunking = chunking;
+    }
+
+    fn chunk_messages(&self, step: &mut Step<C, N>) -> Result<()> {
+        let chunk_size = self.chunking.chunk_size;
+        let mut messages = Vec::with_capacity(step.messages.len());
+        for msg in step.messages.drain(..) {
+            let oversized = self.chunking.applies_to(&msg.message) && wire::serialized_size(&msg.message).map_err(Error::SerializeChunk)? > chunk_size as u64;
+            if !oversized {
+                messages.push(msg);
+                continue;
+            }
+            let payload = wire::to_vec(&msg.message).map_err(Error::SerializeChunk)?;
+            for chunk in chunk::split(self.era, &payload, chunk_size, &*self.signer)? {
+                messages.push(msg.target.clone().message(Message::Chunk(Box::new(chunk))));
+            }
+        }
+        step.messages = messages;
+        Ok(())
+    }
+
+    fn handle_chunk<R: Rng>(&mut self, sender_id: &N, chunk: Chunk, rng: &mut R) -> Result<Step<C, N>> {
+        let candidate_key = self.key_gen_state.as_ref().and_then(|kgs| kgs.public_keys().get(sender_id));
+        let mut valid = false;
+        for pub_key in self.pub_keys.get(sender_id).into_iter().chain(candidate_key) {
+            valid = valid || chunk.verify(pub_key)?;
+        }
+        if !valid {
+            return Ok(Fault::new(sender_id.clone(), FaultKind::InvalidChunk).into());
+        }
+        let epoch = self.next_epoch();
+        let (fault_log, payload) = self.reassembler.add(sender_id, chunk, epoch);
+        let payload = match payload {
+            Some(payload) => payload,
+            None => return Ok(fault_log.into()),
+        };
+        match wire::from_slice::<Message<N>>(&payload) {
+            Ok(Message::Chunk(_)) | Err(_) => Ok(Fault::new(sender_id.clone(), FaultKind::InvalidChunk).into()),
+            Ok(message) => self.dispatch_message(sender_id, message, rng),
+        }
+    }
+
+    pub fn retention(&self) -> &Retention {
+        &self.retention
+    }
+
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+        self.collect_garbage();
+    }
+
+    pub fn collect_garbage(&mut self) {
+        while self.era_history.len() > self.retention.eras {
+            self.era_history.pop_front();
+        }
+        let min_epoch = self.next_epoch().saturating_sub(self.retention.epochs);
+        for summary in self.era_history.iter_mut().chain(Some(&mut self.era_summary)) {
+            summary.prune_digests(min_epoch);
+        }
+        self.batch_signer.prune(min_epoch);
+        let era = self.era;
+        self.key_gen_msg_buffer.retain(|kg_msg| kg_msg.era() >= era);
+        while self.membership_changes.len() > self.retention.membership_changes {
+            self.membership_changes.pop_front();
+        }
+        let min_era = self.era_history.front().map_or(era, EraSummary::era);
+        self.evidence.retain(|evidence| evidence.era() >= min_era);
+    }
+
+    pub fn retained_state(&self) -> RetainedState {
+        RetainedState {
+            eras: self.era_history.len() + 1,
+            batch_digests: self.era_summaries().map(|summary| summary.batch_digests().len()).sum(),
+            key_gen_messages: self.key_gen_msg_buffer.len(),
+            pending_votes: self.vote_counter.pending_len(),
+            pending_batches: self.batch_signer.len(),
+            queued_proposals: self.proposals.len(),
+            evidence: self.evidence.len(),
+            membership_changes: self.membership_changes.len(),
+            partial_chunks: self.reassembler.len(),
+        }
+    }
+
+    pub fn change_state(&self) -> ChangeState<N> {
+        match self.key_gen_state {
+            Some(ref kgs) => ChangeState::InProgress(Change::NodeChange(kgs.public_keys().clone())),
+            None => ChangeState::None,
+        }
+    }
+
+    pub fn key_gen_progress(&self) -> Option<KeyGenProgress<N>> {
+        self.key_gen_state.as_ref().map(KeyGenState::progress)
+    }
+
+    pub fn contribution_limits(&self) -> &ContributionLimits {
+        &self.contribution_limits
+    }
+
+    pub fn set_contribution_limits(&mut self, limits: ContributionLimits) {
+        self.contribution_limits = limits;
+    }
+
+    pub fn sign_batches(&self) -> bool {
+        self.sign_batches
+    }
+
+    pub fn set_sign_batches(&mut self, sign_batches: bool) {
+        self.sign_batches = sign_batches;
+    }
+
+    fn handle_batch_signature_share(&mut self, sender_id: &N, era: u64, epoch: u64, share: SignatureShare) -> Step<C, N> {
+        if !self.sign_batches || (era < self.era && !self.batch_signer.has_era(era)) {
+            return Step::default();
+        }
+        let next_epoch = self.next_epoch();
+        let (fault_log, batches) = self.batch_signer.add_share(sender_id, era, epoch, share, next_epoch);
+        let mut step: Step<C, N> = fault_log.into();
+        step.output.extend(batches);
+        step
+    }
+
+    fn add_flow_hint(&mut self, sender_id: &N, hint: FlowHint) {
+        let entry = self.flow_hints.entry(sender_id.clone()).or_insert(hint);
+        *entry = (*entry).max(hint);
+    }
+
+    pub fn take_flow_hints(&mut self) -> BTreeMap<N, FlowHint> {
+        mem::replace(&mut self.flow_hints, BTreeMap::new())
+    }
+
+    pub fn take_evidence(&mut self) -> Vec<Evidence<N>> {
+        let mut evidence = mem::replace(&mut self.evidence, Vec::new());
+        evidence.extend(self.vote_counter.take_evidence());
+        evidence
+    }
+
+    pub fn request_catch_up(&mut self) -> Step<C, N> {
+        if !self.catch_up.start(self.era) {
+            return Step::default();
+        }
+        Target::all().message(Message::CatchUpRequest(self.era)).into()
+    }
+
+    pub fn era_summaries(&self) -> impl Iterator<Item = &EraSummary<N>> {
+        self.era_history.iter().chain(Some(&self.era_summary))
+    }
+
+    pub fn era_history(&self) -> impl Iterator<Item = &MembershipChange<N>> {
+        self.membership_changes.iter()
+    }
+
+    pub fn take_caught_up(&mut self) -> Vec<EraSummary<N>> {
+        mem::replace(&mut self.caught_up, Vec::new())
+    }
+
+    fn handle_catch_up_request(&mut self, sender_id: &N, era: u64) -> Result<Step<C, N>> {
+        if era >= self.era || self.is_observer() {
+            return Ok(Step::default());
+        }
+        let mut summaries: Vec<_> = self.era_history.iter().filter(|summary| summary.era() >= era).cloned().collect();
+        summaries.push(self.era_summary.without_digests());
+        let our_id = self.our_id().clone();
+        let signed = SignedCatchUp::new(CatchUp::new(summaries), our_id, &*self.signer)?;
+        let msg = Message::CatchUpResponse(Box::new(signed));
+        Ok(Target::node(sender_id.clone()).message(msg).into())
+    }
+
+    fn handle_catch_up_response(&mut self, sender_id: &N, signed: SignedCatchUp<N>) -> Result<Step<C, N>> {
+        if self.catch_up.requested != Some(self.era) || signed.era() <= self.era {
+            return Ok(Step::default());
+        }
+        if signed.signer() != sender_id || !signed.verify(&self.pub_keys)? {
+            return Ok(Fault::new(sender_id.clone(), FaultKind::InvalidCatchUpSignature).into());
+        }
+        let count = self.catch_up.add_response(signed)?;
+        if count <= self.netinfo().num_faulty() {
+            if self.catch_up.responders() >= self.netinfo().num_correct() {
+                self.catch_up.reset();
+            }
+            return Ok(Step::default());
+        }
+        if let Some(catch_up) = self.catch_up.take(count) {
+            self.fast_forward(catch_up);
+        }
+        Ok(Step::default())
+    }
+
+    fn fast_forward(&mut self, catch_up: CatchUp<N>) {
+        let target = match catch_up.target() {
+            Some(target) => target.clone(),
+            None => return,
+        };
+        debug!("{}: Catching up to era {}.", self, target.era());
+        let completed_key_gen = self.key_gen_state.as_ref().map_or(false, |kgs| kgs.public_keys() == target.public_keys());
+        let sk_share = if target.public_key_set() == self.netinfo().public_key_set() {
+            self.netinfo().secret_key_share().cloned()
+        } else if completed_key_gen {
+            self.key_gen_state.as_ref().and_then(|kgs| kgs.key_gen.generate().ok()).filter(|(pk_set, _)| pk_set == target.public_key_set()).and_then(|(_, sk_share)| sk_share)
+        } else {
+            None
+        };
+        let our_id = self.our_id().clone();
+        let netinfo = Arc::new(NetworkInfo::new(our_id, sk_share, target.public_key_set().clone(), target.public_keys().keys()));
+        self.pub_keys = target.public_keys().clone();
+        if completed_key_gen {
+            self.key_gen_state = None;
+        }
+        self.restart_honey_badger(target.era(), target.params().clone(), netinfo);
+        self.caught_up.extend(catch_up.into_eras());
+    }
+
     pub fn secret_key(&self) -> &SecretKey {
         &self.secret_key
     }
 
+    pub fn signer(&self) -> &Arc<dyn Signer> {
+        &self.signer
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = Some(clock);
+    }
+
+    fn epoch_latency(&mut self, epoch: u64) -> Option<u64> {
+        match (self.proposed_at, self.clock.as_ref()) {
+            (Some((proposed, started)), Some(clock)) if proposed == epoch => {
+                self.proposed_at = None;
+                Some(clock.now_millis().saturating_sub(started))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn set_verifier(&mut self, verifier: Arc<dyn Verifier>) {
+        self.verifier = verifier;
+    }
+
+    pub fn set_signer(&mut self, signer: Arc<dyn Signer>) {
+        assert_eq!(signer.public_key(), self.secret_key.public_key(),
+        "The signer must use the node's key.");
+
+        self.vote_counter.set_signer(signer.clone());
+        self.signer = signer;
+    }
+
     pub fn public_key(&self) -> &PubKeyMap<N> {
         &self.pub_keys
     }
@@ -172,7 +777,7 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
     }
 
     pub fn should_propose(&self) -> bool {
-        if self.has_input() {
+        if self.is_observer() || self.has_input() {
             false
         }
         if self.honey_badger.received_proposals() > self.netinfo().num_faulty() {
@@ -211,11 +816,34 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
 
         if kgs.count_messages(sender_id) > kgs.key_gen.num_nodes() + 1 {
             let fault_kind = FaultKind::TooManyKeyGenMessages;
-            Ok(Fault::new(sender_id.clone(), fault_kind).into());
+            return Ok(Fault::new(sender_id.clone(), fault_kind).into());
         }
         let tx = SignedKeyGenMsg(self.era, sender_id.clone(), kg_msg, sig);
-        self.key_gen_msg_buffer.push(tx);
-        Ok(FaultLog::default())
+        if self.buffer_key_gen_message(tx) {
+            Ok(Fault::new(sender_id.clone(), FaultKind::TooManyBufferedKeyGenMessages).into())
+        } else {
+            Ok(FaultLog::default())
+        }
+    }
+
+    fn key_gen_sender_limit(&self) -> usize {
+        (self.buffer_limits.key_gen_messages / self.netinfo().num_nodes().max(1)).max(1)
+    }
+
+    fn buffer_key_gen_message(&mut self, kg_msg: SignedKeyGenMsg<N>) -> bool {
+        if kg_msg.1 == *self.our_id() {
+            self.key_gen_msg_buffer.push(kg_msg);
+            return false;
+        }
+        let limit = self.key_gen_sender_limit();
+        let full = self.key_gen_msg_buffer.iter().filter(|msg| msg.1 == kg_msg.1).count() >= limit;
+        if full {
+            if let Some(pos) = self.key_gen_msg_buffer.iter().position(|msg| msg.1 == kg_msg.1) {
+                self.key_gen_msg_buffer.remove(pos);
+            }
+        }
+        self.key_gen_msg_buffer.push(kg_msg);
+        full
     }
 
     fn process_output<R: Rng>(&mut self, hb_step: honey_badger::Step<InternalContrib<C, N>, N>, rng: &mut R,) -> Result<Step<C, N>> {
@@ -226,34 +854,58 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
         for hb_batch in output {
             let batch_era = self.era;
             let batch_epoch = hb_batch.epoch + batch_era;
+            let batch_netinfo = self.netinfo().clone();
+            let batch_public = self.public_netinfo.clone();
+            self.vote_counter.set_epoch(batch_epoch);
+            self.reassembler.expire(batch_epoch);
             let mut batch_contributions = BTreeMap::new();
+            let verified = self.verify_committed(&hb_batch.contributions)?;
+            let mut stats = ProposerStats::new();
+            stats.late = batch_netinfo.all_ids().filter(|id| !hb_batch.contributions.contains_key(id)).cloned().collect();
 
             for (id, int_contrib) in hb_batch.contributions {
                 let InternalContrib {
                     votes, key_gen_messages, contrib,
                 } = int_contrib;
-                step.fault_log.extend(self.vote_counter.add_committed_votes(&id, votes)?);
-                batch_contributions.insert(id.clone(), contrib);
+                step.fault_log.extend(if verified {
+                    self.vote_counter.add_verified_votes(&id, votes)?
+                } else {
+                    self.vote_counter.add_committed_votes(&id, votes)?
+                });
+                if self.contribution_limits.allows_size(&contrib) {
+                    stats.sizes.insert(id.clone(), wire::serialized_size(&contrib).map_err(Error::SerializeBatch)?);
+                    batch_contributions.insert(id.clone(), contrib);
+                } else {
+                    step.fault_log.append(id.clone(), FaultKind::OversizedContribution);
+                }
                 self.key_gen_msg_buffer.retain(|skgm| !key_gen_messages.contains(skgm));
                 
                 for SignedKeyGenMsg(era, s_id, kg_msg, sig) in key_gen_messages {}
                     if ear != self.era {
                         let fault_kind = FaultKind::InvalidKeyGenMessageEra;
                         step.fault_log.append(id.clone(), fault_kind);
-                    } else if !self.verify_signature(&s_id, &sig, &kg_msg)? {
+                    } else if !verified && !self.verify_signature(&s_id, &sig, &kg_msg)? {
                         let fault_kind = FaultKind::InvalidKeyGenMessageSignature;
                         step.fault_log.append(id.clone(), fault_kind);
                     } else {
+                        if let Some(kgs) = self.key_gen_state.as_mut() {
+                            kgs.history.push((s_id.clone(), kg_msg.clone()));
+                        }
                         step.extend(match kg_msg {
-                            KeyGenMessage::Part(part) => self.handle_part(&s_id, part, rng)?,
+                            KeyGenMessage::Part(part) => self.handle_part(&s_id, part, &sig, rng)?,
                             KeyGenMessage::Ack(ack) => self.handle_ack(&s_id, ack)?,
                         });
                     }
                 }
             }
+            let digest = catch_up::batch_digest(batch_epoch, &batch_contributions)?;
+            self.era_summary.push_digest(batch_epoch, digest);
+            self.collect_garbage();
 
             let change = if let Some(kgs) = self.take_ready_key_gen() {
                 debug!("{}: DKG for complete for: {:?}", self, kgs.public_keys());
+                let old_pub_keys = self.pub_keys.clone();
+                let old_pub_key_set = self.netinfo().public_key_set().clone();
                 self.pub_keys = kgs.key_gen.public_keys().clone();
                 let (pk_set, sk_share) = kgs.key_gen.generate().map_err(Error::SyncKeyGen)?;
                 let our_id = self.our_id().clone();
@@ -261,8 +913,16 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
                 let netinfo = Arc::new(NetworkInfo::new(our_id, sk_share, pk_set, all_ids));
                 let params = self.honey_badger.params().clone();
                 self.restart_honey_badger(batch_epoch + 1, params, netinfo);
-                ChangeState::Complete(Change::NodeChange(self.pub_keys.clone()))
-            } else if let Some(change) = self.vote_counter.compute_winner().cloned() {
+                let change = ChangeState::Complete(Change::NodeChange(self.pub_keys.clone()));
+                let join_plan = JoinPlan {
+                    era: self.era, epoch: 0, change: change.clone(), pub_keys: self.pub_keys.clone(), pub_key_set: self.netinfo().public_key_set().clone(), params: self.honey_badger.params().clone(),
+                };
+                self.membership_changes.push_back(MembershipChange {
+                    era: self.era, old_pub_keys, old_pub_key_set, join_plan,
+                });
+                self.collect_garbage();
+                change
+            } else if let Some(change) = self.vote_counter.compute_winner() {
                 match change {
                     Change::NodeChange(ref pub_keys) => {
                         step.extend(self.update_key_gen(batch_epoch + 1, pub_keys.clone(), rng)?);
@@ -270,23 +930,46 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
                     Change::EncryptionSchedule(schedule) => {
                         self.update_encryption_schedule(batch_epoch + 1, schedule);
                     }
+                    Change::Custom(_) => {
+                        let params = self.honey_badger.params().clone();
+                        self.restart_honey_badger(batch_epoch + 1, params, self.netinfo().clone());
+                    }
+                    Change::Add(..) | Change::Remove(_) | Change::Multiple(_) | Change::Atomic(_) => {}
                 }
                 match change {
-                    Change::NodeChange(_) => ChangeState::InProgress(change),
-                    Change::EncryptionSchedule(_) => ChangeState::Complete(change),
+                    Change::EncryptionSchedule(_) | Change::Custom(_) => ChangeState::Complete(change),
+                    _ => ChangeState::InProgress(change),
                 }
             } else {
                 ChangeState::None
             };
-            step.output.push(Batch {
+            let batch = Batch {
                 epoch: batch_epoch,
                 era: batch_era,
                 change,
+                key_gen_running: self.key_gen_state.is_some(),
                 pub_keys: self.pub_keys.clone(),
-                netinfo: self.netinfo().clone(),
+                netinfo: self.public_netinfo.clone(),
                 contributions: batch_contributions,
                 params: self.honey_badger.params().clone(),
-            });
+                signature: None,
+                stats,
+            };
+            if self.sign_batches {
+                let (share, batches) = self.batch_signer.add_batch(batch, batch_public, batch_netinfo.secret_key_share(), &mut step.fault_log)?;
+                if let Some(share) = share {
+                    let msg = Message::BatchSignatureShare(batch_era, batch_epoch, Box::new(share));
+                    step.messages.push(Target::all().message(msg));
+                }
+                step.output.extend(batches);
+            } else {
+                step.output.push(batch);
+            }
+        }
+        if !self.has_input() && !self.is_observer() {
+            if let Some(contrib) = self.proposals.pop_front() {
+                step.extend(self.propose_now(contrib, rng)?);
+            }
         }
         Ok(step)
     }
@@ -306,9 +989,8 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
         let params = self.honey_badger.params().clone();
         self.restart_honey_badger(era, params, self.netinfo().clone());
         let threshold = util::max_faulty(pub_keys.len());
-        let sk = self.secret_key.clone();
         let our_id = self.our_id().clone();
-        let (key_gen, part) = SyncKeyGen::new(our_id, sk, pub_keys, threshold, rng).map_err(Error::SyncKeyGen)?;
+        let (key_gen, part) = SyncKeyGen::new(our_id, SecretKey::clone(&self.secret_key), pub_keys, threshold, rng).map_err(Error::SyncKeyGen)?;
         self.key_gen_state = Some(KeyGenState::new(key_gen));
         if let Some(part) = part {
             self.send_transaction(KeyGenMessage::Part(part))
@@ -318,18 +1000,30 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
     }
 
     fn restart_honey_badger(&mut self, era: u64, params: Params, netinfo: Arc<NetworkInfo<N>>) {
+        let summary = EraSummary::new(era, self.pub_keys.clone(), netinfo.public_key_set().clone(), params.clone());
+        self.era_history.push_back(mem::replace(&mut self.era_summary, summary));
+        let evidence = self.vote_counter.take_evidence();
+        self.evidence.extend(evidence);
         self.era = era;
+        self.catch_up.advance(era);
         self.key_gen_msg_buffer.retain(|kg_msg| kg_msg.0 >= era);
         self.vote_counter = VoteCounter::new(
             self.our_id().clone(),
-            self.secret_key.clone(),
+            self.signer.clone(),
             self.pub_keys.clone(),
             era,
         );
+        self.vote_counter.set_vote_window(self.vote_ttl, self.max_future_epochs);
+        self.vote_counter.set_quorum(self.quorum.clone());
+        self.vote_counter.set_custom_kinds(self.custom_kinds.clone());
+        self.vote_counter.set_observer(self.observer.clone());
+        self.public_netinfo = without_share(&netinfo);
         self.honey_badger = HoneyBadger::builder(netinfo).session_id(era).params(params).build();
+        self.collect_garbage();
     }
 
-    fn handle_part<R: Rng>(&mut self, sender_id: &N, part: Part, rng: &mut R,) -> Result<Step<C, N>> {
+    fn handle_part<R: Rng>(&mut self, sender_id: &N, part: Part, sig: &Signature, rng: &mut R,) -> Result<Step<C, N>> {
+        let evidence_part = part.clone();
         let outcome = if let Some(kgs) = self.key_gen_state.as_mut() {
             kgs.key_gen.handle_part(&sender_id, part, rng).map_err(Error::SyncKeyGen)?
         } else {
@@ -341,6 +1035,11 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
             PartOutcome::Valid(Some(ack)) => self.send_transaction(KeyGenMessage::Ack(ack)),
             PartOutcome::Valid(None) => Ok(Step::default()),
             PartOutcome::Invalid(fault) => {
+                if fault == PartFault::RowCount {
+                    self.evidence.push(Evidence::InvalidPart {
+                        era: self.era, sender: sender_id.clone(), part: evidence_part, sig: sig.clone(),
+                    });
+                }
                 let fault_kind = FaultKind::SyncKeyGenPart(fault);
                 Ok(Fault::new(sender_id.clone(), fault_kind).into())
             }
@@ -365,12 +1064,12 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
     }
 
     fn send_transaction(&mut self, kg_msg: KeyGenMessage) -> Result<Step<C, N>> {
-        let ser = bincode::serialize(&kg_msg).map_err(|err| Error:SerializeKeyGen(*err))?;
-        let sig = Box::new(self.secret_key.sign(ser));
+        let ser = wire::to_vec(&kg_msg).map_err(Error::SerializeKeyGen)?;
+        let sig = Box::new(self.signer.sign(&ser));
         if self.netinfo().is_validator() {
             let our_id = self.our_id().clone();
             let signed_msg = SignedKeyGenMsg(self.era, our_id, kg_msg.clone(), *sig.clone());
-            self.key_gen_msg_buffer.push(signed_msg);
+            self.buffer_key_gen_message(signed_msg);
         }
         let msg = Message::KeyGen(self.era, kg_msg, sig);
         Ok(Target::all().message(msg).into())
@@ -384,8 +1083,32 @@ impl<C, N> DynamicHoneyBadger<C, N> where C: Contribution + Serialize + Deserial
         }
     }
 
+    fn verify_committed(&self, contributions: &BTreeMap<N, InternalContrib<C, N>>) -> Result<bool> {
+        let mut items = Vec::new();
+        for int_contrib in contributions.values() {
+            for signed_vote in &int_contrib.votes {
+                match self.pub_keys.get(signed_vote.voter()) {
+                    Some(pk) => items.push((pk, signed_vote.signed_bytes()?, signed_vote.signature())),
+                    None => return Ok(false),
+                }
+            }
+            for SignedKeyGenMsg(_, s_id, kg_msg, sig) in &int_contrib.key_gen_messages {
+                let kgs = self.key_gen_state.as_ref();
+                match self.pub_keys.get(s_id).or_else(|| kgs.and_then(|kgs| kgs.public_keys().get(s_id))) {
+                    Some(pk) => items.push((pk, wire::to_vec(kg_msg).map_err(Error::SerializeKeyGen)?, sig)),
+                    None => return Ok(false),
+                }
+            }
+        }
+        if items.is_empty() {
+            return Ok(true);
+        }
+        let items: Vec<_> = items.iter().map(|(pk, msg, sig)| (*pk, &msg[..], *sig)).collect();
+        Ok(self.verifier.verify_batch(&items))
+    }
+
     fn verify_signature(&self, node_id: &N, sig: &Signature, kg_msg: &KeyGenMessage,) -> Result<bool> {
-        let ser = bincode::serialize(kg_msg).map_err(|err| Error::SerializeKeyGen(*err))?;
+        let ser = wire::to_vec(kg_msg).map_err(Error::SerializeKeyGen)?;
         let verify = |opt_pk: Option<&PublicKey>| opt_pk.map_or(false, |pk| pk.verift(&sig, &ser));
         let kgs = self.key_gen_state.as_ref();
         let current_key = self.pub_keys.get(node_id);
@@ -409,4 +1132,7 @@ impl<C, N> Epoched for DynamicHoneyBadger<C, N> where C: Contribution + Serializ
     fn epoch(&self) -> (u64, u64) {
         (self.era, self.honey_badger.epoch())
     }
-}
\ No newline at end of file
+}
+fn without_share<N: NodeIdT>(netinfo: &NetworkInfo<N>) -> Arc<NetworkInfo<N>> {
+    Arc::new(NetworkInfo::new(netinfo.our_id().clone(), None, netinfo.public_key_set().clone(), netinfo.all_ids()))
+}
@@ -0,0 +1,55 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::votes::SignedVote;
+use super::{wire, Error, KeyGenMessage, Result};
+use crate::crypto::{SecretKey, Signature};
+use crate::sync_key_gen::{Part, PartFault, PartOutcome, SyncKeyGen};
+use crate::{util, NodeIdT, PubKeyMap};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Evidence<N: Ord> {
+    ConflictingVotes(SignedVote<N>, SignedVote<N>),
+    InvalidPart {
+        era: u64,
+        sender: N,
+        part: Part,
+        sig: Signature,
+    },
+}
+
+impl<N: NodeIdT + Serialize> Evidence<N> {
+    pub fn accused(&self) -> &N {
+        match *self {
+            Evidence::ConflictingVotes(ref first, _) => first.voter(),
+            Evidence::InvalidPart { ref sender, .. } => sender,
+        }
+    }
+
+    pub fn era(&self) -> u64 {
+        match *self {
+            Evidence::ConflictingVotes(ref first, _) => first.era(),
+            Evidence::InvalidPart { era, .. } => era,
+        }
+    }
+
+    pub fn verify<R: Rng>(&self, pub_keys: &PubKeyMap<N>, rng: &mut R) -> Result<bool> {
+        match *self {
+            Evidence::ConflictingVotes(ref first, ref second) => {
+                Ok(first.conflicts_with(second) && first.verify(pub_keys)? && second.verify(pub_keys)?)
+            }
+            Evidence::InvalidPart { ref sender, ref part, ref sig, .. } => {
+                let kg_msg = KeyGenMessage::Part(part.clone());
+                let ser = wire::to_vec(&kg_msg).map_err(Error::SerializeKeyGen)?;
+                if !pub_keys.get(sender).map_or(false, |pk| pk.verify(sig, ser)) {
+                    return Ok(false);
+                }
+                let threshold = util::max_faulty(pub_keys.len());
+                let sk = rng.gen::<SecretKey>();
+                let (mut key_gen, _) = SyncKeyGen::new(sender.clone(), sk, pub_keys.clone(), threshold, rng).map_err(Error::SyncKeyGen)?;
+                let outcome = key_gen.handle_part(sender, part.clone(), rng).map_err(Error::SyncKeyGen)?;
+                Ok(matches!(outcome, PartOutcome::Invalid(PartFault::RowCount)))
+            }
+        }
+    }
+}
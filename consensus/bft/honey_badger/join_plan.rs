@@ -0,0 +1,72 @@
+use alloc::vec::Vec;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Bincode, Error, JoinPlan, Result, WireFormat};
+
+const MAGIC: &[u8; 2] = b"JP";
+const VERSION: u8 = 1;
+
+const TAG_ERA: u8 = 1;
+const TAG_CHANGE: u8 = 2;
+const TAG_PUB_KEYS: u8 = 3;
+const TAG_PUB_KEY_SET: u8 = 4;
+const TAG_PARAMS: u8 = 5;
+const TAG_EPOCH: u8 = 6;
+
+fn put_field<T: Serialize>(bytes: &mut Vec<u8>, tag: u8, value: &T) -> Result<()> {
+    let ser = Bincode::serialize(value).map_err(Error::SerializeJoinPlan)?;
+    bytes.push(tag);
+    bytes.extend_from_slice(&(ser.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&ser);
+    Ok(())
+}
+
+fn get_field<T: DeserializeOwned>(field: Option<&[u8]>) -> Result<T> {
+    let field = field.ok_or(Error::InvalidJoinPlanEncoding)?;
+    Bincode::deserialize(field).map_err(|_| Error::InvalidJoinPlanEncoding)
+}
+
+impl<N: Ord + Serialize + DeserializeOwned> JoinPlan<N> {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        put_field(&mut bytes, TAG_ERA, &self.era)?;
+        put_field(&mut bytes, TAG_CHANGE, &self.change)?;
+        put_field(&mut bytes, TAG_PUB_KEYS, &self.pub_keys)?;
+        put_field(&mut bytes, TAG_PUB_KEY_SET, &self.pub_key_set)?;
+        put_field(&mut bytes, TAG_PARAMS, &self.params)?;
+        put_field(&mut bytes, TAG_EPOCH, &self.epoch)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 3 || bytes[..2] != MAGIC[..] || bytes[2] == 0 || bytes[2] > VERSION {
+            return Err(Error::InvalidJoinPlanEncoding);
+        }
+        let mut fields: [Option<&[u8]>; 7] = [None; 7];
+        let mut rest = &bytes[3..];
+        while !rest.is_empty() {
+            if rest.len() < 5 {
+                return Err(Error::InvalidJoinPlanEncoding);
+            }
+            let tag = rest[0];
+            let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+            if rest.len() - 5 < len {
+                return Err(Error::InvalidJoinPlanEncoding);
+            }
+            if let Some(field) = fields.get_mut(tag as usize) {
+                *field = Some(&rest[5..5 + len]);
+            }
+            rest = &rest[5 + len..];
+        }
+        Ok(JoinPlan {
+            era: get_field(fields[TAG_ERA as usize])?,
+            epoch: fields[TAG_EPOCH as usize].map_or(Ok(0), |field| get_field(Some(field)))?,
+            change: get_field(fields[TAG_CHANGE as usize])?,
+            pub_keys: get_field(fields[TAG_PUB_KEYS as usize])?,
+            pub_key_set: get_field(fields[TAG_PUB_KEY_SET as usize])?,
+            params: get_field(fields[TAG_PARAMS as usize])?,
+        })
+    }
+}
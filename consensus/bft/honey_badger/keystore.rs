@@ -0,0 +1,108 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::Rng;
+use scrypt::{scrypt, Params as ScryptParams};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::{Bincode, WireError, WireFormat};
+use crate::crypto::serde_impl::SerdeSecret;
+use crate::crypto::SecretKey;
+use crate::{NodeIdT, PubKeyMap};
+
+const VERSION: u8 = 1;
+const LOG_N: u8 = 15;
+const R: u32 = 8;
+const P: u32 = 1;
+
+#[derive(Debug)]
+pub enum KeystoreError {
+    Io(io::Error),
+    Wire(WireError),
+    Kdf,
+    Decrypt,
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            KeystoreError::Io(ref err) => write!(f, "keystore io: {}", err),
+            KeystoreError::Wire(ref err) => write!(f, "keystore encoding: {}", err),
+            KeystoreError::Kdf => write!(f, "invalid keystore kdf parameters"),
+            KeystoreError::Decrypt => write!(f, "wrong passphrase or corrupted keystore"),
+            KeystoreError::UnsupportedVersion(version) => write!(f, "unsupported keystore version {}", version),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedKeystore {
+    version: u8,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: [u8; 16],
+    nonce: [u8; 12],
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Keystore<N: Ord> {
+    pub our_id: N,
+    pub secret_key: SecretKey,
+    pub pub_keys: PubKeyMap<N>,
+}
+
+fn derive_key(passphrase: &[u8], salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<Zeroizing<[u8; 32]>, KeystoreError> {
+    let params = ScryptParams::new(log_n, r, p).map_err(|_| KeystoreError::Kdf)?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt(passphrase, salt, &params, &mut key[..]).map_err(|_| KeystoreError::Kdf)?;
+    Ok(key)
+}
+
+impl<N: NodeIdT + Serialize + DeserializeOwned> Keystore<N> {
+    pub fn new(our_id: N, secret_key: SecretKey, pub_keys: PubKeyMap<N>) -> Self {
+        Keystore { our_id, secret_key, pub_keys }
+    }
+
+    pub fn encrypt<R: Rng>(&self, passphrase: &[u8], rng: &mut R) -> Result<Vec<u8>, KeystoreError> {
+        let plain = Zeroizing::new(Bincode::serialize(&(&self.our_id, SerdeSecret(&self.secret_key), &self.pub_keys)).map_err(KeystoreError::Wire)?);
+        let salt: [u8; 16] = rng.gen();
+        let nonce: [u8; 12] = rng.gen();
+        let key = derive_key(passphrase, &salt, LOG_N, R, P)?;
+        let cipher = Aes256Gcm::new(Key::from_slice(&key[..]));
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), &plain[..]).map_err(|_| KeystoreError::Decrypt)?;
+        let encrypted = EncryptedKeystore {
+            version: VERSION, log_n: LOG_N, r: R, p: P, salt, nonce, ciphertext,
+        };
+        Bincode::serialize(&encrypted).map_err(KeystoreError::Wire)
+    }
+
+    pub fn decrypt(bytes: &[u8], passphrase: &[u8]) -> Result<Self, KeystoreError> {
+        let encrypted: EncryptedKeystore = Bincode::deserialize(bytes).map_err(KeystoreError::Wire)?;
+        if encrypted.version != VERSION {
+            return Err(KeystoreError::UnsupportedVersion(encrypted.version));
+        }
+        let key = derive_key(passphrase, &encrypted.salt, encrypted.log_n, encrypted.r, encrypted.p)?;
+        let cipher = Aes256Gcm::new(Key::from_slice(&key[..]));
+        let plain = Zeroizing::new(cipher.decrypt(Nonce::from_slice(&encrypted.nonce), encrypted.ciphertext.as_ref()).map_err(|_| KeystoreError::Decrypt)?);
+        let (our_id, SerdeSecret(secret_key), pub_keys) = Bincode::deserialize(&plain[..]).map_err(KeystoreError::Wire)?;
+        Ok(Keystore { our_id, secret_key, pub_keys })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, passphrase: &[u8]) -> Result<Self, KeystoreError> {
+        let bytes = fs::read(path).map_err(KeystoreError::Io)?;
+        Self::decrypt(&bytes, passphrase)
+    }
+
+    pub fn save<P: AsRef<Path>, R: Rng>(&self, path: P, passphrase: &[u8], rng: &mut R) -> Result<(), KeystoreError> {
+        let bytes = self.encrypt(passphrase, rng)?;
+        fs::write(path, bytes).map_err(KeystoreError::Io)
+    }
+}
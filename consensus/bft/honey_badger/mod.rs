@@ -1,20 +1,76 @@
+#[cfg(feature = "async")]
+mod async_adapter;
 mod batch;
 mod builder;
+mod canonical;
+mod catch_up;
 mod change;
+mod chunk;
+mod commitment;
+#[cfg(feature = "compression")]
+mod compression;
 mod dynamic_honey_badger;
+mod evidence;
+mod join_plan;
+#[cfg(feature = "keystore")]
+mod keystore;
+#[cfg(feature = "noise")]
+mod noise;
+mod observer;
+mod platform;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod queueing_honey_badger;
+mod rate_limit;
+mod sender_queue;
+mod signer;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "std")]
+mod transport;
 mod votes;
+mod wire;
 
-use std::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
 use serde::{Deserialize, Serialize};
-use self::votes::SignedVote;
-use crate::crypto::{PublicKeySet, Signature};
+use tiny_keccak::sha3_256;
+use self::catch_up::MAX_CATCH_UP_ERAS;
+use self::votes::{SignedVote, VoteCounterState};
+use crate::crypto::{PublicKeySet, Signature, SignatureShare};
 use crate::honey_badger::{EncryptionSchedule, Message as HbMessage, Params};
 use crate::sync_key_gen::{Ack, Part, SyncKeyGen};
-use crate::{NodeIdT, PubKeyMap};
-pub use self::batch::Batch;
+use crate::{util, NodeIdT, PubKeyMap};
+#[cfg(feature = "async")]
+pub use self::async_adapter::{AsyncInput, DhbStream, OutgoingMessage};
+pub use self::batch::{Batch, BatchSummary, ProposerStats};
 pub use self::builder::DynamicHoneyBadgerBuilder;
-pub use self::Change::{Change, ChangeState};
+pub use self::catch_up::{CatchUp, Digest, EraSummary, SignedCatchUp};
+pub use self::change::{Change, ChangeState, CustomChange, MembershipChange};
+pub use self::chunk::Chunk;
+#[cfg(feature = "compression")]
+pub use self::compression::DEFAULT_COMPRESSION_THRESHOLD;
 pub use self::dynamic_honey_badger::DynamicHoneyBadger;
+pub use self::evidence::Evidence;
+#[cfg(feature = "keystore")]
+pub use self::keystore::{Keystore, KeystoreError};
+pub use self::observer::ConsensusObserver;
+pub use self::platform::{Clock, Entropy};
+#[cfg(feature = "std")]
+pub use self::platform::{OsEntropy, SystemClock};
+pub use self::queueing_honey_badger::{QueueingHoneyBadger, QueueingHoneyBadgerBuilder};
+pub use self::rate_limit::{MessageKind, RateLimit, RateLimits};
+pub use self::sender_queue::{SenderQueue, SenderQueueMessage};
+pub use self::signer::{DefaultVerifier, Signer, Verifier};
+#[cfg(feature = "std")]
+pub use self::transport::{FrameRead, FrameWrite, Transport, MAX_FRAME_LEN};
+pub use self::wire::{Bincode, CanonicalWire, WireError, WireFormat};
+#[cfg(feature = "cbor")]
+pub use self::wire::Cbor;
+#[cfg(feature = "protobuf")]
+pub use self::wire::Protobuf;
 pub use self::error::{Error, FaultKind, Result};
 
 pub type Step<C, N> = crate::CpStep<DynamicHoneyBadger<C, N>>;
@@ -36,6 +92,10 @@ pub enum Message<N: Ord> {
     HoneyBadger(u64, HbMessage<N>),
     KeyGen(u64, KeyGenMessage, Box<Signature>),
     SignedVote(SignedVote<N>),
+    CatchUpRequest(u64),
+    CatchUpResponse(Box<SignedCatchUp<N>>),
+    BatchSignatureShare(u64, u64, Box<SignatureShare>),
+    Chunk(Box<Chunk>),
 }
 
 impl<N: Ord> Message<N> {
@@ -44,6 +104,24 @@ impl<N: Ord> Message<N> {
             Message::HoneyBadger(era, _) => era,
             Message::KeyGen(era, _, _) => era,
             Message::SignedVote(ref signed_vote) => signed_vote.era(),
+            Message::CatchUpRequest(era) => era,
+            Message::CatchUpResponse(ref signed) => signed.era(),
+            Message::BatchSignatureShare(era, _, _) => era,
+            Message::Chunk(ref chunk) => chunk.era(),
+        }
+    }
+
+    fn is_immediate(&self) -> bool {
+        match *self {
+            Message::CatchUpRequest(_) | Message::CatchUpResponse(_) | Message::BatchSignatureShare(..) => true,
+            _ => false,
+        }
+    }
+
+    fn epoch(&self) -> (u64, u64) {
+        match *self {
+            Message::HoneyBadger(era, ref hb_msg) => (era, hb_msg.epoch()),
+            _ => (self.era(), 0),
         }
     }
 }
@@ -51,6 +129,7 @@ impl<N: Ord> Message<N> {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JoinPlan<N: Ord> {
     era: u64,
+    epoch: u64,
     change: ChangeState<N>,
     pub_keys: PubKeyMap<N>,
     pub_key_set: PublicKeySet,
@@ -59,20 +138,250 @@ pub struct JoinPlan<N: Ord> {
 
 impl<N: Ord> JoinPlan<N> {
     pub fn next_epoch(&self) -> u64 {
+        self.era + self.epoch
+    }
+
+    pub fn digest(&self) -> Result<Digest> where N: Serialize, {
+        let ser = Bincode::serialize(self).map_err(Error::SerializeJoinPlan)?;
+        Ok(sha3_256(&ser))
+    }
+
+    pub fn verify_digest(&self, expected: &Digest) -> Result<()> where N: Serialize, {
+        if self.digest()? != *expected {
+            return Err(Error::JoinPlanDigestMismatch);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SavedState<N: Ord> {
+    era: u64,
+    epoch: u64,
+    pub_keys: PubKeyMap<N>,
+    pub_key_set: PublicKeySet,
+    params: Params,
+    votes: VoteCounterState<N>,
+    key_gen_msg_buffer: Vec<SignedKeyGenMsg<N>>,
+    key_gen: Option<(PubKeyMap<N>, Vec<(N, KeyGenMessage)>)>,
+}
+
+impl<N: Ord> SavedState<N> {
+    pub fn era(&self) -> u64 {
         self.era
     }
+
+    pub fn next_epoch(&self) -> u64 {
+        self.era + self.epoch
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferLimits {
+    pub key_gen_messages: usize,
+    pub queued_proposals: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        BufferLimits {
+            key_gen_messages: 65_536,
+            queued_proposals: 3,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum QuorumRule {
+    ExceedsFaulty,
+    Majority,
+    TwoThirds,
+    Custom(Arc<dyn Fn(usize) -> usize + Send + Sync>),
+}
+
+impl QuorumRule {
+    pub fn required_votes(&self, num_validators: usize) -> usize {
+        let max_faulty = util::max_faulty(num_validators);
+        match *self {
+            QuorumRule::ExceedsFaulty => max_faulty + 1,
+            QuorumRule::Majority => num_validators / 2 + 1,
+            QuorumRule::TwoThirds => 2 * max_faulty + 1,
+            QuorumRule::Custom(ref required) => required(num_validators).max(1),
+        }
+    }
+}
+
+impl Default for QuorumRule {
+    fn default() -> Self {
+        QuorumRule::ExceedsFaulty
+    }
+}
+
+impl fmt::Debug for QuorumRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QuorumRule::ExceedsFaulty => write!(f, "ExceedsFaulty"),
+            QuorumRule::Majority => write!(f, "Majority"),
+            QuorumRule::TwoThirds => write!(f, "TwoThirds"),
+            QuorumRule::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retention {
+    pub eras: usize,
+    pub epochs: u64,
+    pub membership_changes: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            eras: MAX_CATCH_UP_ERAS, epochs: u64::max_value(), membership_changes: usize::max_value(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetainedState {
+    pub eras: usize,
+    pub batch_digests: usize,
+    pub key_gen_messages: usize,
+    pub pending_votes: usize,
+    pub pending_batches: usize,
+    pub queued_proposals: usize,
+    pub evidence: usize,
+    pub membership_changes: usize,
+    pub partial_chunks: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvictionPolicy {
+    pub threshold: usize,
+    pub window: u64,
+    pub kinds: Vec<FaultKind>,
+}
+
+impl EvictionPolicy {
+    pub fn new(threshold: usize, window: u64) -> Self {
+        EvictionPolicy {
+            threshold, window, kinds: Vec::new(),
+        }
+    }
+
+    pub fn kind(mut self, kind: FaultKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    fn counts(&self, kind: &FaultKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(kind)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdaptiveEncryption {
+    pub schedule: EncryptionSchedule,
+    pub epochs: u64,
+    pub kinds: Vec<FaultKind>,
+}
+
+impl AdaptiveEncryption {
+    pub fn new(schedule: EncryptionSchedule, epochs: u64) -> Self {
+        AdaptiveEncryption {
+            schedule, epochs, kinds: Vec::new(),
+        }
+    }
+
+    pub fn kind(mut self, kind: FaultKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    fn counts(&self, kind: &FaultKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(kind)
+    }
+
+    fn schedule_at(&self, censored_at: Option<u64>, epoch: u64) -> EncryptionSchedule {
+        match censored_at {
+            Some(at) if at.saturating_add(self.epochs) >= epoch => EncryptionSchedule::Always,
+            _ => self.schedule,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FlowHint {
+    MessagePostponed,
+    PeerTooFarAhead,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chunking {
+    pub chunk_size: usize,
+    pub honey_badger: bool,
+    pub timeout_epochs: u64,
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Chunking {
+            chunk_size: 1 << 20, honey_badger: false, timeout_epochs: 16,
+        }
+    }
+}
+
+impl Chunking {
+    fn applies_to<N: Ord>(&self, message: &Message<N>) -> bool {
+        match *message {
+            Message::KeyGen(..) => true,
+            Message::HoneyBadger(..) => self.honey_badger,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContributionLimits {
+    pub max_bytes: usize,
+    pub max_transactions: usize,
+}
+
+impl Default for ContributionLimits {
+    fn default() -> Self {
+        ContributionLimits {
+            max_bytes: usize::max_value(), max_transactions: usize::max_value(),
+        }
+    }
+}
+
+impl ContributionLimits {
+    fn allows_size<C: Serialize>(&self, contrib: &C) -> bool {
+        self.max_bytes == usize::max_value() || wire::serialized_size(contrib).map_or(false, |size| size <= self.max_bytes as u64)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyGenProgress<N: Ord> {
+    pub complete: usize,
+    pub total: usize,
+    pub missing_parts: BTreeSet<N>,
+    pub missing_acks: BTreeSet<N>,
+    pub is_ready: bool,
 }
 
 #[derive(Debug)]
 struct KeyGenState<N: Ord> {
     key_gen: SyncKeyGen<N>,
     msg_count: BTreeMap<N, usize>,
+    history: Vec<(N, KeyGenMessage)>,
 }
 
 impl<N: NodeIdT> KeyGenState<N> {
     fn new(key_gen: SyncKeyGen<N>) -> Self {
         KeyGenState {
-            key_gen, msg_count: BTreeMap::new(),
+            key_gen, msg_count: BTreeMap::new(), history: Vec::new(),
         }
     }
 
@@ -85,6 +394,28 @@ impl<N: NodeIdT> KeyGenState<N> {
         self.key_gen.public_keys()
     }
 
+    fn progress(&self) -> KeyGenProgress<N> {
+        let mut acks: BTreeMap<&N, usize> = BTreeMap::new();
+        let mut missing_parts: BTreeSet<N> = self.public_keys().keys().cloned().collect();
+        for (sender_id, kg_msg) in &self.history {
+            match *kg_msg {
+                KeyGenMessage::Part(_) => {
+                    missing_parts.remove(sender_id);
+                }
+                KeyGenMessage::Ack(_) => *acks.entry(sender_id).or_insert(0) += 1,
+            }
+        }
+        let num_parts = self.public_keys().len() - missing_parts.len();
+        let missing_acks = self.public_keys().keys().filter(|id| acks.get(id).map_or(true, |n| *n < num_parts)).cloned().collect();
+        KeyGenProgress {
+            complete: self.key_gen.count_complete(),
+            total: self.public_keys().len(),
+            missing_parts,
+            missing_acks,
+            is_ready: self.is_ready(),
+        }
+    }
+
     fn count_messages(&mut self, node_id: &N) -> usize {
         let count = self.msg_count.entry(node_id.clone()).or_insert(0);
         *count += 1;
@@ -0,0 +1,119 @@
+use std::io;
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+
+use snow::{Builder, StatelessTransportState};
+
+use super::transport::{FrameRead, FrameWrite, MAX_FRAME_LEN};
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const MAX_NOISE_MSG: usize = 65535;
+const TAG_LEN: usize = 16;
+const MAX_CHUNK: usize = MAX_NOISE_MSG - TAG_LEN;
+
+fn noise_error(err: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+pub(super) struct NoiseWriter {
+    stream: TcpStream,
+    session: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl NoiseWriter {
+    fn send(&mut self, plain: &[u8]) -> io::Result<()> {
+        let mut buf = vec![0u8; plain.len() + TAG_LEN];
+        let len = self.session.write_message(self.nonce, plain, &mut buf).map_err(noise_error)?;
+        self.nonce += 1;
+        self.stream.write_frame(&buf[..len])
+    }
+}
+
+impl FrameWrite for NoiseWriter {
+    fn write_frame(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+        }
+        self.send(&(payload.len() as u32).to_be_bytes())?;
+        for chunk in payload.chunks(MAX_CHUNK) {
+            self.send(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+pub(super) struct NoiseReader {
+    stream: TcpStream,
+    session: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl NoiseReader {
+    fn recv(&mut self) -> io::Result<Vec<u8>> {
+        let frame = self.stream.read_frame()?;
+        if frame.len() > MAX_NOISE_MSG {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "noise message too large"));
+        }
+        let mut buf = vec![0u8; frame.len()];
+        let len = self.session.read_message(self.nonce, &frame, &mut buf).map_err(noise_error)?;
+        self.nonce += 1;
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+impl FrameRead for NoiseReader {
+    fn read_frame(&mut self) -> io::Result<Vec<u8>> {
+        let header = self.recv()?;
+        if header.len() != 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad frame header"));
+        }
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+        }
+        let mut payload = Vec::with_capacity(len);
+        while payload.len() < len {
+            let chunk = self.recv()?;
+            if chunk.is_empty() || payload.len() + chunk.len() > len {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "bad frame chunk"));
+            }
+            payload.extend_from_slice(&chunk);
+        }
+        Ok(payload)
+    }
+}
+
+pub(super) fn handshake(stream: &mut TcpStream, initiator: bool) -> io::Result<(Vec<u8>, NoiseWriter, NoiseReader)> {
+    let params = NOISE_PARAMS.parse().map_err(noise_error)?;
+    let keypair = Builder::new(NOISE_PARAMS.parse().map_err(noise_error)?).generate_keypair().map_err(noise_error)?;
+    let builder = Builder::new(params).local_private_key(&keypair.private);
+    let mut hs = if initiator { builder.build_initiator() } else { builder.build_responder() }.map_err(noise_error)?;
+    let mut buf = vec![0u8; MAX_NOISE_MSG];
+    for turn in 0..3 {
+        if (turn % 2 == 0) == initiator {
+            let len = hs.write_message(&[], &mut buf).map_err(noise_error)?;
+            stream.write_frame(&buf[..len])?;
+        } else {
+            let frame = stream.read_frame()?;
+            if frame.len() > MAX_NOISE_MSG {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "noise message too large"));
+            }
+            hs.read_message(&frame, &mut buf).map_err(noise_error)?;
+        }
+    }
+    let hash = hs.get_handshake_hash().to_vec();
+    let session = Arc::new(hs.into_stateless_transport_mode().map_err(noise_error)?);
+    let writer = NoiseWriter {
+        stream: stream.try_clone()?, session: session.clone(), nonce: 0,
+    };
+    let reader = NoiseReader {
+        stream: stream.try_clone()?, session, nonce: 0,
+    };
+    Ok((hash, writer, reader))
+}
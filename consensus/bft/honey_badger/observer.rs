@@ -0,0 +1,75 @@
+use alloc::sync::Arc;
+use core::fmt;
+
+use serde::Serialize;
+
+use super::{wire, FaultKind, Message, MessageKind};
+use crate::Target;
+
+pub trait ConsensusObserver<N>: Send + Sync {
+    fn on_epoch_output(&self, _era: u64, _epoch: u64, _contributions: usize) {}
+
+    fn on_epoch_latency(&self, _era: u64, _epoch: u64, _millis: u64) {}
+
+    fn on_fault(&self, _node_id: &N, _kind: &FaultKind) {}
+
+    fn on_message_in(&self, _sender_id: &N, _kind: MessageKind, _bytes: usize) {}
+
+    fn on_message_out(&self, _target: &Target<N>, _kind: MessageKind, _bytes: usize) {}
+
+    fn on_era_change(&self, _old_era: u64, _new_era: u64) {}
+
+    fn on_vote(&self, _voter: &N, _era: u64, _committed: bool) {}
+}
+
+pub(super) struct ObserverHandle<N>(Option<Arc<dyn ConsensusObserver<N>>>);
+
+impl<N> Default for ObserverHandle<N> {
+    fn default() -> Self {
+        ObserverHandle(None)
+    }
+}
+
+impl<N> Clone for ObserverHandle<N> {
+    fn clone(&self) -> Self {
+        ObserverHandle(self.0.clone())
+    }
+}
+
+impl<N> fmt::Debug for ObserverHandle<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObserverHandle({})", if self.0.is_some() { "Some(..)" } else { "None" })
+    }
+}
+
+impl<N: Ord + Serialize> ObserverHandle<N> {
+    pub(super) fn new(observer: Option<Arc<dyn ConsensusObserver<N>>>) -> Self {
+        ObserverHandle(observer)
+    }
+
+    pub(super) fn get(&self) -> Option<&Arc<dyn ConsensusObserver<N>>> {
+        self.0.as_ref()
+    }
+
+    pub(super) fn message_in(&self, sender_id: &N, message: &Message<N>) {
+        if let Some(ref observer) = self.0 {
+            observer.on_message_in(sender_id, MessageKind::from(message), wire_size(message));
+        }
+    }
+
+    pub(super) fn message_out(&self, target: &Target<N>, message: &Message<N>) {
+        if let Some(ref observer) = self.0 {
+            observer.on_message_out(target, MessageKind::from(message), wire_size(message));
+        }
+    }
+
+    pub(super) fn vote(&self, voter: &N, era: u64, committed: bool) {
+        if let Some(ref observer) = self.0 {
+            observer.on_vote(voter, era, committed);
+        }
+    }
+}
+
+fn wire_size<N: Ord + Serialize>(message: &Message<N>) -> usize {
+    wire::serialized_size(message).map_or(0, |size| size as usize)
+}
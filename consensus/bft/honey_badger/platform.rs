@@ -0,0 +1,31 @@
+use core::fmt;
+
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now_millis(&self) -> u64;
+}
+
+pub trait Entropy: fmt::Debug + Send + Sync {
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsEntropy;
+
+#[cfg(feature = "std")]
+impl Entropy for OsEntropy {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        rand::Rng::fill(&mut rand::thread_rng(), dest);
+    }
+}
@@ -0,0 +1,797 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+use core::{fmt, str};
+
+use serde::de::{self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple, SerializeTupleStruct, SerializeTupleVariant};
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_BYTES: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+const ROOT_FIELD: u32 = 1;
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtoError(String);
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ser::StdError for ProtoError {}
+
+impl ser::Error for ProtoError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ProtoError(msg.to_string())
+    }
+}
+
+impl de::Error for ProtoError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ProtoError(msg.to_string())
+    }
+}
+
+fn error(msg: &str) -> ProtoError {
+    ProtoError(msg.to_string())
+}
+
+type Result<T> = core::result::Result<T, ProtoError>;
+
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    value.serialize(FieldSerializer { out: &mut out, field: ROOT_FIELD })?;
+    Ok(out)
+}
+
+pub fn from_slice<'de, T: de::Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+    let mut reader = MessageReader { input: bytes };
+    let value = reader.expect_field(ROOT_FIELD)?;
+    if !reader.input.is_empty() {
+        return Err(error("trailing bytes"));
+    }
+    T::deserialize(ValueDeserializer { value, depth: 0 })
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_tag(out: &mut Vec<u8>, field: u32, wire: u8) {
+    put_varint(out, u64::from(field) << 3 | u64::from(wire));
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_tag(out, field, WIRE_BYTES);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+struct FieldSerializer<'a> {
+    out: &'a mut Vec<u8>,
+    field: u32,
+}
+
+impl<'a> FieldSerializer<'a> {
+    fn varint(self, value: u64) -> Result<()> {
+        put_tag(self.out, self.field, WIRE_VARINT);
+        put_varint(self.out, value);
+        Ok(())
+    }
+
+    fn message(self) -> MessageSerializer<'a> {
+        MessageSerializer { out: self.out, field: self.field, body: Vec::new(), next: 1 }
+    }
+}
+
+struct MessageSerializer<'a> {
+    out: &'a mut Vec<u8>,
+    field: u32,
+    body: Vec<u8>,
+    next: u32,
+}
+
+impl<'a> MessageSerializer<'a> {
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(FieldSerializer { out: &mut self.body, field: self.next })?;
+        self.next += 1;
+        Ok(())
+    }
+
+    fn repeated<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(FieldSerializer { out: &mut self.body, field: 1 })
+    }
+
+    fn finish(self) -> Result<()> {
+        put_bytes(self.out, self.field, &self.body);
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for FieldSerializer<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+    type SerializeSeq = MessageSerializer<'a>;
+    type SerializeTuple = MessageSerializer<'a>;
+    type SerializeTupleStruct = MessageSerializer<'a>;
+    type SerializeTupleVariant = VariantMessage<'a>;
+    type SerializeMap = MapSerializer<'a>;
+    type SerializeStruct = MessageSerializer<'a>;
+    type SerializeStructVariant = VariantMessage<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.varint(v as u64)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.varint(zigzag(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.varint(zigzag(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.varint(zigzag(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.varint(zigzag(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.varint(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.varint(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.varint(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.varint(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        put_tag(self.out, self.field, WIRE_FIXED32);
+        self.out.extend_from_slice(&v.to_bits().to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        put_tag(self.out, self.field, WIRE_FIXED64);
+        self.out.extend_from_slice(&v.to_bits().to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.varint(u64::from(u32::from(v)))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        put_bytes(self.out, self.field, v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        put_bytes(self.out, self.field, v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.message().finish()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        let mut msg = self.message();
+        msg.element(value)?;
+        msg.finish()
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.message().finish()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.message().finish()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, variant_index: u32, _variant: &'static str) -> Result<()> {
+        VariantMessage::new(self, variant_index).end_variant()
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, variant_index: u32, _variant: &'static str, value: &T) -> Result<()> {
+        let mut msg = VariantMessage::new(self, variant_index);
+        msg.element(value)?;
+        msg.end_variant()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<MessageSerializer<'a>> {
+        Ok(self.message())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<MessageSerializer<'a>> {
+        Ok(self.message())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<MessageSerializer<'a>> {
+        Ok(self.message())
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, variant_index: u32, _variant: &'static str, _len: usize) -> Result<VariantMessage<'a>> {
+        Ok(VariantMessage::new(self, variant_index))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer<'a>> {
+        Ok(MapSerializer { msg: self.message(), entry: Vec::new() })
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<MessageSerializer<'a>> {
+        Ok(self.message())
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, variant_index: u32, _variant: &'static str, _len: usize) -> Result<VariantMessage<'a>> {
+        Ok(VariantMessage::new(self, variant_index))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl<'a> SerializeSeq for MessageSerializer<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.repeated(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> SerializeTuple for MessageSerializer<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> SerializeTupleStruct for MessageSerializer<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+impl<'a> SerializeStruct for MessageSerializer<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.finish()
+    }
+}
+
+struct VariantMessage<'a> {
+    msg: MessageSerializer<'a>,
+    variant: u32,
+    body: Vec<u8>,
+    next: u32,
+}
+
+impl<'a> VariantMessage<'a> {
+    fn new(field: FieldSerializer<'a>, variant_index: u32) -> Self {
+        VariantMessage { msg: field.message(), variant: variant_index + 1, body: Vec::new(), next: 1 }
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(FieldSerializer { out: &mut self.body, field: self.next })?;
+        self.next += 1;
+        Ok(())
+    }
+
+    fn end_variant(mut self) -> Result<()> {
+        put_bytes(&mut self.msg.body, self.variant, &self.body);
+        self.msg.finish()
+    }
+}
+
+impl<'a> SerializeTupleVariant for VariantMessage<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.end_variant()
+    }
+}
+
+impl<'a> SerializeStructVariant for VariantMessage<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        self.end_variant()
+    }
+}
+
+struct MapSerializer<'a> {
+    msg: MessageSerializer<'a>,
+    entry: Vec<u8>,
+}
+
+impl<'a> SerializeMap for MapSerializer<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.entry.clear();
+        key.serialize(FieldSerializer { out: &mut self.entry, field: 1 })
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(FieldSerializer { out: &mut self.entry, field: 2 })?;
+        put_bytes(&mut self.msg.body, 1, &self.entry);
+        Ok(())
+    }
+
+    fn end(self) -> Result<()> {
+        self.msg.finish()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Value<'de> {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    Bytes(&'de [u8]),
+}
+
+struct MessageReader<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> MessageReader<'de> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for (i, &byte) in self.input.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.input = &self.input[i + 1..];
+                return Ok(value);
+            }
+        }
+        Err(error("malformed varint"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        if len > self.input.len() {
+            return Err(error("unexpected end of message"));
+        }
+        let (head, tail) = self.input.split_at(len);
+        self.input = tail;
+        Ok(head)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u32, Value<'de>)>> {
+        if self.input.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.varint()?;
+        let field = u32::try_from(tag >> 3).map_err(|_| error("field number out of range"))?;
+        let value = match (tag & 7) as u8 {
+            WIRE_VARINT => Value::Varint(self.varint()?),
+            WIRE_FIXED64 => Value::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            WIRE_FIXED32 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            WIRE_BYTES => {
+                let len = usize::try_from(self.varint()?).map_err(|_| error("length out of range"))?;
+                Value::Bytes(self.take(len)?)
+            },
+            _ => return Err(error("unsupported wire type")),
+        };
+        Ok(Some((field, value)))
+    }
+
+    fn expect_field(&mut self, field: u32) -> Result<Value<'de>> {
+        match self.next_field()? {
+            Some((number, value)) if number == field => Ok(value),
+            Some(_) => Err(error("unexpected field number")),
+            None => Err(error("missing field")),
+        }
+    }
+}
+
+struct ValueDeserializer<'de> {
+    value: Value<'de>,
+    depth: usize,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn varint(&self) -> Result<u64> {
+        match self.value {
+            Value::Varint(v) => Ok(v),
+            _ => Err(error("expected varint")),
+        }
+    }
+
+    fn bytes(&self) -> Result<&'de [u8]> {
+        match self.value {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(error("expected length-delimited field")),
+        }
+    }
+
+    fn message(&self) -> Result<MessageAccess<'de>> {
+        if self.depth >= MAX_DEPTH {
+            return Err(error("message nested too deeply"));
+        }
+        Ok(MessageAccess { reader: MessageReader { input: self.bytes()? }, next: 1, repeated: false, pending: None, depth: self.depth + 1 })
+    }
+
+    fn repeated(&self) -> Result<MessageAccess<'de>> {
+        let mut access = self.message()?;
+        access.repeated = true;
+        Ok(access)
+    }
+
+    fn signed(&self) -> Result<i64> {
+        self.varint().map(unzigzag)
+    }
+}
+
+fn narrow<T: TryFrom<U>, U>(value: U) -> Result<T> {
+    T::try_from(value).map_err(|_| error("integer out of range"))
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = ProtoError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(error("protobuf encoding is not self-describing"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.varint()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(error("invalid bool")),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(narrow(self.signed()?)?)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i16(narrow(self.signed()?)?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(narrow(self.signed()?)?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(self.signed()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u8(narrow(self.varint()?)?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u16(narrow(self.varint()?)?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(narrow(self.varint()?)?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(self.varint()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Fixed32(bits) => visitor.visit_f32(f32::from_bits(bits)),
+            _ => Err(error("expected fixed32")),
+        }
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            Value::Fixed64(bits) => visitor.visit_f64(f64::from_bits(bits)),
+            _ => Err(error("expected fixed64")),
+        }
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let c = narrow::<u32, _>(self.varint()?)?;
+        visitor.visit_char(char::from_u32(c).ok_or_else(|| error("invalid char"))?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_borrowed_str(str::from_utf8(self.bytes()?).map_err(|_| error("invalid utf-8"))?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_borrowed_bytes(self.bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut access = self.message()?;
+        match access.reader.next_field()? {
+            None => visitor.visit_none(),
+            Some((1, value)) if access.reader.input.is_empty() => visitor.visit_some(ValueDeserializer { value, depth: access.depth }),
+            Some(_) => Err(error("malformed option")),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if !self.bytes()?.is_empty() {
+            return Err(error("expected empty message"));
+        }
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut access = self.repeated()?;
+        let value = visitor.visit_seq(&mut access)?;
+        access.end(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        let mut access = self.message()?;
+        let value = visitor.visit_seq(&mut access)?;
+        access.end(value)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut access = self.repeated()?;
+        let value = visitor.visit_map(&mut access)?;
+        access.end(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        let mut access = self.message()?;
+        let (field, value) = access.reader.next_field()?.ok_or_else(|| error("missing enum variant"))?;
+        if !access.reader.input.is_empty() || field == 0 {
+            return Err(error("malformed enum"));
+        }
+        visitor.visit_enum(VariantDeserializer { index: field - 1, payload: ValueDeserializer { value, depth: access.depth } })
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct MessageAccess<'de> {
+    reader: MessageReader<'de>,
+    next: u32,
+    repeated: bool,
+    pending: Option<Value<'de>>,
+    depth: usize,
+}
+
+impl<'de> MessageAccess<'de> {
+    fn advance(&mut self) -> Result<Option<Value<'de>>> {
+        match self.reader.next_field()? {
+            None => Ok(None),
+            Some((field, value)) if field == self.next => {
+                if !self.repeated {
+                    self.next += 1;
+                }
+                Ok(Some(value))
+            },
+            Some(_) => Err(error("unexpected field number")),
+        }
+    }
+
+    fn end<T>(self, value: T) -> Result<T> {
+        if self.reader.input.is_empty() { Ok(value) } else { Err(error("trailing fields")) }
+    }
+}
+
+impl<'de> SeqAccess<'de> for &mut MessageAccess<'de> {
+    type Error = ProtoError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.advance()? {
+            Some(value) => seed.deserialize(ValueDeserializer { value, depth: self.depth }).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for &mut MessageAccess<'de> {
+    type Error = ProtoError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let entry = match self.advance()? {
+            Some(Value::Bytes(entry)) => entry,
+            Some(_) => return Err(error("malformed map entry")),
+            None => return Ok(None),
+        };
+        let mut reader = MessageReader { input: entry };
+        let key = seed.deserialize(ValueDeserializer { value: reader.expect_field(1)?, depth: self.depth })?;
+        let value = reader.expect_field(2)?;
+        if !reader.input.is_empty() {
+            return Err(error("malformed map entry"));
+        }
+        self.pending = Some(value);
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.pending.take().ok_or_else(|| error("map value without key"))?;
+        seed.deserialize(ValueDeserializer { value, depth: self.depth })
+    }
+}
+
+struct VariantDeserializer<'de> {
+    index: u32,
+    payload: ValueDeserializer<'de>,
+}
+
+impl<'de> EnumAccess<'de> for VariantDeserializer<'de> {
+    type Error = ProtoError;
+    type Variant = ValueDeserializer<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant)> {
+        let variant = seed.deserialize(IntoDeserializer::<ProtoError>::into_deserializer(self.index))?;
+        Ok((variant, self.payload))
+    }
+}
+
+impl<'de> VariantAccess<'de> for ValueDeserializer<'de> {
+    type Error = ProtoError;
+
+    fn unit_variant(self) -> Result<()> {
+        if self.bytes()?.is_empty() { Ok(()) } else { Err(error("expected unit variant")) }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        let mut access = self.message()?;
+        let value = access.reader.expect_field(1)?;
+        let value = seed.deserialize(ValueDeserializer { value, depth: access.depth })?;
+        access.end(value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::string::String;
+    use std::vec::Vec;
+    use serde::{Deserialize, Serialize};
+
+    use super::{from_slice, to_vec};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Kind {
+        Empty,
+        Count(u64),
+        Pair(i32, String),
+        Named { flag: Option<u8>, items: Vec<u16> },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Envelope {
+        era: u64,
+        delta: i64,
+        kinds: Vec<Kind>,
+        weights: BTreeMap<String, (bool, f64)>,
+        nested: Option<Option<()>>,
+        digest: [u8; 3],
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut weights = BTreeMap::new();
+        weights.insert(String::from("a"), (true, 1.5));
+        weights.insert(String::from("b"), (false, -2.0));
+        let envelope = Envelope {
+            era: 300, delta: -5, kinds: vec![Kind::Empty, Kind::Count(7), Kind::Pair(-1, String::from("hi")), Kind::Named { flag: None, items: vec![1, 2] }], weights, nested: Some(None), digest: [1, 2, 3],
+        };
+        let bytes = to_vec(&envelope).expect("encode");
+        assert_eq!(from_slice::<Envelope>(&bytes).expect("decode"), envelope);
+        assert!(from_slice::<Envelope>(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_varint_matches_protobuf() {
+        assert_eq!(to_vec(&150u32).expect("encode"), vec![0x08, 0x96, 0x01]);
+        assert_eq!(to_vec(&-1i32).expect("encode"), vec![0x08, 0x01]);
+    }
+}
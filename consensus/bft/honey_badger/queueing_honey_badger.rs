@@ -0,0 +1,173 @@
+use alloc::vec::Vec;
+use core::cmp;
+use core::hash::Hash;
+#[cfg(not(feature = "std"))]
+use hashbrown::HashSet;
+#[cfg(feature = "std")]
+use std::collections::HashSet;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Batch, Change, DynamicHoneyBadger, Error, FaultKind, Input, Message, Result};
+use crate::fault_log::FaultLog;
+use crate::{ConsensusProtocol, Contribution, Epoched, NodeIdT};
+
+pub type Step<T, N> = crate::CpStep<QueueingHoneyBadger<T, N>>;
+
+pub struct QueueingHoneyBadgerBuilder<T, N: Ord> {
+    dyn_hb: DynamicHoneyBadger<Vec<T>, N>,
+    batch_size: usize,
+    queue: Vec<T>,
+}
+
+impl<T, N> QueueingHoneyBadgerBuilder<T, N> where T: Contribution + Serialize + DeserializeOwned + Clone + Hash, N: NodeIdT + Serialize + DeserializeOwned, {
+    pub fn new(dyn_hb: DynamicHoneyBadger<Vec<T>, N>) -> Self {
+        QueueingHoneyBadgerBuilder {
+            dyn_hb, batch_size: 100, queue: Vec::new(),
+        }
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn queue(mut self, queue: Vec<T>) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    pub fn build<R: Rng>(self, rng: &mut R) -> Result<(QueueingHoneyBadger<T, N>, Step<T, N>)> {
+        let mut qhb = QueueingHoneyBadger {
+            dyn_hb: self.dyn_hb, batch_size: self.batch_size, queue: self.queue,
+        };
+        let step = qhb.propose(rng)?;
+        Ok((qhb, step))
+    }
+}
+
+#[derive(Debug)]
+pub struct QueueingHoneyBadger<T, N: Ord> {
+    dyn_hb: DynamicHoneyBadger<Vec<T>, N>,
+    batch_size: usize,
+    queue: Vec<T>,
+}
+
+impl<T, N> ConsensusProtocol for QueueingHoneyBadger<T, N> where T: Contribution + Serialize + DeserializeOwned + Clone + Hash, N: NodeIdT + Serialize + DeserializeOwned, {
+    type NodeId = N;
+    type Input = Input<T, N>;
+    type Output = Batch<Vec<T>, N>;
+    type Message = Message<N>;
+    type Error = Error;
+    type FaultKind = FaultKind;
+
+    fn handle_input<R: Rng>(&mut self, input: Self::Input, rng: &mut R) -> Result<Step<T, N>> {
+        match input {
+            Input::User(tx) => self.push_transaction(tx, rng),
+            Input::Change(change) => self.vote_for(change, rng),
+        }
+    }
+
+    fn handle_message<R: Rng>(&mut self, sender_id: &N, message: Self::Message, rng: &mut R,) -> Result<Step<T, N>> {
+        self.handle_message(sender_id, message, rng)
+    }
+
+    fn terminated(&self) -> bool {
+        false
+    }
+
+    fn our_id(&self) -> &N {
+        self.dyn_hb.our_id()
+    }
+}
+
+impl<T, N> QueueingHoneyBadger<T, N> where T: Contribution + Serialize + DeserializeOwned + Clone + Hash, N: NodeIdT + Serialize + DeserializeOwned, {
+    pub fn builder(dyn_hb: DynamicHoneyBadger<Vec<T>, N>) -> QueueingHoneyBadgerBuilder<T, N> {
+        QueueingHoneyBadgerBuilder::new(dyn_hb)
+    }
+
+    pub fn push_transaction<R: Rng>(&mut self, tx: T, rng: &mut R) -> Result<Step<T, N>> {
+        self.queue.push(tx);
+        self.propose(rng)
+    }
+
+    pub fn vote_for<R: Rng>(&mut self, change: Change<N>, rng: &mut R) -> Result<Step<T, N>> {
+        let step = self.dyn_hb.vote_for(change)?;
+        self.process_step(step, rng)
+    }
+
+    pub fn handle_message<R: Rng>(&mut self, sender_id: &N, message: Message<N>, rng: &mut R,) -> Result<Step<T, N>> {
+        let step = self.dyn_hb.handle_message(sender_id, message, rng)?;
+        self.process_step(step, rng)
+    }
+
+    pub fn dyn_hb(&self) -> &DynamicHoneyBadger<Vec<T>, N> {
+        &self.dyn_hb
+    }
+
+    pub fn queue(&self) -> &[T] {
+        &self.queue
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn process_step<R: Rng>(&mut self, mut dhb_step: super::Step<Vec<T>, N>, rng: &mut R) -> Result<Step<T, N>> {
+        let mut step = Step::default();
+        self.check_batches(&mut dhb_step.output, &mut step.fault_log);
+        self.remove_committed(&dhb_step.output);
+        step.output.extend(dhb_step.output);
+        step.fault_log.extend(dhb_step.fault_log);
+        step.messages.extend(dhb_step.messages);
+        step.extend(self.propose(rng)?);
+        Ok(step)
+    }
+
+    fn check_batches(&self, batches: &mut [Batch<Vec<T>, N>], fault_log: &mut FaultLog<N, FaultKind>) {
+        let max_transactions = self.dyn_hb.contribution_limits().max_transactions;
+        for batch in batches {
+            let oversized: Vec<N> = batch.contributions.iter().filter(|(_, txs)| txs.len() > max_transactions).map(|(id, _)| id.clone()).collect();
+            for id in oversized {
+                batch.contributions.remove(&id);
+                fault_log.append(id, FaultKind::OversizedContribution);
+            }
+        }
+    }
+
+    fn remove_committed(&mut self, batches: &[Batch<Vec<T>, N>]) {
+        if batches.is_empty() {
+            return;
+        }
+        let committed: HashSet<&T> = batches.iter().flat_map(|batch| batch.iter()).collect();
+        self.queue.retain(|tx| !committed.contains(tx));
+    }
+
+    fn propose<R: Rng>(&mut self, rng: &mut R) -> Result<Step<T, N>> {
+        let mut step = Step::default();
+        while self.dyn_hb.should_propose() {
+            let num_validators = cmp::max(1, self.dyn_hb.netinfo().num_nodes());
+            let limits = *self.dyn_hb.contribution_limits();
+            let amount = cmp::min(cmp::max(1, self.batch_size / num_validators), limits.max_transactions);
+            let window = cmp::min(self.batch_size, self.queue.len());
+            let mut contrib: Vec<T> = self.queue[..window].choose_multiple(rng, amount).cloned().collect();
+            while !limits.allows_size(&contrib) && contrib.pop().is_some() {}
+            let mut dhb_step = self.dyn_hb.handle_input(Input::User(contrib), rng)?;
+            self.check_batches(&mut dhb_step.output, &mut step.fault_log);
+            self.remove_committed(&dhb_step.output);
+            step.output.extend(dhb_step.output);
+            step.fault_log.extend(dhb_step.fault_log);
+            step.messages.extend(dhb_step.messages);
+        }
+        Ok(step)
+    }
+}
+
+impl<T, N> Epoched for QueueingHoneyBadger<T, N> where T: Contribution + Serialize + DeserializeOwned + Clone + Hash, N: NodeIdT + Serialize + DeserializeOwned, {
+    type Epoch = (u64, u64);
+    fn epoch(&self) -> (u64, u64) {
+        self.dyn_hb.epoch()
+    }
+}
@@ -0,0 +1,150 @@
+use alloc::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::{wire, Message};
+use crate::NodeIdT;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MessageKind {
+    HoneyBadger,
+    KeyGen,
+    SignedVote,
+    CatchUp,
+    BatchSignature,
+    Chunk,
+}
+
+impl<'a, N: Ord> From<&'a Message<N>> for MessageKind {
+    fn from(message: &'a Message<N>) -> Self {
+        match *message {
+            Message::HoneyBadger(..) => MessageKind::HoneyBadger,
+            Message::KeyGen(..) => MessageKind::KeyGen,
+            Message::SignedVote(..) => MessageKind::SignedVote,
+            Message::CatchUpRequest(..) | Message::CatchUpResponse(..) => MessageKind::CatchUp,
+            Message::BatchSignatureShare(..) => MessageKind::BatchSignature,
+            Message::Chunk(..) => MessageKind::Chunk,
+        }
+    }
+}
+
+impl MessageKind {
+    pub fn priority(self) -> u8 {
+        match self {
+            MessageKind::CatchUp => 0,
+            MessageKind::KeyGen | MessageKind::SignedVote | MessageKind::Chunk => 1,
+            MessageKind::BatchSignature => 2,
+            MessageKind::HoneyBadger => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+impl RateLimit {
+    pub fn new(messages: usize, bytes: usize) -> Self {
+        RateLimit { messages, bytes }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    per_sender: Option<RateLimit>,
+    per_kind: BTreeMap<MessageKind, RateLimit>,
+}
+
+impl RateLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn per_sender(mut self, limit: RateLimit) -> Self {
+        self.per_sender = Some(limit);
+        self
+    }
+
+    pub fn per_kind(mut self, kind: MessageKind, limit: RateLimit) -> Self {
+        self.per_kind.insert(kind, limit);
+        self
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.per_sender.is_none() && self.per_kind.is_empty()
+    }
+
+    fn counts_bytes(&self, kind: MessageKind) -> bool {
+        self.per_sender.iter().chain(self.per_kind.get(&kind)).any(|limit| limit.bytes != usize::max_value())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Usage {
+    messages: usize,
+    bytes: usize,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes = self.bytes.saturating_add(bytes);
+    }
+
+    fn exceeds(&self, limit: Option<&RateLimit>) -> bool {
+        limit.map_or(false, |limit| self.messages > limit.messages || self.bytes > limit.bytes)
+    }
+}
+
+#[derive(Debug, Default)]
+struct SenderUsage {
+    total: Usage,
+    kinds: BTreeMap<MessageKind, Usage>,
+}
+
+#[derive(Debug)]
+pub(super) struct RateLimiter<N: Ord> {
+    limits: RateLimits,
+    epoch: (u64, u64),
+    usage: BTreeMap<N, SenderUsage>,
+}
+
+impl<N: NodeIdT + Serialize> RateLimiter<N> {
+    pub(super) fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            limits, epoch: (0, 0), usage: BTreeMap::new(),
+        }
+    }
+
+    pub(super) fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    pub(super) fn set_limits(&mut self, limits: RateLimits) {
+        self.limits = limits;
+        self.usage.clear();
+    }
+
+    pub(super) fn allow(&mut self, sender_id: &N, message: &Message<N>, epoch: (u64, u64)) -> bool {
+        if self.limits.is_unlimited() {
+            return true;
+        }
+        if epoch != self.epoch {
+            self.epoch = epoch;
+            self.usage.clear();
+        }
+        let kind = MessageKind::from(message);
+        let bytes = if self.limits.counts_bytes(kind) {
+            wire::serialized_size(message).map_or(usize::max_value(), |size| size as usize)
+        } else {
+            0
+        };
+        let usage = self.usage.entry(sender_id.clone()).or_insert_with(SenderUsage::default);
+        let kind_usage = usage.kinds.entry(kind).or_insert_with(Usage::default);
+        usage.total.add(bytes);
+        kind_usage.add(bytes);
+        !usage.total.exceeds(self.limits.per_sender.as_ref()) && !kind_usage.exceeds(self.limits.per_kind.get(&kind))
+    }
+}
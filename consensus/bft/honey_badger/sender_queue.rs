@@ -0,0 +1,193 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{Batch, DynamicHoneyBadger, MessageKind, Error, FaultKind, FlowHint, Input, Message, Result};
+use crate::{ConsensusProtocol, Contribution, Epoched, NodeIdT, Target, TargetedMessage};
+
+pub type Step<C, N> = crate::CpStep<SenderQueue<C, N>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SenderQueueMessage<N: Ord> {
+    EpochStarted(u64, u64),
+    Algo(Message<N>),
+}
+
+#[derive(Debug)]
+pub struct SenderQueue<C, N: Ord> {
+    algo: DynamicHoneyBadger<C, N>,
+    our_epoch: (u64, u64),
+    peer_epochs: BTreeMap<N, (u64, u64)>,
+    outgoing: BTreeMap<N, BTreeMap<(u64, u64), Vec<Message<N>>>>,
+}
+
+enum Delivery {
+    Now,
+    Later,
+    Never,
+}
+
+impl<C, N> ConsensusProtocol for SenderQueue<C, N> where C: Contribution + Serialize + DeserializeOwned, N: NodeIdT + Serialize + DeserializeOwned, {
+    type NodeId = N;
+    type Input = Input<C, N>;
+    type Output = Batch<C, N>;
+    type Message = SenderQueueMessage<N>;
+    type Error = Error;
+    type FaultKind = FaultKind;
+
+    fn handle_input<R: Rng>(&mut self, input: Self::Input, rng: &mut R) -> Result<Step<C, N>> {
+        let step = self.algo.handle_input(input, rng)?;
+        Ok(self.apply(step))
+    }
+
+    fn handle_message<R: Rng>(&mut self, sender_id: &N, msg: Self::Message, rng: &mut R,) -> Result<Step<C, N>> {
+        self.handle_message(sender_id, msg, rng)
+    }
+
+    fn terminated(&self) -> bool {
+        false
+    }
+
+    fn our_id(&self) -> &N {
+        self.algo.our_id()
+    }
+}
+
+impl<C, N> SenderQueue<C, N> where C: Contribution + Serialize + DeserializeOwned, N: NodeIdT + Serialize + DeserializeOwned, {
+    pub fn new(algo: DynamicHoneyBadger<C, N>) -> (Self, Step<C, N>) {
+        let our_epoch = algo.epoch();
+        let sq = SenderQueue {
+            algo, our_epoch, peer_epochs: BTreeMap::new(), outgoing: BTreeMap::new(),
+        };
+        let step = Target::all().message(SenderQueueMessage::EpochStarted(our_epoch.0, our_epoch.1)).into();
+        (sq, step)
+    }
+
+    pub fn algo(&self) -> &DynamicHoneyBadger<C, N> {
+        &self.algo
+    }
+
+    pub fn algo_mut(&mut self) -> &mut DynamicHoneyBadger<C, N> {
+        &mut self.algo
+    }
+
+    pub fn peer_epoch(&self, peer_id: &N) -> Option<(u64, u64)> {
+        self.peer_epochs.get(peer_id).cloned()
+    }
+
+    pub fn take_flow_hints(&mut self) -> BTreeMap<N, FlowHint> {
+        self.algo.take_flow_hints()
+    }
+
+    pub fn queued_messages(&self, peer_id: &N) -> usize {
+        self.outgoing.get(peer_id).map_or(0, |queue| queue.values().map(Vec::len).sum())
+    }
+
+    pub fn handle_message<R: Rng>(&mut self, sender_id: &N, msg: SenderQueueMessage<N>, rng: &mut R,) -> Result<Step<C, N>> {
+        match msg {
+            SenderQueueMessage::EpochStarted(era, epoch) => Ok(self.handle_epoch_started(sender_id, (era, epoch))),
+            SenderQueueMessage::Algo(msg) => {
+                let step = self.algo.handle_message(sender_id, msg, rng)?;
+                Ok(self.apply(step))
+            }
+        }
+    }
+
+    pub fn handle_messages<I, R: Rng>(&mut self, messages: I, rng: &mut R) -> Result<Step<C, N>> where I: IntoIterator<Item = (N, SenderQueueMessage<N>)>, {
+        let mut messages: Vec<_> = messages.into_iter().collect();
+        messages.sort_by_key(|(_, msg)| match *msg {
+            SenderQueueMessage::EpochStarted(..) => 0,
+            SenderQueueMessage::Algo(ref msg) => MessageKind::from(msg).priority(),
+        });
+        let mut step = Step::default();
+        for (sender_id, msg) in messages {
+            step.extend(self.handle_message(&sender_id, msg, rng)?);
+        }
+        Ok(step)
+    }
+
+    fn handle_epoch_started(&mut self, sender_id: &N, epoch: (u64, u64)) -> Step<C, N> {
+        let peer_epoch = self.peer_epochs.entry(sender_id.clone()).or_insert(epoch);
+        if *peer_epoch > epoch {
+            return Step::default();
+        }
+        *peer_epoch = epoch;
+        let mut step = Step::default();
+        let mut queue = match self.outgoing.remove(sender_id) {
+            Some(queue) => queue,
+            None => return step,
+        };
+        let ready_end = epoch.1.saturating_add(self.algo.max_future_epochs()).saturating_add(1);
+        let mut ready = queue.split_off(&(epoch.0, 0));
+        let later = ready.split_off(&(epoch.0, ready_end));
+        for msg in ready.into_iter().flat_map(|(_, msgs)| msgs) {
+            step.messages.push(Target::node(sender_id.clone()).message(SenderQueueMessage::Algo(msg)));
+        }
+        if !later.is_empty() {
+            self.outgoing.insert(sender_id.clone(), later);
+        }
+        step
+    }
+
+    fn apply(&mut self, dhb_step: super::Step<C, N>) -> Step<C, N> {
+        let mut step = Step::default();
+        step.output.extend(dhb_step.output);
+        step.fault_log.extend(dhb_step.fault_log);
+        for TargetedMessage { target, message } in dhb_step.messages {
+            let msg_epoch = message.epoch();
+            for peer_id in self.peers().into_iter().filter(|id| target.contains(id)) {
+                let delivery = if message.is_immediate() { Delivery::Now } else { self.delivery(&peer_id, msg_epoch) };
+                match delivery {
+                    Delivery::Now => {
+                        step.messages.push(Target::node(peer_id).message(SenderQueueMessage::Algo(message.clone())));
+                    }
+                    Delivery::Later => {
+                        let queue = self.outgoing.entry(peer_id).or_insert_with(BTreeMap::new);
+                        queue.entry(msg_epoch).or_insert_with(Vec::new).push(message.clone());
+                    }
+                    Delivery::Never => {}
+                }
+            }
+        }
+        let epoch = self.algo.epoch();
+        if epoch != self.our_epoch {
+            self.our_epoch = epoch;
+            step.messages.push(Target::all().message(SenderQueueMessage::EpochStarted(epoch.0, epoch.1)));
+        }
+        step
+    }
+
+    fn peers(&self) -> Vec<N> {
+        let our_id = self.our_id();
+        let mut peers: Vec<N> = self.algo.netinfo().all_ids().filter(|id| *id != our_id).cloned().collect();
+        for id in self.peer_epochs.keys() {
+            if id != our_id && !peers.contains(id) {
+                peers.push(id.clone());
+            }
+        }
+        peers
+    }
+
+    fn delivery(&self, peer_id: &N, (era, epoch): (u64, u64)) -> Delivery {
+        let (peer_era, peer_epoch) = match self.peer_epochs.get(peer_id) {
+            Some(peer_epoch) => *peer_epoch,
+            None => return Delivery::Later,
+        };
+        if era < peer_era {
+            Delivery::Never
+        } else if era > peer_era || epoch > peer_epoch.saturating_add(self.algo.max_future_epochs()) {
+            Delivery::Later
+        } else {
+            Delivery::Now
+        }
+    }
+}
+
+impl<C, N> Epoched for SenderQueue<C, N> where C: Contribution + Serialize + DeserializeOwned, N: NodeIdT + Serialize + DeserializeOwned, {
+    type Epoch = (u64, u64);
+    fn epoch(&self) -> (u64, u64) {
+        self.our_epoch
+    }
+}
@@ -0,0 +1,30 @@
+use core::fmt;
+
+use crate::crypto::{PublicKey, SecretKey, Signature};
+
+pub trait Signer: fmt::Debug + Send + Sync {
+    fn public_key(&self) -> PublicKey;
+
+    fn sign(&self, msg: &[u8]) -> Signature;
+}
+
+impl Signer for SecretKey {
+    fn public_key(&self) -> PublicKey {
+        SecretKey::public_key(self)
+    }
+
+    fn sign(&self, msg: &[u8]) -> Signature {
+        SecretKey::sign(self, msg)
+    }
+}
+
+pub trait Verifier: fmt::Debug + Send + Sync {
+    fn verify_batch(&self, items: &[(&PublicKey, &[u8], &Signature)]) -> bool {
+        items.iter().all(|(pk, msg, sig)| pk.verify(sig, msg))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultVerifier;
+
+impl Verifier for DefaultVerifier {}
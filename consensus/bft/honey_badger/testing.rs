@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Batch, Change, DynamicHoneyBadger, Message, Result, Step};
+use crate::crypto::{SecretKey, SecretKeySet};
+use crate::{to_pub_keys, util, Contribution, NetworkInfo};
+
+#[derive(Clone, Debug)]
+pub struct NetConfig {
+    pub min_delay: u64,
+    pub max_delay: u64,
+    pub drop_rate: f64,
+    pub reorder: bool,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        NetConfig {
+            min_delay: 0, max_delay: 0, drop_rate: 0.0, reorder: false,
+        }
+    }
+}
+
+pub trait Adversary {
+    fn tamper(&mut self, _from: usize, _to: usize, message: Message<usize>, _rng: &mut ChaChaRng) -> Vec<Message<usize>> {
+        vec![message]
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PassiveAdversary;
+
+impl Adversary for PassiveAdversary {}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SilentAdversary;
+
+impl Adversary for SilentAdversary {
+    fn tamper(&mut self, _from: usize, _to: usize, _message: Message<usize>, _rng: &mut ChaChaRng) -> Vec<Message<usize>> {
+        Vec::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplayAdversary;
+
+impl Adversary for ReplayAdversary {
+    fn tamper(&mut self, _from: usize, _to: usize, message: Message<usize>, _rng: &mut ChaChaRng) -> Vec<Message<usize>> {
+        vec![message.clone(), message]
+    }
+}
+
+struct InFlight {
+    from: usize,
+    to: usize,
+    message: Message<usize>,
+    deliver_at: u64,
+}
+
+pub struct TestNode<C> {
+    pub dhb: DynamicHoneyBadger<C, usize>,
+    pub outputs: Vec<Batch<C, usize>>,
+    pub faulty: bool,
+}
+
+pub struct TestNetwork<C> {
+    nodes: BTreeMap<usize, TestNode<C>>,
+    in_flight: Vec<InFlight>,
+    partitions: BTreeSet<(usize, usize)>,
+    config: NetConfig,
+    adversary: Box<dyn Adversary>,
+    time: u64,
+    rng: ChaChaRng,
+}
+
+impl<C> TestNetwork<C> where C: Contribution + Serialize + DeserializeOwned + PartialEq, {
+    pub fn new(num_nodes: usize, num_faulty: usize, config: NetConfig, adversary: Box<dyn Adversary>, seed: u64) -> Self {
+        let mut rng = ChaChaRng::seed_from_u64(seed);
+        let sk_set = SecretKeySet::random(util::max_faulty(num_nodes), &mut rng);
+        let pk_set = sk_set.public_keys();
+        let sec_keys: BTreeMap<usize, SecretKey> = (0..num_nodes).map(|id| (id, rng.gen())).collect();
+        let pub_keys = to_pub_keys(&sec_keys);
+        let nodes = sec_keys.into_iter().map(|(id, sk)| {
+            let netinfo = NetworkInfo::new(id, sk_set.secret_key_share(id), pk_set.clone(), pub_keys.keys());
+            let dhb = DynamicHoneyBadger::builder().build(netinfo, sk, pub_keys.clone());
+            (id, TestNode { dhb, outputs: Vec::new(), faulty: id < num_faulty })
+        }).collect();
+        TestNetwork {
+            nodes, in_flight: Vec::new(), partitions: BTreeSet::new(), config, adversary, time: 0, rng,
+        }
+    }
+
+    pub fn node(&self, id: usize) -> Option<&TestNode<C>> {
+        self.nodes.get(&id)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (&usize, &TestNode<C>)> {
+        self.nodes.iter()
+    }
+
+    pub fn correct_nodes(&self) -> impl Iterator<Item = &TestNode<C>> {
+        self.nodes.values().filter(|node| !node.faulty)
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn partition(&mut self, side_a: &[usize], side_b: &[usize]) {
+        for a in side_a {
+            for b in side_b {
+                self.partitions.insert((*a, *b));
+                self.partitions.insert((*b, *a));
+            }
+        }
+    }
+
+    pub fn heal(&mut self) {
+        self.partitions.clear();
+    }
+
+    pub fn propose(&mut self, id: usize, contrib: C) -> Result<()> {
+        let step = match self.nodes.get_mut(&id) {
+            Some(node) => node.dhb.propose(contrib, &mut self.rng)?,
+            None => return Ok(()),
+        };
+        self.dispatch(id, step);
+        Ok(())
+    }
+
+    pub fn vote_for(&mut self, id: usize, change: Change<usize>) -> Result<()> {
+        let step = match self.nodes.get_mut(&id) {
+            Some(node) => node.dhb.vote_for(change)?,
+            None => return Ok(()),
+        };
+        self.dispatch(id, step);
+        Ok(())
+    }
+
+    fn dispatch(&mut self, from: usize, step: Step<C, usize>) {
+        let faulty = match self.nodes.get_mut(&from) {
+            Some(node) => {
+                node.outputs.extend(step.output);
+                node.faulty
+            }
+            None => return,
+        };
+        let ids: Vec<usize> = self.nodes.keys().cloned().collect();
+        for msg in step.messages {
+            for to in ids.iter().cloned().filter(|to| *to != from && msg.target.contains(to)) {
+                let messages = if faulty { self.adversary.tamper(from, to, msg.message.clone(), &mut self.rng) } else { vec![msg.message.clone()] };
+                for message in messages {
+                    if self.partitions.contains(&(from, to)) || self.rng.gen::<f64>() < self.config.drop_rate {
+                        continue;
+                    }
+                    let delay = self.rng.gen_range(self.config.min_delay, self.config.max_delay.max(self.config.min_delay) + 1);
+                    self.in_flight.push(InFlight {
+                        from, to, message, deliver_at: self.time + delay,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn step(&mut self) -> Result<bool> {
+        let next = match self.in_flight.iter().map(|msg| msg.deliver_at).min() {
+            Some(next) => next,
+            None => return Ok(false),
+        };
+        self.time = self.time.max(next);
+        let time = self.time;
+        let ready: Vec<usize> = self.in_flight.iter().enumerate().filter(|(_, msg)| msg.deliver_at <= time).map(|(idx, _)| idx).collect();
+        let idx = if self.config.reorder { ready[self.rng.gen_range(0, ready.len())] } else { ready[0] };
+        let InFlight { from, to, message, .. } = self.in_flight.remove(idx);
+        let step = match self.nodes.get_mut(&to) {
+            Some(node) => node.dhb.handle_message(&from, message, &mut self.rng)?,
+            None => return Ok(true),
+        };
+        self.dispatch(to, step);
+        Ok(true)
+    }
+
+    pub fn run_until<F>(&mut self, max_steps: usize, done: F) -> Result<bool> where F: Fn(&Self) -> bool, {
+        for _ in 0..max_steps {
+            if done(self) {
+                return Ok(true);
+            }
+            if !self.step()? {
+                break;
+            }
+        }
+        Ok(done(self))
+    }
+
+    pub fn check_agreement(&self) -> bool {
+        let mut correct = self.correct_nodes();
+        let first = match correct.next() {
+            Some(node) => node,
+            None => return true,
+        };
+        correct.all(|node| node.outputs.iter().zip(&first.outputs).all(|(a, b)| a.public_eq(b)))
+    }
+
+    pub fn check_validity(&self) -> bool {
+        self.correct_nodes().flat_map(|node| &node.outputs).all(|batch| {
+            let num_nodes = batch.public_keys().len();
+            batch.contributions().count() >= num_nodes - util::max_faulty(num_nodes)
+        })
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[cfg(feature = "compression")]
+use super::compression;
+#[cfg(feature = "noise")]
+use super::noise;
+use super::wire::{Bincode, WireFormat};
+use crate::crypto::{SecretKey, Signature};
+use crate::{NodeIdT, PubKeyMap, TargetedMessage};
+
+pub const MAX_FRAME_LEN: usize = 16 << 20;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(250);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CHALLENGE_LEN: usize = 32;
+const HELLO_DOMAIN: &[u8] = b"hbbft transport hello";
+
+const FLAG_RAW: u8 = 0;
+const FLAG_COMPRESSED: u8 = 1;
+
+pub trait FrameWrite: Send {
+    fn write_frame(&mut self, payload: &[u8]) -> io::Result<()>;
+    fn close(&mut self) {}
+}
+
+pub trait FrameRead: Send {
+    fn read_frame(&mut self) -> io::Result<Vec<u8>>;
+}
+
+impl FrameWrite for TcpStream {
+    fn write_frame(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+        }
+        self.write_all(&(payload.len() as u32).to_be_bytes())?;
+        self.write_all(payload)?;
+        self.flush()
+    }
+
+    fn close(&mut self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+impl FrameRead for TcpStream {
+    fn read_frame(&mut self) -> io::Result<Vec<u8>> {
+        let mut len = [0u8; 4];
+        self.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+        }
+        let mut payload = vec![0u8; len];
+        self.read_exact(&mut payload)?;
+        Ok(payload)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Hello<N> {
+    node_id: N,
+    sig: Signature,
+    compression: bool,
+}
+
+fn hello_transcript(challenge: &[u8], binding: Option<&[u8]>) -> Vec<u8> {
+    let mut transcript = HELLO_DOMAIN.to_vec();
+    transcript.extend_from_slice(challenge);
+    transcript.extend_from_slice(binding.unwrap_or(&[]));
+    transcript
+}
+
+fn invalid_data<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(feature = "compression")]
+fn compress(payload: &[u8]) -> io::Result<Vec<u8>> {
+    compression::compress(payload)
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_payload: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Other, "compression support is not enabled"))
+}
+
+#[cfg(feature = "compression")]
+fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    compression::decompress(data, MAX_FRAME_LEN)
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(invalid_data("compression support is not enabled"))
+}
+
+fn encode_frame(payload: &[u8], threshold: usize) -> io::Result<Vec<u8>> {
+    let (flag, body) = if payload.len() > threshold {
+        (FLAG_COMPRESSED, compress(payload)?)
+    } else {
+        (FLAG_RAW, payload.to_vec())
+    };
+    let mut frame = Vec::with_capacity(body.len() + 1);
+    frame.push(flag);
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+fn decode_frame(frame: &[u8]) -> io::Result<Vec<u8>> {
+    match frame.split_first() {
+        Some((&FLAG_RAW, body)) => Ok(body.to_vec()),
+        Some((&FLAG_COMPRESSED, body)) => decompress(body),
+        _ => Err(invalid_data("bad frame flag")),
+    }
+}
+
+type SharedWriter = Arc<Mutex<Box<dyn FrameWrite>>>;
+
+struct Peer {
+    conn: usize,
+    compress: bool,
+    writer: SharedWriter,
+}
+
+struct Shared<N: Ord, M, W> {
+    our_id: N,
+    secret_key: SecretKey,
+    noise: bool,
+    compression: RwLock<Option<usize>>,
+    pub_keys: RwLock<PubKeyMap<N>>,
+    peers: Mutex<BTreeMap<N, Peer>>,
+    incoming: Mutex<Sender<(N, M)>>,
+    next_conn: AtomicUsize,
+    shutdown: AtomicBool,
+    wire: PhantomData<fn() -> W>,
+}
+
+impl<N, M, W> Shared<N, M, W> where N: NodeIdT + Serialize + DeserializeOwned + Send + Sync + 'static, M: Serialize + DeserializeOwned + Send + 'static, W: WireFormat + 'static, {
+    fn handshake(&self, mut stream: TcpStream, expected: Option<&N>) -> io::Result<(N, bool, Box<dyn FrameWrite>, Box<dyn FrameRead>)> {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        if self.noise {
+            return self.noise_handshake(stream, expected);
+        }
+        let mut writer = stream.try_clone()?;
+        let (peer_id, compress) = self.exchange_hello(&mut writer, &mut stream, expected, None)?;
+        Ok((peer_id, compress, Box::new(writer), Box::new(stream)))
+    }
+
+    #[cfg(feature = "noise")]
+    fn noise_handshake(&self, mut stream: TcpStream, expected: Option<&N>) -> io::Result<(N, bool, Box<dyn FrameWrite>, Box<dyn FrameRead>)> {
+        let (hash, mut writer, mut reader) = noise::handshake(&mut stream, expected.is_some())?;
+        let (peer_id, compress) = self.exchange_hello(&mut writer, &mut reader, expected, Some(&hash))?;
+        Ok((peer_id, compress, Box::new(writer), Box::new(reader)))
+    }
+
+    #[cfg(not(feature = "noise"))]
+    fn noise_handshake(&self, _stream: TcpStream, _expected: Option<&N>) -> io::Result<(N, bool, Box<dyn FrameWrite>, Box<dyn FrameRead>)> {
+        Err(io::Error::new(io::ErrorKind::Other, "noise support is not enabled"))
+    }
+
+    fn exchange_hello(&self, writer: &mut dyn FrameWrite, reader: &mut dyn FrameRead, expected: Option<&N>, binding: Option<&[u8]>,) -> io::Result<(N, bool)> {
+        let challenge: [u8; CHALLENGE_LEN] = rand::random();
+        writer.write_frame(&challenge)?;
+        let peer_challenge = reader.read_frame()?;
+        if peer_challenge.len() != CHALLENGE_LEN {
+            return Err(invalid_data("bad challenge"));
+        }
+        let our_compression = self.compression.read().unwrap().is_some();
+        let hello = Hello {
+            node_id: self.our_id.clone(), sig: self.secret_key.sign(hello_transcript(&peer_challenge, binding)), compression: our_compression,
+        };
+        writer.write_frame(&W::serialize(&hello).map_err(invalid_data)?)?;
+        let Hello { node_id, sig, compression } = W::deserialize::<Hello<N>>(&reader.read_frame()?).map_err(invalid_data)?;
+        if node_id == self.our_id || expected.map_or(false, |id| *id != node_id) {
+            return Err(invalid_data("unexpected peer id"));
+        }
+        let pub_key = self.pub_keys.read().unwrap().get(&node_id).cloned().ok_or_else(|| invalid_data("unknown peer id"))?;
+        if !pub_key.verify(&sig, hello_transcript(&challenge, binding)) {
+            return Err(invalid_data("invalid identity signature"));
+        }
+        Ok((node_id, our_compression && compression))
+    }
+
+    fn run_peer(&self, stream: TcpStream, expected: Option<&N>) -> io::Result<()> {
+        let (peer_id, compress, writer, mut reader) = self.handshake(stream, expected)?;
+        let conn = self.next_conn.fetch_add(1, Ordering::Relaxed);
+        let peer = Peer { conn, compress, writer: Arc::new(Mutex::new(writer)) };
+        if let Some(old) = self.peers.lock().unwrap().insert(peer_id.clone(), peer) {
+            old.writer.lock().unwrap().close();
+        }
+        let result = self.read_loop(&peer_id, compress, &mut *reader);
+        let mut peers = self.peers.lock().unwrap();
+        if peers.get(&peer_id).map_or(false, |peer| peer.conn == conn) {
+            peers.remove(&peer_id);
+        }
+        result
+    }
+
+    fn read_loop(&self, peer_id: &N, compress: bool, reader: &mut dyn FrameRead) -> io::Result<()> {
+        while !self.shutdown.load(Ordering::Relaxed) {
+            let frame = reader.read_frame()?;
+            let msg = if compress { W::deserialize(&decode_frame(&frame)?) } else { W::deserialize(&frame) }.map_err(invalid_data)?;
+            if self.incoming.lock().unwrap().send((peer_id.clone(), msg)).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn accept_loop(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming() {
+            if self.shutdown.load(Ordering::Relaxed) {
+                break;
+            }
+            if let Ok(stream) = stream {
+                let shared = self.clone();
+                thread::spawn(move || shared.run_peer(stream, None));
+            }
+        }
+    }
+
+    fn dial_loop(self: Arc<Self>, peer_id: N, addr: SocketAddr) {
+        let mut delay = MIN_RECONNECT_DELAY;
+        while !self.shutdown.load(Ordering::Relaxed) {
+            if let Ok(stream) = TcpStream::connect(addr) {
+                delay = MIN_RECONNECT_DELAY;
+                let _ = self.run_peer(stream, Some(&peer_id));
+            }
+            thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+}
+
+pub struct Transport<N: Ord, M, W = Bincode> {
+    shared: Arc<Shared<N, M, W>>,
+    incoming: Receiver<(N, M)>,
+    local_addr: SocketAddr,
+}
+
+impl<N, M, W> Transport<N, M, W> where N: NodeIdT + Serialize + DeserializeOwned + Send + Sync + 'static, M: Serialize + DeserializeOwned + Send + 'static, W: WireFormat + 'static, {
+    pub fn bind(addr: SocketAddr, our_id: N, secret_key: SecretKey, pub_keys: PubKeyMap<N>) -> io::Result<Self> {
+        Self::start(addr, our_id, secret_key, false, pub_keys)
+    }
+
+    #[cfg(feature = "noise")]
+    pub fn bind_noise(addr: SocketAddr, our_id: N, secret_key: SecretKey, pub_keys: PubKeyMap<N>) -> io::Result<Self> {
+        Self::start(addr, our_id, secret_key, true, pub_keys)
+    }
+
+    fn start(addr: SocketAddr, our_id: N, secret_key: SecretKey, noise: bool, pub_keys: PubKeyMap<N>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (sender, incoming) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            our_id, secret_key, noise, compression: RwLock::new(None), pub_keys: RwLock::new(pub_keys), peers: Mutex::new(BTreeMap::new()), incoming: Mutex::new(sender), next_conn: AtomicUsize::new(0), shutdown: AtomicBool::new(false), wire: PhantomData,
+        });
+        let accept = shared.clone();
+        thread::spawn(move || accept.accept_loop(listener));
+        Ok(Transport { shared, incoming, local_addr })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn connect(&self, peer_id: N, addr: SocketAddr) {
+        let shared = self.shared.clone();
+        thread::spawn(move || shared.dial_loop(peer_id, addr));
+    }
+
+    #[cfg(feature = "compression")]
+    pub fn set_compression(&self, threshold: Option<usize>) {
+        *self.shared.compression.write().unwrap() = threshold;
+    }
+
+    pub fn set_public_keys(&self, pub_keys: PubKeyMap<N>) {
+        *self.shared.pub_keys.write().unwrap() = pub_keys;
+    }
+
+    pub fn connected_peers(&self) -> Vec<N> {
+        self.shared.peers.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn send<I>(&self, messages: I) where I: IntoIterator<Item = TargetedMessage<M, N>>, {
+        let threshold = self.shared.compression.read().unwrap().unwrap_or(usize::max_value());
+        let peers: Vec<(N, usize, bool, SharedWriter)> = self.shared.peers.lock().unwrap().iter().map(|(id, peer)| (id.clone(), peer.conn, peer.compress, peer.writer.clone())).collect();
+        let mut failed: Vec<(N, usize)> = Vec::new();
+        for TargetedMessage { target, message } in messages {
+            let payload = match W::serialize(&message) {
+                Ok(payload) => payload,
+                Err(_) => continue,
+            };
+            let mut encoded = None;
+            for (peer_id, conn, compress, writer) in peers.iter().filter(|(id, ..)| target.contains(id)) {
+                if failed.iter().any(|(id, _)| id == peer_id) {
+                    continue;
+                }
+                let result = if *compress {
+                    if encoded.is_none() {
+                        encoded = encode_frame(&payload, threshold).ok();
+                    }
+                    match encoded {
+                        Some(ref frame) => writer.lock().unwrap().write_frame(frame),
+                        None => continue,
+                    }
+                } else {
+                    writer.lock().unwrap().write_frame(&payload)
+                };
+                if result.is_err() {
+                    failed.push((peer_id.clone(), *conn));
+                }
+            }
+        }
+        let mut peers = self.shared.peers.lock().unwrap();
+        for (peer_id, conn) in failed {
+            if peers.get(&peer_id).map_or(false, |peer| peer.conn == conn) {
+                if let Some(peer) = peers.remove(&peer_id) {
+                    peer.writer.lock().unwrap().close();
+                }
+            }
+        }
+    }
+
+    pub fn recv(&self) -> Option<(N, M)> {
+        self.incoming.recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<(N, M)> {
+        match self.incoming.recv_timeout(timeout) {
+            Ok(msg) => Some(msg),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<(N, M)> {
+        self.incoming.try_recv().ok()
+    }
+
+    pub fn shutdown(&self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+        for peer in self.shared.peers.lock().unwrap().values() {
+            peer.writer.lock().unwrap().close();
+        }
+        let _ = TcpStream::connect(self.local_addr);
+    }
+}
+
+impl<N: Ord, M, W> Drop for Transport<N, M, W> {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+    }
+}
@@ -1,50 +1,119 @@
-use std::collections::{BTreeMap, HashMap};
-use crate::crypto::{SecretKey, Signature};
-use bincode;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::{string::String, vec::Vec};
+use core::mem;
+#[cfg(not(feature = "std"))]
+use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+use crate::crypto::Signature;
 use serde::{Deserialize, Serialize};
-use super::{Change, Error, FaultKind, Result};
-use crate::{fault_log, util, NodeIdT, PubKeyMap};
+use super::evidence::Evidence;
+use super::observer::ObserverHandle;
+use super::wire;
+use super::{Change, Error, FaultKind, QuorumRule, Result, Signer};
+use crate::{fault_log, NodeIdT, PubKeyMap};
 
 pub type FaultLog<N> = fault_log::FaultLog<N, FaultKind>;
 
+pub const DEFAULT_VOTE_TTL: u64 = 1000;
+pub const DEFAULT_MAX_FUTURE_EPOCHS: u64 = 3;
+
 #[derive(Debug)]
 pub struct VoteCounter<N: Ord> {
     our_id: N,
-    secret_key: SecretKey,
+    signer: Arc<dyn Signer>,
     pub_keys: PubKeyMap<N>,
     era: u64,
+    epoch: u64,
+    vote_ttl: u64,
+    max_future_epochs: u64,
+    quorum: QuorumRule,
+    custom_kinds: BTreeSet<String>,
     pending: BTreeMap<N, SignedVote<N>>,
     committed: BTreeMap<N, Vote<N>>,
+    observer: ObserverHandle<N>,
+    evidence: Vec<Evidence<N>>,
 }
 
 impl<N> VoteCounter<N> where N: NodeIdT + Serialize, {
-    pub fn new(our_id: N, secret_key: SecretKey, pub_keys: PubKeyMap<N>, era: u64) -> Self {
+    pub fn new(our_id: N, signer: Arc<dyn Signer>, pub_keys: PubKeyMap<N>, era: u64) -> Self {
         VoteCounter {
-            our_id, secret_key, pub_keys, era, pending: BTreeMap::new(), committed: BTreeMap::new(),
+            our_id, signer, pub_keys, era, epoch: era, vote_ttl: DEFAULT_VOTE_TTL, max_future_epochs: DEFAULT_MAX_FUTURE_EPOCHS, quorum: QuorumRule::default(), custom_kinds: BTreeSet::new(), pending: BTreeMap::new(), committed: BTreeMap::new(), observer: ObserverHandle::default(), evidence: Vec::new(),
+        }
+    }
+
+    pub(super) fn set_observer(&mut self, observer: ObserverHandle<N>) {
+        self.observer = observer;
+    }
+
+    pub(super) fn set_signer(&mut self, signer: Arc<dyn Signer>) {
+        self.signer = signer;
+    }
+
+    pub fn set_vote_window(&mut self, vote_ttl: u64, max_future_epochs: u64) {
+        self.vote_ttl = vote_ttl;
+        self.max_future_epochs = max_future_epochs;
+    }
+
+    pub fn set_quorum(&mut self, quorum: QuorumRule) {
+        self.quorum = quorum;
+    }
+
+    pub fn set_custom_kinds(&mut self, custom_kinds: BTreeSet<String>) {
+        self.custom_kinds = custom_kinds;
+    }
+
+    fn is_known(&self, change: &Change<N>) -> bool {
+        match *change {
+            Change::Custom(ref custom) => self.custom_kinds.contains(custom.kind()),
+            _ => true,
         }
     }
 
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.epoch = epoch;
+        let expired: Vec<N> = self.pending.iter().filter(|(id, sv)| sv.vote.expires < epoch && **id != self.our_id).map(|(id, _)| id.clone()).collect();
+        for id in expired {
+            self.pending.remove(&id);
+        }
+    }
+
+    fn is_current(&self, vote: &Vote<N>) -> bool {
+        vote.era == self.era && self.is_known(&vote.change) && vote.expires >= self.epoch && vote.epoch <= self.epoch.saturating_add(self.max_future_epochs)
+    }
+
     pub fn sing_vote_for(&mut self, change: Change<N>) -> Result<&SignedVote<N>> {
         let voter = self.our_id.clone();
         let vote = Vote {
             change,
             era: self.era,
+            epoch: self.epoch,
+            expires: self.epoch.saturating_add(self.vote_ttl),
             num: self.pending.get(&voter).map_or(0, |sv| sv.vote.num + 1),
         };
 
-        let ser_vote = bincode::serialize(&vote).map_err(|err| Error::SerializeVote(*err))?;
+        let ser_vote = wire::to_vec(&vote).map_err(Error::SerializeVote)?;
         let signed_vote = SignedVote {
             vote,
             voter: voter.clone(),
-            sig: self.secret_key.sign(ser_vote),
+            sig: self.signer.sign(&ser_vote),
         };
         self.pending.remove(&voter);
         Ok(self.pending.entry(voter).or_insert(signed_vote))
     }
 
     pub fn add_pending_vote(&mut self, sender_id: &N, signed_vote: SignedVote<N>) -> Result<FaultLog<N>> {
-        if signed_vote.vote.era != self.era || self.pending.get(&signed_vote.voter).map_or(false, |sv| sv.vote.num >= signed_vote.vote.num) {
-            Ok(FaultLog::new());
+        let conflict = self.pending.get(&signed_vote.voter).filter(|sv| sv.conflicts_with(&signed_vote)).cloned();
+        if let Some(existing) = conflict {
+            if self.validate(&signed_vote)? {
+                let voter = signed_vote.voter.clone();
+                self.evidence.push(Evidence::ConflictingVotes(existing, signed_vote));
+                return Ok(FaultLog::init(voter, FaultKind::ConflictingVotes));
+            }
+        }
+        if !self.is_current(&signed_vote.vote) || self.pending.get(&signed_vote.voter).map_or(false, |sv| sv.vote.num >= signed_vote.vote.num) {
+            return Ok(FaultLog::new());
         }
         if !self.validate(&signed_vote)? {
             return Ok(FaultLog::init(
@@ -52,62 +121,143 @@ impl<N> VoteCounter<N> where N: NodeIdT + Serialize, {
                 FaultKind::InvalidVoteSignature,
             ));
         }
-        self.pending.insert(signed_vote.voter.clone(), signed_vote);
+        let voter = signed_vote.voter.clone();
+        self.observer.vote(&voter, self.era, false);
+        self.pending.insert(voter, signed_vote);
         Ok(FaultLog::new())
     }
 
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
     pub fn pending_votes(&self) -> impl Iterator<Item = &SignedVote<N>> {
         self.pending.values().filter(move |signed_vote| {
-            self.committed.get(&signed_vote.voter).map_or(true, |vote| vote.num < signed_vote.vote.num)
+            signed_vote.vote.expires >= self.epoch && self.committed.get(&signed_vote.voter).map_or(true, |vote| vote.num < signed_vote.vote.num)
         })
     }
 
     pub fn add_committed_votes<I>(&mut self, proposer_id: &N, signed_votes: I,) -> Result<FaultLog<N>> where I: IntoIterator<Item = SignedVote<N>>, {
+        self.commit_votes(proposer_id, signed_votes, false)
+    }
+
+    pub(super) fn add_verified_votes<I>(&mut self, proposer_id: &N, signed_votes: I,) -> Result<FaultLog<N>> where I: IntoIterator<Item = SignedVote<N>>, {
+        self.commit_votes(proposer_id, signed_votes, true)
+    }
+
+    fn commit_votes<I>(&mut self, proposer_id: &N, signed_votes: I, verified: bool) -> Result<FaultLog<N>> where I: IntoIterator<Item = SignedVote<N>>, {
         let mut fault_log = FaultLog::new();
         for signed_vote in signed_votes {
-            fault_log.extend(self.add_committed_vote(proposer_id, signed_vote)?);
+            fault_log.extend(self.commit_vote(proposer_id, signed_vote, verified)?);
         }
         Ok(fault_log)
     }
 
     pub fn add_committed_vote(&mut self, proposer_id: &N, signed_vote: SignedVote<N>,) -> Result<FaultLog<N>> {
+        self.commit_vote(proposer_id, signed_vote, false)
+    }
+
+    fn commit_vote(&mut self, proposer_id: &N, signed_vote: SignedVote<N>, verified: bool) -> Result<FaultLog<N>> {
         if self.committed.get(&signed_vote.voter).map_or(false, |vote| vote.num >= signed_vote.vote.num) {
             return Ok(FaultLog::new());
         }
-        if signed_vote.vote.era != self.era || !self.validate(&signed_vote)? {
+        if signed_vote.vote.era == self.era && !self.is_current(&signed_vote.vote) {
+            return Ok(FaultLog::new());
+        }
+        if signed_vote.vote.era != self.era || !(verified || self.validate(&signed_vote)?) {
             return Ok(FaultLog::init(
                 proposer_id.clone(),
                 FaultKind::InvalidCommittedVote,
             ));
         }
+        self.observer.vote(&signed_vote.voter, self.era, true);
         self.committed.insert(signed_vote.voter, signed_vote.vote);
         Ok(FaultLog::new())
     }
 
-    pub fn compute_winner(&self) -> Option<&Change<N>> {
+    pub fn compute_winner(&self) -> Option<Change<N>> {
+        let required = self.quorum.required_votes(self.pub_keys.len());
         let mut vote_counts: HashMap<&Change<N>, usize> = HashMap::new();
-        for vote in self.committed.values() {
+        let mut diff_counts: HashMap<&Change<N>, usize> = HashMap::new();
+        for vote in self.committed.values().filter(|vote| self.is_known(&vote.change)) {
             let change = &vote.change;
+            if change.is_diff() {
+                let diffs: HashSet<_> = change.diffs().into_iter().collect();
+                for diff in diffs {
+                    *diff_counts.entry(diff).or_insert(0) += 1;
+                }
+                continue;
+            }
             let entry = vote_counts.entry(change).or_insert(0);
             *entry += 1;
-            if *entry > util::max_faulty(self.pub_keys.len()) {
-                return Some(change);
+            if *entry >= required {
+                return change.resolve(&self.pub_keys);
+            }
+        }
+        let mut winners: BTreeMap<&N, (&Change<N>, usize)> = BTreeMap::new();
+        for (diff, count) in diff_counts.into_iter().filter(|(_, count)| *count >= required) {
+            let node_id = match diff.node_id() {
+                Some(node_id) => node_id,
+                None => continue,
+            };
+            let replace = winners.get(node_id).map_or(true, |(other, other_count)| {
+                (count, wire::to_vec(diff).ok()) > (*other_count, wire::to_vec(other).ok())
+            });
+            if replace {
+                winners.insert(node_id, (diff, count));
             }
         }
-        None
+        let new_pub_keys = Change::apply_diffs(&self.pub_keys, winners.values().map(|(diff, _)| *diff));
+        if new_pub_keys == self.pub_keys {
+            return None;
+        }
+        Some(Change::NodeChange(new_pub_keys))
+    }
+
+    pub fn pending_diffs(&self, voter: &N) -> Vec<Change<N>> {
+        self.pending.get(voter).map_or_else(Vec::new, |sv| sv.vote.change.diffs().into_iter().cloned().collect())
+    }
+
+    pub fn pending_change(&self, voter: &N) -> Option<&Change<N>> {
+        self.pending.get(voter).map(|sv| &sv.vote.change)
+    }
+
+    pub fn state(&self) -> VoteCounterState<N> {
+        VoteCounterState {
+            pending: self.pending.clone(), committed: self.committed.clone(),
+        }
+    }
+
+    pub fn restore(our_id: N, signer: Arc<dyn Signer>, pub_keys: PubKeyMap<N>, era: u64, state: VoteCounterState<N>) -> Self {
+        let VoteCounterState { pending, committed } = state;
+        let pending = pending.into_iter().filter(|(_, sv)| sv.vote.era == era).collect();
+        let committed = committed.into_iter().filter(|(_, vote)| vote.era == era).collect();
+        VoteCounter {
+            our_id, signer, pub_keys, era, epoch: era, vote_ttl: DEFAULT_VOTE_TTL, max_future_epochs: DEFAULT_MAX_FUTURE_EPOCHS, quorum: QuorumRule::default(), custom_kinds: BTreeSet::new(), pending, committed, observer: ObserverHandle::default(), evidence: Vec::new(),
+        }
+    }
+
+    pub fn take_evidence(&mut self) -> Vec<Evidence<N>> {
+        mem::replace(&mut self.evidence, Vec::new())
     }
 
     fn validate(&self, signed_vote: &SignedVote<N>) -> Result<bool> {
-        let ser_vote = bincode::serialize(&signed_vote.vote).map_err(|err| Error::SerializeVote(*err))?;
-        let pk_opt = self.pub_keys.get(&signed_vote.voter);
-        Ok(pk_opt.map_or(false, |pk| pk.verify(&signed_vote.sig, ser_vote)))
+        signed_vote.verify(&self.pub_keys)
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoteCounterState<N: Ord> {
+    pending: BTreeMap<N, SignedVote<N>>,
+    committed: BTreeMap<N, Vote<N>>,
+}
+
 #[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Hash, Clone)]
 struct Vote<N: Ord> {
     change: Change<N>,
     era: u64,
+    epoch: u64,
+    expires: u64,
     num: u64,
 }
 
@@ -126,6 +276,27 @@ impl<N: Ord> SignedVote<N> {
     pub fn voter(&self) -> &N {
         &self.voter
     }
+
+    pub fn expires(&self) -> u64 {
+        self.vote.expires
+    }
+
+    pub(super) fn conflicts_with(&self, other: &Self) -> bool {
+        self.voter == other.voter && self.vote.era == other.vote.era && self.vote.num == other.vote.num && self.vote.change != other.vote.change
+    }
+
+    pub(super) fn signature(&self) -> &Signature {
+        &self.sig
+    }
+
+    pub(super) fn signed_bytes(&self) -> Result<Vec<u8>> where N: Serialize, {
+        wire::to_vec(&self.vote).map_err(Error::SerializeVote)
+    }
+
+    pub(super) fn verify(&self, pub_keys: &PubKeyMap<N>) -> Result<bool> where N: Serialize, {
+        let ser_vote = wire::to_vec(&self.vote).map_err(Error::SerializeVote)?;
+        Ok(pub_keys.get(&self.voter).map_or(false, |pk| pk.verify(&self.sig, ser_vote)))
+    }
 }
 
 #[cfg(test)]
@@ -134,7 +305,9 @@ mod tests {
     use std::iter;
     use std::sync::Arc;
     use rand::{rngs, Rng};
-    use super::{Change, FaultKind, SecretKey, SignedVote, VoteCounter};
+    use super::super::CustomChange;
+    use super::{Change, FaultKind, QuorumRule, SignedVote, VoteCounter, VoteCounterState};
+    use crate::crypto::SecretKey;
     use crate::{fault_log::FaultLog, to_pub_keys};
 
     fn setup(node_num: usize, era: u64) -> (Vec<VoteCounter<usize>>, Vec<Vec<SignedVote<usize>>>) {
@@ -142,7 +315,7 @@ mod tests {
         let sec_keys:BTreeMap<_, SecretKey> = (0..node_num).map(|id| (id, rng.gen())).collect();
         let pub_keys = to_pub_keys(&sec_keys);
 
-        let create_counter = |(id, sk)| VoteCounter::new(id, sk, pub_keys.clone(), era);
+        let create_counter = |(id, sk)| VoteCounter::new(id, Arc::new(sk), pub_keys.clone(), era);
         let mut counters: Vec<_> = sec_keys.into_iter().map(create_counter).collect();
 
         let sign_votes = |counter: &mut VoteCounter<usize>| {
@@ -225,4 +398,146 @@ mod tests {
             winner => panic!("Winner: {:?}", winner),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_restore_state() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, sv) = setup(node_num, era);
+        let ct = &mut counters[0];
+
+        ct.add_pending_vote(&1, sv[1][2].clone()).expect("add pending");
+        ct.add_committed_votes(&1, vec![sv[1][1].clone(), sv[2][1].clone()]).expect("add committed");
+        let ser = bincode::serialize(&ct.state()).expect("serialize state");
+        let state: VoteCounterState<usize> = bincode::deserialize(&ser).expect("deserialize state");
+
+        let restored = VoteCounter::restore(0, ct.signer.clone(), ct.pub_keys.clone(), era, state.clone());
+        assert_eq!(restored.pending_votes().collect::<Vec<_>>(), ct.pending_votes().collect::<Vec<_>>());
+        assert_eq!(restored.compute_winner(), ct.compute_winner());
+
+        let next_era = VoteCounter::restore(0, ct.signer.clone(), ct.pub_keys.clone(), era + 1, state);
+        assert_eq!(next_era.pending_votes().count(), 0);
+        assert_eq!(next_era.compute_winner(), None);
+    }
+
+    #[test]
+    fn test_diff_votes() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, _) = setup(node_num, era);
+        let both = Change::Multiple(vec![Change::Remove(3), Change::Remove(2)]);
+        let sv1 = counters[1].sing_vote_for(both).expect("sign vote").clone();
+        let sv2 = counters[2].sing_vote_for(Change::Remove(3)).expect("sign vote").clone();
+        let sv3 = counters[3].sing_vote_for(Change::Remove(2)).expect("sign vote").clone();
+        let ct = &mut counters[0];
+
+        ct.add_committed_votes(&1, vec![sv1, sv2]).expect("add committed");
+        match ct.compute_winner() {
+            Some(Change::NodeChange(pub_keys)) => assert!(pub_keys.keys().eq(&[0, 1, 2])),
+            winner => panic!("Winner: {:?}", winner),
+        }
+        ct.add_committed_vote(&1, sv3).expect("add committed");
+        match ct.compute_winner() {
+            Some(Change::NodeChange(pub_keys)) => assert!(pub_keys.keys().eq(&[0, 1])),
+            winner => panic!("Winner: {:?}", winner),
+        }
+    }
+
+    #[test]
+    fn test_atomic_votes() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, _) = setup(node_num, era);
+        let mut rng = rngs::OsRng::new().expect("Couldn't initialize osrng");
+        let new_key = rng.gen::<SecretKey>().public_key();
+        let change = Change::atomic(vec![(4, new_key), (5, new_key)], vec![3, 2]);
+        let sv1 = counters[1].sing_vote_for(change.clone()).expect("sign vote").clone();
+        let sv2 = counters[2].sing_vote_for(change).expect("sign vote").clone();
+        let ct = &mut counters[0];
+
+        ct.add_committed_vote(&1, sv1).expect("add committed");
+        assert_eq!(ct.compute_winner(), None);
+        ct.add_committed_vote(&1, sv2).expect("add committed");
+        match ct.compute_winner() {
+            Some(Change::NodeChange(pub_keys)) => assert!(pub_keys.keys().eq(&[0, 1, 4, 5])),
+            winner => panic!("Winner: {:?}", winner),
+        }
+    }
+
+    #[test]
+    fn test_expired_votes() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, _) = setup(node_num, era);
+        counters[1].set_vote_window(10, 10);
+        let stale = counters[1].sing_vote_for(Change::Remove(3)).expect("sign vote").clone();
+        counters[2].set_epoch(era + 30);
+        let early = counters[2].sing_vote_for(Change::Remove(3)).expect("sign vote").clone();
+        let ct = &mut counters[0];
+        ct.set_vote_window(10, 10);
+        ct.set_epoch(era + 11);
+
+        let faults = ct.add_pending_vote(&1, stale.clone()).expect("add pending");
+        assert!(faults.is_empty());
+        let faults = ct.add_pending_vote(&2, early.clone()).expect("add pending");
+        assert!(faults.is_empty());
+        assert!(ct.pending_votes().all(|sv| *sv.voter() == 0));
+        ct.add_committed_votes(&1, vec![stale, early]).expect("add committed");
+        assert_eq!(ct.compute_winner(), None);
+    }
+
+    #[test]
+    fn test_quorum_rule() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, sv) = setup(node_num, era);
+        let ct = &mut counters[0];
+        ct.set_quorum(QuorumRule::TwoThirds);
+
+        ct.add_committed_votes(&1, vec![sv[1][1].clone(), sv[2][1].clone()]).expect("add committed");
+        assert_eq!(ct.compute_winner(), None);
+        ct.add_committed_vote(&1, sv[3][1].clone()).expect("add committed");
+        assert!(ct.compute_winner().is_some());
+
+        ct.set_quorum(QuorumRule::Custom(Arc::new(|num| num)));
+        assert_eq!(ct.compute_winner(), None);
+    }
+
+    #[test]
+    fn test_custom_votes() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, _) = setup(node_num, era);
+        let change = Change::Custom(CustomChange::new("batch_size", &500u64).expect("encode change"));
+        let sv1 = counters[1].sing_vote_for(change.clone()).expect("sign vote").clone();
+        let sv2 = counters[2].sing_vote_for(change.clone()).expect("sign vote").clone();
+        let ct = &mut counters[0];
+
+        ct.add_committed_votes(&1, vec![sv1, sv2]).expect("add committed");
+        assert_eq!(ct.compute_winner(), None);
+        ct.set_custom_kinds(iter::once("batch_size".to_owned()).collect());
+        match ct.compute_winner() {
+            Some(Change::Custom(custom)) => assert_eq!(custom.decode::<u64>().expect("decode change"), 500),
+            winner => panic!("Winner: {:?}", winner),
+        }
+    }
+
+    #[test]
+    fn test_conflicting_votes() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, sv) = setup(node_num, era);
+        let mut rng = rngs::OsRng::new().expect("Couldn't initialize osrng");
+        let mut fresh = VoteCounter::new(1, counters[1].signer.clone(), counters[1].pub_keys.clone(), era);
+        let conflicting = fresh.sing_vote_for(Change::Remove(0)).expect("sign vote").clone();
+        let ct = &mut counters[0];
+
+        ct.add_pending_vote(&2, sv[1][0].clone()).expect("add pending");
+        let faults = ct.add_pending_vote(&2, conflicting).expect("add pending");
+        assert_eq!(faults, FaultLog::init(1, FaultKind::ConflictingVotes));
+        let evidence = ct.take_evidence();
+        assert_eq!(evidence.len(), 1);
+        assert_eq!(*evidence[0].accused(), 1);
+        assert!(evidence[0].verify(&ct.pub_keys, &mut rng).expect("verify evidence"));
+    }
+}
@@ -0,0 +1,108 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::canonical;
+#[cfg(feature = "protobuf")]
+use super::protobuf;
+use super::Message;
+
+#[derive(Debug)]
+pub enum WireError {
+    Bincode(canonical::CanonicalError),
+    #[cfg(feature = "cbor")]
+    Cbor(serde_cbor::Error),
+    #[cfg(feature = "protobuf")]
+    Protobuf(protobuf::ProtoError),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            WireError::Bincode(ref err) => write!(f, "bincode: {}", err),
+            #[cfg(feature = "cbor")]
+            WireError::Cbor(ref err) => write!(f, "cbor: {}", err),
+            #[cfg(feature = "protobuf")]
+            WireError::Protobuf(ref err) => write!(f, "protobuf: {}", err),
+        }
+    }
+}
+
+pub trait WireFormat {
+    fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, WireError>;
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, WireError>;
+
+    fn serialized_size<T: Serialize + ?Sized>(value: &T) -> Result<u64, WireError> {
+        Self::serialize(value).map(|bytes| bytes.len() as u64)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+impl WireFormat for Bincode {
+    fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, WireError> {
+        canonical::to_vec(value).map_err(WireError::Bincode)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, WireError> {
+        canonical::from_slice(bytes).map_err(WireError::Bincode)
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl WireFormat for Cbor {
+    fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, WireError> {
+        serde_cbor::to_vec(value).map_err(WireError::Cbor)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, WireError> {
+        serde_cbor::from_slice(bytes).map_err(WireError::Cbor)
+    }
+}
+
+#[cfg(feature = "protobuf")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Protobuf;
+
+#[cfg(feature = "protobuf")]
+impl WireFormat for Protobuf {
+    fn serialize<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, WireError> {
+        protobuf::to_vec(value).map_err(WireError::Protobuf)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, WireError> {
+        protobuf::from_slice(bytes).map_err(WireError::Protobuf)
+    }
+}
+
+/// The encoding every node signs and hashes, whatever format carries its messages.
+pub type CanonicalWire = Bincode;
+
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, WireError> {
+    CanonicalWire::serialize(value)
+}
+
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, WireError> {
+    CanonicalWire::deserialize(bytes)
+}
+
+pub fn serialized_size<T: Serialize + ?Sized>(value: &T) -> Result<u64, WireError> {
+    CanonicalWire::serialized_size(value)
+}
+
+impl<N: Ord + Serialize + DeserializeOwned> Message<N> {
+    pub fn to_wire<W: WireFormat>(&self) -> Result<Vec<u8>, WireError> {
+        W::serialize(self)
+    }
+
+    pub fn from_wire<W: WireFormat>(bytes: &[u8]) -> Result<Self, WireError> {
+        W::deserialize(bytes)
+    }
+}
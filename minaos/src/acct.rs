@@ -0,0 +1,136 @@
+use crate::{hart::{MAX_HARTS, NO_PID},
+            lifecycle,
+            lock::Mutex,
+            process::{ProcessState, PROCESS_LIST, PROCESS_LIST_MUTEX},
+            sched,
+            time::{self, ticks_to_nanos},
+            vma};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+pub const STATE_RUNNING: u8 = 0;
+pub const STATE_SLEEPING: u8 = 1;
+pub const STATE_WAITING: u8 = 2;
+pub const STATE_DEAD: u8 = 3;
+pub const STATE_ZOMBIE: u8 = 4;
+
+#[derive(Copy, Clone, Default)]
+pub struct Times {
+    pub utime: u64,
+    pub stime: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ProcInfo {
+    pub pid: u16,
+    pub ppid: u16,
+    pub state: u8,
+    pub nice: i8,
+    pub hart: u16,
+    pub utime_ns: u64,
+    pub stime_ns: u64,
+    pub vsize: u64,
+}
+
+const ZERO: AtomicU64 = AtomicU64::new(0);
+const NONE: AtomicU16 = AtomicU16::new(NO_PID);
+
+static LAST_TRANSITION: [AtomicU64; MAX_HARTS] = [ZERO; MAX_HARTS];
+static TRAPPED_PID: [AtomicU16; MAX_HARTS] = [NONE; MAX_HARTS];
+
+static mut TIMES: Option<BTreeMap<u16, Times>> = None;
+static mut ACCT_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        TIMES.replace(BTreeMap::new());
+    }
+}
+
+fn with_times<T, F: FnOnce(&mut BTreeMap<u16, Times>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        ACCT_LOCK.spin_lock();
+        if let Some(mut times) = TIMES.take() {
+            ret = Some(f(&mut times));
+            TIMES.replace(times);
+        }
+        ACCT_LOCK.unlock();
+    }
+    ret
+}
+
+fn elapsed(hart: usize) -> u64 {
+    let now = time::ticks();
+    now.wrapping_sub(LAST_TRANSITION[hart].swap(now, Ordering::SeqCst))
+}
+
+pub fn enter_kernel(hart: usize, pid: u16) {
+    let ticks = elapsed(hart);
+    TRAPPED_PID[hart].store(pid, Ordering::SeqCst);
+    if pid != NO_PID {
+        with_times(|times| times.entry(pid).or_default().utime += ticks);
+    }
+}
+
+pub fn leave_kernel(hart: usize) {
+    let ticks = elapsed(hart);
+    let pid = TRAPPED_PID[hart].swap(NO_PID, Ordering::SeqCst);
+    if pid != NO_PID {
+        with_times(|times| times.entry(pid).or_default().stime += ticks);
+    }
+}
+
+pub fn times(pid: u16) -> Times {
+    with_times(|times| times.get(&pid).copied().unwrap_or_default()).unwrap_or_default()
+}
+
+pub fn release(pid: u16) {
+    with_times(|times| times.remove(&pid));
+}
+
+pub fn snapshot() -> Vec<ProcInfo> {
+    let mut procs = Vec::new();
+    unsafe {
+        PROCESS_LIST_MUTEX.spin_lock();
+        if let Some(pl) = PROCESS_LIST.take() {
+            for p in pl.iter() {
+                let state = match p.state {
+                    ProcessState::Running => STATE_RUNNING,
+                    ProcessState::Sleeping => STATE_SLEEPING,
+                    ProcessState::Waiting => STATE_WAITING,
+                    ProcessState::Dead => STATE_DEAD,
+                };
+                procs.push((p.pid, state, (*p.frame).hartid as u16));
+            }
+            PROCESS_LIST.replace(pl);
+        }
+        PROCESS_LIST_MUTEX.unlock();
+    }
+    procs.into_iter()
+         .map(|(pid, state, hart)| {
+             let t = times(pid);
+             ProcInfo {
+                 pid,
+                 ppid: lifecycle::parent_of(pid),
+                 state,
+                 nice: sched::get_nice(pid),
+                 hart,
+                 utime_ns: ticks_to_nanos(t.utime),
+                 stime_ns: ticks_to_nanos(t.stime),
+                 vsize: vma::vmas(pid).iter().map(|v| (v.end - v.start) as u64).sum(),
+             }
+         })
+         .chain(lifecycle::zombies().into_iter().map(|(pid, ppid)| ProcInfo {
+             pid,
+             ppid,
+             state: STATE_ZOMBIE,
+             nice: 0,
+             hart: 0,
+             utime_ns: 0,
+             stime_ns: 0,
+             vsize: 0,
+         }))
+         .collect()
+}
@@ -0,0 +1,255 @@
+pub const ESC: u8 = 0x1b;
+pub const MAX_PARAMS: usize = 8;
+pub const MAX_SEQUENCE: usize = 32;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Color {
+    Black = 0,
+    Red = 1,
+    Green = 2,
+    Yellow = 3,
+    Blue = 4,
+    Magenta = 5,
+    Cyan = 6,
+    White = 7,
+    Default = 9,
+}
+
+impl Color {
+    pub fn from_code(code: u16) -> Color {
+        match code {
+            0 => Color::Black,
+            1 => Color::Red,
+            2 => Color::Green,
+            3 => Color::Yellow,
+            4 => Color::Blue,
+            5 => Color::Magenta,
+            6 => Color::Cyan,
+            7 => Color::White,
+            _ => Color::Default,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AnsiCommand {
+    Print(u8),
+    Control(u8),
+    ResetAttributes,
+    Bold(bool),
+    Underline(bool),
+    Reverse(bool),
+    Foreground(Color, bool),
+    Background(Color, bool),
+    CursorUp(u16),
+    CursorDown(u16),
+    CursorForward(u16),
+    CursorBack(u16),
+    CursorPosition(u16, u16),
+    EraseDisplay(u16),
+    EraseLine(u16),
+    SaveCursor,
+    RestoreCursor,
+    ShowCursor(bool),
+    Unknown,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+pub trait AnsiSink {
+    fn put(&mut self, c: u8);
+
+    fn command(&mut self, cmd: AnsiCommand, raw: &[u8]) {
+        match cmd {
+            AnsiCommand::Print(c) | AnsiCommand::Control(c) => self.put(c),
+            _ => {
+                for &c in raw {
+                    self.put(c);
+                }
+            },
+        }
+    }
+}
+
+pub struct AnsiParser {
+    state: State,
+    private: bool,
+    params: [u16; MAX_PARAMS],
+    nparams: usize,
+    seq: [u8; MAX_SEQUENCE],
+    seq_len: usize,
+}
+
+impl AnsiParser {
+    pub const fn new() -> Self {
+        AnsiParser {
+            state: State::Ground,
+            private: false,
+            params: [0; MAX_PARAMS],
+            nparams: 0,
+            seq: [0; MAX_SEQUENCE],
+            seq_len: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state = State::Ground;
+        self.private = false;
+        self.params = [0; MAX_PARAMS];
+        self.nparams = 0;
+        self.seq_len = 0;
+    }
+
+    pub fn in_sequence(&self) -> bool {
+        self.state != State::Ground
+    }
+
+    fn record(&mut self, c: u8) -> bool {
+        if self.seq_len >= MAX_SEQUENCE {
+            return false;
+        }
+        self.seq[self.seq_len] = c;
+        self.seq_len += 1;
+        true
+    }
+
+    fn param(&self, idx: usize, default: u16) -> u16 {
+        if idx >= self.nparams || self.params[idx] == 0 {
+            default
+        } else {
+            self.params[idx]
+        }
+    }
+
+    pub fn feed<S: AnsiSink>(&mut self, c: u8, sink: &mut S) {
+        match self.state {
+            State::Ground => {
+                if c == ESC {
+                    self.reset();
+                    self.record(c);
+                    self.state = State::Escape;
+                } else {
+                    Self::text(c, sink);
+                }
+            },
+            State::Escape => {
+                self.record(c);
+                match c {
+                    b'[' => {
+                        self.nparams = 1;
+                        self.state = State::Csi;
+                    },
+                    b'7' => self.finish(AnsiCommand::SaveCursor, sink),
+                    b'8' => self.finish(AnsiCommand::RestoreCursor, sink),
+                    b'c' => self.finish(AnsiCommand::ResetAttributes, sink),
+                    _ => self.finish(AnsiCommand::Unknown, sink),
+                }
+            },
+            State::Csi => {
+                if !self.record(c) {
+                    self.abort(sink);
+                    self.feed(c, sink);
+                    return;
+                }
+                match c {
+                    b'0'..=b'9' => {
+                        let p = &mut self.params[self.nparams - 1];
+                        *p = p.saturating_mul(10).saturating_add((c - b'0') as u16);
+                    },
+                    b';' => {
+                        if self.nparams < MAX_PARAMS {
+                            self.nparams += 1;
+                        }
+                    },
+                    b'?' => {
+                        self.private = true;
+                    },
+                    0x40..=0x7e => self.dispatch_csi(c, sink),
+                    _ => {},
+                }
+            },
+        }
+    }
+
+    fn dispatch_csi<S: AnsiSink>(&mut self, final_byte: u8, sink: &mut S) {
+        if final_byte == b'm' {
+            self.dispatch_sgr(sink);
+            return;
+        }
+        let cmd = match (self.private, final_byte) {
+            (false, b'A') => AnsiCommand::CursorUp(self.param(0, 1)),
+            (false, b'B') => AnsiCommand::CursorDown(self.param(0, 1)),
+            (false, b'C') => AnsiCommand::CursorForward(self.param(0, 1)),
+            (false, b'D') => AnsiCommand::CursorBack(self.param(0, 1)),
+            (false, b'H') | (false, b'f') => AnsiCommand::CursorPosition(self.param(0, 1), self.param(1, 1)),
+            (false, b'J') => AnsiCommand::EraseDisplay(self.param(0, 0)),
+            (false, b'K') => AnsiCommand::EraseLine(self.param(0, 0)),
+            (false, b's') => AnsiCommand::SaveCursor,
+            (false, b'u') => AnsiCommand::RestoreCursor,
+            (true, b'h') if self.param(0, 0) == 25 => AnsiCommand::ShowCursor(true),
+            (true, b'l') if self.param(0, 0) == 25 => AnsiCommand::ShowCursor(false),
+            _ => AnsiCommand::Unknown,
+        };
+        self.finish(cmd, sink);
+    }
+
+    fn dispatch_sgr<S: AnsiSink>(&mut self, sink: &mut S) {
+        let raw = self.seq;
+        let raw_len = self.seq_len;
+        let nparams = self.nparams;
+        for i in 0..nparams {
+            let p = self.params[i];
+            let cmd = match p {
+                0 => AnsiCommand::ResetAttributes,
+                1 => AnsiCommand::Bold(true),
+                4 => AnsiCommand::Underline(true),
+                7 => AnsiCommand::Reverse(true),
+                22 => AnsiCommand::Bold(false),
+                24 => AnsiCommand::Underline(false),
+                27 => AnsiCommand::Reverse(false),
+                30..=37 | 39 => AnsiCommand::Foreground(Color::from_code(p - 30), false),
+                40..=47 | 49 => AnsiCommand::Background(Color::from_code(p - 40), false),
+                90..=97 => AnsiCommand::Foreground(Color::from_code(p - 90), true),
+                100..=107 => AnsiCommand::Background(Color::from_code(p - 100), true),
+                _ => AnsiCommand::Unknown,
+            };
+            // Only the first command carries the raw bytes so pass-through sinks
+            // emit the sequence exactly once.
+            if i == 0 {
+                sink.command(cmd, &raw[..raw_len]);
+            } else {
+                sink.command(cmd, &[]);
+            }
+        }
+        self.reset();
+    }
+
+    fn text<S: AnsiSink>(c: u8, sink: &mut S) {
+        if c < 0x20 || c == 0x7f {
+            sink.command(AnsiCommand::Control(c), &[c]);
+        } else {
+            sink.command(AnsiCommand::Print(c), &[c]);
+        }
+    }
+
+    fn abort<S: AnsiSink>(&mut self, sink: &mut S) {
+        let raw = self.seq;
+        let raw_len = self.seq_len;
+        self.reset();
+        for &c in &raw[..raw_len] {
+            Self::text(c, sink);
+        }
+    }
+
+    fn finish<S: AnsiSink>(&mut self, cmd: AnsiCommand, sink: &mut S) {
+        let raw = self.seq;
+        let raw_len = self.seq_len;
+        self.reset();
+        sink.command(cmd, &raw[..raw_len]);
+    }
+}
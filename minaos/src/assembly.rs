@@ -1,3 +1,4 @@
 global_asm!(include_str!("asm/boot.S"));
 global_asm!(include_str!("asm/mem.S"));
-global_asm!(include_str!("asm/trap.S"));
\ No newline at end of file
+global_asm!(include_str!("asm/trap.S"));
+global_asm!(include_str!("asm/ksyms.S"));
\ No newline at end of file
@@ -0,0 +1,144 @@
+use crate::{hart::{current_pid, hart_id, MAX_HARTS, TRAP_STACK_SIZE},
+            page::PAGE_SIZE,
+            process::{get_by_pid, STACK_PAGES}};
+use core::{mem::size_of, slice, str};
+
+pub const MAX_FRAMES: usize = 32;
+pub const KSYMS_SIZE: usize = 256 * 1024;
+pub const KSYMS_MAGIC: u32 = 0x4b53_594d;
+
+extern "C" {
+    static TEXT_START: usize;
+    static TEXT_END: usize;
+    static KERNEL_STACK_START: usize;
+    static KERNEL_STACK_END: usize;
+    static KSYMS: [u8; KSYMS_SIZE];
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct KsymsHeader {
+    magic: u32,
+    count: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct KsymEntry {
+    addr: u64,
+    name_off: u32,
+    name_len: u32,
+}
+
+fn in_text(addr: usize) -> bool {
+    unsafe { addr >= TEXT_START && addr < TEXT_END }
+}
+
+pub fn stack_bounds(sp: usize) -> (usize, usize) {
+    let end = unsafe { KERNEL_STACK_END };
+    for hart in 0..MAX_HARTS {
+        let top = end - hart * TRAP_STACK_SIZE;
+        if sp > top - TRAP_STACK_SIZE && sp <= top {
+            return (top - TRAP_STACK_SIZE, top);
+        }
+    }
+    let p = unsafe { get_by_pid(current_pid(hart_id())) };
+    if !p.is_null() {
+        let bottom = unsafe { (*p).stack as usize };
+        let top = bottom + STACK_PAGES * PAGE_SIZE;
+        if bottom != 0 && sp > bottom && sp <= top {
+            return (bottom, top);
+        }
+    }
+    unsafe { (KERNEL_STACK_START, KERNEL_STACK_END) }
+}
+
+fn ksyms_read<T: Copy>(offset: usize) -> Option<T> {
+    if offset + size_of::<T>() > KSYMS_SIZE {
+        return None;
+    }
+    Some(unsafe { (KSYMS.as_ptr().add(offset) as *const T).read_volatile() })
+}
+
+pub fn resolve(addr: usize) -> Option<(&'static str, usize)> {
+    let header = ksyms_read::<KsymsHeader>(0)?;
+    if header.magic != KSYMS_MAGIC || header.count == 0 {
+        return None;
+    }
+    let entry_at = |idx: usize| ksyms_read::<KsymEntry>(size_of::<KsymsHeader>() + idx * size_of::<KsymEntry>());
+    let (mut lo, mut hi) = (0, header.count as usize);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if entry_at(mid)?.addr <= addr as u64 {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    if lo == 0 {
+        return None;
+    }
+    let entry = entry_at(lo - 1)?;
+    let start = entry.name_off as usize;
+    let end = start + entry.name_len as usize;
+    if end > KSYMS_SIZE {
+        return None;
+    }
+    let name = unsafe { slice::from_raw_parts(KSYMS.as_ptr().add(start), end - start) };
+    Some((str::from_utf8(name).unwrap_or("?"), addr - entry.addr as usize))
+}
+
+pub fn walk<F: FnMut(usize)>(mut fp: usize, bounds: (usize, usize), mut f: F) {
+    let (bottom, top) = bounds;
+    for _ in 0..MAX_FRAMES {
+        if fp == 0 || fp % size_of::<usize>() != 0 || fp < bottom + 2 * size_of::<usize>() || fp > top {
+            break;
+        }
+        let ra = unsafe { (fp as *const usize).sub(1).read() };
+        let prev = unsafe { (fp as *const usize).sub(2).read() };
+        if !in_text(ra) {
+            break;
+        }
+        f(ra);
+        if prev <= fp {
+            break;
+        }
+        fp = prev;
+    }
+}
+
+pub fn print_addr(idx: usize, addr: usize) {
+    match resolve(addr) {
+        Some((name, off)) => early_println!("  #{:<2} 0x{:016x} {}+0x{:x}", idx, addr, name, off),
+        None => early_println!("  #{:<2} 0x{:016x} ??", idx, addr),
+    }
+}
+
+pub fn print_frame(pc: usize, ra: usize, fp: usize, sp: usize) {
+    early_println!("Backtrace:");
+    print_addr(0, pc);
+    let mut idx = 1;
+    if in_text(ra) {
+        print_addr(idx, ra);
+        idx += 1;
+    }
+    walk(fp, stack_bounds(sp), |addr| {
+        print_addr(idx, addr);
+        idx += 1;
+    });
+}
+
+pub fn print_backtrace() {
+    let fp: usize;
+    let sp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+        asm!("mv {}, sp", out(reg) sp);
+    }
+    early_println!("Backtrace:");
+    let mut idx = 0;
+    walk(fp, stack_bounds(sp), |addr| {
+        print_addr(idx, addr);
+        idx += 1;
+    });
+}
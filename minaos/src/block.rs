@@ -1,11 +1,14 @@
-use crate::{kmem::{kfree, kmalloc},
+use crate::{slab::{kfree, kmalloc},
+            spinlock::SpinLock,
             page::{zalloc, PAGE_SIZE},
-            process::{add_kernel_process_args, get_by_pid, set_running, set_waiting},
+            process::get_by_pid,
             io,
-        io::{Descriptor, MmioOffsets, Queue, StatusField, IO_RING_SIZE}};
+            irqstat,
+            kthread,
+        io::{Descriptor, MmioOffsets, Queue, StatusField, IO_RING_SIZE},
+            waitqueue::WaitQueue};
 
 use core::mem::size_of;
-use alloc::boxed::Box;
 
 #[repr(C)]
 pub struct Geometry {
@@ -106,7 +109,9 @@ pub enum BlockErrors {
     ReadOnly,
 }
 
-static mut BLOCK_DEVICES: [Option<BlockDevice>; 8] = [None, None, None, None, None, None, None, None];
+unsafe impl Send for BlockDevice {}
+
+static BLOCK_DEVICES: SpinLock<[Option<BlockDevice>; 8]> = SpinLock::new("block_devices", [None, None, None, None, None, None, None, None]);
 
 pub fn setup_block_device(ptr: *mut u32) -> bool {
     unsafe {
@@ -156,7 +161,7 @@ pub fn setup_block_device(ptr: *mut u32) -> bool {
             ack_used_idx: 0,
             read_only: ro,
         };
-        BLOCK_DEVICES[idx] = Some(bd);
+        BLOCK_DEVICES.lock()[idx] = Some(bd);
 
         status_bits |= StatusField::DriverOk.val32();
         ptr.add(MmioOffsets::Status.scale32()).write_volatile(status_bits);
@@ -177,8 +182,9 @@ pub fn fill_next_descriptor(bd: &mut BlockDevice, desc: Descriptor) -> u16 {
 }
 
 pub fn block_op(dev: usize, buffer: *mut u8, size: u32, offset: u64, write: bool, watcher: u16) -> Result<u32, BlockErrors> {
+    let mut devices = BLOCK_DEVICES.lock();
     unsafe {
-        if let Some(bdev) = BLOCK_DEVICES[dev - 1].as_mut() {
+        if let Some(bdev) = devices[dev - 1].as_mut() {
             if bdev.read_only && write {
                 return Err(BlockErrors::ReadOnly);
             }
@@ -234,7 +240,7 @@ pub fn read(dev: usize,
             buffer: *mut u8,
             size: u32,
             offset: u64) -> Result<u32, BlockErrors> {
-                block_op(dev, buffer, size, offset, false = 0)
+                block_op(dev, buffer, size, offset, false, 0)
             }
 
 pub fn write(dev: usize,
@@ -244,6 +250,8 @@ pub fn write(dev: usize,
                 block_op(dev, buffer, size, offset, true, 0)
             }
 
+pub static BLOCK_WAIT: WaitQueue = WaitQueue::new("block");
+
 pub fn pending(bd: &mut BlockDevice) {
     unsafe {
         let ref queue = *bd.queue;
@@ -253,9 +261,11 @@ pub fn pending(bd: &mut BlockDevice) {
             let rq = queue.desc[elem.id as usize].addr as *const Request;
             let pid_of_watcher = (*rq).watcher;
             if pid_of_watcher > 0 {
-                set_running(pid_of_watcher);
                 let proc = get_by_pid(pid_of_watcher);
-                (*(*proc).frame).regs[10] = (*rq).status.status as usize;
+                if !proc.is_null() {
+                    (*(*proc).frame).regs[10] = (*rq).status.status as usize;
+                }
+                BLOCK_WAIT.wake(pid_of_watcher);
             }
             kfree(rq as *mut u8);
         }
@@ -263,48 +273,22 @@ pub fn pending(bd: &mut BlockDevice) {
 }
 
 pub fn handle_interrupt(idx: usize) {
-    unsafe {
-        if let Some(bdev) = BLOCK_DEVICES[idx].as_mut() {
-            pending(bdev);
-        } else {
-            println!("Invalid block device for interrupt {}", idx + 1);
-        }
+    irqstat::record_source(idx + irqstat::VIRTIO_SOURCE_BASE);
+    if let Some(bdev) = BLOCK_DEVICES.lock()[idx].as_mut() {
+        pending(bdev);
+    } else {
+        println!("Invalid block device for interrupt {}", idx + 1);
     }
 }
 
-// kernel (?!)
-struct ProcArgs {
-    pub pid: u16,
-    pub dev: usize,
-    pub buffer: *mut u8,
-    pub size: u32,
-    pub offset: u64,
-}
-
-fn read_proc(args_addr: usize) {
-    let args = unsafe {Box::from_raw(args_addr as *mut ProcArgs)};
-    let _ = block_op(args.dev, args.buffer, args.size, args.offset, false, args.pid,);
-}
-
-fn process_read(pid: u16, dev: usize, buffer: *mut u8, size: u32, offset: u64) {
-    let args = ProcArgs {
-        pid, dev, buffer, size, offset,
-    };
-    let boxed_args = Box::new(args);
-    set_waiting(pid);
-    let _ = add_kernel_process_args(read_proc, Box::into_raw(boxed_args) as usize,);
-}
-
-fn write_proc(args_addr: usize) {
-    let args = unsafe {Box::from_raw(args_addr as *mut ProcArgs)};
-    let _ = block_op(args.dev, args.buffer, args.size, args.offset, true, args.pid);
+pub fn process_read(pid: u16, dev: usize, buffer: *mut u8, size: u32, offset: u64) {
+    let buffer = buffer as usize;
+    BLOCK_WAIT.sleep(pid);
+    let _ = kthread::spawn("blk read", move || block_op(dev, buffer as *mut u8, size, offset, false, pid));
 }
 
 pub fn process_write(pid: u16, dev: usize, buffer: *mut u8, size: u32, offset: u64) {
-    let args = ProcArgs {
-        pid, dev, buffer, size, offset,
-    };
-    let boxed_args = Box::new(args);
-    set_waiting(pid);
-    let _ = add_kernel_process_args(write_proc, Box::into_raw(boxed_args) as usize,);
-}
\ No newline at end of file
+    let buffer = buffer as usize;
+    BLOCK_WAIT.sleep(pid);
+    let _ = kthread::spawn("blk write", move || block_op(dev, buffer as *mut u8, size, offset, true, pid));
+}
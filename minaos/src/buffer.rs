@@ -1,19 +1,65 @@
-use crate::{cpu::memcpy, kmem::{kmalloc, kfree}};
-use core::{ptr::null_mut, ops::{Index, IndexMut}};
+use crate::{page::{zalloc, PAGE_SIZE},
+            scrub,
+            slab::{alloc_raw, kfree, kmalloc, MAX_OBJECT, MIN_OBJECT}};
+use core::{marker::PhantomData,
+           mem::{align_of, size_of},
+           ops::{Deref, DerefMut, Index, IndexMut},
+           ptr::null_mut,
+           slice};
 
 pub struct Buffer {
     buffer: *mut u8,
-    len: usize
+    len: usize,
+    align: usize,
+    pages: usize
 }
 
 impl Buffer {
     pub fn new(sz: usize) -> Self {
         Self {
             buffer: kmalloc(sz),
-            len: sz
+            len: sz,
+            align: 1,
+            pages: 0
         }
     }
 
+    pub fn new_aligned(sz: usize, align: usize) -> Self {
+        let align = align.max(MIN_OBJECT).next_power_of_two();
+        let class = sz.max(align).next_power_of_two();
+        if class <= MAX_OBJECT {
+            return Self {
+                buffer: alloc_raw(class),
+                len: sz,
+                align,
+                pages: 0
+            };
+        }
+        if align > PAGE_SIZE {
+            return Self {
+                buffer: null_mut(),
+                len: 0,
+                align,
+                pages: 0
+            };
+        }
+        let pages = (sz + PAGE_SIZE - 1) / PAGE_SIZE;
+        Self {
+            buffer: zalloc(pages),
+            len: sz,
+            align,
+            pages
+        }
+    }
+
+    pub fn phys_addr(&self) -> usize {
+        self.buffer as usize
+    }
+
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
     pub fn get_mut(&mut  self) -> *mut u8 {
         self.buffer
     }
@@ -25,6 +71,50 @@ impl Buffer {
     pub fn len(&self) -> usize {
         self.len
     }
+
+    pub fn as_slice(&self) -> &[u8] {
+        if self.buffer.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.buffer, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        if self.buffer.is_null() {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(self.buffer, self.len) }
+    }
+
+    pub fn copy_from_slice(&mut self, offset: usize, src: &[u8]) -> bool {
+        match offset.checked_add(src.len()) {
+            Some(end) if end <= self.as_slice().len() => {
+                self.as_mut_slice()[offset..end].copy_from_slice(src);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn read_at<T: Copy>(&self, offset: usize) -> Option<T> {
+        let end = offset.checked_add(size_of::<T>())?;
+        let bytes = self.as_slice().get(offset..end)?;
+        Some(unsafe { (bytes.as_ptr() as *const T).read_unaligned() })
+    }
+
+    pub fn write_at<T: Copy>(&mut self, offset: usize, val: T) -> bool {
+        let end = match offset.checked_add(size_of::<T>()) {
+            Some(end) => end,
+            None => return false,
+        };
+        match self.as_mut_slice().get_mut(offset..end) {
+            Some(bytes) => {
+                unsafe { (bytes.as_mut_ptr() as *mut T).write_unaligned(val) };
+                true
+            },
+            None => false,
+        }
+    }
 }
 
 impl Default for Buffer {
@@ -36,29 +126,24 @@ impl Default for Buffer {
 impl Index<usize> for Buffer {
     type Output = u8;
     fn index(&self, idx: usize) -> &Self::Output {
-        unsafe {
-            self.get().add(idx).as_ref().unwrap()
-        }
+        &self.as_slice()[idx]
     }
 }
 
 impl IndexMut<usize> for Buffer {
     fn index_mut(&mut self, idx: usize) -> &mut Self::Output {
-        unsafe {
-            self.get_mut().add(idx).as_mut().unwrap()
-        }
+        &mut self.as_mut_slice()[idx]
     }
 }
 
 impl Clone for Buffer {
     fn clone(&self) -> Self {
-        let mut new = Self {
-            buffer: kmalloc(self.len())
-            len: self.len()
+        let mut new = if self.pages == 0 && self.align == 1 {
+            Self::new(self.len())
+        } else {
+            Self::new_aligned(self.len(), self.align)
         };
-        unsafe {
-            memcpy(new.get_mut(), self.get(), self.len());
-        }
+        new.copy_from_slice(0, self.as_slice());
         new
     }
 }
@@ -66,8 +151,175 @@ impl Clone for Buffer {
 impl Drop for Buffer {
     fn drop(&mut self) {
         if !self.buffer.is_null() {
-            kfree(self.buffer);
+            if self.pages > 0 {
+                unsafe {
+                    scrub::free_pages(self.buffer, self.pages);
+                }
+            } else {
+                kfree(self.buffer);
+            }
             self.buffer = null_mut();
         }
     }
-}
\ No newline at end of file
+}
+pub struct DmaVec<T: Copy> {
+    buffer: Buffer,
+    len: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T: Copy> DmaVec<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut ret = Self {
+            buffer: Buffer::new_aligned(0, align_of::<T>()),
+            len: 0,
+            marker: PhantomData,
+        };
+        ret.reserve(capacity);
+        ret
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len() / size_of::<T>().max(1)
+    }
+
+    pub fn phys_addr(&self) -> usize {
+        self.buffer.phys_addr()
+    }
+
+    pub fn byte_len(&self) -> usize {
+        self.len * size_of::<T>()
+    }
+
+    pub fn reserve(&mut self, additional: usize) -> bool {
+        let needed = match self.len.checked_add(additional) {
+            Some(needed) => needed,
+            None => return false,
+        };
+        if needed <= self.capacity() {
+            return true;
+        }
+        let capacity = needed.max(self.capacity() * 2).max(MIN_OBJECT / size_of::<T>().max(1));
+        let bytes = match capacity.checked_mul(size_of::<T>()) {
+            Some(bytes) => bytes,
+            None => return false,
+        };
+        let mut buffer = Buffer::new_aligned(bytes, align_of::<T>());
+        if buffer.get().is_null() {
+            return false;
+        }
+        buffer.copy_from_slice(0, &self.buffer.as_slice()[..self.byte_len()]);
+        self.buffer = buffer;
+        true
+    }
+
+    pub fn push(&mut self, val: T) -> bool {
+        if !self.reserve(1) {
+            return false;
+        }
+        unsafe {
+            (self.buffer.get_mut() as *mut T).add(self.len).write(val);
+        }
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let val = self[self.len - 1];
+        self.len -= 1;
+        Some(val)
+    }
+
+    pub fn extend_from_slice(&mut self, src: &[T]) -> bool {
+        if !self.reserve(src.len()) {
+            return false;
+        }
+        unsafe {
+            (self.buffer.get_mut() as *mut T).add(self.len).copy_from_nonoverlapping(src.as_ptr(), src.len());
+        }
+        self.len += src.len();
+        true
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.buffer.get() as *const T, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(self.buffer.get_mut() as *mut T, self.len) }
+    }
+}
+
+impl<T: Copy> Default for DmaVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy> Deref for DmaVec<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T: Copy> DerefMut for DmaVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T: Copy> Extend<T> for DmaVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for val in iter {
+            if !self.push(val) {
+                break;
+            }
+        }
+    }
+}
+
+impl<'a, T: Copy + 'a> Extend<&'a T> for DmaVec<T> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+impl<T: Copy> Clone for DmaVec<T> {
+    fn clone(&self) -> Self {
+        let mut new = Self::with_capacity(self.len);
+        new.extend_from_slice(self.as_slice());
+        new
+    }
+}
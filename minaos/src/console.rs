@@ -1,6 +1,12 @@
 use alloc::collections::VecDeque;
+use crate::ansi::{AnsiCommand, AnsiParser, AnsiSink};
+use crate::fbcon;
 use crate::lock::Mutex;
-use crate::process::{get_by_pid, set_running};
+use crate::poll::{self, POLLIN, POLLOUT};
+use crate::session;
+use crate::signal::{SIGINT, SIGQUIT, SIGTSTP};
+use crate::uart;
+use crate::waitqueue::WaitQueue;
 
 pub static mut IN_BUFFER: Option<VecDeque<u8>> = None;
 pub static mut OUT_BUFFER: Option<VecDeque<u8>> = None;
@@ -11,7 +17,20 @@ pub static mut OUT_LOCK: Mutex = Mutex::new();
 pub const DEFAULT_OUT_BUFFER_SIZE: usize = 10_000;
 pub const DEFAULT_IN_BUFFER_SIZE: usize = 1_000;
 
-pub static mut CONSOLE_QUEUE: Option<VecDeque<u16>> = None;
+pub static STDIN_WAIT: WaitQueue = WaitQueue::new("stdin");
+
+pub static mut OUT_PARSER: AnsiParser = AnsiParser::new();
+
+pub static mut CANONICAL: bool = true;
+pub static mut ECHO: bool = true;
+
+pub const CTRL_C: u8 = 3;
+pub const CTRL_Z: u8 = 26;
+pub const CTRL_BACKSLASH: u8 = 28;
+
+pub const TIOCSCTTY: usize = 0x540E;
+pub const TIOCGPGRP: usize = 0x540F;
+pub const TIOCSPGRP: usize = 0x5410;
 
 pub fn init() {
     unsafe {
@@ -20,7 +39,21 @@ pub fn init() {
     }
 }
 
-pub fn pust_stdout(c: u8) {
+pub fn push_stdout(c: u8) {
+    unsafe {
+        OUT_LOCK.spin_lock();
+        if let Some(mut buf) = OUT_BUFFER.take() {
+            if buf.len() < DEFAULT_OUT_BUFFER_SIZE {
+                buf.push_back(c);
+            }
+            OUT_BUFFER.replace(buf);
+        }
+        OUT_LOCK.unlock();
+    }
+}
+
+pub fn pop_stdout() -> u8 {
+    let mut ret = None;
     unsafe {
         OUT_LOCK.spin_lock();
         if let Some(mut buf) = OUT_BUFFER.take() {
@@ -32,28 +65,182 @@ pub fn pust_stdout(c: u8) {
     ret.unwrap_or(0)
 }
 
+pub fn flush_stdout<S: AnsiSink>(sink: &mut S) {
+    unsafe {
+        OUT_LOCK.spin_lock();
+        if let Some(mut buf) = OUT_BUFFER.take() {
+            for c in buf.drain(..) {
+                OUT_PARSER.feed(c, sink);
+            }
+            OUT_BUFFER.replace(buf);
+        }
+        OUT_LOCK.unlock();
+    }
+}
+
+struct Tee<'a, A: AnsiSink, B: AnsiSink>(&'a mut A, &'a mut B);
+
+impl<A: AnsiSink, B: AnsiSink> AnsiSink for Tee<'_, A, B> {
+    fn put(&mut self, c: u8) {
+        self.0.put(c);
+        self.1.put(c);
+    }
+
+    fn command(&mut self, cmd: AnsiCommand, raw: &[u8]) {
+        self.0.command(cmd, raw);
+        self.1.command(cmd, raw);
+    }
+}
+
+pub fn write_stdout(data: &[u8]) {
+    let mut uart = uart::Uart::new(0x1000_0000);
+    for chunk in data.chunks(DEFAULT_OUT_BUFFER_SIZE) {
+        for &c in chunk {
+            push_stdout(c);
+        }
+        match fbcon::console() {
+            Some(fb) => {
+                flush_stdout(&mut Tee(&mut uart, &mut *fb));
+                fb.flush();
+            },
+            None => flush_stdout(&mut uart),
+        }
+    }
+}
+
+fn is_line_end(c: u8) -> bool {
+    c == 10 || c == 13
+}
+
+fn tty_signal(c: u8) -> Option<usize> {
+    if !unsafe { CANONICAL } {
+        return None;
+    }
+    match c {
+        CTRL_C => Some(SIGINT),
+        CTRL_Z => Some(SIGTSTP),
+        CTRL_BACKSLASH => Some(SIGQUIT),
+        _ => None,
+    }
+}
+
 pub fn push_stdin(c: u8) {
+    if let Some(sig) = tty_signal(c) {
+        session::tty_signal(sig);
+        return;
+    }
+    let mut wake = false;
     unsafe {
         IN_LOCK.spin_lock();
         if let Some(mut buf) = IN_BUFFER.take() {
-            if buf.len() < DEFAULT_IN_BUFFER_SIZE {
+            if buf.len() < DEFAULT_IN_BUFFER_SIZE || (CANONICAL && is_line_end(c)) {
                 buf.push_back(c);
-                if c == 10 || c == 11 {
-                    if let Some(mut q) = CONSOLE_QUEUE.take() {
-                        for i in q.drain(..) {
-                            set_running(i);
+                wake = stdin_ready(&buf);
+            }
+            IN_BUFFER.replace(buf);
+        }
+        IN_LOCK.unlock();
+    }
+    if wake {
+        STDIN_WAIT.wake_all();
+        poll::notify();
+    }
+}
+
+pub fn set_canonical(canonical: bool) {
+    unsafe {
+        IN_LOCK.spin_lock();
+        CANONICAL = canonical;
+        IN_LOCK.unlock();
+    }
+    if !canonical {
+        STDIN_WAIT.wake_all();
+        poll::notify();
+    }
+}
+
+fn stdin_ready(buf: &VecDeque<u8>) -> bool {
+    if unsafe { CANONICAL } {
+        buf.len() >= DEFAULT_IN_BUFFER_SIZE || buf.iter().any(|&c| is_line_end(c))
+    } else {
+        !buf.is_empty()
+    }
+}
+
+pub fn wait_for_stdin(pid: u16) -> bool {
+    STDIN_WAIT.sleep_on(pid, || {
+        let mut ready = false;
+        unsafe {
+            IN_LOCK.spin_lock();
+            if let Some(buf) = IN_BUFFER.take() {
+                ready = stdin_ready(&buf);
+                IN_BUFFER.replace(buf);
+            }
+            IN_LOCK.unlock();
+        }
+        ready
+    })
+}
+
+pub fn poll() -> i16 {
+    let mut ready = false;
+    unsafe {
+        IN_LOCK.spin_lock();
+        if let Some(buf) = IN_BUFFER.take() {
+            ready = stdin_ready(&buf);
+            IN_BUFFER.replace(buf);
+        }
+        IN_LOCK.unlock();
+    }
+    if ready {
+        POLLIN | POLLOUT
+    } else {
+        POLLOUT
+    }
+}
+
+pub fn read_stdin(buffer: *mut u8, size: usize) -> usize {
+    let mut read = 0;
+    unsafe {
+        IN_LOCK.spin_lock();
+        if let Some(mut buf) = IN_BUFFER.take() {
+            while read < size {
+                match buf.pop_front() {
+                    Some(c) => {
+                        buffer.add(read).write(c);
+                        read += 1;
+                        if CANONICAL && is_line_end(c) {
+                            break;
                         }
-                        CONSOLE_QUEUE.replace(q);
-                    }
+                    },
+                    None => break,
                 }
             }
             IN_BUFFER.replace(buf);
         }
         IN_LOCK.unlock();
     }
+    uart::unthrottle();
+    read
 }
 
-pub fn pop_stdin() -> u8 {
+pub fn stdin_space() -> usize {
+    let mut len = DEFAULT_IN_BUFFER_SIZE;
+    unsafe {
+        if let Some(buf) = IN_BUFFER.as_ref() {
+            len = buf.len();
+        }
+    }
+    DEFAULT_IN_BUFFER_SIZE.saturating_sub(len)
+}
+
+pub fn set_echo(echo: bool) {
+    unsafe {
+        ECHO = echo;
+    }
+}
+
+pub fn try_pop_stdin() -> Option<u8> {
     let mut ret = None;
     unsafe {
         IN_LOCK.spin_lock();
@@ -63,14 +250,20 @@ pub fn pop_stdin() -> u8 {
         }
         IN_LOCK.unlock();
     }
-    ret.unwrap_or(0)
+    uart::unthrottle();
+    ret
 }
 
-pub fn push_queue(pid: u16) {
+pub fn pop_stdin() -> u8 {
+    let mut ret = None;
     unsafe {
-        if let Some(mut q) = CONSOLE_QUEUE.take() {
-            q.push_back(pid);
-            CONSOLE_QUEUE.replace(q);
+        IN_LOCK.spin_lock();
+        if let Some(mut buf) = IN_BUFFER.take() {
+            ret = buf.pop_front();
+            IN_BUFFER.replace(buf);
         }
+        IN_LOCK.unlock();
     }
-}
\ No newline at end of file
+    uart::unthrottle();
+    ret.unwrap_or(0)
+}
@@ -0,0 +1,211 @@
+use crate::{acct,
+            cpu::TrapFrame,
+            elf::{Header, ProgramHeader, CLASS_64, DATA_LSB, MACHINE_RISCV, MAGIC, PF_R, PF_W, PF_X, PT_LOAD, PT_NOTE,
+                  ROOT_BDEV, TYPE_CORE},
+            fs::FileSystem,
+            kthread,
+            lifecycle,
+            page::{Table, PAGE_SIZE},
+            paging,
+            process::get_by_pid,
+            session,
+            time::ticks_to_nanos,
+            vma::{self, VMA_EXEC, VMA_READ, VMA_WRITE}};
+use alloc::vec::Vec;
+use core::{mem::size_of,
+           slice,
+           sync::atomic::{AtomicBool, Ordering}};
+
+pub const CORE_PATH: &str = "/core";
+pub const MAX_CORE_SIZE: usize = 1024 * 1024;
+pub const NT_PRSTATUS: u32 = 1;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct PrStatus {
+    signo: i32,
+    code: i32,
+    errno: i32,
+    cursig: u16,
+    pad: u16,
+    sigpend: u64,
+    sighold: u64,
+    pid: i32,
+    ppid: i32,
+    pgrp: i32,
+    sid: i32,
+    utime: [i64; 2],
+    stime: [i64; 2],
+    cutime: [i64; 2],
+    cstime: [i64; 2],
+    regs: [u64; 32],
+    fpvalid: i32,
+    pad2: i32,
+}
+
+#[repr(C)]
+struct NoteHeader {
+    namesz: u32,
+    descsz: u32,
+    note_type: u32,
+    name: [u8; 8],
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+fn push<T>(out: &mut Vec<u8>, val: &T) {
+    out.extend_from_slice(unsafe { slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) });
+}
+
+fn timeval(ns: u64) -> [i64; 2] {
+    [(ns / 1_000_000_000) as i64, (ns % 1_000_000_000 / 1000) as i64]
+}
+
+fn segment_flags(flags: usize) -> u32 {
+    let mut ret = 0;
+    if flags & VMA_READ != 0 {
+        ret |= PF_R;
+    }
+    if flags & VMA_WRITE != 0 {
+        ret |= PF_W;
+    }
+    if flags & VMA_EXEC != 0 {
+        ret |= PF_X;
+    }
+    ret
+}
+
+unsafe fn build(frame: *const TrapFrame, sig: usize) -> Option<Vec<u8>> {
+    let pid = (*frame).pid as u16;
+    let p = get_by_pid(pid);
+    if p.is_null() {
+        return None;
+    }
+    let table = ((*p).mmu_table as *const Table).as_ref()?;
+    let vmas = vma::vmas(pid);
+    let times = acct::times(pid);
+    let mut status = PrStatus {
+        signo: sig as i32,
+        cursig: sig as u16,
+        pid: pid as i32,
+        ppid: lifecycle::parent_of(pid) as i32,
+        pgrp: session::getpgid(pid) as i32,
+        sid: session::getsid(pid) as i32,
+        utime: timeval(ticks_to_nanos(times.utime)),
+        stime: timeval(ticks_to_nanos(times.stime)),
+        ..PrStatus::default()
+    };
+    status.regs[0] = (*frame).pc as u64;
+    for i in 1..32 {
+        status.regs[i] = (*frame).regs[i] as u64;
+    }
+
+    let phnum = vmas.len() + 1;
+    let note_off = size_of::<Header>() + phnum * size_of::<ProgramHeader>();
+    let note_size = size_of::<NoteHeader>() + size_of::<PrStatus>();
+    let mut data_off = (note_off + note_size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let header = Header {
+        magic: MAGIC,
+        bitsize: CLASS_64,
+        endian: DATA_LSB,
+        ident_abi_version: 1,
+        target_platform: 0,
+        abi_version: 0,
+        padding: [0; 7],
+        obj_type: TYPE_CORE,
+        machine: MACHINE_RISCV,
+        version: 1,
+        entry_addr: 0,
+        phoff: size_of::<Header>(),
+        shoff: 0,
+        flags: 0,
+        ehsize: size_of::<Header>() as u16,
+        phentsize: size_of::<ProgramHeader>() as u16,
+        phnum: phnum as u16,
+        shentsize: 0,
+        shnum: 0,
+        shstrndx: 0,
+    };
+    let mut out = Vec::new();
+    push(&mut out, &header);
+    push(&mut out, &ProgramHeader {
+        seg_type: PT_NOTE,
+        flags: 0,
+        off: note_off,
+        vaddr: 0,
+        paddr: 0,
+        filesz: note_size,
+        memsz: 0,
+        align: 4,
+    });
+    let mut dumped = Vec::new();
+    for v in vmas.iter() {
+        let len = v.end - v.start;
+        let filesz = if data_off + len <= MAX_CORE_SIZE { len } else { 0 };
+        push(&mut out, &ProgramHeader {
+            seg_type: PT_LOAD,
+            flags: segment_flags(v.flags),
+            off: data_off,
+            vaddr: v.start,
+            paddr: 0,
+            filesz,
+            memsz: len,
+            align: PAGE_SIZE,
+        });
+        if filesz > 0 {
+            dumped.push(*v);
+            data_off += filesz;
+        }
+    }
+    push(&mut out, &NoteHeader {
+        namesz: 5,
+        descsz: size_of::<PrStatus>() as u32,
+        note_type: NT_PRSTATUS,
+        name: *b"CORE\0\0\0\0",
+    });
+    push(&mut out, &status);
+    out.resize((out.len() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1), 0);
+    for v in dumped.iter() {
+        let mut vaddr = v.start;
+        while vaddr < v.end {
+            match paging::translate(table, vaddr) {
+                Some(paddr) => out.extend_from_slice(slice::from_raw_parts(paddr as *const u8, PAGE_SIZE)),
+                None => out.resize(out.len() + PAGE_SIZE, 0),
+            }
+            vaddr += PAGE_SIZE;
+        }
+    }
+    Some(out)
+}
+
+fn write_core(pid: u16, core: Vec<u8>) {
+    let (inode_num, mut inode) = match FileSystem::create(ROOT_BDEV, CORE_PATH) {
+        Ok(found) => found,
+        Err(_) => {
+            klog!("coredump: cannot create {}, core of {} discarded", CORE_PATH, pid);
+            return;
+        },
+    };
+    inode.size = 0;
+    let written = FileSystem::write(ROOT_BDEV, inode_num, &mut inode, core.as_ptr(), core.len() as u32, 0);
+    klog!("coredump: wrote {} of {} bytes for {} to {}", written, core.len(), pid, CORE_PATH);
+}
+
+pub unsafe fn dump(frame: *const TrapFrame, sig: usize) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+    let pid = (*frame).pid as u16;
+    match build(frame, sig) {
+        Some(core) => kthread::spawn("coredump", move || write_core(pid, core)).is_some(),
+        None => false,
+    }
+}
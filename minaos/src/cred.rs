@@ -0,0 +1,156 @@
+use crate::{fs::{Inode, S_IFDIR},
+            lock::Mutex};
+use alloc::collections::BTreeMap;
+
+pub const ROOT_UID: u32 = 0;
+pub const ROOT_GID: u32 = 0;
+
+pub const S_ISUID: u16 = 0o4000;
+pub const S_ISGID: u16 = 0o2000;
+
+pub const MAY_EXEC: u16 = 1;
+pub const MAY_WRITE: u16 = 2;
+pub const MAY_READ: u16 = 4;
+
+pub enum CredError {
+    Permission,
+}
+
+#[derive(Copy, Clone)]
+pub struct Credentials {
+    pub uid: u32,
+    pub euid: u32,
+    pub suid: u32,
+    pub gid: u32,
+    pub egid: u32,
+    pub sgid: u32,
+}
+
+impl Credentials {
+    pub const fn root() -> Self {
+        Credentials {
+            uid: ROOT_UID,
+            euid: ROOT_UID,
+            suid: ROOT_UID,
+            gid: ROOT_GID,
+            egid: ROOT_GID,
+            sgid: ROOT_GID,
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.euid == ROOT_UID
+    }
+
+    pub fn is_setid(&self) -> bool {
+        self.uid != self.euid || self.gid != self.egid
+    }
+
+    pub fn may_access(&self, inode: &Inode, want: u16) -> bool {
+        if self.is_root() {
+            return want & MAY_EXEC == 0 || inode.mode & S_IFDIR != 0 || inode.mode & 0o111 != 0;
+        }
+        let bits = if self.euid == inode.uid as u32 {
+            inode.mode >> 6
+        } else if self.egid == inode.gid as u32 {
+            inode.mode >> 3
+        } else {
+            inode.mode
+        };
+        bits & want == want
+    }
+}
+
+static mut CREDENTIALS: Option<BTreeMap<u16, Credentials>> = None;
+static mut CRED_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        CREDENTIALS.replace(BTreeMap::new());
+    }
+}
+
+fn with_credentials<T, F: FnOnce(&mut BTreeMap<u16, Credentials>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        CRED_LOCK.spin_lock();
+        if let Some(mut creds) = CREDENTIALS.take() {
+            ret = Some(f(&mut creds));
+            CREDENTIALS.replace(creds);
+        }
+        CRED_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn get(pid: u16) -> Credentials {
+    with_credentials(|creds| creds.get(&pid).copied().unwrap_or(Credentials::root())).unwrap_or(Credentials::root())
+}
+
+pub fn set(pid: u16, cred: Credentials) {
+    with_credentials(|creds| creds.insert(pid, cred));
+}
+
+pub fn fork(parent: u16, child: u16) {
+    set(child, get(parent));
+}
+
+pub fn release(pid: u16) {
+    with_credentials(|creds| creds.remove(&pid));
+}
+
+pub fn can_signal(sender: u16, target: u16) -> bool {
+    let from = get(sender);
+    let to = get(target);
+    from.is_root() || from.uid == to.uid || from.euid == to.uid
+}
+
+pub fn may_access(pid: u16, inode: &Inode, want: u16) -> bool {
+    get(pid).may_access(inode, want)
+}
+
+pub fn setuid(pid: u16, uid: u32) -> Result<(), CredError> {
+    with_credentials(|creds| {
+        let cred = creds.entry(pid).or_insert(Credentials::root());
+        if cred.is_root() {
+            cred.uid = uid;
+            cred.euid = uid;
+            cred.suid = uid;
+        } else if uid == cred.uid || uid == cred.suid {
+            cred.euid = uid;
+        } else {
+            return Err(CredError::Permission);
+        }
+        Ok(())
+    }).unwrap_or(Err(CredError::Permission))
+}
+
+pub fn setgid(pid: u16, gid: u32) -> Result<(), CredError> {
+    with_credentials(|creds| {
+        let cred = creds.entry(pid).or_insert(Credentials::root());
+        if cred.is_root() {
+            cred.gid = gid;
+            cred.egid = gid;
+            cred.sgid = gid;
+        } else if gid == cred.gid || gid == cred.sgid {
+            cred.egid = gid;
+        } else {
+            return Err(CredError::Permission);
+        }
+        Ok(())
+    }).unwrap_or(Err(CredError::Permission))
+}
+
+pub fn exec(pid: u16, inode: &Inode) {
+    with_credentials(|creds| {
+        let cred = creds.entry(pid).or_insert(Credentials::root());
+        if inode.mode & S_ISUID != 0 {
+            cred.euid = inode.uid as u32;
+        }
+        if inode.mode & S_ISGID != 0 {
+            cred.egid = inode.gid as u32;
+        }
+        cred.suid = cred.euid;
+        cred.sgid = cred.egid;
+    });
+}
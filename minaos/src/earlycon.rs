@@ -0,0 +1,103 @@
+use core::{fmt::{self, Write},
+           panic::PanicInfo,
+           sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+use crate::{backtrace, gdbstub, uart::{UART_LSR, UART_LSR_THRE, UART_THR}};
+
+pub const EARLY_UART_BASE: usize = 0x1000_0000;
+pub const NO_HART: usize = usize::MAX;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+static PANIC_HART: AtomicUsize = AtomicUsize::new(NO_HART);
+
+pub struct EarlyConsole {
+    base_address: usize,
+}
+
+impl EarlyConsole {
+    pub const fn new() -> Self {
+        EarlyConsole { base_address: EARLY_UART_BASE }
+    }
+
+    pub fn put(&mut self, c: u8) {
+        let ptr = self.base_address as *mut u8;
+        unsafe {
+            while ptr.add(UART_LSR).read_volatile() & UART_LSR_THRE == 0 {}
+            ptr.add(UART_THR).write_volatile(c);
+        }
+    }
+}
+
+impl Write for EarlyConsole {
+    fn write_str(&mut self, out: &str) -> fmt::Result {
+        for c in out.bytes() {
+            if c == b'\n' {
+                self.put(b'\r');
+            }
+            self.put(c);
+        }
+        Ok(())
+    }
+}
+
+pub fn print(args: fmt::Arguments) {
+    let _ = EarlyConsole::new().write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! early_print {
+    ($($args:tt)+) => ({
+        $crate::earlycon::print(format_args!($($args)+));
+    });
+}
+
+#[macro_export]
+macro_rules! early_println {
+    () => ({
+        $crate::early_print!("\n")
+    });
+    ($fmt:expr) => ({
+        $crate::early_print!(concat!($fmt, "\n"))
+    });
+    ($fmt:expr, $($args:tt)+) => ({
+        $crate::early_print!(concat!($fmt, "\n"), $($args)+)
+    });
+}
+
+fn hart_id() -> usize {
+    let hart;
+    unsafe {
+        asm!("csrr {}, mhartid", out(reg) hart);
+    }
+    hart
+}
+
+fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let hart = hart_id();
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        if PANIC_HART.load(Ordering::SeqCst) == hart {
+            early_println!("\nNested panic on hart {}, halting.", hart);
+        }
+        halt();
+    }
+    PANIC_HART.store(hart, Ordering::SeqCst);
+    early_print!("\nKernel panic on hart {}: ", hart);
+    if let Some(p) = info.location() {
+        early_print!("line {}, file {}: ", p.line(), p.file());
+    }
+    match info.message() {
+        Some(msg) => early_println!("{}", msg),
+        None => early_println!("no information available."),
+    }
+    backtrace::print_backtrace();
+    gdbstub::enter_on_panic();
+    halt();
+}
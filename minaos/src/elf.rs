@@ -0,0 +1,417 @@
+use crate::{buffer::Buffer,
+            cpu::{build_satp, memcpy, CpuMode, Registers, SatpMode, TrapFrame},
+            cred::{self, MAY_EXEC},
+            fs::{FileSystem, Inode, S_IFDIR},
+            page::{dealloc, zalloc, Table, PAGE_SIZE},
+            paging,
+            process::{Process, ProcessData, ProcessState, NEXT_PID, PROCESS_LIST, PROCESS_LIST_MUTEX, STACK_ADDR, STACK_PAGES},
+            random,
+            signal,
+            vma::{self, Vma, VmaBacking, VMA_EXEC, VMA_GROWSDOWN, VMA_READ, VMA_WRITE}};
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+pub const ROOT_BDEV: usize = 8;
+
+pub const MAGIC: u32 = 0x464c_457f;
+pub const CLASS_64: u8 = 2;
+pub const DATA_LSB: u8 = 1;
+pub const TYPE_EXEC: u16 = 2;
+pub const TYPE_CORE: u16 = 4;
+pub const MACHINE_RISCV: u16 = 0xf3;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_NOTE: u32 = 4;
+
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_ENTRY: usize = 9;
+pub const AT_UID: usize = 11;
+pub const AT_EUID: usize = 12;
+pub const AT_GID: usize = 13;
+pub const AT_EGID: usize = 14;
+pub const AT_SECURE: usize = 23;
+pub const AT_RANDOM: usize = 25;
+
+pub const AT_RANDOM_BYTES: usize = 16;
+
+pub const MAX_PROGRAM_SIZE: usize = 64 * 1024 * 1024;
+pub const MAX_ARG_STRINGS: usize = 64;
+pub const MAX_ARG_BYTES: usize = PAGE_SIZE;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Header {
+    pub magic: u32,
+    pub bitsize: u8,
+    pub endian: u8,
+    pub ident_abi_version: u8,
+    pub target_platform: u8,
+    pub abi_version: u8,
+    pub padding: [u8; 7],
+    pub obj_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry_addr: usize,
+    pub phoff: usize,
+    pub shoff: usize,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ProgramHeader {
+    pub seg_type: u32,
+    pub flags: u32,
+    pub off: usize,
+    pub vaddr: usize,
+    pub paddr: usize,
+    pub filesz: usize,
+    pub memsz: usize,
+    pub align: usize,
+}
+
+#[derive(Debug)]
+pub enum LoadErrors {
+    Magic,
+    Machine,
+    TypeExec,
+    Truncated,
+    BadSegment,
+    TooLarge,
+    NoLoadSegments,
+    TooManyArgs,
+    OutOfMemory,
+    NotFound,
+    IsDirectory,
+    Permission,
+}
+
+pub struct File {
+    pub header: Header,
+    pub programs: Vec<ProgramHeader>,
+}
+
+fn align_up(val: usize, align: usize) -> usize {
+    (val + align - 1) & !(align - 1)
+}
+
+impl File {
+    pub fn load(buffer: &Buffer) -> Result<Self, LoadErrors> {
+        if buffer.len() < size_of::<Header>() {
+            return Err(LoadErrors::Truncated);
+        }
+        let header = unsafe { (buffer.get() as *const Header).read_unaligned() };
+        if header.magic != MAGIC || header.bitsize != CLASS_64 || header.endian != DATA_LSB {
+            return Err(LoadErrors::Magic);
+        }
+        if header.machine != MACHINE_RISCV {
+            return Err(LoadErrors::Machine);
+        }
+        if header.obj_type != TYPE_EXEC {
+            return Err(LoadErrors::TypeExec);
+        }
+        if header.phentsize as usize != size_of::<ProgramHeader>() {
+            return Err(LoadErrors::Truncated);
+        }
+        let ph_end = header.phnum as usize * size_of::<ProgramHeader>() + header.phoff;
+        if header.phoff > buffer.len() || ph_end > buffer.len() {
+            return Err(LoadErrors::Truncated);
+        }
+        let mut programs = Vec::with_capacity(header.phnum as usize);
+        for i in 0..header.phnum as usize {
+            let ph = unsafe {
+                (buffer.get().add(header.phoff + i * size_of::<ProgramHeader>()) as *const ProgramHeader).read_unaligned()
+            };
+            if ph.seg_type != PT_LOAD || ph.memsz == 0 {
+                continue;
+            }
+            if ph.filesz > ph.memsz || ph.off.checked_add(ph.filesz).map_or(true, |end| end > buffer.len()) {
+                return Err(LoadErrors::BadSegment);
+            }
+            match ph.vaddr.checked_add(ph.memsz) {
+                Some(end) if end <= STACK_ADDR => {},
+                _ => return Err(LoadErrors::BadSegment),
+            }
+            programs.push(ph);
+        }
+        if programs.is_empty() {
+            return Err(LoadErrors::NoLoadSegments);
+        }
+        Ok(File { header, programs })
+    }
+
+    fn span(&self) -> (usize, usize) {
+        let start = self.programs.iter().map(|p| p.vaddr).min().unwrap_or(0) & !(PAGE_SIZE - 1);
+        let end = self.programs.iter().map(|p| p.vaddr + p.memsz).max().unwrap_or(0);
+        (start, align_up(end, PAGE_SIZE))
+    }
+
+    fn phdr_vaddr(&self) -> usize {
+        let phoff = self.header.phoff;
+        self.programs
+            .iter()
+            .find(|p| p.off <= phoff && phoff < p.off + p.filesz)
+            .map_or(0, |p| p.vaddr + (phoff - p.off))
+    }
+
+    fn page_flags(&self, vaddr: usize) -> u32 {
+        self.programs
+            .iter()
+            .filter(|p| p.vaddr < vaddr + PAGE_SIZE && vaddr < p.vaddr + p.memsz)
+            .fold(0, |flags, p| flags | p.flags)
+    }
+
+    pub fn load_proc(buffer: &Buffer, argv: &[&str], envp: &[&str]) -> Result<Process, LoadErrors> {
+        let pid = unsafe {
+            let pid = NEXT_PID;
+            NEXT_PID += 1;
+            pid
+        };
+        File::load_proc_as(buffer, argv, envp, pid)
+    }
+
+    pub fn load_proc_as(buffer: &Buffer, argv: &[&str], envp: &[&str], pid: u16) -> Result<Process, LoadErrors> {
+        let elf_fl = File::load(buffer)?;
+        let arg_bytes: usize = argv.iter().chain(envp.iter()).map(|s| s.len() + 1).sum();
+        if argv.len() + envp.len() > MAX_ARG_STRINGS || arg_bytes > MAX_ARG_BYTES {
+            return Err(LoadErrors::TooManyArgs);
+        }
+        let (start, end) = elf_fl.span();
+        if end - start > MAX_PROGRAM_SIZE {
+            return Err(LoadErrors::TooLarge);
+        }
+        let program_pages = (end - start) / PAGE_SIZE;
+        let fresh_asid = paging::asid_of(pid).is_none();
+        let asid = paging::alloc_asid(pid).ok_or(LoadErrors::OutOfMemory)?;
+        let program = zalloc(program_pages);
+        let stack = zalloc(STACK_PAGES);
+        let frame = zalloc(1) as *mut TrapFrame;
+        let mmu_table = zalloc(1) as *mut Table;
+        if program.is_null() || stack.is_null() || frame.is_null() || mmu_table.is_null() {
+            for page in [program, stack, frame as *mut u8, mmu_table as *mut u8].iter() {
+                if !page.is_null() {
+                    dealloc(*page);
+                }
+            }
+            if fresh_asid {
+                paging::release(pid);
+            }
+            return Err(LoadErrors::OutOfMemory);
+        }
+        for ph in elf_fl.programs.iter() {
+            unsafe {
+                memcpy(program.add(ph.vaddr - start), buffer.get().add(ph.off), ph.filesz);
+            }
+        }
+        let table = unsafe { &mut *mmu_table };
+        let mut mapped = true;
+        let mut i = 0;
+        while i < program_pages {
+            let flags = vma_flags(elf_fl.page_flags(start + i * PAGE_SIZE));
+            let mut end = i + 1;
+            while end < program_pages && vma_flags(elf_fl.page_flags(start + end * PAGE_SIZE)) == flags {
+                end += 1;
+            }
+            if flags != 0 {
+                let (vaddr, paddr, len) = (start + i * PAGE_SIZE, program as usize + i * PAGE_SIZE, (end - i) * PAGE_SIZE);
+                mapped &= paging::map_range(table, vaddr, paddr, len, Vma::entry_bits_for(flags)).is_ok();
+            }
+            i = end;
+        }
+        for i in 0..STACK_PAGES {
+            let vaddr = STACK_ADDR + i * PAGE_SIZE;
+            mapped &= paging::map(table, vaddr, stack as usize + i * PAGE_SIZE, Vma::entry_bits_for(VMA_READ | VMA_WRITE)).is_ok();
+        }
+        if !mapped {
+            paging::free_tables(table);
+            for page in [program, stack, frame as *mut u8, mmu_table as *mut u8].iter() {
+                dealloc(*page);
+            }
+            if fresh_asid {
+                paging::release(pid);
+            }
+            return Err(LoadErrors::OutOfMemory);
+        }
+        let my_proc = Process {
+            frame,
+            stack,
+            pid,
+            mmu_table,
+            state: ProcessState::Running,
+            data: ProcessData::new(),
+            sleep_until: 0,
+            program,
+            brk: end,
+        };
+        signal::attach(pid);
+        vma::release(pid);
+        let mut run: Option<Vma> = None;
+        for i in 0..program_pages {
+            let vaddr = start + i * PAGE_SIZE;
+            let flags = vma_flags(elf_fl.page_flags(vaddr));
+            let page = Vma {
+                start: vaddr,
+                end: vaddr + PAGE_SIZE,
+                flags,
+                backing: VmaBacking::Anonymous,
+            };
+            run = match run {
+                Some(mut r) if r.flags == flags && r.end == vaddr => {
+                    r.end = page.end;
+                    Some(r)
+                },
+                Some(r) => {
+                    let _ = vma::add_vma(pid, r);
+                    Some(page)
+                },
+                None => Some(page),
+            };
+        }
+        if let Some(r) = run {
+            let _ = vma::add_vma(pid, r);
+        }
+        let _ = vma::add_vma(pid, Vma {
+            start: STACK_ADDR,
+            end: STACK_ADDR + STACK_PAGES * PAGE_SIZE,
+            flags: VMA_READ | VMA_WRITE | VMA_GROWSDOWN,
+            backing: VmaBacking::Anonymous,
+        });
+        vma::set_heap_start(pid, end);
+        let cred = cred::get(pid);
+        let auxv = [(AT_PHDR, elf_fl.phdr_vaddr()),
+                    (AT_PHENT, elf_fl.header.phentsize as usize),
+                    (AT_PHNUM, elf_fl.header.phnum as usize),
+                    (AT_PAGESZ, PAGE_SIZE),
+                    (AT_ENTRY, elf_fl.header.entry_addr),
+                    (AT_UID, cred.uid as usize),
+                    (AT_EUID, cred.euid as usize),
+                    (AT_GID, cred.gid as usize),
+                    (AT_EGID, cred.egid as usize),
+                    (AT_SECURE, cred.is_setid() as usize)];
+        let sp = setup_stack(stack, argv, envp, &auxv, random_bytes());
+        unsafe {
+            (*frame).pc = elf_fl.header.entry_addr;
+            (*frame).regs[Registers::Sp as usize] = sp;
+            (*frame).regs[Registers::A0 as usize] = argv.len();
+            (*frame).regs[Registers::A1 as usize] = sp + size_of::<usize>();
+            (*frame).regs[Registers::A2 as usize] = sp + (argv.len() + 2) * size_of::<usize>();
+            (*frame).mode = CpuMode::User as usize;
+            (*frame).pid = pid as usize;
+            (*frame).satp = build_satp(SatpMode::Sv39, asid as usize, mmu_table as usize);
+        }
+        Ok(my_proc)
+    }
+}
+
+fn vma_flags(flags: u32) -> usize {
+    let mut ret = 0;
+    if flags & PF_R != 0 {
+        ret |= VMA_READ;
+    }
+    if flags & PF_W != 0 {
+        ret |= VMA_WRITE;
+    }
+    if flags & PF_X != 0 {
+        ret |= VMA_EXEC;
+    }
+    ret
+}
+
+fn random_bytes() -> [u8; AT_RANDOM_BYTES] {
+    let mut bytes = [0u8; AT_RANDOM_BYTES];
+    random::fill(&mut bytes);
+    bytes
+}
+
+fn setup_stack(stack: *mut u8, argv: &[&str], envp: &[&str], auxv: &[(usize, usize)], at_random: [u8; AT_RANDOM_BYTES]) -> usize {
+    let stack_top = STACK_PAGES * PAGE_SIZE;
+    let to_vaddr = |off: usize| STACK_ADDR + off;
+    let mut off = stack_top;
+    let mut push_str = |s: &str| {
+        off -= s.len() + 1;
+        unsafe {
+            memcpy(stack.add(off), s.as_ptr(), s.len());
+            stack.add(off + s.len()).write(0);
+        }
+        to_vaddr(off)
+    };
+    let argv_ptrs: Vec<usize> = argv.iter().map(|s| push_str(s)).collect();
+    let envp_ptrs: Vec<usize> = envp.iter().map(|s| push_str(s)).collect();
+    off = (off - AT_RANDOM_BYTES) & !0xf;
+    unsafe {
+        memcpy(stack.add(off), at_random.as_ptr(), AT_RANDOM_BYTES);
+    }
+    let random_ptr = to_vaddr(off);
+    let words = 1 + argv_ptrs.len() + 1 + envp_ptrs.len() + 1 + (auxv.len() + 2) * 2;
+    off = (off - words * size_of::<usize>()) & !0xf;
+    let sp = off;
+    let mut table = Vec::with_capacity(words);
+    table.push(argv_ptrs.len());
+    table.extend_from_slice(&argv_ptrs);
+    table.push(0);
+    table.extend_from_slice(&envp_ptrs);
+    table.push(0);
+    for (key, val) in auxv.iter().chain([(AT_RANDOM, random_ptr), (AT_NULL, 0)].iter()) {
+        table.push(*key);
+        table.push(*val);
+    }
+    unsafe {
+        memcpy(stack.add(sp), table.as_ptr() as *const u8, words * size_of::<usize>());
+    }
+    to_vaddr(sp)
+}
+
+pub fn read_file(bdev: usize, path: &str) -> Result<Buffer, LoadErrors> {
+    let inode = FileSystem::open(bdev, path).map_err(|_| LoadErrors::NotFound)?;
+    read_inode(bdev, &inode)
+}
+
+pub fn open_executable(bdev: usize, path: &str, pid: u16) -> Result<(Inode, Buffer), LoadErrors> {
+    let inode = FileSystem::open(bdev, path).map_err(|_| LoadErrors::NotFound)?;
+    if inode.mode & S_IFDIR == 0 && !cred::may_access(pid, &inode, MAY_EXEC) {
+        return Err(LoadErrors::Permission);
+    }
+    let buffer = read_inode(bdev, &inode)?;
+    Ok((inode, buffer))
+}
+
+fn read_inode(bdev: usize, inode: &Inode) -> Result<Buffer, LoadErrors> {
+    if inode.mode & S_IFDIR != 0 {
+        return Err(LoadErrors::IsDirectory);
+    }
+    let mut buffer = Buffer::new(inode.size as usize);
+    let bytes = FileSystem::read(bdev, inode, buffer.get_mut(), inode.size, 0);
+    if bytes != inode.size {
+        return Err(LoadErrors::Truncated);
+    }
+    Ok(buffer)
+}
+
+pub fn exec(bdev: usize, path: &str, argv: &[&str], envp: &[&str]) -> Result<u16, LoadErrors> {
+    let buffer = read_file(bdev, path)?;
+    let prc = File::load_proc(&buffer, argv, envp)?;
+    let pid = prc.pid;
+    unsafe {
+        PROCESS_LIST_MUTEX.spin_lock();
+        if let Some(mut pl) = PROCESS_LIST.take() {
+            pl.push_back(prc);
+            PROCESS_LIST.replace(pl);
+        }
+        PROCESS_LIST_MUTEX.unlock();
+    }
+    Ok(pid)
+}
@@ -0,0 +1,342 @@
+use crate::{ansi::{AnsiCommand, AnsiSink, Color},
+            gpu::{self, Pixel}};
+use core::ptr;
+
+pub const CELL_WIDTH: u32 = 8;
+pub const CELL_HEIGHT: u32 = 8;
+pub const TAB_WIDTH: u32 = 8;
+
+const FIRST_GLYPH: u8 = 0x20;
+const LAST_GLYPH: u8 = 0x7e;
+
+static FONT: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x18, 0x3c, 0x3c, 0x18, 0x18, 0x00, 0x18, 0x00],
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x36, 0x36, 0x7f, 0x36, 0x7f, 0x36, 0x36, 0x00],
+    [0x0c, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x0c, 0x00],
+    [0x00, 0x63, 0x33, 0x18, 0x0c, 0x66, 0x63, 0x00],
+    [0x1c, 0x36, 0x1c, 0x6e, 0x3b, 0x33, 0x6e, 0x00],
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x18, 0x0c, 0x06, 0x06, 0x06, 0x0c, 0x18, 0x00],
+    [0x06, 0x0c, 0x18, 0x18, 0x18, 0x0c, 0x06, 0x00],
+    [0x00, 0x66, 0x3c, 0xff, 0x3c, 0x66, 0x00, 0x00],
+    [0x00, 0x0c, 0x0c, 0x3f, 0x0c, 0x0c, 0x00, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x06],
+    [0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x00],
+    [0x60, 0x30, 0x18, 0x0c, 0x06, 0x03, 0x01, 0x00],
+    [0x3e, 0x63, 0x73, 0x7b, 0x6f, 0x67, 0x3e, 0x00],
+    [0x0c, 0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x3f, 0x00],
+    [0x1e, 0x33, 0x30, 0x1c, 0x06, 0x33, 0x3f, 0x00],
+    [0x1e, 0x33, 0x30, 0x1c, 0x30, 0x33, 0x1e, 0x00],
+    [0x38, 0x3c, 0x36, 0x33, 0x7f, 0x30, 0x78, 0x00],
+    [0x3f, 0x03, 0x1f, 0x30, 0x30, 0x33, 0x1e, 0x00],
+    [0x1c, 0x06, 0x03, 0x1f, 0x33, 0x33, 0x1e, 0x00],
+    [0x3f, 0x33, 0x30, 0x18, 0x0c, 0x0c, 0x0c, 0x00],
+    [0x1e, 0x33, 0x33, 0x1e, 0x33, 0x33, 0x1e, 0x00],
+    [0x1e, 0x33, 0x33, 0x3e, 0x30, 0x18, 0x0e, 0x00],
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x00],
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x06],
+    [0x18, 0x0c, 0x06, 0x03, 0x06, 0x0c, 0x18, 0x00],
+    [0x00, 0x00, 0x3f, 0x00, 0x00, 0x3f, 0x00, 0x00],
+    [0x06, 0x0c, 0x18, 0x30, 0x18, 0x0c, 0x06, 0x00],
+    [0x1e, 0x33, 0x30, 0x18, 0x0c, 0x00, 0x0c, 0x00],
+    [0x3e, 0x63, 0x7b, 0x7b, 0x7b, 0x03, 0x1e, 0x00],
+    [0x0c, 0x1e, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x00],
+    [0x3f, 0x66, 0x66, 0x3e, 0x66, 0x66, 0x3f, 0x00],
+    [0x3c, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3c, 0x00],
+    [0x1f, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1f, 0x00],
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x46, 0x7f, 0x00],
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x06, 0x0f, 0x00],
+    [0x3c, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7c, 0x00],
+    [0x33, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x33, 0x00],
+    [0x1e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00],
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e, 0x00],
+    [0x67, 0x66, 0x36, 0x1e, 0x36, 0x66, 0x67, 0x00],
+    [0x0f, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7f, 0x00],
+    [0x63, 0x77, 0x7f, 0x7f, 0x6b, 0x63, 0x63, 0x00],
+    [0x63, 0x67, 0x6f, 0x7b, 0x73, 0x63, 0x63, 0x00],
+    [0x1c, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1c, 0x00],
+    [0x3f, 0x66, 0x66, 0x3e, 0x06, 0x06, 0x0f, 0x00],
+    [0x1e, 0x33, 0x33, 0x33, 0x3b, 0x1e, 0x38, 0x00],
+    [0x3f, 0x66, 0x66, 0x3e, 0x36, 0x66, 0x67, 0x00],
+    [0x1e, 0x33, 0x07, 0x0e, 0x38, 0x33, 0x1e, 0x00],
+    [0x3f, 0x2d, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00],
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3f, 0x00],
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00],
+    [0x63, 0x63, 0x63, 0x6b, 0x7f, 0x77, 0x63, 0x00],
+    [0x63, 0x63, 0x36, 0x1c, 0x1c, 0x36, 0x63, 0x00],
+    [0x33, 0x33, 0x33, 0x1e, 0x0c, 0x0c, 0x1e, 0x00],
+    [0x7f, 0x63, 0x31, 0x18, 0x4c, 0x66, 0x7f, 0x00],
+    [0x1e, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1e, 0x00],
+    [0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x40, 0x00],
+    [0x1e, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1e, 0x00],
+    [0x08, 0x1c, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff],
+    [0x0c, 0x0c, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x1e, 0x30, 0x3e, 0x33, 0x6e, 0x00],
+    [0x07, 0x06, 0x06, 0x3e, 0x66, 0x66, 0x3b, 0x00],
+    [0x00, 0x00, 0x1e, 0x33, 0x03, 0x33, 0x1e, 0x00],
+    [0x38, 0x30, 0x30, 0x3e, 0x33, 0x33, 0x6e, 0x00],
+    [0x00, 0x00, 0x1e, 0x33, 0x3f, 0x03, 0x1e, 0x00],
+    [0x1c, 0x36, 0x06, 0x0f, 0x06, 0x06, 0x0f, 0x00],
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x1f],
+    [0x07, 0x06, 0x36, 0x6e, 0x66, 0x66, 0x67, 0x00],
+    [0x0c, 0x00, 0x0e, 0x0c, 0x0c, 0x0c, 0x1e, 0x00],
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e],
+    [0x07, 0x06, 0x66, 0x36, 0x1e, 0x36, 0x67, 0x00],
+    [0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00],
+    [0x00, 0x00, 0x33, 0x7f, 0x7f, 0x6b, 0x63, 0x00],
+    [0x00, 0x00, 0x1f, 0x33, 0x33, 0x33, 0x33, 0x00],
+    [0x00, 0x00, 0x1e, 0x33, 0x33, 0x33, 0x1e, 0x00],
+    [0x00, 0x00, 0x3b, 0x66, 0x66, 0x3e, 0x06, 0x0f],
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x78],
+    [0x00, 0x00, 0x3b, 0x6e, 0x66, 0x06, 0x0f, 0x00],
+    [0x00, 0x00, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x00],
+    [0x08, 0x0c, 0x3e, 0x0c, 0x0c, 0x2c, 0x18, 0x00],
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6e, 0x00],
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00],
+    [0x00, 0x00, 0x63, 0x6b, 0x7f, 0x7f, 0x36, 0x00],
+    [0x00, 0x00, 0x63, 0x36, 0x1c, 0x36, 0x63, 0x00],
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3e, 0x30, 0x1f],
+    [0x00, 0x00, 0x3f, 0x19, 0x0c, 0x26, 0x3f, 0x00],
+    [0x38, 0x0c, 0x0c, 0x07, 0x0c, 0x0c, 0x38, 0x00],
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00],
+    [0x07, 0x0c, 0x0c, 0x38, 0x0c, 0x0c, 0x07, 0x00],
+    [0x6e, 0x3b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
+
+static PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xaa, 0x00, 0x00),
+    (0x00, 0xaa, 0x00),
+    (0xaa, 0x55, 0x00),
+    (0x00, 0x00, 0xaa),
+    (0xaa, 0x00, 0xaa),
+    (0x00, 0xaa, 0xaa),
+    (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55),
+    (0xff, 0x55, 0x55),
+    (0x55, 0xff, 0x55),
+    (0xff, 0xff, 0x55),
+    (0x55, 0x55, 0xff),
+    (0xff, 0x55, 0xff),
+    (0x55, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+static mut FB_CONSOLE: Option<FbConsole> = None;
+
+pub struct FbConsole {
+    dev: usize,
+    fb: *mut Pixel,
+    width: u32,
+    height: u32,
+    cols: u32,
+    rows: u32,
+    x: u32,
+    y: u32,
+    saved: (u32, u32),
+    fg: (Color, bool),
+    bg: (Color, bool),
+    bold: bool,
+    reverse: bool,
+    dirty: bool,
+}
+
+impl FbConsole {
+    fn new(dev: usize, fb: *mut Pixel, width: u32, height: u32) -> Self {
+        FbConsole {
+            dev,
+            fb,
+            width,
+            height,
+            cols: width / CELL_WIDTH,
+            rows: height / CELL_HEIGHT,
+            x: 0,
+            y: 0,
+            saved: (0, 0),
+            fg: (Color::Default, false),
+            bg: (Color::Default, false),
+            bold: false,
+            reverse: false,
+            dirty: false,
+        }
+    }
+
+    fn pixel((color, bright): (Color, bool), default: Color) -> Pixel {
+        let color = if color == Color::Default { default } else { color };
+        let (r, g, b) = PALETTE[color as usize + if bright { 8 } else { 0 }];
+        Pixel { r, g, b, a: 255 }
+    }
+
+    fn colors(&self) -> (Pixel, Pixel) {
+        let fg = Self::pixel((self.fg.0, self.fg.1 || self.bold), Color::White);
+        let bg = Self::pixel(self.bg, Color::Black);
+        if self.reverse { (bg, fg) } else { (fg, bg) }
+    }
+
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: Pixel) {
+        for row in y..(y + height).min(self.height) {
+            for col in x..(x + width).min(self.width) {
+                unsafe { self.fb.add((row * self.width + col) as usize).write(color) };
+            }
+        }
+        self.dirty = true;
+    }
+
+    fn clear_cells(&mut self, row: u32, from: u32, to: u32) {
+        let (_, bg) = self.colors();
+        self.fill(from * CELL_WIDTH, row * CELL_HEIGHT, (to - from) * CELL_WIDTH, CELL_HEIGHT, bg);
+    }
+
+    fn draw(&mut self, c: u8) {
+        let glyph = if (FIRST_GLYPH..=LAST_GLYPH).contains(&c) { FONT[(c - FIRST_GLYPH) as usize] } else { FONT[(b'?' - FIRST_GLYPH) as usize] };
+        let (fg, bg) = self.colors();
+        let (px, py) = (self.x * CELL_WIDTH, self.y * CELL_HEIGHT);
+        for (dy, bits) in glyph.iter().enumerate() {
+            for dx in 0..CELL_WIDTH {
+                let color = if bits & (1 << dx) != 0 { fg } else { bg };
+                unsafe { self.fb.add(((py + dy as u32) * self.width + px + dx) as usize).write(color) };
+            }
+        }
+        self.dirty = true;
+    }
+
+    fn scroll(&mut self) {
+        let line = (self.width * CELL_HEIGHT) as usize;
+        let total = (self.width * self.rows * CELL_HEIGHT) as usize;
+        unsafe { ptr::copy(self.fb.add(line), self.fb, total - line) };
+        let last = self.rows - 1;
+        self.clear_cells(last, 0, self.cols);
+    }
+
+    fn newline(&mut self) {
+        self.x = 0;
+        if self.y + 1 >= self.rows {
+            self.scroll();
+        } else {
+            self.y += 1;
+        }
+    }
+
+    fn print(&mut self, c: u8) {
+        if self.x >= self.cols {
+            self.newline();
+        }
+        self.draw(c);
+        self.x += 1;
+    }
+
+    fn control(&mut self, c: u8) {
+        match c {
+            b'\n' => self.newline(),
+            b'\r' => self.x = 0,
+            8 => self.x = self.x.saturating_sub(1),
+            b'\t' => self.x = ((self.x / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1),
+            _ => {},
+        }
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        let (x, y, rows, cols) = (self.x, self.y, self.rows, self.cols);
+        match mode {
+            0 => {
+                self.clear_cells(y, x.min(cols), cols);
+                for row in y + 1..rows {
+                    self.clear_cells(row, 0, cols);
+                }
+            },
+            1 => {
+                for row in 0..y {
+                    self.clear_cells(row, 0, cols);
+                }
+                self.clear_cells(y, 0, (x + 1).min(cols));
+            },
+            _ => {
+                for row in 0..rows {
+                    self.clear_cells(row, 0, cols);
+                }
+            },
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        let (x, y, cols) = (self.x, self.y, self.cols);
+        match mode {
+            0 => self.clear_cells(y, x.min(cols), cols),
+            1 => self.clear_cells(y, 0, (x + 1).min(cols)),
+            _ => self.clear_cells(y, 0, cols),
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.dirty {
+            gpu::transfer(self.dev, 0, 0, self.width, self.height);
+            self.dirty = false;
+        }
+    }
+}
+
+impl AnsiSink for FbConsole {
+    fn put(&mut self, c: u8) {
+        if c < 0x20 || c == 0x7f {
+            self.control(c);
+        } else {
+            self.print(c);
+        }
+    }
+
+    fn command(&mut self, cmd: AnsiCommand, _raw: &[u8]) {
+        match cmd {
+            AnsiCommand::Print(c) => self.print(c),
+            AnsiCommand::Control(c) => self.control(c),
+            AnsiCommand::ResetAttributes => {
+                self.fg = (Color::Default, false);
+                self.bg = (Color::Default, false);
+                self.bold = false;
+                self.reverse = false;
+            },
+            AnsiCommand::Bold(on) => self.bold = on,
+            AnsiCommand::Reverse(on) => self.reverse = on,
+            AnsiCommand::Foreground(color, bright) => self.fg = (color, bright),
+            AnsiCommand::Background(color, bright) => self.bg = (color, bright),
+            AnsiCommand::CursorUp(n) => self.y = self.y.saturating_sub(n as u32),
+            AnsiCommand::CursorDown(n) => self.y = (self.y + n as u32).min(self.rows - 1),
+            AnsiCommand::CursorForward(n) => self.x = (self.x + n as u32).min(self.cols - 1),
+            AnsiCommand::CursorBack(n) => self.x = self.x.saturating_sub(n as u32),
+            AnsiCommand::CursorPosition(row, col) => {
+                self.y = (row as u32).saturating_sub(1).min(self.rows - 1);
+                self.x = (col as u32).saturating_sub(1).min(self.cols - 1);
+            },
+            AnsiCommand::EraseDisplay(mode) => self.erase_display(mode),
+            AnsiCommand::EraseLine(mode) => self.erase_line(mode),
+            AnsiCommand::SaveCursor => self.saved = (self.x, self.y),
+            AnsiCommand::RestoreCursor => {
+                let (x, y) = self.saved;
+                self.x = x;
+                self.y = y;
+            },
+            AnsiCommand::Underline(_) | AnsiCommand::ShowCursor(_) | AnsiCommand::Unknown => {},
+        }
+    }
+}
+
+pub fn attach(dev: usize) {
+    unsafe {
+        if let Some(gpu) = gpu::GPU_DEVICES[dev].as_ref() {
+            let (width, height) = (gpu.get_width(), gpu.get_height());
+            if width >= CELL_WIDTH && height >= CELL_HEIGHT {
+                let mut con = FbConsole::new(dev, gpu.get_framebuffer(), width, height);
+                con.erase_display(2);
+                con.flush();
+                FB_CONSOLE.replace(con);
+            }
+        }
+    }
+}
+
+pub fn console() -> Option<&'static mut FbConsole> {
+    unsafe { FB_CONSOLE.as_mut() }
+}
@@ -0,0 +1,219 @@
+use crate::{console::{self, TIOCGPGRP, TIOCSCTTY, TIOCSPGRP},
+            cpu::TrapFrame,
+            file,
+            lock::Mutex,
+            pipe::{self, PipeResult},
+            poll::{POLLIN, POLLNVAL, POLLOUT},
+            session,
+            shm,
+            signal::{self, SIGPIPE},
+            uaccess::{copy_to_user, read_user, write_user}};
+use alloc::collections::BTreeMap;
+use core::slice;
+
+pub const MAX_FDS: usize = 32;
+
+pub const STDIN_FILENO: usize = 0;
+pub const STDOUT_FILENO: usize = 1;
+pub const STDERR_FILENO: usize = 2;
+
+#[derive(Copy, Clone)]
+pub enum Descriptor {
+    Console,
+    PipeRead(usize),
+    PipeWrite(usize),
+    Shm(usize),
+    File(usize),
+}
+
+impl Descriptor {
+    fn acquire(&self) {
+        match *self {
+            Descriptor::PipeRead(id) => pipe::add_reader(id),
+            Descriptor::PipeWrite(id) => pipe::add_writer(id),
+            Descriptor::Shm(id) => shm::add_ref(id),
+            Descriptor::File(id) => file::add_ref(id),
+            Descriptor::Console => {},
+        }
+    }
+
+    fn release(&self) {
+        match *self {
+            Descriptor::PipeRead(id) => pipe::close_reader(id),
+            Descriptor::PipeWrite(id) => pipe::close_writer(id),
+            Descriptor::Shm(id) => shm::drop_ref(id),
+            Descriptor::File(id) => file::close(id),
+            Descriptor::Console => {},
+        }
+    }
+}
+
+pub enum IoResult {
+    Done(usize),
+    Blocked,
+    Pending,
+    Error,
+}
+
+pub enum FdError {
+    BadFd,
+    TooManyFiles,
+    NoProcess,
+}
+
+type FdTable = [Option<Descriptor>; MAX_FDS];
+
+fn default_table() -> FdTable {
+    let mut table = [None; MAX_FDS];
+    table[STDIN_FILENO] = Some(Descriptor::Console);
+    table[STDOUT_FILENO] = Some(Descriptor::Console);
+    table[STDERR_FILENO] = Some(Descriptor::Console);
+    table
+}
+
+static mut FD_TABLES: Option<BTreeMap<u16, FdTable>> = None;
+static mut FD_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        FD_TABLES.replace(BTreeMap::new());
+    }
+}
+
+fn with_table<T, F: FnOnce(&mut FdTable) -> T>(pid: u16, f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        FD_LOCK.spin_lock();
+        if let Some(mut tables) = FD_TABLES.take() {
+            ret = Some(f(tables.entry(pid).or_insert_with(default_table)));
+            FD_TABLES.replace(tables);
+        }
+        FD_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn get(pid: u16, fd: usize) -> Option<Descriptor> {
+    if fd >= MAX_FDS {
+        return None;
+    }
+    with_table(pid, |table| table[fd]).flatten()
+}
+
+pub fn alloc(pid: u16, desc: Descriptor) -> Result<usize, FdError> {
+    with_table(pid, |table| {
+        let fd = table.iter().position(|d| d.is_none()).ok_or(FdError::TooManyFiles)?;
+        table[fd] = Some(desc);
+        Ok(fd)
+    }).unwrap_or(Err(FdError::NoProcess))
+}
+
+pub fn close(pid: u16, fd: usize) -> Result<(), FdError> {
+    if fd >= MAX_FDS {
+        return Err(FdError::BadFd);
+    }
+    let desc = with_table(pid, |table| table[fd].take()).flatten().ok_or(FdError::BadFd)?;
+    desc.release();
+    Ok(())
+}
+
+pub fn fork(parent: u16, child: u16) {
+    let table = with_table(parent, |table| *table).unwrap_or_else(default_table);
+    for desc in table.iter().flatten() {
+        desc.acquire();
+    }
+    with_table(child, |t| *t = table);
+}
+
+pub fn release(pid: u16) {
+    let table = unsafe {
+        FD_LOCK.spin_lock();
+        let table = FD_TABLES.as_mut().and_then(|tables| tables.remove(&pid));
+        FD_LOCK.unlock();
+        table
+    };
+    if let Some(table) = table {
+        for desc in table.iter().flatten() {
+            desc.release();
+        }
+    }
+}
+
+impl From<PipeResult> for IoResult {
+    fn from(result: PipeResult) -> Self {
+        match result {
+            PipeResult::Done(n) => IoResult::Done(n),
+            PipeResult::Blocked => IoResult::Blocked,
+            PipeResult::Broken => IoResult::Error,
+        }
+    }
+}
+
+pub unsafe fn read_console(pid: u16, vaddr: usize, size: usize) -> IoResult {
+    if !session::may_read_tty(pid) || !console::wait_for_stdin(pid) {
+        return IoResult::Blocked;
+    }
+    let mut buffer = vec![0u8; size.min(console::DEFAULT_IN_BUFFER_SIZE + 1)];
+    let read = console::read_stdin(buffer.as_mut_ptr(), buffer.len());
+    match copy_to_user(pid, vaddr, &buffer[..read]) {
+        Ok(()) => IoResult::Done(read),
+        Err(_) => IoResult::Error,
+    }
+}
+
+pub fn read(pid: u16, fd: usize, buffer: *mut u8, size: usize) -> IoResult {
+    match get(pid, fd) {
+        Some(Descriptor::PipeRead(id)) => pipe::read(pid, id, buffer, size).into(),
+        Some(Descriptor::File(id)) => file::read(pid, id, buffer, size),
+        _ => IoResult::Error,
+    }
+}
+
+pub fn poll(pid: u16, fd: usize) -> i16 {
+    match get(pid, fd) {
+        Some(Descriptor::Console) => console::poll(),
+        Some(Descriptor::PipeRead(id)) => pipe::poll_read(id),
+        Some(Descriptor::PipeWrite(id)) => pipe::poll_write(id),
+        Some(Descriptor::File(_)) => POLLIN | POLLOUT,
+        Some(Descriptor::Shm(_)) => 0,
+        None => POLLNVAL,
+    }
+}
+
+pub fn write(pid: u16, fd: usize, buffer: *const u8, size: usize) -> IoResult {
+    match get(pid, fd) {
+        Some(Descriptor::Console) => {
+            console::write_stdout(unsafe { slice::from_raw_parts(buffer, size) });
+            IoResult::Done(size)
+        },
+        Some(Descriptor::PipeWrite(id)) => match pipe::write(pid, id, buffer, size) {
+            PipeResult::Broken => {
+                let _ = signal::send_signal(pid, SIGPIPE);
+                IoResult::Error
+            },
+            result => result.into(),
+        },
+        _ => IoResult::Error,
+    }
+}
+
+pub unsafe fn ioctl(frame: *const TrapFrame, fd: usize, request: usize, arg: usize) -> IoResult {
+    let pid = (*frame).pid as u16;
+    let ok = match (get(pid, fd), request) {
+        (Some(Descriptor::Console), TIOCSCTTY) => session::set_controlling_tty(pid).is_ok(),
+        (Some(Descriptor::Console), TIOCGPGRP) => match session::tcgetpgrp(pid) {
+            Ok(pgid) => write_user(pid, arg, pgid as i32),
+            Err(_) => false,
+        },
+        (Some(Descriptor::Console), TIOCSPGRP) => match read_user::<i32>(pid, arg) {
+            Some(pgid) => session::tcsetpgrp(pid, pgid as u16).is_ok(),
+            None => false,
+        },
+        _ => false,
+    };
+    if ok {
+        IoResult::Done(0)
+    } else {
+        IoResult::Error
+    }
+}
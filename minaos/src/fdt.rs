@@ -0,0 +1,267 @@
+pub const FDT_MAGIC: u32 = 0xd00d_feed;
+
+pub const FDT_BEGIN_NODE: u32 = 1;
+pub const FDT_END_NODE: u32 = 2;
+pub const FDT_PROP: u32 = 3;
+pub const FDT_NOP: u32 = 4;
+pub const FDT_END: u32 = 9;
+
+#[no_mangle]
+pub static mut FDT_ADDR: usize = 0;
+
+#[repr(C)]
+pub struct FdtHeader {
+    magic: u32,
+    totalsize: u32,
+    off_dt_struct: u32,
+    off_dt_strings: u32,
+    off_mem_rsvmap: u32,
+    version: u32,
+    last_comp_version: u32,
+    boot_cpuid_phys: u32,
+    size_dt_strings: u32,
+    size_dt_struct: u32,
+}
+
+pub enum FdtEvent<'a> {
+    BeginNode(&'a str, usize),
+    Prop(&'a str, &'a [u8]),
+    EndNode(usize),
+}
+
+pub struct Fdt {
+    base: *const u8,
+    totalsize: usize,
+    off_struct: usize,
+    off_strings: usize,
+    off_rsvmap: usize,
+}
+
+fn be32(ptr: *const u8) -> u32 {
+    unsafe { u32::from_be(ptr.cast::<u32>().read_unaligned()) }
+}
+
+fn be64(ptr: *const u8) -> u64 {
+    unsafe { u64::from_be(ptr.cast::<u64>().read_unaligned()) }
+}
+
+fn cstr<'a>(ptr: *const u8, max: usize) -> &'a str {
+    unsafe {
+        let mut len = 0;
+        while len < max && *ptr.add(len) != 0 {
+            len += 1;
+        }
+        core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+    }
+}
+
+pub fn prop_u32(value: &[u8], idx: usize) -> Option<u32> {
+    let off = idx * 4;
+    if off + 4 > value.len() {
+        return None;
+    }
+    Some(be32(value[off..].as_ptr()))
+}
+
+pub fn prop_str(value: &[u8]) -> &str {
+    cstr(value.as_ptr(), value.len())
+}
+
+pub fn prop_cells(value: &[u8], idx: usize, cells: usize) -> Option<u64> {
+    (0..cells).try_fold(0u64, |acc, i| Some(acc << 32 | prop_u32(value, idx + i)? as u64))
+}
+
+fn each_reg<F: FnMut(u64, u64)>(value: &[u8], (addr_cells, size_cells): (usize, usize), mut f: F) {
+    let stride = addr_cells + size_cells;
+    if stride == 0 {
+        return;
+    }
+    for i in 0..value.len() / 4 / stride {
+        let addr = prop_cells(value, i * stride, addr_cells);
+        let size = prop_cells(value, i * stride + addr_cells, size_cells);
+        if let (Some(addr), Some(size)) = (addr, size) {
+            f(addr, size);
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum RegNode {
+    Root,
+    Memory,
+    ReservedMemory,
+    Reserved,
+    Other,
+}
+
+impl Fdt {
+    pub fn from_addr(addr: usize) -> Option<Fdt> {
+        if addr == 0 {
+            return None;
+        }
+        let base = addr as *const u8;
+        let header = base as *const FdtHeader;
+        unsafe {
+            if u32::from_be((*header).magic) != FDT_MAGIC {
+                return None;
+            }
+            Some(Fdt {
+                base,
+                totalsize: u32::from_be((*header).totalsize) as usize,
+                off_struct: u32::from_be((*header).off_dt_struct) as usize,
+                off_strings: u32::from_be((*header).off_dt_strings) as usize,
+                off_rsvmap: u32::from_be((*header).off_mem_rsvmap) as usize,
+            })
+        }
+    }
+
+    pub fn boot() -> Option<Fdt> {
+        Self::from_addr(unsafe { FDT_ADDR })
+    }
+
+    pub fn base(&self) -> usize {
+        self.base as usize
+    }
+
+    pub fn size(&self) -> usize {
+        self.totalsize
+    }
+
+    pub fn reserved_entries<F: FnMut(u64, u64)>(&self, mut f: F) {
+        let mut off = self.off_rsvmap;
+        loop {
+            let addr = be64(unsafe { self.base.add(off) });
+            let size = be64(unsafe { self.base.add(off + 8) });
+            if addr == 0 && size == 0 {
+                break;
+            }
+            f(addr, size);
+            off += 16;
+        }
+    }
+
+    pub fn memory<F: FnMut(u64, u64)>(&self, mut f: F) {
+        self.walk_regs(|reserved, addr, size| {
+            if !reserved {
+                f(addr, size);
+            }
+        });
+    }
+
+    pub fn reserved_memory<F: FnMut(u64, u64)>(&self, mut f: F) {
+        self.reserved_entries(&mut f);
+        self.walk_regs(|reserved, addr, size| {
+            if reserved {
+                f(addr, size);
+            }
+        });
+    }
+
+    fn walk_regs<F: FnMut(bool, u64, u64)>(&self, mut f: F) {
+        let mut root_cells = (2, 1);
+        let mut reserved_cells = (2, 1);
+        let mut in_reserved = false;
+        let mut node = RegNode::Other;
+        self.walk(|event| match event {
+            FdtEvent::BeginNode(name, depth) => {
+                node = match depth {
+                    0 => RegNode::Root,
+                    1 if name == "memory" || name.starts_with("memory@") => RegNode::Memory,
+                    1 if name == "reserved-memory" => {
+                        in_reserved = true;
+                        reserved_cells = root_cells;
+                        RegNode::ReservedMemory
+                    },
+                    2 if in_reserved => RegNode::Reserved,
+                    _ => RegNode::Other,
+                };
+            },
+            FdtEvent::Prop(name, value) => match (node, name) {
+                (RegNode::Root, "#address-cells") => root_cells.0 = prop_u32(value, 0).unwrap_or(2) as usize,
+                (RegNode::Root, "#size-cells") => root_cells.1 = prop_u32(value, 0).unwrap_or(1) as usize,
+                (RegNode::ReservedMemory, "#address-cells") => reserved_cells.0 = prop_u32(value, 0).unwrap_or(2) as usize,
+                (RegNode::ReservedMemory, "#size-cells") => reserved_cells.1 = prop_u32(value, 0).unwrap_or(1) as usize,
+                (RegNode::Memory, "reg") => each_reg(value, root_cells, |addr, size| f(false, addr, size)),
+                (RegNode::Reserved, "reg") => each_reg(value, reserved_cells, |addr, size| f(true, addr, size)),
+                _ => {},
+            },
+            FdtEvent::EndNode(depth) => {
+                if depth == 1 {
+                    in_reserved = false;
+                }
+                node = RegNode::Other;
+            },
+        });
+    }
+
+    pub fn walk<F: FnMut(FdtEvent)>(&self, mut f: F) {
+        let mut off = self.off_struct;
+        let mut depth = 0;
+        while off < self.totalsize {
+            let token = be32(unsafe { self.base.add(off) });
+            off += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(unsafe { self.base.add(off) }, self.totalsize - off);
+                    off = (off + name.len() + 1 + 3) & !3;
+                    f(FdtEvent::BeginNode(name, depth));
+                    depth += 1;
+                },
+                FDT_END_NODE => {
+                    depth -= 1;
+                    f(FdtEvent::EndNode(depth));
+                },
+                FDT_PROP => {
+                    let len = be32(unsafe { self.base.add(off) }) as usize;
+                    let nameoff = be32(unsafe { self.base.add(off + 4) }) as usize;
+                    off += 8;
+                    let name = cstr(unsafe { self.base.add(self.off_strings + nameoff) }, self.totalsize);
+                    let value = unsafe { core::slice::from_raw_parts(self.base.add(off), len) };
+                    f(FdtEvent::Prop(name, value));
+                    off = (off + len + 3) & !3;
+                },
+                FDT_NOP => {},
+                _ => break,
+            }
+        }
+    }
+
+    pub fn count_harts(&self) -> usize {
+        let mut count = 0;
+        let mut in_cpus = false;
+        let mut in_cpu = false;
+        let mut is_cpu = false;
+        let mut okay = true;
+        self.walk(|event| match event {
+            FdtEvent::BeginNode(name, depth) => {
+                if depth == 1 && name == "cpus" {
+                    in_cpus = true;
+                } else if in_cpus && depth == 2 && name.starts_with("cpu@") {
+                    in_cpu = true;
+                    is_cpu = false;
+                    okay = true;
+                }
+            },
+            FdtEvent::Prop(name, value) if in_cpu => {
+                if name == "device_type" && prop_str(value) == "cpu" {
+                    is_cpu = true;
+                } else if name == "status" {
+                    let status = prop_str(value);
+                    okay = status == "okay" || status == "ok";
+                }
+            },
+            FdtEvent::Prop(..) => {},
+            FdtEvent::EndNode(depth) => {
+                if in_cpu && depth == 2 {
+                    if is_cpu && okay {
+                        count += 1;
+                    }
+                    in_cpu = false;
+                } else if in_cpus && depth == 1 {
+                    in_cpus = false;
+                }
+            },
+        });
+        count
+    }
+}
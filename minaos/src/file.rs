@@ -0,0 +1,208 @@
+use crate::{console,
+            cred::{self, MAY_READ, MAY_WRITE},
+            fd::{Descriptor, IoResult},
+            fs::{self, FileSystem, Inode, S_IFDIR},
+            lock::Mutex,
+            pipe::{self, PipeResult}};
+use alloc::collections::BTreeMap;
+use core::slice;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+pub enum FileError {
+    NotFound,
+    IsDirectory,
+    Permission,
+    InvalidSeek,
+    BadFile,
+}
+
+struct OpenFile {
+    bdev: usize,
+    inode_num: u32,
+    inode: Inode,
+    offset: u32,
+    refs: usize,
+}
+
+static mut FILES: Option<BTreeMap<usize, OpenFile>> = None;
+static mut FILE_LOCK: Mutex = Mutex::new();
+static mut NEXT_FILE: usize = 1;
+
+pub fn init() {
+    unsafe {
+        FILES.replace(BTreeMap::new());
+    }
+}
+
+fn with_files<T, F: FnOnce(&mut BTreeMap<usize, OpenFile>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        FILE_LOCK.spin_lock();
+        if let Some(mut files) = FILES.take() {
+            ret = Some(f(&mut files));
+            FILES.replace(files);
+        }
+        FILE_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn open(pid: u16, bdev: usize, path: &str) -> Result<usize, FileError> {
+    let (inode_num, inode) = FileSystem::lookup(bdev, path).map_err(|_| FileError::NotFound)?;
+    if inode.mode & S_IFDIR != 0 {
+        return Err(FileError::IsDirectory);
+    }
+    if !cred::may_access(pid, &inode, MAY_READ) {
+        return Err(FileError::Permission);
+    }
+    with_files(|files| {
+        let id = unsafe {
+            let id = NEXT_FILE;
+            NEXT_FILE += 1;
+            id
+        };
+        files.insert(id, OpenFile {
+            bdev,
+            inode_num,
+            inode,
+            offset: 0,
+            refs: 1,
+        });
+        id
+    }).ok_or(FileError::BadFile)
+}
+
+pub fn add_ref(id: usize) {
+    with_files(|files| {
+        if let Some(file) = files.get_mut(&id) {
+            file.refs += 1;
+        }
+    });
+}
+
+pub fn close(id: usize) {
+    with_files(|files| {
+        let unused = match files.get_mut(&id) {
+            Some(file) => {
+                file.refs = file.refs.saturating_sub(1);
+                file.refs == 0
+            },
+            None => false,
+        };
+        if unused {
+            files.remove(&id);
+        }
+    });
+}
+
+pub fn read(pid: u16, id: usize, buffer: *mut u8, size: usize) -> IoResult {
+    let request = with_files(|files| {
+        let file = files.get_mut(&id)?;
+        let count = (size as u32).min(file.inode.size.saturating_sub(file.offset));
+        let offset = file.offset;
+        file.offset += count;
+        Some((file.bdev, file.inode, offset, count))
+    }).flatten();
+    match request {
+        Some((_, _, _, 0)) => IoResult::Done(0),
+        Some((bdev, inode, offset, count)) => {
+            fs::process_read_inode(pid, bdev, inode, buffer, count, offset);
+            IoResult::Pending
+        },
+        None => IoResult::Error,
+    }
+}
+
+pub fn write(id: usize, buffer: *const u8, size: usize) -> usize {
+    let request = with_files(|files| files.get(&id).map(|file| (file.bdev, file.inode_num, file.inode, file.offset))).flatten();
+    let (bdev, inode_num, mut inode, offset) = match request {
+        Some(request) => request,
+        None => return 0,
+    };
+    let written = FileSystem::write(bdev, inode_num, &mut inode, buffer, size as u32, offset);
+    with_files(|files| {
+        if let Some(file) = files.get_mut(&id) {
+            file.inode = inode;
+            file.offset = offset + written;
+        }
+    });
+    written as usize
+}
+
+pub fn may_write(pid: u16, id: usize) -> bool {
+    with_files(|files| files.get(&id).map_or(false, |file| cred::may_access(pid, &file.inode, MAY_WRITE))).unwrap_or(false)
+}
+
+pub unsafe fn sendfile(pid: u16, id: usize, out: Descriptor, offset_ptr: Option<*mut i64>, count: usize) -> IoResult {
+    let request = with_files(|files| {
+        let file = files.get(&id)?;
+        let offset = match offset_ptr {
+            Some(p) => match p.read() {
+                offset if offset >= 0 && offset <= u32::MAX as i64 => offset as u32,
+                _ => return None,
+            },
+            None => file.offset,
+        };
+        Some((file.bdev, file.inode, offset))
+    }).flatten();
+    let (bdev, inode, offset) = match request {
+        Some(request) => request,
+        None => return IoResult::Error,
+    };
+    let count = (count as u32).min(inode.size.saturating_sub(offset));
+    let sink = match out {
+        Descriptor::Console | Descriptor::PipeWrite(_) => out,
+        Descriptor::File(out_id) if out_id != id && may_write(pid, out_id) => out,
+        _ => return IoResult::Error,
+    };
+    if count == 0 {
+        return IoResult::Done(0);
+    }
+    let offset_ptr = offset_ptr.map(|p| p as usize);
+    fs::process_transfer(pid,
+                         bdev,
+                         inode,
+                         offset,
+                         count,
+                         move |buffer, size| match sink {
+                             Descriptor::Console => {
+                                 console::write_stdout(unsafe { slice::from_raw_parts(buffer, size as usize) });
+                                 size
+                             },
+                             Descriptor::PipeWrite(pipe_id) => match pipe::try_write(pipe_id, buffer, size as usize) {
+                                 PipeResult::Done(n) => n as u32,
+                                 _ => 0,
+                             },
+                             Descriptor::File(out_id) => write(out_id, buffer, size as usize) as u32,
+                             _ => 0,
+                         },
+                         move |sent| match offset_ptr {
+                             Some(p) => unsafe { (p as *mut i64).write((offset + sent) as i64) },
+                             None => {
+                                 with_files(|files| {
+                                     if let Some(file) = files.get_mut(&id) {
+                                         file.offset = offset + sent;
+                                     }
+                                 });
+                             },
+                         });
+    IoResult::Pending
+}
+
+pub fn lseek(id: usize, offset: isize, whence: usize) -> Result<usize, FileError> {
+    with_files(|files| {
+        let file = files.get_mut(&id).ok_or(FileError::BadFile)?;
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => file.offset as isize,
+            SEEK_END => file.inode.size as isize,
+            _ => return Err(FileError::InvalidSeek),
+        };
+        let target = base.checked_add(offset).filter(|t| *t >= 0 && *t <= u32::MAX as isize).ok_or(FileError::InvalidSeek)?;
+        file.offset = target as u32;
+        Ok(target as usize)
+    }).unwrap_or(Err(FileError::BadFile))
+}
@@ -1,6 +1,13 @@
-use crate::{buffer::Buffer};
-use alloc::{boxed::Box, collections::BTreeMap, string::String};
-use core::mem::size_of;
+use crate::{buffer::Buffer,
+            cpu::Registers,
+            kthread,
+            process::get_by_pid,
+            waitqueue::WaitQueue};
+#[cfg(not(test))]
+use crate::{syscall::{syscall_block_read, syscall_block_write},
+            time};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::{mem::size_of, slice};
 
 pub const MAGIC: u16 = 0x4d5a;
 pub const BLOCK_SIZE: u32 = 1024;
@@ -9,6 +16,7 @@ pub const S_IFDIR: u16 = 0o040_000;
 pub const S_IFREG: u16 = 0o100_000;
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct SuperBlock {
     pub ninodes: u32,
     pub pad0: u16,
@@ -40,6 +48,7 @@ pub struct Inode {
 }
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub struct DirEntry {
     pub inode: u32,
     pub name: [u8; 60]
@@ -47,52 +56,46 @@ pub struct DirEntry {
 
 impl FileSystem {
     pub fn get_inode(bdev: usize, inode_num: u32) -> Option<Inode> {
-        let mut buffer = Buffer::new(1024);
-        let super_block = unsafe {&*(buffer.get_mut() as *mut SuperBlock)};
-        let inode = buffer.get_mut as *mut Inode;
-        syc_ready(bdev, buffer.get_mut(), 512, 1024);
-        if super_block.magic == MAGIC {
-            let inode_offset = (2 + super_block.imap_blocks + super_block.zmap_blocks) as usize * BLOCK_SIZE as usize + ((inode_num as usize - 1) / (BLOCK_SIZE as usize / size_of::<Inode>())) * BLOCK_SIZE as usize;
-            syc_read(bdev, buffer.get_mut(), 1024, inode_offset as u32);
-            let read_this_node = (inode_num as usize - 1) % (BLOCK_SIZE as usize / size_of::<Inode>());
-            return unsafe {Some(*(inode.add(read_this_node)))};
+        let super_block = super_block(bdev)?;
+        if inode_num == 0 || inode_num > super_block.ninodes {
+            return None;
         }
-        None
+        let (block, offset) = inode_location(&super_block, inode_num);
+        let mut buffer = new_block();
+        syc_read(bdev, buffer.get_mut(), BLOCK_SIZE, block);
+        buffer.read_at::<Inode>(offset)
     }
 }
 
 impl FileSystem {
-    fn cache_at(btm: &mut BTreeMap<String, Inode>, cwd: &String, inode_num: u32, bdev: usize) {
+    fn cache_at(btm: &mut BTreeMap<String, (u32, Inode)>, cwd: &String, inode_num: u32, bdev: usize) {
         let ino = Self::get_inode(bdev, inode_num).unwrap();
-        let mut buf = Buffer::new((ino.size + BLOCK_SIZE - 1) & !BLOCK_SIZE) as usize);
-        let dirents = buf.get() as *const DirEntry;
+        let mut buf = Buffer::new(((ino.size + BLOCK_SIZE - 1) & !(BLOCK_SIZE - 1)) as usize);
         let sz = Self::read(bdev, &ino, buf.get_mut(), BLOCK_SIZE, 0);
         let num_dirents = sz as usize / size_of::<DirEntry>();
         for i in 2..num_dirents {
-            unsafe {
-                let ref d = *dirents.add(i);
-                let d_ino = Self::get_inode(bdev, d.inode).unwrap();
-                let mut new_cwd = String::with_capacity(120);
-                for i in cwd.bytes() {
-                    new_cwd.push(i as char);
-                }
-                
-                if inode_num != 1 {
-                    new_cwd.push('/');
-                }
-
-                for i in 0..60 {
-                    if d.name[i] == 0 {
-                        break;
-                    }
-                    new_cwd.push(d.name[i] as char);
-                }
-                new_cwd.shrink_to_fit();
-                if d_ino.mode & S_IFDIR != 0 {
-                    Self::cache_at(btm, &new_cwd, d.inode, bdev);
-                } else {
-                    btm.insert(new_cwd, d_ino);
-                }
+            let d = match buf.read_at::<DirEntry>(i * size_of::<DirEntry>()) {
+                Some(d) => d,
+                None => break,
+            };
+            let d_ino = Self::get_inode(bdev, d.inode).unwrap();
+            let mut new_cwd = String::with_capacity(120);
+            for i in cwd.bytes() {
+                new_cwd.push(i as char);
+            }
+
+            if inode_num != 1 {
+                new_cwd.push('/');
+            }
+
+            for &c in d.name.iter().take_while(|&&c| c != 0) {
+                new_cwd.push(c as char);
+            }
+            new_cwd.shrink_to_fit();
+            if d_ino.mode & S_IFDIR != 0 {
+                Self::cache_at(btm, &new_cwd, d.inode, bdev);
+            } else {
+                btm.insert(new_cwd, (d.inode, d_ino));
             }
         }
     }
@@ -111,179 +114,347 @@ impl FileSystem {
         }
     }
 
-    pub fn open(bdev: usize, path: &str) -> Result<Inode, FsError> {
+    pub fn paths(bdev: usize) -> Vec<String> {
+        let mut ret = Vec::new();
         if let Some(cache) = unsafe {MFS_INODE_CACHE[bdev - 1].take()} {
-            ret = Ok(*inode);
-        } else {
-            ret = Err(FsError::FileNotFound);
-        }
-        unsafe {
-            MFS_INODE_CACHE[bdev - 1].replace(cache);
+            for path in cache.keys() {
+                ret.push(path.clone());
+            }
+            unsafe {
+                MFS_INODE_CACHE[bdev - 1].replace(cache);
+            }
         }
         ret
-    } else {
-        Err(FsError::FileNotFound)
     }
-}
-
-pub fn read(bdev: usize, inode: &Inode, buffer: *mut u8, size: u32, offset: u32) -> u32 {
-    let mut blocks_seen = 0u32;
-    let offset_block = offset / BLOCK_SIZE;
-    let mut offset_byte = offset % BLOCK_SIZE;
-    let mut bytes_left = if size > inode.size {
-        inode.size
-    } else {
-        size
-    };
 
-    let mut bytes_read = 0u32;
-    let mut block_buffer = Buffer::new(BLOCK_SIZE as usize);
-
-    let mut indirect_buffer = Buffer::new(BLOCK_SIZE as usize);
-    let mut iindirect_buffer = Buffer::new(BLOCK_SIZE as usize);
-    let mut iiindirect_buffer = Buffer::new(BLOCK_SIZE as usize);
+    pub fn is_mounted(bdev: usize) -> bool {
+        unsafe {MFS_INODE_CACHE[bdev - 1].is_some()}
+    }
 
-    let izones = indirect_buffer.get() as *const u32;
-    let iizones = iindirect_buffer.get() as *const u32;
-    let iiizones = iiindirect_buffer.get() as *const u32;
+    pub fn open(bdev: usize, path: &str) -> Result<Inode, FsError> {
+        Self::lookup(bdev, path).map(|(_, inode)| inode)
+    }
 
-    for i in 0..7 {
-        if inode.zones[i] == 0 {
-            continue;
+    pub fn lookup(bdev: usize, path: &str) -> Result<(u32, Inode), FsError> {
+        if let Some(cache) = unsafe {MFS_INODE_CACHE[bdev - 1].take()} {
+            let ret = cache.get(path).copied().ok_or(FsError::FileNotFound);
+            unsafe {
+                MFS_INODE_CACHE[bdev - 1].replace(cache);
+            }
+            ret
+        } else {
+            Err(FsError::FileNotFound)
         }
-        if offset_block <= blocks_seen {
-            let zone_offset = inode.zones[i] * BLOCK_SIZE;
-            syc_read(bdev, block_buffer.get_mut(), BLOCK_SIZE, zone_offset);
+    }
 
-            let read_this_many = if BLOCK_SIZE - offset_byte > bytes_left {
-                bytes_left
-            } else {
-                BLOCK_SIZE - offset_byte
-            };
-            
+    fn update_cache(bdev: usize, inode_num: u32, inode: &Inode) {
+        if let Some(mut cache) = unsafe {MFS_INODE_CACHE[bdev - 1].take()} {
+            for entry in cache.values_mut().filter(|entry| entry.0 == inode_num) {
+                entry.1 = *inode;
+            }
             unsafe {
-                memcpy(buffer.add(bytes_read as usize), block_buffer.get().add(offset_byte as usize), read_this_many as usize);
+                MFS_INODE_CACHE[bdev - 1].replace(cache);
             }
+        }
+    }
 
-            offset_byte = 0;
-            bytes_read += read_this_many;
-            bytes_left -= read_this_many;
-            if bytes_left == 0 {
-                return bytes_read;
+    fn find_entry(bdev: usize, dir: &Inode, name: &str) -> Option<u32> {
+        let mut buf = Buffer::new(((dir.size + BLOCK_SIZE - 1) & !(BLOCK_SIZE - 1)) as usize);
+        let sz = Self::read(bdev, dir, buf.get_mut(), dir.size, 0);
+        (0..sz as usize / size_of::<DirEntry>())
+            .filter_map(|i| buf.read_at::<DirEntry>(i * size_of::<DirEntry>()))
+            .find(|d| d.inode != 0 && d.name.iter().take_while(|&&c| c != 0).eq(name.as_bytes().iter()))
+            .map(|d| d.inode)
+    }
+
+    fn lookup_dir(bdev: usize, path: &str) -> Result<(u32, Inode), FsError> {
+        let mut inode_num = 1;
+        let mut inode = Self::get_inode(bdev, inode_num).ok_or(FsError::FileNotFound)?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if inode.mode & S_IFDIR == 0 {
+                return Err(FsError::IsFile);
             }
+            inode_num = Self::find_entry(bdev, &inode, name).ok_or(FsError::FileNotFound)?;
+            inode = Self::get_inode(bdev, inode_num).ok_or(FsError::FileNotFound)?;
         }
-        blocks_seen += 1;
+        if inode.mode & S_IFDIR == 0 {
+            return Err(FsError::IsFile);
+        }
+        Ok((inode_num, inode))
     }
 
-    if inode.zones[7] != 0 {
-        syc_read(bdev, indirect_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * inode.zones[7]);
-        let izones = indirect_buffer.get() as *conts u32;
-        for i in 0..NUM_IPTRS {
+    pub fn create(bdev: usize, path: &str) -> Result<(u32, Inode), FsError> {
+        if let Ok(found) = Self::lookup(bdev, path) {
+            return Ok(found);
+        }
+        if !Self::is_mounted(bdev) {
+            return Err(FsError::FileNotFound);
+        }
+        let (dir, name) = path.rsplit_once('/').ok_or(FsError::FileNotFound)?;
+        if name.is_empty() || name.len() > 60 {
+            return Err(FsError::FileNotFound);
+        }
+        let (dir_num, mut dir_inode) = Self::lookup_dir(bdev, dir)?;
+        if Self::find_entry(bdev, &dir_inode, name).is_some() {
+            return Err(FsError::IsDirectory);
+        }
+        let super_block = super_block(bdev).ok_or(FsError::FileNotFound)?;
+        let inode_num = alloc_bit(bdev, 2, super_block.imap_blocks as u32, super_block.ninodes + 1).ok_or(FsError::NoSpace)?;
+        let now = now();
+        let inode = Inode {
+            mode: S_IFREG | 0o644,
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            zones: [0; 10]
+        };
+        put_inode(bdev, &super_block, inode_num, &inode);
+        let mut entry = DirEntry {
+            inode: inode_num,
+            name: [0; 60]
+        };
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+        let entry_size = size_of::<DirEntry>() as u32;
+        let offset = dir_inode.size;
+        if Self::write(bdev, dir_num, &mut dir_inode, &entry as *const DirEntry as *const u8, entry_size, offset) != entry_size {
+            free_bit(bdev, 2, inode_num);
+            return Err(FsError::NoSpace);
+        }
+        if let Some(mut cache) = unsafe {MFS_INODE_CACHE[bdev - 1].take()} {
+            cache.insert(String::from(path), (inode_num, inode));
             unsafe {
-                if izones.add(i).read() != 0 {
-                    if offset_block <= blocks_seen {
-                        syc_read(bdev, block_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * izones.add(i).read());
-                        let read_this_many = if BLOCK_SIZE - offset_byte > bytes_left {
-                            bytes_left
-                        }
-                        else {
-                            BLOCK_SIZE - offset_byte
-                        };
-                        memcpy(buffer.add(bytes_read as usize), block_buffer.get().add(offset_byte as usize), read_this_many as usize);
-                        bytes_read += read_this_many;
-                        bytes_left -= read_this_many;
-                        offset_byte = 0;
-                        if bytes_left == 0 {
-                            return bytes_read;
-                        }
-                    }
-                    block_seen += 1;
-                }
+                MFS_INODE_CACHE[bdev - 1].replace(cache);
             }
         }
+        Ok((inode_num, inode))
     }
+}
 
-    if inode.zones[8] != 0 {
-        syc_read(bdev, indirect_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * inode.zones[8]);
-        unsafe {
-            for i in 0..NUM_IPTRS {
-                if izones.add(i).read() != 0 {
-                    syc_read(bdev, iindirect_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * izones.add(i).read());
-                    for j in 0..NUM_IPTRS {
-                        if iizones.add(j).read() != 0 {
-                            if offset_block <= block_seen {
-                                syc_read(bdev, block_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * iizones.add(j).read());
-                                let read_this_many = if BLOCK_SIZE - offset_byte > bytes_left {
-                                    bytes_left
-                                }
-                                else {
-                                    BLOCK_SIZE - offset_byte
-                                };
-                                memcpy(
-                                    buffer.add(bytes_read as usize),
-                                    block_buffer.get().add(offset_byte as usize),
-                                    read_this_many as usize
-                                );
-                                bytes_read += read_this_many;
-                                bytes_left -= read_this_many;
-                                offset_byte = 0;
-                                if bytes_left == 0 {
-                                    return bytes_read;
-                                }
-                            }
-                            block_seen += 1;
-                        }
-                    }
-                }
+fn super_block(bdev: usize) -> Option<SuperBlock> {
+    let mut buffer = new_block();
+    syc_read(bdev, buffer.get_mut(), 512, 1024);
+    buffer.read_at::<SuperBlock>(0).filter(|super_block| super_block.magic == MAGIC)
+}
+
+fn inode_location(super_block: &SuperBlock, inode_num: u32) -> (u32, usize) {
+    let per_block = BLOCK_SIZE / size_of::<Inode>() as u32;
+    let first = 2 + super_block.imap_blocks as u32 + super_block.zmap_blocks as u32;
+    let block = (first + (inode_num - 1) / per_block) * BLOCK_SIZE;
+    (block, (inode_num - 1) as usize % per_block as usize * size_of::<Inode>())
+}
+
+fn put_inode(bdev: usize, super_block: &SuperBlock, inode_num: u32, inode: &Inode) {
+    let (block, offset) = inode_location(super_block, inode_num);
+    let mut buffer = new_block();
+    syc_read(bdev, buffer.get_mut(), BLOCK_SIZE, block);
+    if buffer.write_at(offset, *inode) {
+        syc_write(bdev, buffer.get_mut(), BLOCK_SIZE, block);
+    }
+}
+
+fn alloc_bit(bdev: usize, first_block: u32, nblocks: u32, limit: u32) -> Option<u32> {
+    let mut map = new_block();
+    for block in 0..nblocks {
+        let map_offset = (first_block + block) * BLOCK_SIZE;
+        syc_read(bdev, map.get_mut(), BLOCK_SIZE, map_offset);
+        for byte in 0..BLOCK_SIZE as usize {
+            if map[byte] == 0xff {
+                continue;
+            }
+            let bit = (!map[byte]).trailing_zeros();
+            let num = (block * BLOCK_SIZE + byte as u32) * 8 + bit;
+            if num >= limit {
+                return None;
             }
+            map[byte] |= 1 << bit;
+            syc_write(bdev, map.get_mut(), BLOCK_SIZE, map_offset);
+            return Some(num);
         }
     }
+    None
+}
 
-    if inode.zones[9] != 0 {
-        syc_read(bdev, indirect_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * inode.zones[9]);
-        unsafe {
-            for i in 0..NUM_IPTRS {
-                if izones.add(i).read() != 0 {
-                    syc_read(bdev, iindirect_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * izones.add(i).read());
-                    for j in 0..NUM_IPTRS {
-                        if iizones.add(j).read() != 0 {
-                            syc_read(bdev, iiindirect_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * iizones.add(j).read());
-                            for k in 0..NUM_IPTRS {
-                                if iiizones.add(k).read() != 0 {
-                                    if offset_block <= block_seen {
-                                        syc_read(bdev, block_buffer.get_mut(), BLOCK_SIZE, BLOCK_SIZE * iiizones.add(k).read());
-                                        let read_this_many = if BLOCK_SIZE - offset_byte > bytes_left {
-                                            bytes_left
-                                        }
-                                        else {
-                                            BLOCK_SIZE - offset_byte
-                                        };
-                                        memcpy(
-                                            buffer.add(bytes_read as usize),
-                                            block_buffer.get().add(offset_byte as usize),
-                                            read_this_many as usize
-                                        );
-                                        bytes_read += read_this_many;
-                                        bytes_left -= read_this_many;
-                                        offset_byte = 0;
-                                        if bytes_left == 0 {
-                                            return bytes_read;
-                                        }
-                                    }
-                                    block_seen += 1;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+fn free_bit(bdev: usize, first_block: u32, num: u32) {
+    let mut map = new_block();
+    let map_offset = (first_block + num / (BLOCK_SIZE * 8)) * BLOCK_SIZE;
+    let byte = (num % (BLOCK_SIZE * 8) / 8) as usize;
+    syc_read(bdev, map.get_mut(), BLOCK_SIZE, map_offset);
+    map[byte] &= !(1 << (num % 8));
+    syc_write(bdev, map.get_mut(), BLOCK_SIZE, map_offset);
+}
+
+fn alloc_zone(bdev: usize, super_block: &SuperBlock) -> Option<u32> {
+    let first_zone = super_block.first_data_zone as u32;
+    let bit = alloc_bit(bdev, 2 + super_block.imap_blocks as u32, super_block.zmap_blocks as u32, super_block.zones - first_zone + 1)?;
+    let zone = first_zone + bit - 1;
+    let mut zeroed = new_block();
+    zeroed.as_mut_slice().fill(0);
+    syc_write(bdev, zeroed.get_mut(), BLOCK_SIZE, zone * BLOCK_SIZE);
+    Some(zone)
+}
+
+fn indirect_zone(bdev: usize, super_block: &SuperBlock, table: &mut u32, path: &[usize]) -> Option<u32> {
+    let (&slot, rest) = path.split_first()?;
+    if *table == 0 {
+        *table = alloc_zone(bdev, super_block)?;
+    }
+    let mut buffer = new_block();
+    syc_read(bdev, buffer.get_mut(), BLOCK_SIZE, *table * BLOCK_SIZE);
+    let old = buffer.read_at::<u32>(slot * size_of::<u32>()).unwrap_or(0);
+    let mut next = old;
+    let zone = if rest.is_empty() {
+        if next == 0 {
+            next = alloc_zone(bdev, super_block)?;
         }
+        next
+    } else {
+        indirect_zone(bdev, super_block, &mut next, rest)?
+    };
+    if next != old && buffer.write_at(slot * size_of::<u32>(), next) {
+        syc_write(bdev, buffer.get_mut(), BLOCK_SIZE, *table * BLOCK_SIZE);
     }
-    bytes_read
+    Some(zone)
 }
 
-pub fn write(&mut self, _desc: &Inode: _buffer: *const u8, _offset: u32, _size: u32) -> u32 {
-    0
+fn zone_at(bdev: usize, super_block: &SuperBlock, inode: &mut Inode, index: u32) -> Option<u32> {
+    let index = index as usize;
+    if index < 7 {
+        if inode.zones[index] == 0 {
+            inode.zones[index] = alloc_zone(bdev, super_block)?;
+        }
+        return Some(inode.zones[index]);
+    }
+    let index = index - 7;
+    if index < NUM_IPTRS {
+        return indirect_zone(bdev, super_block, &mut inode.zones[7], &[index]);
+    }
+    let index = index - NUM_IPTRS;
+    if index < NUM_IPTRS * NUM_IPTRS {
+        return indirect_zone(bdev, super_block, &mut inode.zones[8], &[index / NUM_IPTRS, index % NUM_IPTRS]);
+    }
+    None
+}
+
+fn new_block() -> Buffer {
+    Buffer::new_aligned(BLOCK_SIZE as usize, BLOCK_SIZE as usize)
+}
+
+fn walk_indirect<F: FnMut(u32) -> bool>(bdev: usize, zone: u32, depth: usize, tables: &mut [Buffer], f: &mut F) -> bool {
+    let (table, rest) = match tables.split_first_mut() {
+        Some(split) => split,
+        None => return false,
+    };
+    syc_read(bdev, table.get_mut(), BLOCK_SIZE, BLOCK_SIZE * zone);
+    for i in 0..NUM_IPTRS {
+        let next = table.read_at::<u32>(i * size_of::<u32>()).unwrap_or(0);
+        if next == 0 {
+            continue;
+        }
+        let more = if depth == 0 {
+            f(next)
+        } else {
+            walk_indirect(bdev, next, depth - 1, rest, f)
+        };
+        if !more {
+            return false;
+        }
+    }
+    true
+}
+
+fn for_each_zone<F: FnMut(u32) -> bool>(bdev: usize, inode: &Inode, mut f: F) {
+    for &zone in inode.zones[..7].iter() {
+        if zone != 0 && !f(zone) {
+            return;
+        }
+    }
+    let mut tables = [new_block(), new_block(), new_block()];
+    for (depth, &zone) in inode.zones[7..].iter().enumerate() {
+        if zone != 0 && !walk_indirect(bdev, zone, depth, &mut tables, &mut f) {
+            return;
+        }
+    }
+}
+
+pub fn read(bdev: usize, inode: &Inode, buffer: *mut u8, size: u32, offset: u32) -> u32 {
+    let size = size.min(inode.size) as usize;
+    if size == 0 || buffer.is_null() {
+        return 0;
+    }
+    let out = unsafe { slice::from_raw_parts_mut(buffer, size) };
+    let offset_block = offset / BLOCK_SIZE;
+    let mut offset_byte = (offset % BLOCK_SIZE) as usize;
+    let mut blocks_seen = 0u32;
+    let mut bytes_read = 0usize;
+    let mut block_buffer = new_block();
+
+    for_each_zone(bdev, inode, |zone| {
+        if offset_block <= blocks_seen {
+            syc_read(bdev, block_buffer.get_mut(), BLOCK_SIZE, zone * BLOCK_SIZE);
+            let read_this_many = (BLOCK_SIZE as usize - offset_byte).min(size - bytes_read);
+            out[bytes_read..bytes_read + read_this_many]
+                .copy_from_slice(&block_buffer.as_slice()[offset_byte..offset_byte + read_this_many]);
+            offset_byte = 0;
+            bytes_read += read_this_many;
+        }
+        blocks_seen += 1;
+        bytes_read < size
+    });
+    bytes_read as u32
+}
+
+fn write_zone(bdev: usize, zone: u32, block_buffer: &mut Buffer, data: &[u8], offset_byte: u32) {
+    let zone_offset = zone * BLOCK_SIZE;
+    if offset_byte != 0 || data.len() != BLOCK_SIZE as usize {
+        syc_read(bdev, block_buffer.get_mut(), BLOCK_SIZE, zone_offset);
+    }
+    if block_buffer.copy_from_slice(offset_byte as usize, data) {
+        syc_write(bdev, block_buffer.get_mut(), BLOCK_SIZE, zone_offset);
+    }
+}
+
+pub fn write(bdev: usize, inode_num: u32, inode: &mut Inode, buffer: *const u8, size: u32, offset: u32) -> u32 {
+    if size == 0 || buffer.is_null() {
+        return 0;
+    }
+    let super_block = match super_block(bdev) {
+        Some(super_block) => super_block,
+        None => return 0,
+    };
+    let data = unsafe { slice::from_raw_parts(buffer, size as usize) };
+    let zones = inode.zones;
+    let mut bytes_written = 0u32;
+    let mut block_buffer = new_block();
+
+    while bytes_written < size {
+        let position = offset + bytes_written;
+        let offset_byte = position % BLOCK_SIZE;
+        let zone = match zone_at(bdev, &super_block, inode, position / BLOCK_SIZE) {
+            Some(zone) => zone,
+            None => break,
+        };
+        let write_this_many = (BLOCK_SIZE - offset_byte).min(size - bytes_written);
+        let start = bytes_written as usize;
+        write_zone(bdev, zone, &mut block_buffer, &data[start..start + write_this_many as usize], offset_byte);
+        bytes_written += write_this_many;
+    }
+    if bytes_written > 0 {
+        let now = now();
+        inode.mtime = now;
+        inode.ctime = now;
+        if offset + bytes_written > inode.size {
+            inode.size = offset + bytes_written;
+        }
+    }
+    if bytes_written > 0 || inode.zones != zones {
+        put_inode(bdev, &super_block, inode_num, inode);
+        FileSystem::update_cache(bdev, inode_num, inode);
+    }
+    bytes_written
 }
 
 pub fn stat(&self, inode: &Inode) -> Stat {
@@ -291,52 +462,109 @@ pub fn stat(&self, inode: &Inode) -> Stat {
         mode: inode.mode,
         size: inode.size,
         uid: inode.uid,
-        gid: inode.gid
+        gid: inode.gid,
+        atime: inode.atime,
+        mtime: inode.mtime,
+        ctime: inode.ctime
     }
 }
 
+#[cfg(not(test))]
+fn now() -> u32 {
+    time::wall_secs() as u32
+}
+
+#[cfg(test)]
+fn now() -> u32 {
+    0
+}
+
+#[cfg(not(test))]
 fn syc_read(bdev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
     syscall_block_read(bdev, buffer, size, offset)
 }
 
-struct ProcArgs {
-    pub pid: u16,
-    pub dev: usize,
-    pub buffer: *mut u8,
-    pub size: u32,
-    pub offset: u32,
-    pub node: u32
+#[cfg(not(test))]
+fn syc_write(bdev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
+    syscall_block_write(bdev, buffer, size, offset)
 }
 
-fn read_proc(args_addr: usize) {
-    let args = unsafe {Box::from_raw(args_addr as *mut ProcArgs)};
+#[cfg(test)]
+fn syc_read(_bdev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
+    unsafe { tests::DISK.as_ptr().add(offset as usize).copy_to_nonoverlapping(buffer, size as usize) };
+    0
+}
 
-    let inode = FileSystem::get_inode(args.dev, args.node);
-    let bytes = FileSystem::read(args.dev, &inode.unwrap(), args.buffer, args.size, args.offset);
+#[cfg(test)]
+fn syc_write(_bdev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
+    unsafe { tests::DISK.as_mut_ptr().add(offset as usize).copy_from_nonoverlapping(buffer, size as usize) };
+    0
+}
+
+pub static FS_WAIT: WaitQueue = WaitQueue::new("fs");
 
+fn complete_read(pid: u16, bytes: u32) {
     unsafe {
-        let ptr = get_by_pid(args.pid);
+        let ptr = get_by_pid(pid);
         if !ptr.is_null() {
             (*(*ptr).frame).regs[Registers::A0 as usize] = bytes as usize;
         }
     }
-    set_running(args.pid);
+    FS_WAIT.wake(pid);
 }
 
 pub fn process_read(pid: u16, dev: usize, node: u32, buffer: *mut u8, size: u32, offset: u32) {
-    let args = ProcArgs {
-        pid, dev, buffer, size, offset, node
-    };
-    let boxed_args = Box::new(args);
-    set_waiting(pid);
-    let _ = add_kernel_process_args(read_proc, Box::into_raw(boxed_args) as usize);
+    let buffer = buffer as usize;
+    FS_WAIT.sleep(pid);
+    let _ = kthread::spawn("fs read", move || {
+        let inode = FileSystem::get_inode(dev, node);
+        let bytes = FileSystem::read(dev, &inode.unwrap(), buffer as *mut u8, size, offset);
+        complete_read(pid, bytes);
+    });
+}
+
+pub fn process_read_inode(pid: u16, dev: usize, inode: Inode, buffer: *mut u8, size: u32, offset: u32) {
+    let buffer = buffer as usize;
+    FS_WAIT.sleep(pid);
+    let _ = kthread::spawn("fs read", move || {
+        let bytes = FileSystem::read(dev, &inode, buffer as *mut u8, size, offset);
+        complete_read(pid, bytes);
+    });
+}
+
+pub fn process_transfer<F, D>(pid: u16, dev: usize, inode: Inode, offset: u32, count: u32, mut sink: F, done: D)
+    where F: FnMut(*const u8, u32) -> u32 + Send + 'static,
+          D: FnOnce(u32) + Send + 'static
+{
+    FS_WAIT.sleep(pid);
+    let _ = kthread::spawn("fs transfer", move || {
+        let mut buffer = new_block();
+        let mut sent = 0;
+        while sent < count {
+            let chunk = (count - sent).min(BLOCK_SIZE);
+            let read = FileSystem::read(dev, &inode, buffer.get_mut(), chunk, offset + sent);
+            if read == 0 {
+                break;
+            }
+            let written = sink(buffer.get(), read);
+            sent += written;
+            if written < read {
+                break;
+            }
+        }
+        done(sent);
+        complete_read(pid, sent);
+    });
 }
 
 pub struct Stat {
     pub mode: u16,
     pub size: u32,
     pub uid: u16,
-    pub gid: u16
+    pub gid: u16,
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32
 }
 
 pub enum FsError {
@@ -344,5 +572,80 @@ pub enum FsError {
     FileNotFound,
     Permission,
     IsFile,
-    IsDirectory
-}
\ No newline at end of file
+    IsDirectory,
+    NoSpace
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISK_BLOCKS: usize = 64;
+
+    pub(super) static mut DISK: [u8; DISK_BLOCKS * BLOCK_SIZE as usize] = [0; DISK_BLOCKS * BLOCK_SIZE as usize];
+
+    unsafe fn put<T: Copy>(offset: usize, val: T) {
+        (DISK.as_mut_ptr().add(offset) as *mut T).write_unaligned(val);
+    }
+
+    unsafe fn format() {
+        let block = BLOCK_SIZE as usize;
+        put(block, SuperBlock {
+            ninodes: 16,
+            pad0: 0,
+            imap_blocks: 1,
+            zmap_blocks: 1,
+            first_data_zone: 5,
+            log_zone_size: 0,
+            pad1: 0,
+            max_size: u32::MAX,
+            zones: DISK_BLOCKS as u32,
+            magic: MAGIC,
+            pad2: 0,
+            block_size: BLOCK_SIZE as u16,
+            disk_version: 0
+        });
+        DISK[2 * block] = 0b11;
+        DISK[3 * block] = 0b11;
+        let mut zones = [0; 10];
+        zones[0] = 5;
+        put(4 * block, Inode {
+            mode: S_IFDIR | 0o755,
+            nlinks: 2,
+            uid: 0,
+            gid: 0,
+            size: 2 * size_of::<DirEntry>() as u32,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            zones
+        });
+        for (i, name) in [&b"."[..], &b".."[..]].iter().enumerate() {
+            let mut entry = DirEntry {
+                inode: 1,
+                name: [0; 60]
+            };
+            entry.name[..name.len()].copy_from_slice(name);
+            put(5 * block + i * size_of::<DirEntry>(), entry);
+        }
+    }
+
+    #[test]
+    fn test_write_past_end() {
+        unsafe { format() };
+        FileSystem::init(1);
+        let (inode_num, mut inode) = FileSystem::create(1, "/grow").ok().unwrap();
+        let head = [7u8; 10];
+        assert_eq!(write(1, inode_num, &mut inode, head.as_ptr(), head.len() as u32, 0), 10);
+        let tail: Vec<u8> = (0..9000u32).map(|i| i as u8).collect();
+        assert_eq!(write(1, inode_num, &mut inode, tail.as_ptr(), tail.len() as u32, 10), 9000);
+
+        let on_disk = FileSystem::get_inode(1, inode_num).unwrap();
+        assert_eq!(on_disk.size, 9010);
+        assert_ne!(on_disk.zones[7], 0);
+        assert_eq!(FileSystem::open(1, "/grow").ok().unwrap().size, 9010);
+        let mut back = vec![0u8; 9010];
+        assert_eq!(read(1, &on_disk, back.as_mut_ptr(), 9010, 0), 9010);
+        assert_eq!(&back[..10], &head[..]);
+        assert_eq!(&back[10..], &tail[..]);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{cpu::Registers,
+            process::{get_by_pid, set_running, set_waiting},
+            spinlock::SpinLock,
+            timer::{self, TimerCallback}};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+
+pub const FUTEX_BUCKETS: usize = 64;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum FutexKey {
+    Private(u16, usize),
+    Shared(usize),
+}
+
+impl FutexKey {
+    fn bucket(&self) -> &'static Bucket {
+        let hash = match *self {
+            FutexKey::Private(pid, addr) => (addr >> 2) ^ (pid as usize).wrapping_mul(0x9e37),
+            FutexKey::Shared(addr) => addr >> 2,
+        };
+        &BUCKETS[hash % FUTEX_BUCKETS]
+    }
+}
+
+pub enum FutexError {
+    WouldBlock,
+}
+
+struct Waiter {
+    key: FutexKey,
+    pid: u16,
+    timer: Option<u64>,
+}
+
+struct Bucket {
+    waiters: SpinLock<Vec<Waiter>>,
+}
+
+const EMPTY_BUCKET: Bucket = Bucket { waiters: SpinLock::new("futex", Vec::new()) };
+
+static BUCKETS: [Bucket; FUTEX_BUCKETS] = [EMPTY_BUCKET; FUTEX_BUCKETS];
+
+pub fn wait(pid: u16, key: FutexKey, value: *const u32, expected: u32, timeout_ns: Option<u64>) -> Result<(), FutexError> {
+    let mut waiters = key.bucket().waiters.lock();
+    let current = unsafe { (*(value as *const AtomicU32)).load(Ordering::SeqCst) };
+    if current != expected {
+        return Err(FutexError::WouldBlock);
+    }
+    let timer = timeout_ns.map(|ns| timer::add_timer(ns, TimerCallback::Call(expire, pid as usize)));
+    waiters.push(Waiter { key, pid, timer });
+    set_waiting(pid);
+    Ok(())
+}
+
+pub fn wake(key: FutexKey, count: usize) -> usize {
+    let mut woken = Vec::new();
+    {
+        let mut waiters = key.bucket().waiters.lock();
+        let mut i = 0;
+        while i < waiters.len() && woken.len() < count {
+            if waiters[i].key == key {
+                woken.push(waiters.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+    }
+    for waiter in woken.iter() {
+        if let Some(id) = waiter.timer {
+            timer::cancel_timer(id);
+        }
+        set_running(waiter.pid);
+    }
+    woken.len()
+}
+
+fn take_waiter(pid: u16) -> Option<Waiter> {
+    for bucket in BUCKETS.iter() {
+        let mut waiters = bucket.waiters.lock();
+        if let Some(idx) = waiters.iter().position(|w| w.pid == pid) {
+            return Some(waiters.remove(idx));
+        }
+    }
+    None
+}
+
+fn expire(arg: usize) {
+    let pid = arg as u16;
+    if take_waiter(pid).is_some() {
+        unsafe {
+            let p = get_by_pid(pid);
+            if !p.is_null() {
+                (*(*p).frame).regs[Registers::A0 as usize] = -1isize as usize;
+            }
+        }
+        set_running(pid);
+    }
+}
+
+pub fn release(pid: u16) {
+    if let Some(Waiter { timer: Some(id), .. }) = take_waiter(pid) {
+        timer::cancel_timer(id);
+    }
+}
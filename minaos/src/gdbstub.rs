@@ -0,0 +1,434 @@
+use crate::{cpu::{Registers, TrapFrame},
+            uart::Uart};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub const GDB_UART_BASE: usize = 0x1000_0100;
+pub const GDB_MAGIC: &[u8] = b"\x1b[gdb";
+pub const MAX_PACKET: usize = 4096;
+
+pub const SIGINT: u8 = 2;
+pub const SIGTRAP: u8 = 5;
+pub const SIGSEGV: u8 = 11;
+
+const EBREAK: u32 = 0x0010_0073;
+const C_EBREAK: u16 = 0x9002;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static MAGIC_POS: AtomicUsize = AtomicUsize::new(0);
+
+struct StepBreakpoint {
+    addr: usize,
+    saved: u32,
+    len: usize,
+}
+
+static mut STEP_BREAKPOINTS: [Option<StepBreakpoint>; 2] = [None, None];
+
+pub fn init() {
+    Uart::new(GDB_UART_BASE).init();
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn check_magic(c: u8) -> bool {
+    let pos = MAGIC_POS.load(Ordering::Relaxed);
+    let next = if GDB_MAGIC[pos] == c {
+        pos + 1
+    } else if GDB_MAGIC[0] == c {
+        1
+    } else {
+        0
+    };
+    if next == GDB_MAGIC.len() {
+        MAGIC_POS.store(0, Ordering::Relaxed);
+        if is_enabled() {
+            REQUESTED.store(true, Ordering::SeqCst);
+        }
+        true
+    } else {
+        MAGIC_POS.store(next, Ordering::Relaxed);
+        false
+    }
+}
+
+pub fn take_request() -> bool {
+    REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut val = 0usize;
+    for &c in s {
+        val = val.checked_mul(16)? | hex_digit(c)? as usize;
+    }
+    Some(val)
+}
+
+fn parse_addr_len(s: &[u8]) -> Option<(usize, usize)> {
+    let comma = s.iter().position(|&c| c == b',')?;
+    Some((parse_hex(&s[..comma])?, parse_hex(&s[comma + 1..])?))
+}
+
+fn push_hex_byte(out: &mut Vec<u8>, b: u8) {
+    const DIGITS: &[u8] = b"0123456789abcdef";
+    out.push(DIGITS[(b >> 4) as usize]);
+    out.push(DIGITS[(b & 0xf) as usize]);
+}
+
+fn push_hex_usize(out: &mut Vec<u8>, val: usize) {
+    for b in val.to_le_bytes().iter() {
+        push_hex_byte(out, *b);
+    }
+}
+
+fn decode_usize(s: &[u8]) -> Option<usize> {
+    if s.len() < 16 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for i in 0..8 {
+        bytes[i] = hex_digit(s[i * 2])? << 4 | hex_digit(s[i * 2 + 1])?;
+    }
+    Some(usize::from_le_bytes(bytes))
+}
+
+struct Connection {
+    uart: Uart,
+}
+
+impl Connection {
+    fn get(&mut self) -> u8 {
+        loop {
+            if let Some(c) = self.uart.get() {
+                return c;
+            }
+        }
+    }
+
+    fn read_packet(&mut self) -> Vec<u8> {
+        loop {
+            while self.get() != b'$' {}
+            let mut data = Vec::new();
+            let mut sum: u8 = 0;
+            loop {
+                let c = self.get();
+                if c == b'#' {
+                    break;
+                }
+                if data.len() < MAX_PACKET {
+                    data.push(c);
+                }
+                sum = sum.wrapping_add(c);
+            }
+            let hi = hex_digit(self.get());
+            let lo = hex_digit(self.get());
+            match (hi, lo) {
+                (Some(hi), Some(lo)) if hi << 4 | lo == sum => {
+                    self.uart.put(b'+');
+                    return data;
+                },
+                _ => {
+                    self.uart.put(b'-');
+                },
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &[u8]) {
+        let sum = data.iter().fold(0u8, |s, &c| s.wrapping_add(c));
+        let mut tail = Vec::with_capacity(2);
+        push_hex_byte(&mut tail, sum);
+        loop {
+            self.uart.put(b'$');
+            for &c in data {
+                self.uart.put(c);
+            }
+            self.uart.put(b'#');
+            self.uart.put(tail[0]);
+            self.uart.put(tail[1]);
+            if self.get() == b'+' {
+                break;
+            }
+        }
+    }
+}
+
+fn read_registers(frame: &TrapFrame, pc: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(33 * 16);
+    push_hex_usize(&mut out, 0);
+    for i in 1..32 {
+        push_hex_usize(&mut out, frame.regs[i]);
+    }
+    push_hex_usize(&mut out, pc);
+    out
+}
+
+fn write_registers(frame: &mut TrapFrame, pc: &mut usize, data: &[u8]) -> bool {
+    if data.len() < 33 * 16 {
+        return false;
+    }
+    for i in 1..32 {
+        match decode_usize(&data[i * 16..]) {
+            Some(val) => frame.regs[i] = val,
+            None => return false,
+        }
+    }
+    match decode_usize(&data[32 * 16..]) {
+        Some(val) => {
+            *pc = val;
+            true
+        },
+        None => false,
+    }
+}
+
+fn read_memory(addr: usize, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len * 2);
+    for i in 0..len {
+        let b = unsafe { (addr as *const u8).add(i).read_volatile() };
+        push_hex_byte(&mut out, b);
+    }
+    out
+}
+
+fn write_memory(addr: usize, data: &[u8]) -> bool {
+    for (i, pair) in data.chunks(2).enumerate() {
+        if pair.len() != 2 {
+            return false;
+        }
+        match (hex_digit(pair[0]), hex_digit(pair[1])) {
+            (Some(hi), Some(lo)) => unsafe {
+                (addr as *mut u8).add(i).write_volatile(hi << 4 | lo);
+            },
+            _ => return false,
+        }
+    }
+    unsafe {
+        asm!("fence.i");
+    }
+    true
+}
+
+fn reg(frame: &TrapFrame, r: usize) -> usize {
+    if r == 0 {
+        0
+    } else {
+        frame.regs[r]
+    }
+}
+
+fn sign_extend(val: usize, bits: usize) -> usize {
+    let shift = 64 - bits;
+    (((val << shift) as isize) >> shift) as usize
+}
+
+fn next_pcs(frame: &TrapFrame, pc: usize) -> [Option<usize>; 2] {
+    let low = unsafe { (pc as *const u16).read_volatile() } as usize;
+    if low & 3 != 3 {
+        let funct3 = low >> 13 & 7;
+        let fallthrough = Some(pc + 2);
+        return match (low & 3, funct3) {
+            (1, 5) => {
+                let imm = (low >> 12 & 1) << 11
+                          | (low >> 11 & 1) << 4
+                          | (low >> 9 & 3) << 8
+                          | (low >> 8 & 1) << 10
+                          | (low >> 7 & 1) << 6
+                          | (low >> 6 & 1) << 7
+                          | (low >> 3 & 7) << 1
+                          | (low >> 2 & 1) << 5;
+                [Some(pc.wrapping_add(sign_extend(imm, 12))), None]
+            },
+            (1, 6) | (1, 7) => {
+                let imm = (low >> 12 & 1) << 8
+                          | (low >> 10 & 3) << 3
+                          | (low >> 5 & 3) << 6
+                          | (low >> 3 & 3) << 1
+                          | (low >> 2 & 1) << 5;
+                [fallthrough, Some(pc.wrapping_add(sign_extend(imm, 9)))]
+            },
+            (2, 4) if low >> 2 & 0x1f == 0 && low >> 7 & 0x1f != 0 => {
+                [Some(reg(frame, low >> 7 & 0x1f)), None]
+            },
+            _ => [fallthrough, None],
+        };
+    }
+    let inst = unsafe { (pc as *const u32).read_volatile() } as usize;
+    let rs1 = inst >> 15 & 0x1f;
+    let fallthrough = Some(pc + 4);
+    match inst & 0x7f {
+        0x6f => {
+            let imm = (inst >> 31 & 1) << 20 | (inst >> 12 & 0xff) << 12 | (inst >> 20 & 1) << 11 | (inst >> 21 & 0x3ff) << 1;
+            [Some(pc.wrapping_add(sign_extend(imm, 21))), None]
+        },
+        0x67 => {
+            let imm = sign_extend(inst >> 20, 12);
+            [Some(reg(frame, rs1).wrapping_add(imm) & !1), None]
+        },
+        0x63 => {
+            let imm = (inst >> 31 & 1) << 12 | (inst >> 7 & 1) << 11 | (inst >> 25 & 0x3f) << 5 | (inst >> 8 & 0xf) << 1;
+            [fallthrough, Some(pc.wrapping_add(sign_extend(imm, 13)))]
+        },
+        _ => [fallthrough, None],
+    }
+}
+
+fn insert_step_breakpoints(frame: &TrapFrame, pc: usize) {
+    let targets = next_pcs(frame, pc);
+    for (slot, target) in targets.iter().enumerate() {
+        if let Some(addr) = *target {
+            let bp = unsafe {
+                let saved = (addr as *const u32).read_unaligned();
+                if saved as u16 & 3 == 3 {
+                    (addr as *mut u32).write_unaligned(EBREAK);
+                    StepBreakpoint { addr, saved, len: 4 }
+                } else {
+                    (addr as *mut u16).write_volatile(C_EBREAK);
+                    StepBreakpoint { addr, saved, len: 2 }
+                }
+            };
+            unsafe {
+                STEP_BREAKPOINTS[slot] = Some(bp);
+            }
+        }
+    }
+    unsafe {
+        asm!("fence.i");
+    }
+}
+
+fn remove_step_breakpoints() -> bool {
+    let mut removed = false;
+    unsafe {
+        for slot in STEP_BREAKPOINTS.iter_mut() {
+            if let Some(bp) = slot.take() {
+                if bp.len == 4 {
+                    (bp.addr as *mut u32).write_unaligned(bp.saved);
+                } else {
+                    (bp.addr as *mut u16).write_volatile(bp.saved as u16);
+                }
+                removed = true;
+            }
+        }
+        asm!("fence.i");
+    }
+    removed
+}
+
+pub fn handle_exception(frame: *mut TrapFrame, epc: usize, signal: u8) -> usize {
+    let frame = unsafe { &mut *frame };
+    let mut pc = epc;
+    if remove_step_breakpoints() {
+        println!("gdbstub: step complete at 0x{:08x}", pc);
+    }
+    let mut conn = Connection { uart: Uart::new(GDB_UART_BASE) };
+    let mut reply = Vec::new();
+    reply.push(b'S');
+    push_hex_byte(&mut reply, signal);
+    conn.send_packet(&reply);
+    loop {
+        let packet = conn.read_packet();
+        let (cmd, args) = match packet.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => continue,
+        };
+        match cmd {
+            b'?' => {
+                conn.send_packet(&reply);
+            },
+            b'g' => {
+                conn.send_packet(&read_registers(frame, pc));
+            },
+            b'G' => {
+                let ok = write_registers(frame, &mut pc, args);
+                conn.send_packet(if ok { b"OK" } else { b"E01" });
+            },
+            b'p' => match parse_hex(args) {
+                Some(r) if r < 32 => {
+                    let mut out = Vec::new();
+                    push_hex_usize(&mut out, reg(frame, r));
+                    conn.send_packet(&out);
+                },
+                Some(32) => {
+                    let mut out = Vec::new();
+                    push_hex_usize(&mut out, pc);
+                    conn.send_packet(&out);
+                },
+                _ => conn.send_packet(b"E01"),
+            },
+            b'm' => match parse_addr_len(args) {
+                Some((addr, len)) if len <= MAX_PACKET / 2 => conn.send_packet(&read_memory(addr, len)),
+                _ => conn.send_packet(b"E01"),
+            },
+            b'M' => {
+                let colon = args.iter().position(|&c| c == b':');
+                let ok = match colon.and_then(|c| parse_addr_len(&args[..c]).map(|al| (al, c))) {
+                    Some(((addr, len), c)) if args.len() - c - 1 == len * 2 => write_memory(addr, &args[c + 1..]),
+                    _ => false,
+                };
+                conn.send_packet(if ok { b"OK" } else { b"E01" });
+            },
+            b'c' => {
+                if let Some(addr) = parse_hex(args) {
+                    pc = addr;
+                }
+                break;
+            },
+            b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    pc = addr;
+                }
+                insert_step_breakpoints(frame, pc);
+                break;
+            },
+            b'D' => {
+                conn.send_packet(b"OK");
+                break;
+            },
+            b'k' => {
+                break;
+            },
+            b'q' if args.starts_with(b"Supported") => {
+                conn.send_packet(b"PacketSize=1000");
+            },
+            b'q' if args.starts_with(b"Attached") => {
+                conn.send_packet(b"1");
+            },
+            _ => {
+                conn.send_packet(b"");
+            },
+        }
+    }
+    pc
+}
+
+pub fn enter_on_panic() {
+    if !is_enabled() {
+        return;
+    }
+    let mut frame = TrapFrame::new();
+    let pc: usize;
+    unsafe {
+        asm!("auipc {}, 0", out(reg) pc);
+        asm!("mv {}, ra", out(reg) frame.regs[Registers::Ra as usize]);
+        asm!("mv {}, sp", out(reg) frame.regs[Registers::Sp as usize]);
+        asm!("mv {}, s0", out(reg) frame.regs[Registers::S0 as usize]);
+    }
+    handle_exception(&mut frame, pc, SIGSEGV);
+}
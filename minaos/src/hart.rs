@@ -0,0 +1,55 @@
+use crate::{cpu::TrapFrame,
+            idle,
+            kstack};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const MAX_HARTS: usize = 8;
+pub const TRAP_STACK_SIZE: usize = 0x10000;
+pub const NO_PID: u16 = 0;
+
+pub static mut KERNEL_TRAP_FRAMES: [TrapFrame; MAX_HARTS] = [TrapFrame::new(); MAX_HARTS];
+static mut CURRENT_PID: [u16; MAX_HARTS] = [NO_PID; MAX_HARTS];
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn hart_id() -> usize {
+    let hart;
+    unsafe {
+        asm!("csrr {}, mhartid", out(reg) hart);
+    }
+    hart
+}
+
+pub fn init_hart(hart: usize) {
+    assert!(hart < MAX_HARTS, "Hart {} exceeds MAX_HARTS", hart);
+    unsafe {
+        let frame = &mut KERNEL_TRAP_FRAMES[hart] as *mut TrapFrame;
+        (*frame).hartid = hart;
+        asm!("csrw mscratch, {}", in(reg) frame);
+        CURRENT_PID[hart] = NO_PID;
+    }
+    kstack::init_hart(hart);
+    idle::init_hart(hart);
+    ONLINE_HARTS.fetch_or(1 << hart, Ordering::SeqCst);
+}
+
+pub fn online_mask() -> usize {
+    ONLINE_HARTS.load(Ordering::SeqCst)
+}
+
+pub fn online_count() -> usize {
+    online_mask().count_ones() as usize
+}
+
+pub fn current_pid(hart: usize) -> u16 {
+    unsafe { CURRENT_PID[hart] }
+}
+
+pub fn set_current_pid(hart: usize, pid: u16) {
+    unsafe {
+        CURRENT_PID[hart] = pid;
+    }
+}
+
+pub fn is_running_elsewhere(hart: usize, pid: u16) -> bool {
+    (0..MAX_HARTS).any(|h| h != hart && current_pid(h) == pid)
+}
@@ -0,0 +1,254 @@
+use crate::{lock::Mutex,
+            process::{set_running, set_waiting}};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const MAX_TRIGGERS: usize = 4;
+
+pub const WATCH_EXEC: usize = 1 << 0;
+pub const WATCH_READ: usize = 1 << 1;
+pub const WATCH_WRITE: usize = 1 << 2;
+
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_SETTRIGGER: usize = 0x4300;
+pub const PTRACE_CLEARTRIGGER: usize = 0x4301;
+pub const PTRACE_GETHIT: usize = 0x4302;
+
+pub const MCONTROL_TYPE: usize = 2 << 60;
+pub const MCONTROL_M: usize = 1 << 6;
+pub const MCONTROL_S: usize = 1 << 4;
+pub const MCONTROL_U: usize = 1 << 3;
+pub const MCONTROL_EXECUTE: usize = 1 << 2;
+pub const MCONTROL_STORE: usize = 1 << 1;
+pub const MCONTROL_LOAD: usize = 1 << 0;
+
+pub enum TriggerError {
+    Unsupported,
+    InvalidKind,
+    NoFreeSlot,
+    InvalidSlot,
+    NotOwner,
+}
+
+#[derive(Copy, Clone)]
+pub struct Trigger {
+    pub addr: usize,
+    pub kind: usize,
+    pub owner: u16,
+}
+
+impl Trigger {
+    fn tdata1(&self) -> usize {
+        let mut val = MCONTROL_TYPE | MCONTROL_U;
+        if self.kind & WATCH_EXEC != 0 {
+            val |= MCONTROL_EXECUTE;
+        }
+        if self.kind & WATCH_READ != 0 {
+            val |= MCONTROL_LOAD;
+        }
+        if self.kind & WATCH_WRITE != 0 {
+            val |= MCONTROL_STORE;
+        }
+        val
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct TriggerHit {
+    pub pid: usize,
+    pub slot: usize,
+    pub addr: usize,
+    pub pc: usize,
+}
+
+struct DebugState {
+    triggers: BTreeMap<u16, [Option<Trigger>; MAX_TRIGGERS]>,
+    hits: Vec<(u16, TriggerHit)>,
+    stopped: BTreeMap<u16, u16>,
+}
+
+static mut DEBUG_STATE: Option<DebugState> = None;
+static mut DEBUG_LOCK: Mutex = Mutex::new();
+static TRIGGER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn write_trigger(slot: usize, tdata1: usize, tdata2: usize) {
+    unsafe {
+        asm!("csrw tselect, {}", in(reg) slot);
+        asm!("csrw tdata1, zero");
+        asm!("csrw tdata2, {}", in(reg) tdata2);
+        asm!("csrw tdata1, {}", in(reg) tdata1);
+    }
+}
+
+pub fn init() {
+    let mut count = 0;
+    while count < MAX_TRIGGERS {
+        let (selected, tdata1): (usize, usize);
+        unsafe {
+            asm!("csrw tselect, {}", in(reg) count);
+            asm!("csrr {}, tselect", out(reg) selected);
+            asm!("csrr {}, tdata1", out(reg) tdata1);
+        }
+        if selected != count || tdata1 >> 60 != 2 {
+            break;
+        }
+        write_trigger(count, 0, 0);
+        count += 1;
+    }
+    TRIGGER_COUNT.store(count, Ordering::SeqCst);
+    unsafe {
+        DEBUG_STATE.replace(DebugState {
+            triggers: BTreeMap::new(),
+            hits: Vec::new(),
+            stopped: BTreeMap::new(),
+        });
+    }
+}
+
+pub fn trigger_count() -> usize {
+    TRIGGER_COUNT.load(Ordering::SeqCst)
+}
+
+fn with_state<T, F: FnOnce(&mut DebugState) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        DEBUG_LOCK.spin_lock();
+        if let Some(mut state) = DEBUG_STATE.take() {
+            ret = Some(f(&mut state));
+            DEBUG_STATE.replace(state);
+        }
+        DEBUG_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn set_trigger(owner: u16, pid: u16, addr: usize, kind: usize) -> Result<usize, TriggerError> {
+    if kind == 0 || kind & !(WATCH_EXEC | WATCH_READ | WATCH_WRITE) != 0 {
+        return Err(TriggerError::InvalidKind);
+    }
+    let count = trigger_count();
+    if count == 0 {
+        return Err(TriggerError::Unsupported);
+    }
+    with_state(|state| {
+        let slots = state.triggers.entry(pid).or_insert([None; MAX_TRIGGERS]);
+        let slot = slots[..count].iter().position(|t| t.is_none()).ok_or(TriggerError::NoFreeSlot)?;
+        slots[slot] = Some(Trigger { addr, kind, owner });
+        Ok(slot)
+    }).unwrap_or(Err(TriggerError::Unsupported))
+}
+
+pub fn clear_trigger(owner: u16, pid: u16, slot: usize) -> Result<(), TriggerError> {
+    if slot >= trigger_count() {
+        return Err(TriggerError::InvalidSlot);
+    }
+    with_state(|state| {
+        let slots = state.triggers.get_mut(&pid).ok_or(TriggerError::InvalidSlot)?;
+        match slots[slot] {
+            Some(t) if t.owner != owner => Err(TriggerError::NotOwner),
+            Some(_) => {
+                slots[slot] = None;
+                Ok(())
+            },
+            None => Err(TriggerError::InvalidSlot),
+        }
+    }).unwrap_or(Err(TriggerError::Unsupported))
+}
+
+pub fn triggers(pid: u16) -> [Option<Trigger>; MAX_TRIGGERS] {
+    with_state(|state| state.triggers.get(&pid).copied()).flatten().unwrap_or([None; MAX_TRIGGERS])
+}
+
+pub fn load(pid: u16) {
+    let count = trigger_count();
+    if count == 0 {
+        return;
+    }
+    let slots = triggers(pid);
+    for (slot, trigger) in slots[..count].iter().enumerate() {
+        match trigger {
+            Some(t) => write_trigger(slot, t.tdata1(), t.addr),
+            None => write_trigger(slot, 0, 0),
+        }
+    }
+}
+
+pub fn handle_hit(pid: u16, epc: usize, tval: usize) -> bool {
+    let owner = with_state(|state| {
+        let slots = state.triggers.get_mut(&pid)?;
+        let slot = slots.iter().position(|t| match t {
+            Some(t) => t.addr == tval || (t.kind & WATCH_EXEC != 0 && t.addr == epc),
+            None => false,
+        })?;
+        let trigger = slots[slot].take()?;
+        state.hits.push((trigger.owner, TriggerHit {
+            pid: pid as usize,
+            slot,
+            addr: trigger.addr,
+            pc: epc,
+        }));
+        state.stopped.insert(pid, trigger.owner);
+        Some(trigger.owner)
+    }).flatten();
+    match owner {
+        Some(owner) => {
+            unload();
+            set_waiting(pid);
+            set_running(owner);
+            true
+        },
+        None => false,
+    }
+}
+
+pub fn unload() {
+    for slot in 0..trigger_count() {
+        write_trigger(slot, 0, 0);
+    }
+}
+
+pub fn wait_hit(owner: u16) -> Option<TriggerHit> {
+    with_state(|state| match state.hits.iter().position(|(o, _)| *o == owner) {
+        Some(idx) => Some(state.hits.remove(idx).1),
+        None => {
+            set_waiting(owner);
+            None
+        },
+    }).flatten()
+}
+
+pub fn resume(owner: u16, pid: u16) -> Result<(), TriggerError> {
+    let owns = with_state(|state| match state.stopped.get(&pid) {
+        Some(o) if *o == owner => {
+            state.stopped.remove(&pid);
+            true
+        },
+        _ => false,
+    }).unwrap_or(false);
+    if !owns {
+        return Err(TriggerError::NotOwner);
+    }
+    set_running(pid);
+    Ok(())
+}
+
+pub fn release(pid: u16) {
+    let orphans = with_state(|state| {
+        state.triggers.remove(&pid);
+        state.hits.retain(|(owner, h)| *owner != pid && h.pid != pid as usize);
+        for slots in state.triggers.values_mut() {
+            for slot in slots.iter_mut() {
+                if slot.map_or(false, |t| t.owner == pid) {
+                    *slot = None;
+                }
+            }
+        }
+        let orphans: Vec<u16> = state.stopped.iter().filter(|(_, owner)| **owner == pid).map(|(p, _)| *p).collect();
+        state.stopped.retain(|p, owner| *p != pid && *owner != pid);
+        orphans
+    }).unwrap_or_default();
+    for p in orphans {
+        set_running(p);
+    }
+}
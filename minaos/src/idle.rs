@@ -0,0 +1,78 @@
+use crate::{cpu::{CpuMode, Registers, TrapFrame},
+            hart::{MAX_HARTS, NO_PID},
+            page::{zalloc, PAGE_SIZE},
+            trap::MMIO_MTIME};
+use core::{ptr::null_mut,
+           sync::atomic::{AtomicBool, AtomicU64, Ordering}};
+
+pub const IDLE_STACK_PAGES: usize = 1;
+
+const ZERO: AtomicU64 = AtomicU64::new(0);
+const BUSY: AtomicBool = AtomicBool::new(false);
+
+static IDLE_TICKS: [AtomicU64; MAX_HARTS] = [ZERO; MAX_HARTS];
+static BUSY_TICKS: [AtomicU64; MAX_HARTS] = [ZERO; MAX_HARTS];
+static LAST_SWITCH: [AtomicU64; MAX_HARTS] = [ZERO; MAX_HARTS];
+static IDLING: [AtomicBool; MAX_HARTS] = [BUSY; MAX_HARTS];
+
+static mut IDLE_FRAMES: [*mut TrapFrame; MAX_HARTS] = [null_mut(); MAX_HARTS];
+
+fn idle_loop() {
+    loop {
+        unsafe {
+            asm!("wfi");
+        }
+    }
+}
+
+fn now() -> u64 {
+    unsafe { MMIO_MTIME.read_volatile() }
+}
+
+pub fn init_hart(hart: usize) {
+    let frame = zalloc(1) as *mut TrapFrame;
+    let stack = zalloc(IDLE_STACK_PAGES);
+    if frame.is_null() || stack.is_null() {
+        panic!("Unable to allocate idle task for hart {}", hart);
+    }
+    unsafe {
+        (*frame).pc = idle_loop as usize;
+        (*frame).regs[Registers::Sp as usize] = stack as usize + IDLE_STACK_PAGES * PAGE_SIZE;
+        (*frame).mode = CpuMode::Machine as usize;
+        (*frame).pid = NO_PID as usize;
+        (*frame).hartid = hart;
+        IDLE_FRAMES[hart] = frame;
+    }
+    LAST_SWITCH[hart].store(now(), Ordering::SeqCst);
+}
+
+fn account(hart: usize, idle: bool) {
+    let now = now();
+    let elapsed = now.wrapping_sub(LAST_SWITCH[hart].swap(now, Ordering::SeqCst));
+    if IDLING[hart].swap(idle, Ordering::SeqCst) {
+        IDLE_TICKS[hart].fetch_add(elapsed, Ordering::SeqCst);
+    } else {
+        BUSY_TICKS[hart].fetch_add(elapsed, Ordering::SeqCst);
+    }
+}
+
+pub fn enter(hart: usize) -> *mut TrapFrame {
+    account(hart, true);
+    unsafe { IDLE_FRAMES[hart] }
+}
+
+pub fn leave(hart: usize) {
+    account(hart, false);
+}
+
+pub fn is_idle(hart: usize) -> bool {
+    IDLING[hart].load(Ordering::SeqCst)
+}
+
+pub fn idle_ticks(hart: usize) -> u64 {
+    IDLE_TICKS[hart].load(Ordering::SeqCst)
+}
+
+pub fn busy_ticks(hart: usize) -> u64 {
+    BUSY_TICKS[hart].load(Ordering::SeqCst)
+}
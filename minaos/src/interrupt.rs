@@ -0,0 +1,59 @@
+pub const MSTATUS_MIE: usize = 1 << 3;
+
+#[derive(Copy, Clone)]
+pub struct InterruptState(usize);
+
+impl InterruptState {
+    pub fn was_enabled(&self) -> bool {
+        self.0 & MSTATUS_MIE != 0
+    }
+}
+
+pub fn enabled() -> bool {
+    let mstatus: usize;
+    unsafe {
+        asm!("csrr {}, mstatus", out(reg) mstatus);
+    }
+    mstatus & MSTATUS_MIE != 0
+}
+
+pub fn disable() -> InterruptState {
+    let prev: usize;
+    unsafe {
+        asm!("csrrc {}, mstatus, {}", out(reg) prev, in(reg) MSTATUS_MIE);
+    }
+    InterruptState(prev & MSTATUS_MIE)
+}
+
+pub fn enable() {
+    unsafe {
+        asm!("csrs mstatus, {}", in(reg) MSTATUS_MIE);
+    }
+}
+
+pub fn restore(state: InterruptState) {
+    if state.was_enabled() {
+        enable();
+    }
+}
+
+pub struct InterruptGuard {
+    state: InterruptState,
+}
+
+impl InterruptGuard {
+    pub fn new() -> Self {
+        InterruptGuard { state: disable() }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        restore(self.state);
+    }
+}
+
+pub fn without_interrupts<T, F: FnOnce() -> T>(f: F) -> T {
+    let _guard = InterruptGuard::new();
+    f()
+}
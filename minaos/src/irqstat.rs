@@ -0,0 +1,128 @@
+use crate::hart::{hart_id, MAX_HARTS};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const MAX_SOURCES: usize = 64;
+pub const MAX_CAUSES: usize = 16;
+
+pub const UART_SOURCE: usize = 10;
+pub const VIRTIO_SOURCE_BASE: usize = 1;
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+const ZERO_ROW: [AtomicUsize; MAX_HARTS] = [ZERO; MAX_HARTS];
+
+static SOURCE_COUNTS: [[AtomicUsize; MAX_HARTS]; MAX_SOURCES] = [ZERO_ROW; MAX_SOURCES];
+static ASYNC_COUNTS: [[AtomicUsize; MAX_HARTS]; MAX_CAUSES] = [ZERO_ROW; MAX_CAUSES];
+static SYNC_COUNTS: [[AtomicUsize; MAX_HARTS]; MAX_CAUSES] = [ZERO_ROW; MAX_CAUSES];
+static SPURIOUS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn record_source(id: usize) {
+    let hart = hart_id();
+    if id < MAX_SOURCES && hart < MAX_HARTS {
+        SOURCE_COUNTS[id][hart].fetch_add(1, Ordering::Relaxed);
+    } else {
+        SPURIOUS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn record_trap(hart: usize, is_async: bool, cause: usize) {
+    if cause >= MAX_CAUSES || hart >= MAX_HARTS {
+        return;
+    }
+    let table = if is_async { &ASYNC_COUNTS } else { &SYNC_COUNTS };
+    table[cause][hart].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_spurious() {
+    SPURIOUS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn source_count(id: usize) -> usize {
+    if id >= MAX_SOURCES {
+        return 0;
+    }
+    SOURCE_COUNTS[id].iter().map(|c| c.load(Ordering::Relaxed)).sum()
+}
+
+pub fn source_count_on(id: usize, hart: usize) -> usize {
+    if id >= MAX_SOURCES || hart >= MAX_HARTS {
+        return 0;
+    }
+    SOURCE_COUNTS[id][hart].load(Ordering::Relaxed)
+}
+
+pub fn trap_count(is_async: bool, cause: usize) -> usize {
+    if cause >= MAX_CAUSES {
+        return 0;
+    }
+    let table = if is_async { &ASYNC_COUNTS } else { &SYNC_COUNTS };
+    table[cause].iter().map(|c| c.load(Ordering::Relaxed)).sum()
+}
+
+pub fn spurious_count() -> usize {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+pub fn reset() {
+    for row in SOURCE_COUNTS.iter().chain(ASYNC_COUNTS.iter()).chain(SYNC_COUNTS.iter()) {
+        for c in row.iter() {
+            c.store(0, Ordering::Relaxed);
+        }
+    }
+    SPURIOUS.store(0, Ordering::Relaxed);
+}
+
+fn source_name(id: usize) -> &'static str {
+    match id {
+        UART_SOURCE => "uart",
+        1..=8 => "virtio",
+        _ => "",
+    }
+}
+
+fn async_name(cause: usize) -> &'static str {
+    match cause {
+        3 => "msoft",
+        7 => "mtimer",
+        11 => "mext",
+        _ => "",
+    }
+}
+
+pub fn dump() {
+    print!("{:>8}", "");
+    for hart in 0..MAX_HARTS {
+        print!(" {:>10}", hart);
+    }
+    println!();
+    for id in 0..MAX_SOURCES {
+        if source_count(id) == 0 {
+            continue;
+        }
+        print!("{:>8}", id);
+        for hart in 0..MAX_HARTS {
+            print!(" {:>10}", SOURCE_COUNTS[id][hart].load(Ordering::Relaxed));
+        }
+        println!("  plic {}", source_name(id));
+    }
+    for cause in 0..MAX_CAUSES {
+        if trap_count(true, cause) == 0 {
+            continue;
+        }
+        print!("{:>8}", cause);
+        for hart in 0..MAX_HARTS {
+            print!(" {:>10}", ASYNC_COUNTS[cause][hart].load(Ordering::Relaxed));
+        }
+        println!("  async {}", async_name(cause));
+    }
+    for cause in 0..MAX_CAUSES {
+        if trap_count(false, cause) == 0 {
+            continue;
+        }
+        print!("{:>8}", cause);
+        for hart in 0..MAX_HARTS {
+            print!(" {:>10}", SYNC_COUNTS[cause][hart].load(Ordering::Relaxed));
+        }
+        println!("  sync");
+    }
+    println!("{:>8} {:>10}", "SPU", spurious_count());
+}
@@ -0,0 +1,208 @@
+use crate::{slab::{self, MIN_OBJECT},
+            spinlock::SpinLock};
+use core::{mem::size_of,
+           panic::Location,
+           ptr::null_mut,
+           sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+pub const REDZONE: usize = 16;
+pub const REDZONE_BYTE: u8 = 0xfc;
+pub const FREED_BYTE: u8 = 0xfb;
+pub const QUARANTINE_SLOTS: usize = 256;
+pub const QUARANTINE_BYTES: usize = 256 * 1024;
+
+const LIVE: usize = 0x6b61_7361_6e4c_4956;
+const FREED: usize = 0x6b61_7361_6e46_5245;
+const OFFSET: usize = size_of::<Header>() + REDZONE;
+
+type Site = &'static Location<'static>;
+
+#[repr(C)]
+struct Header {
+    magic: usize,
+    size: usize,
+    alloc_site: Site,
+    free_site: Option<Site>,
+}
+
+struct Quarantine {
+    objects: [*mut u8; QUARANTINE_SLOTS],
+    head: usize,
+    len: usize,
+    bytes: usize,
+}
+
+unsafe impl Send for Quarantine {}
+
+impl Quarantine {
+    fn push(&mut self, ptr: *mut u8, bytes: usize) {
+        self.objects[(self.head + self.len) % QUARANTINE_SLOTS] = ptr;
+        self.len += 1;
+        self.bytes += bytes;
+    }
+
+    fn pop(&mut self) -> Option<*mut u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let ptr = self.objects[self.head];
+        self.head = (self.head + 1) % QUARANTINE_SLOTS;
+        self.len -= 1;
+        self.bytes -= footprint(unsafe { (*header_of(ptr)).size });
+        Some(ptr)
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REPORTS: AtomicUsize = AtomicUsize::new(0);
+static QUARANTINE: SpinLock<Quarantine> = SpinLock::new("kasan", Quarantine {
+    objects: [null_mut(); QUARANTINE_SLOTS],
+    head: 0,
+    len: 0,
+    bytes: 0,
+});
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+    if !enabled {
+        drain();
+    }
+}
+
+pub fn reports() -> usize {
+    REPORTS.load(Ordering::SeqCst)
+}
+
+pub fn quarantined() -> (usize, usize) {
+    let q = QUARANTINE.lock();
+    (q.len, q.bytes)
+}
+
+fn footprint(size: usize) -> usize {
+    OFFSET + (size + MIN_OBJECT - 1) / MIN_OBJECT * MIN_OBJECT + REDZONE
+}
+
+fn header_of(ptr: *mut u8) -> *mut Header {
+    (ptr as usize - OFFSET) as *mut Header
+}
+
+pub fn owns(ptr: *mut u8) -> bool {
+    if ptr as usize % MIN_OBJECT != 0 || (ptr as usize) < OFFSET {
+        return false;
+    }
+    let magic = unsafe { (*header_of(ptr)).magic };
+    magic == LIVE ^ ptr as usize || magic == FREED ^ ptr as usize
+}
+
+pub fn size_of_object(ptr: *mut u8) -> usize {
+    unsafe {
+        match (*header_of(ptr)).magic ^ ptr as usize {
+            LIVE => (*header_of(ptr)).size,
+            _ => 0,
+        }
+    }
+}
+
+fn report(what: &str, ptr: *mut u8, h: &Header, site: Option<Site>) {
+    REPORTS.fetch_add(1, Ordering::SeqCst);
+    match site {
+        Some(site) => println!("kasan: {} on {}-byte object {:p} at {}", what, h.size, ptr, site),
+        None => println!("kasan: {} on {}-byte object {:p}", what, h.size, ptr),
+    }
+    println!("kasan:   allocated at {}", h.alloc_site);
+    if let Some(free_site) = h.free_site {
+        println!("kasan:   freed at {}", free_site);
+    }
+}
+
+unsafe fn corrupted(from: *mut u8, len: usize, expect: u8) -> Option<usize> {
+    (0..len).find(|&i| from.add(i).read() != expect)
+}
+
+unsafe fn check_redzones(ptr: *mut u8, h: &Header, site: Option<Site>) {
+    if let Some(i) = corrupted(ptr.sub(REDZONE), REDZONE, REDZONE_BYTE) {
+        report("buffer underflow", ptr, h, site);
+        println!("kasan:   {} bytes before the object overwritten", REDZONE - i);
+    }
+    let tail = footprint(h.size) - OFFSET - h.size;
+    if let Some(i) = corrupted(ptr.add(h.size), tail, REDZONE_BYTE) {
+        report("buffer overflow", ptr, h, site);
+        println!("kasan:   byte {} past the end overwritten", i);
+    }
+}
+
+pub fn alloc(size: usize, site: Site) -> *mut u8 {
+    let base = slab::alloc_raw(footprint(size));
+    if base.is_null() {
+        return null_mut();
+    }
+    unsafe {
+        let ptr = base.add(OFFSET);
+        (base as *mut Header).write(Header {
+            magic: LIVE ^ ptr as usize,
+            size,
+            alloc_site: site,
+            free_site: None,
+        });
+        ptr.sub(REDZONE).write_bytes(REDZONE_BYTE, REDZONE);
+        ptr.add(size).write_bytes(REDZONE_BYTE, footprint(size) - OFFSET - size);
+        ptr
+    }
+}
+
+pub fn free(ptr: *mut u8, site: Site) {
+    unsafe {
+        let h = &mut *header_of(ptr);
+        if h.magic == FREED ^ ptr as usize {
+            report("double free", ptr, h, Some(site));
+            return;
+        }
+        check_redzones(ptr, h, Some(site));
+        h.magic = FREED ^ ptr as usize;
+        h.free_site = Some(site);
+        ptr.write_bytes(FREED_BYTE, h.size);
+        let bytes = footprint(h.size);
+        loop {
+            let victim = {
+                let mut q = QUARANTINE.lock();
+                if q.len < QUARANTINE_SLOTS && (q.len == 0 || q.bytes + bytes <= QUARANTINE_BYTES) {
+                    q.push(ptr, bytes);
+                    None
+                } else {
+                    q.pop()
+                }
+            };
+            match victim {
+                Some(victim) => release(victim),
+                None => break,
+            }
+        }
+    }
+}
+
+unsafe fn release(ptr: *mut u8) {
+    let h = &mut *header_of(ptr);
+    if let Some(i) = corrupted(ptr, h.size, FREED_BYTE) {
+        report("use after free", ptr, h, None);
+        println!("kasan:   byte {} written after the object was freed", i);
+    }
+    check_redzones(ptr, h, None);
+    h.magic = 0;
+    slab::free_raw(h as *mut Header as *mut u8);
+}
+
+pub fn drain() -> usize {
+    let mut released = 0;
+    loop {
+        let ptr = QUARANTINE.lock().pop();
+        match ptr {
+            Some(ptr) => unsafe { release(ptr) },
+            None => return released,
+        }
+        released += 1;
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{lock::Mutex, time};
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::fmt::{self, Write};
+
+pub const KLOG_CAPACITY: usize = 512;
+
+#[derive(Clone)]
+pub struct LogEntry {
+    pub timestamp: u64,
+    pub message: String,
+}
+
+static mut KLOG: Option<VecDeque<LogEntry>> = None;
+static mut KLOG_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        KLOG.replace(VecDeque::with_capacity(KLOG_CAPACITY));
+    }
+}
+
+pub fn log(args: fmt::Arguments) {
+    let mut message = String::new();
+    let _ = message.write_fmt(args);
+    let entry = LogEntry {
+        timestamp: time::monotonic_nanos(),
+        message,
+    };
+    unsafe {
+        KLOG_LOCK.spin_lock();
+        if let Some(mut log) = KLOG.take() {
+            if log.len() >= KLOG_CAPACITY {
+                log.pop_front();
+            }
+            log.push_back(entry);
+            KLOG.replace(log);
+        }
+        KLOG_LOCK.unlock();
+    }
+}
+
+pub fn entries() -> Vec<LogEntry> {
+    let mut ret = Vec::new();
+    unsafe {
+        KLOG_LOCK.spin_lock();
+        if let Some(log) = KLOG.take() {
+            ret.extend(log.iter().cloned());
+            KLOG.replace(log);
+        }
+        KLOG_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn dump() {
+    for entry in entries() {
+        let secs = entry.timestamp / time::NSEC_PER_SEC;
+        let micros = (entry.timestamp % time::NSEC_PER_SEC) / 1_000;
+        println!("[{:>5}.{:06}] {}", secs, micros, entry.message);
+    }
+}
+
+#[macro_export]
+macro_rules! klog {
+    ($($args:tt)+) => ({
+        $crate::klog::log(format_args!($($args)+));
+    });
+}
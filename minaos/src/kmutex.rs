@@ -0,0 +1,130 @@
+use crate::{hart::NO_PID,
+            kthread,
+            process::set_waiting,
+            sched,
+            spinlock::SpinLock};
+use alloc::collections::VecDeque;
+use core::{cell::UnsafeCell,
+           ops::{Deref, DerefMut}};
+
+struct State {
+    owner: u16,
+    waiters: Option<VecDeque<u16>>,
+}
+
+pub struct KMutex<T> {
+    state: SpinLock<State>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for KMutex<T> {}
+unsafe impl<T: Send> Send for KMutex<T> {}
+
+pub struct KMutexGuard<'a, T> {
+    mutex: &'a KMutex<T>,
+}
+
+impl<T> KMutex<T> {
+    pub const fn new(name: &'static str, data: T) -> Self {
+        KMutex {
+            state: SpinLock::new(name, State {
+                owner: NO_PID,
+                waiters: None,
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    pub fn owner(&self) -> u16 {
+        self.state.lock().owner
+    }
+
+    pub fn try_lock(&self, pid: u16) -> Option<KMutexGuard<T>> {
+        let mut state = self.state.lock();
+        if state.owner != NO_PID && state.owner != pid {
+            return None;
+        }
+        state.owner = pid;
+        if let Some(q) = state.waiters.as_mut() {
+            q.retain(|&p| p != pid);
+        }
+        Some(KMutexGuard { mutex: self })
+    }
+
+    pub fn lock(&self, pid: u16) -> Option<KMutexGuard<T>> {
+        let owner = {
+            let mut state = self.state.lock();
+            if state.owner == NO_PID || state.owner == pid {
+                state.owner = pid;
+                if let Some(q) = state.waiters.as_mut() {
+                    q.retain(|&p| p != pid);
+                }
+                return Some(KMutexGuard { mutex: self });
+            }
+            let q = state.waiters.get_or_insert_with(VecDeque::new);
+            if !q.contains(&pid) {
+                q.push_back(pid);
+            }
+            set_waiting(pid);
+            state.owner
+        };
+        let prio = sched::rt_priority(pid);
+        if prio > sched::rt_priority(owner) {
+            sched::inherit(owner, self.key(), prio);
+        }
+        None
+    }
+
+    pub fn lock_kthread(&self) -> KMutexGuard<T> {
+        let pid = kthread::current();
+        loop {
+            if let Some(guard) = self.lock(pid) {
+                return guard;
+            }
+            kthread::park();
+        }
+    }
+
+    fn unlock(&self) {
+        let (owner, next) = {
+            let mut state = self.state.lock();
+            let owner = core::mem::replace(&mut state.owner, NO_PID);
+            let next = state.waiters.as_mut().and_then(|q| {
+                let best = q.iter().enumerate().max_by_key(|&(i, &p)| (sched::rt_priority(p), usize::MAX - i))?.0;
+                q.remove(best)
+            });
+            (owner, next)
+        };
+        sched::disinherit(owner, self.key());
+        if let Some(pid) = next {
+            kthread::unpark(pid);
+            if sched::rt_priority(pid) > sched::rt_priority(owner) {
+                sched::preempt();
+            }
+        }
+    }
+}
+
+impl<'a, T> Deref for KMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for KMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for KMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
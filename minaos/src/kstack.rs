@@ -0,0 +1,90 @@
+use crate::{cpu::{CpuMode, Registers, TrapFrame},
+            hart::{MAX_HARTS, NO_PID, TRAP_STACK_SIZE},
+            page::PAGE_SIZE,
+            process::get_by_pid};
+
+pub const GUARD_SIZE: usize = PAGE_SIZE;
+
+const PMP_R: usize = 1 << 0;
+const PMP_W: usize = 1 << 1;
+const PMP_X: usize = 1 << 2;
+const PMP_NAPOT: usize = 3 << 3;
+const PMP_LOCK: usize = 1 << 7;
+
+extern "C" {
+    static KERNEL_STACK_START: usize;
+    static KERNEL_STACK_END: usize;
+}
+
+pub enum Overflow {
+    TrapStack { hart: usize, guard: usize },
+    KernelThread { pid: u16, sp: usize, guard: usize },
+}
+
+pub fn trap_guard(hart: usize) -> Option<usize> {
+    let (start, end) = unsafe { (KERNEL_STACK_START, KERNEL_STACK_END) };
+    let bottom = end.checked_sub((hart + 1) * TRAP_STACK_SIZE)?;
+    let guard = (bottom + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    if bottom < start || guard + GUARD_SIZE >= end - hart * TRAP_STACK_SIZE {
+        return None;
+    }
+    Some(guard)
+}
+
+pub fn init_hart(hart: usize) {
+    let guard = match trap_guard(hart) {
+        Some(guard) => guard,
+        None => {
+            println!("kstack: no room for a guard page under hart {} trap stack", hart);
+            return;
+        },
+    };
+    unsafe {
+        asm!("csrw pmpaddr15, {}", in(reg) usize::MAX >> 10);
+        asm!("csrc pmpcfg2, {}", in(reg) 0xffusize << 56);
+        asm!("csrs pmpcfg2, {}", in(reg) (PMP_NAPOT | PMP_R | PMP_W | PMP_X) << 56);
+        asm!("csrw pmpaddr0, {}", in(reg) (guard >> 2) | (GUARD_SIZE / 8 - 1));
+        asm!("csrc pmpcfg0, {}", in(reg) 0xffusize);
+        asm!("csrs pmpcfg0, {}", in(reg) PMP_NAPOT | PMP_LOCK);
+    }
+}
+
+pub fn guard_hit(addr: usize) -> Option<Overflow> {
+    (0..MAX_HARTS).find_map(|hart| {
+        let guard = trap_guard(hart)?;
+        if addr >= guard && addr < guard + GUARD_SIZE {
+            Some(Overflow::TrapStack { hart, guard })
+        } else {
+            None
+        }
+    })
+}
+
+pub unsafe fn check(frame: *const TrapFrame) -> Option<Overflow> {
+    let pid = (*frame).pid as u16;
+    if (*frame).mode != CpuMode::Machine as usize || pid == NO_PID {
+        return None;
+    }
+    let p = get_by_pid(pid);
+    if p.is_null() || (*p).stack.is_null() {
+        return None;
+    }
+    let guard = (*p).stack as usize;
+    let sp = (*frame).regs[Registers::Sp as usize];
+    let touched = ((guard + GUARD_SIZE - 8) as *const usize).read_volatile() != 0;
+    if sp < guard + GUARD_SIZE || touched {
+        return Some(Overflow::KernelThread { pid, sp, guard });
+    }
+    None
+}
+
+pub fn report(overflow: Overflow) -> ! {
+    match overflow {
+        Overflow::TrapStack { hart, guard } => {
+            panic!("kernel stack overflow: hart {} trap stack hit guard page 0x{:08x}", hart, guard)
+        },
+        Overflow::KernelThread { pid, sp, guard } => {
+            panic!("kernel stack overflow: pid {} sp 0x{:08x} reached guard page 0x{:08x}", pid, sp, guard)
+        },
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{hart::{current_pid, hart_id, NO_PID},
+            lock::Mutex,
+            process::{add_kernel_process_args, set_running, set_waiting},
+            sched,
+            spinlock::SpinLock,
+            syscall::syscall_park};
+use alloc::{boxed::Box, collections::{BTreeMap, BTreeSet}, sync::Arc};
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+struct Threads {
+    names: BTreeMap<u16, &'static str>,
+    tokens: BTreeSet<u16>,
+}
+
+static mut THREADS: Option<Threads> = None;
+static mut THREAD_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        THREADS.replace(Threads {
+            names: BTreeMap::new(),
+            tokens: BTreeSet::new(),
+        });
+    }
+}
+
+fn with_threads<T, F: FnOnce(&mut Threads) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        THREAD_LOCK.spin_lock();
+        if let Some(mut threads) = THREADS.take() {
+            ret = Some(f(&mut threads));
+            THREADS.replace(threads);
+        }
+        THREAD_LOCK.unlock();
+    }
+    ret
+}
+
+struct Packet<T> {
+    result: SpinLock<Option<T>>,
+    finished: AtomicBool,
+    joiner: AtomicU16,
+}
+
+impl<T> Packet<T> {
+    fn new() -> Self {
+        Packet {
+            result: SpinLock::new("kthread result", None),
+            finished: AtomicBool::new(false),
+            joiner: AtomicU16::new(NO_PID),
+        }
+    }
+
+    fn finish(&self, value: T) {
+        *self.result.lock() = Some(value);
+        self.finished.store(true, Ordering::SeqCst);
+        let joiner = self.joiner.load(Ordering::SeqCst);
+        if joiner != NO_PID {
+            unpark(joiner);
+        }
+    }
+}
+
+pub struct JoinHandle<T> {
+    pid: u16,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn pid(&self) -> u16 {
+        self.pid
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.packet.finished.load(Ordering::SeqCst)
+    }
+
+    pub fn join(self) -> Option<T> {
+        self.packet.joiner.store(current(), Ordering::SeqCst);
+        while !self.is_finished() {
+            park();
+        }
+        self.packet.result.lock().take()
+    }
+}
+
+fn trampoline(arg: usize) {
+    let main = unsafe { Box::from_raw(arg as *mut Box<dyn FnOnce()>) };
+    main();
+}
+
+pub fn spawn<F, T>(name: &'static str, f: F) -> Option<JoinHandle<T>>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let packet = Arc::new(Packet::new());
+    let their_packet = packet.clone();
+    let main: Box<dyn FnOnce()> = Box::new(move || {
+        set_name(current(), name);
+        let value = f();
+        their_packet.finish(value);
+    });
+    let arg = Box::into_raw(Box::new(main)) as usize;
+    let pid = add_kernel_process_args(trampoline, arg) as u16;
+    if pid == NO_PID {
+        unsafe {
+            drop(Box::from_raw(arg as *mut Box<dyn FnOnce()>));
+        }
+        return None;
+    }
+    Some(JoinHandle { pid, packet })
+}
+
+pub fn spawn_on<F, T>(name: &'static str, hart: usize, f: F) -> Option<JoinHandle<T>>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let handle = spawn(name, f)?;
+    let _ = sched::pin(handle.pid, hart);
+    Some(handle)
+}
+
+pub fn current() -> u16 {
+    current_pid(hart_id())
+}
+
+pub fn set_name(pid: u16, name: &'static str) {
+    with_threads(|threads| threads.names.insert(pid, name));
+}
+
+pub fn name(pid: u16) -> Option<&'static str> {
+    with_threads(|threads| threads.names.get(&pid).copied()).flatten()
+}
+
+pub fn park() {
+    syscall_park();
+}
+
+pub fn unpark(pid: u16) {
+    with_threads(|threads| {
+        threads.tokens.insert(pid);
+        set_running(pid);
+    });
+}
+
+pub fn park_pid(pid: u16) {
+    with_threads(|threads| {
+        if !threads.tokens.remove(&pid) {
+            set_waiting(pid);
+        }
+    });
+}
+
+pub fn release(pid: u16) {
+    with_threads(|threads| {
+        threads.names.remove(&pid);
+        threads.tokens.remove(&pid);
+    });
+}
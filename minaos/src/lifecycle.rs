@@ -0,0 +1,367 @@
+use crate::{acct,
+            cpu::{build_satp, memcpy, Registers, SatpMode, TrapFrame},
+            cred,
+            elf::{self, File, LoadErrors},
+            fd,
+            futex,
+            hwbreak,
+            kthread,
+            lock::Mutex,
+            page::{dealloc, zalloc, Table, PAGE_SIZE},
+            paging,
+            poll,
+            process::{delete_process, get_by_pid, set_running, set_waiting, Process, ProcessData, ProcessState,
+                      NEXT_PID, PROCESS_LIST, PROCESS_LIST_MUTEX, STACK_ADDR, STACK_PAGES},
+            sched,
+            session,
+            shm,
+            signal::{self, SIGCHLD},
+            strace,
+            timer,
+            vma::{self, VmaBacking, VMA_GROWSDOWN, VMA_HEAP, VMA_MMAP}};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::ptr;
+
+pub const WNOHANG: usize = 1;
+pub const WCOREFLAG: i32 = 0x80;
+pub const NO_PARENT: u16 = 0;
+pub const INIT_PID: u16 = 1;
+pub const INIT_PATH: &str = "/sbin/init";
+
+pub enum WaitResult {
+    Reaped(u16, i32),
+    NotYet,
+    NoChildren,
+    Blocked,
+}
+
+pub enum ForkError {
+    NoProcess,
+    OutOfMemory,
+}
+
+struct Family {
+    parent: u16,
+    children: Vec<u16>,
+    exit_status: Option<i32>,
+}
+
+impl Family {
+    fn new(parent: u16) -> Self {
+        Family {
+            parent,
+            children: Vec::new(),
+            exit_status: None,
+        }
+    }
+}
+
+static mut FAMILIES: Option<BTreeMap<u16, Family>> = None;
+static mut FAMILY_LOCK: Mutex = Mutex::new();
+static mut INIT: u16 = NO_PARENT;
+
+pub fn init() {
+    unsafe {
+        FAMILIES.replace(BTreeMap::new());
+    }
+}
+
+fn with_families<T, F: FnOnce(&mut BTreeMap<u16, Family>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        FAMILY_LOCK.spin_lock();
+        if let Some(mut families) = FAMILIES.take() {
+            ret = Some(f(&mut families));
+            FAMILIES.replace(families);
+        }
+        FAMILY_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn add_child(parent: u16, child: u16) {
+    with_families(|families| {
+        families.insert(child, Family::new(parent));
+        if parent != NO_PARENT {
+            families.entry(parent).or_insert_with(|| Family::new(NO_PARENT)).children.push(child);
+        }
+    });
+}
+
+pub fn parent_of(pid: u16) -> u16 {
+    with_families(|families| families.get(&pid).map_or(NO_PARENT, |f| f.parent)).unwrap_or(NO_PARENT)
+}
+
+pub fn children_of(pid: u16) -> Vec<u16> {
+    with_families(|families| families.get(&pid).map(|f| f.children.clone()).unwrap_or_default()).unwrap_or_default()
+}
+
+pub fn exit(pid: u16, wstatus: i32) {
+    signal::release(pid);
+    vma::release(pid);
+    paging::release(pid);
+    hwbreak::release(pid);
+    fd::release(pid);
+    futex::release(pid);
+    timer::release(pid);
+    poll::release(pid);
+    session::release(pid);
+    kthread::release(pid);
+    cred::release(pid);
+    acct::release(pid);
+    strace::release(pid);
+    let init = init_pid();
+    if pid == init {
+        panic!("init exited with status {}", wstatus);
+    }
+    let (parent, adopted_zombie) = with_families(|families| {
+        let family = families.entry(pid).or_insert_with(|| Family::new(NO_PARENT));
+        let parent = family.parent;
+        let children = core::mem::replace(&mut family.children, Vec::new());
+        if parent == NO_PARENT {
+            families.remove(&pid);
+        } else {
+            family.exit_status = Some(wstatus);
+        }
+        let mut adopted_zombie = false;
+        for child in children {
+            let zombie = match families.get_mut(&child) {
+                Some(f) => {
+                    f.parent = init;
+                    f.exit_status.is_some()
+                },
+                None => continue,
+            };
+            if init == NO_PARENT {
+                if zombie {
+                    families.remove(&child);
+                }
+            } else {
+                families.entry(init).or_insert_with(|| Family::new(NO_PARENT)).children.push(child);
+                adopted_zombie |= zombie;
+            }
+        }
+        (parent, adopted_zombie)
+    }).unwrap_or((NO_PARENT, false));
+    delete_process(pid);
+    if parent != NO_PARENT {
+        notify_parent(parent);
+    }
+    if adopted_zombie && parent != init {
+        notify_parent(init);
+    }
+}
+
+fn notify_parent(parent: u16) {
+    let _ = signal::send_signal(parent, SIGCHLD);
+    set_running(parent);
+}
+
+pub fn zombies() -> Vec<(u16, u16)> {
+    with_families(|families| {
+        families.iter().filter(|(_, f)| f.exit_status.is_some()).map(|(pid, f)| (*pid, f.parent)).collect()
+    }).unwrap_or_default()
+}
+
+pub fn init_pid() -> u16 {
+    unsafe { INIT }
+}
+
+pub fn start_init(bdev: usize) -> Result<u16, LoadErrors> {
+    let pid = unsafe {
+        if get_by_pid(INIT_PID).is_null() {
+            let buffer = elf::read_file(bdev, INIT_PATH)?;
+            let prc = File::load_proc_as(&buffer, &[INIT_PATH], &[], INIT_PID)?;
+            if NEXT_PID <= INIT_PID {
+                NEXT_PID = INIT_PID + 1;
+            }
+            PROCESS_LIST_MUTEX.spin_lock();
+            if let Some(mut pl) = PROCESS_LIST.take() {
+                pl.push_back(prc);
+                PROCESS_LIST.replace(pl);
+            }
+            PROCESS_LIST_MUTEX.unlock();
+            INIT_PID
+        } else {
+            elf::exec(bdev, INIT_PATH, &[INIT_PATH], &[])?
+        }
+    };
+    unsafe {
+        INIT = pid;
+    }
+    add_child(NO_PARENT, pid);
+    Ok(pid)
+}
+
+pub fn wait(pid: u16, target: isize, options: usize) -> WaitResult {
+    with_families(|families| {
+        let children = match families.get(&pid) {
+            Some(f) if !f.children.is_empty() => f.children.clone(),
+            _ => return WaitResult::NoChildren,
+        };
+        let candidates: Vec<u16> = children.into_iter().filter(|c| target <= 0 || *c as isize == target).collect();
+        if candidates.is_empty() {
+            return WaitResult::NoChildren;
+        }
+        let reaped = candidates.iter().find_map(|c| families.get(c).and_then(|f| f.exit_status).map(|s| (*c, s)));
+        match reaped {
+            Some((child, status)) => {
+                families.remove(&child);
+                if let Some(f) = families.get_mut(&pid) {
+                    f.children.retain(|c| *c != child);
+                }
+                WaitResult::Reaped(child, status)
+            },
+            None if options & WNOHANG != 0 => WaitResult::NotYet,
+            None => {
+                set_waiting(pid);
+                WaitResult::Blocked
+            },
+        }
+    }).unwrap_or(WaitResult::NoChildren)
+}
+
+unsafe fn copy_page(src_table: &Table, dst_table: &mut Table, vaddr: usize, dst: *mut u8, bits: i64) -> bool {
+    match paging::translate(src_table, vaddr) {
+        Some(src) => {
+            memcpy(dst, src as *const u8, PAGE_SIZE);
+            paging::map(dst_table, vaddr, dst as usize, bits).is_ok()
+        },
+        None => true,
+    }
+}
+
+unsafe fn abort_fork(child_pid: u16, table: *mut Table, pages: &[*mut u8]) -> ForkError {
+    vma::release(child_pid);
+    paging::release(child_pid);
+    if !table.is_null() {
+        paging::free_tables(&mut *table);
+    }
+    for &page in pages.iter().filter(|page| !page.is_null()) {
+        dealloc(page);
+    }
+    ForkError::OutOfMemory
+}
+
+pub unsafe fn fork(frame: *mut TrapFrame) -> Result<u16, ForkError> {
+    let parent_pid = (*frame).pid as u16;
+    let parent = get_by_pid(parent_pid);
+    if parent.is_null() || (*parent).mmu_table.is_null() {
+        return Err(ForkError::NoProcess);
+    }
+    let parent_table = &*((*parent).mmu_table as *const Table);
+    let vmas = vma::vmas(parent_pid);
+    let program_end = match vma::heap_start(parent_pid) {
+        0 => (*parent).brk,
+        start => start,
+    };
+    let program_start = vmas.iter()
+                            .filter(|v| v.flags & (VMA_GROWSDOWN | VMA_HEAP | VMA_MMAP) == 0 && v.end <= program_end)
+                            .map(|v| v.start)
+                            .min()
+                            .unwrap_or(program_end);
+    let program_pages = (program_end - program_start) / PAGE_SIZE;
+    let program = zalloc(program_pages.max(1));
+    let stack = zalloc(STACK_PAGES);
+    let child_frame = zalloc(1) as *mut TrapFrame;
+    let mmu_table = zalloc(1) as *mut Table;
+    let mut pages = vec![program, stack, child_frame as *mut u8, mmu_table as *mut u8];
+    let child_pid = NEXT_PID;
+    if pages.iter().any(|page| page.is_null()) {
+        return Err(abort_fork(child_pid, ptr::null_mut(), &pages));
+    }
+    let asid = match paging::alloc_asid(child_pid) {
+        Some(asid) => asid,
+        None => return Err(abort_fork(child_pid, ptr::null_mut(), &pages)),
+    };
+    let table = &mut *mmu_table;
+    for v in vmas.iter() {
+        if matches!(v.backing, VmaBacking::Shared { .. } | VmaBacking::Device { .. }) {
+            if vma::add_vma(child_pid, *v).is_ok() {
+                if let VmaBacking::Shared { id, .. } = v.backing {
+                    shm::add_ref(id);
+                }
+            }
+            continue;
+        }
+        let mut vaddr = v.start;
+        while vaddr < v.end {
+            let copied = if vaddr >= program_start && vaddr < program_end {
+                let dst = program.add(vaddr - program_start);
+                copy_page(parent_table, table, vaddr, dst, v.entry_bits())
+            } else if vaddr >= STACK_ADDR && vaddr < STACK_ADDR + STACK_PAGES * PAGE_SIZE {
+                let dst = stack.add(vaddr - STACK_ADDR);
+                copy_page(parent_table, table, vaddr, dst, v.entry_bits())
+            } else if paging::translate(parent_table, vaddr).is_some() {
+                let dst = zalloc(1);
+                pages.push(dst);
+                !dst.is_null() && copy_page(parent_table, table, vaddr, dst, v.entry_bits())
+            } else {
+                true
+            };
+            if !copied {
+                return Err(abort_fork(child_pid, mmu_table, &pages));
+            }
+            vaddr += PAGE_SIZE;
+        }
+        let _ = vma::add_vma(child_pid, *v);
+    }
+    NEXT_PID += 1;
+    let _ = vma::set_stack_limit(child_pid, vma::stack_limit(parent_pid));
+    vma::set_heap_start(child_pid, vma::heap_start(parent_pid));
+    *child_frame = *frame;
+    (*child_frame).regs[Registers::A0 as usize] = 0;
+    (*child_frame).pid = child_pid as usize;
+    (*child_frame).satp = build_satp(SatpMode::Sv39, asid as usize, mmu_table as usize);
+    let child = Process {
+        frame: child_frame,
+        stack,
+        pid: child_pid,
+        mmu_table,
+        state: ProcessState::Running,
+        data: ProcessData::new(),
+        sleep_until: 0,
+        program,
+        brk: (*parent).brk,
+    };
+    add_child(parent_pid, child_pid);
+    fd::fork(parent_pid, child_pid);
+    session::fork(parent_pid, child_pid);
+    cred::fork(parent_pid, child_pid);
+    signal::fork(parent_pid, child_pid);
+    sched::set_nice(child_pid, sched::get_nice(parent_pid));
+    let (policy, prio) = sched::get_scheduler(parent_pid);
+    let _ = sched::set_scheduler(child_pid, policy, prio as usize);
+    PROCESS_LIST_MUTEX.spin_lock();
+    if let Some(mut pl) = PROCESS_LIST.take() {
+        pl.push_back(child);
+        PROCESS_LIST.replace(pl);
+    }
+    PROCESS_LIST_MUTEX.unlock();
+    Ok(child_pid)
+}
+
+pub fn exec(pid: u16, path: &str, argv: &[&str], envp: &[&str]) -> Result<(), LoadErrors> {
+    let (inode, buffer) = elf::open_executable(elf::ROOT_BDEV, path, pid)?;
+    let saved = cred::get(pid);
+    cred::exec(pid, &inode);
+    let prc = match File::load_proc_as(&buffer, argv, envp, pid) {
+        Ok(prc) => prc,
+        Err(e) => {
+            cred::set(pid, saved);
+            return Err(e);
+        },
+    };
+    signal::reset_handlers(pid);
+    unsafe {
+        PROCESS_LIST_MUTEX.spin_lock();
+        if let Some(mut pl) = PROCESS_LIST.take() {
+            if let Some(old) = pl.iter_mut().find(|p| p.pid == pid) {
+                *old = prc;
+            }
+            PROCESS_LIST.replace(pl);
+        }
+        PROCESS_LIST_MUTEX.unlock();
+    }
+    Ok(())
+}
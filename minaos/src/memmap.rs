@@ -0,0 +1,150 @@
+use crate::{fdt::Fdt, page::PAGE_SIZE};
+
+pub const MAX_REGIONS: usize = 16;
+
+extern "C" {
+    static HEAP_START: usize;
+    static HEAP_SIZE: usize;
+}
+
+#[derive(Copy, Clone)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Region {
+    const EMPTY: Region = Region { start: 0, end: 0 };
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+struct Regions {
+    list: [Region; MAX_REGIONS],
+    len: usize,
+}
+
+impl Regions {
+    const fn new() -> Self {
+        Self {
+            list: [Region::EMPTY; MAX_REGIONS],
+            len: 0,
+        }
+    }
+
+    fn add(&mut self, start: u64, size: u64) {
+        let (start, end) = (start as usize, start.saturating_add(size) as usize);
+        if end <= start {
+            return;
+        }
+        if self.len == MAX_REGIONS {
+            println!("memmap: too many regions, ignoring 0x{:08x}-0x{:08x}", start, end);
+            return;
+        }
+        self.list[self.len] = Region { start, end };
+        self.len += 1;
+    }
+
+    fn as_slice(&self) -> &[Region] {
+        &self.list[..self.len]
+    }
+}
+
+struct MemMap {
+    ram: Regions,
+    reserved: Regions,
+    heap: Region,
+}
+
+static mut MAP: Option<MemMap> = None;
+
+fn linker_heap() -> Region {
+    let (start, size) = unsafe { (HEAP_START, HEAP_SIZE) };
+    Region { start, end: start + size }
+}
+
+fn largest_gap(within: Region, reserved: &[Region]) -> Region {
+    let mut best = Region::EMPTY;
+    let mut start = within.start;
+    loop {
+        let next = reserved.iter()
+                           .filter(|r| r.end > start && r.start < within.end)
+                           .min_by_key(|r| r.start);
+        let end = next.map_or(within.end, |r| r.start.max(start));
+        if end - start > best.size() {
+            best = Region { start, end };
+        }
+        match next {
+            Some(r) => start = r.end.max(start),
+            None => return best,
+        }
+    }
+}
+
+fn build() -> MemMap {
+    let mut map = MemMap {
+        ram: Regions::new(),
+        reserved: Regions::new(),
+        heap: linker_heap(),
+    };
+    let fdt = match Fdt::boot() {
+        Some(fdt) => fdt,
+        None => return map,
+    };
+    fdt.memory(|start, size| map.ram.add(start, size));
+    fdt.reserved_memory(|start, size| map.reserved.add(start, size));
+    map.reserved.add(fdt.base() as u64, fdt.size() as u64);
+    let heap = map.heap;
+    let ram = match map.ram.as_slice().iter().find(|r| r.start <= heap.start && heap.start < r.end) {
+        Some(ram) => *ram,
+        None => return map,
+    };
+    let gap = largest_gap(Region { start: heap.start, end: ram.end }, map.reserved.as_slice());
+    let start = (gap.start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let end = gap.end & !(PAGE_SIZE - 1);
+    if end > start {
+        map.heap = Region { start, end };
+    }
+    map
+}
+
+fn map() -> &'static MemMap {
+    unsafe {
+        if MAP.is_none() {
+            MAP = Some(build());
+        }
+        MAP.as_ref().unwrap()
+    }
+}
+
+pub fn init() {
+    let heap = map().heap;
+    if heap.start != linker_heap().start || heap.end != linker_heap().end {
+        println!("memmap: heap 0x{:08x}-0x{:08x} ({} KiB) from the device tree", heap.start, heap.end, heap.size() / 1024);
+    }
+}
+
+pub fn heap() -> Region {
+    map().heap
+}
+
+pub fn ram_size() -> usize {
+    map().ram.as_slice().iter().map(Region::size).sum()
+}
+
+pub fn is_reserved(addr: usize) -> bool {
+    map().reserved.as_slice().iter().any(|r| r.start <= addr && addr < r.end)
+}
+
+pub fn print() {
+    for r in map().ram.as_slice() {
+        println!("ram       0x{:08x}-0x{:08x} {:>8} KiB", r.start, r.end, r.size() / 1024);
+    }
+    for r in map().reserved.as_slice() {
+        println!("reserved  0x{:08x}-0x{:08x} {:>8} KiB", r.start, r.end, r.size() / 1024);
+    }
+    let heap = map().heap;
+    println!("heap      0x{:08x}-0x{:08x} {:>8} KiB", heap.start, heap.end, heap.size() / 1024);
+}
@@ -0,0 +1,124 @@
+use crate::{cpu::TrapFrame, syscall::user_to_phys};
+
+#[derive(Copy, Clone)]
+enum Target {
+    Int(usize),
+    Float(usize),
+}
+
+#[derive(Copy, Clone)]
+struct Access {
+    store: bool,
+    width: usize,
+    signed: bool,
+    target: Target,
+    len: usize,
+}
+
+unsafe fn read_byte(frame: *const TrapFrame, vaddr: usize) -> Option<u8> {
+    let paddr = user_to_phys(frame, vaddr)?;
+    Some((paddr as *const u8).read_volatile())
+}
+
+unsafe fn write_byte(frame: *const TrapFrame, vaddr: usize, val: u8) -> Option<()> {
+    let paddr = user_to_phys(frame, vaddr)?;
+    (paddr as *mut u8).write_volatile(val);
+    Some(())
+}
+
+unsafe fn fetch(frame: *const TrapFrame, epc: usize) -> Option<u32> {
+    let lo = read_byte(frame, epc)? as u32 | (read_byte(frame, epc + 1)? as u32) << 8;
+    if lo & 3 != 3 {
+        return Some(lo);
+    }
+    let hi = read_byte(frame, epc + 2)? as u32 | (read_byte(frame, epc + 3)? as u32) << 8;
+    Some(lo | hi << 16)
+}
+
+fn decode(inst: u32) -> Option<Access> {
+    let inst = inst as usize;
+    if inst & 3 != 3 {
+        let funct3 = inst >> 13 & 7;
+        let rd_prime = 8 + (inst >> 2 & 7);
+        let rd = inst >> 7 & 0x1f;
+        let rs2 = inst >> 2 & 0x1f;
+        let (store, width, target) = match (inst & 3, funct3) {
+            (0, 1) => (false, 8, Target::Float(rd_prime)),
+            (0, 2) => (false, 4, Target::Int(rd_prime)),
+            (0, 3) => (false, 8, Target::Int(rd_prime)),
+            (0, 5) => (true, 8, Target::Float(rd_prime)),
+            (0, 6) => (true, 4, Target::Int(rd_prime)),
+            (0, 7) => (true, 8, Target::Int(rd_prime)),
+            (2, 1) => (false, 8, Target::Float(rd)),
+            (2, 2) => (false, 4, Target::Int(rd)),
+            (2, 3) => (false, 8, Target::Int(rd)),
+            (2, 5) => (true, 8, Target::Float(rs2)),
+            (2, 6) => (true, 4, Target::Int(rs2)),
+            (2, 7) => (true, 8, Target::Int(rs2)),
+            _ => return None,
+        };
+        return Some(Access { store, width, signed: width == 4, target, len: 2 });
+    }
+    let funct3 = inst >> 12 & 7;
+    let rd = inst >> 7 & 0x1f;
+    let rs2 = inst >> 20 & 0x1f;
+    let (store, width, signed, target) = match (inst & 0x7f, funct3) {
+        (0x03, 1) => (false, 2, true, Target::Int(rd)),
+        (0x03, 2) => (false, 4, true, Target::Int(rd)),
+        (0x03, 3) => (false, 8, false, Target::Int(rd)),
+        (0x03, 5) => (false, 2, false, Target::Int(rd)),
+        (0x03, 6) => (false, 4, false, Target::Int(rd)),
+        (0x07, 2) => (false, 4, false, Target::Float(rd)),
+        (0x07, 3) => (false, 8, false, Target::Float(rd)),
+        (0x23, 1) => (true, 2, false, Target::Int(rs2)),
+        (0x23, 2) => (true, 4, false, Target::Int(rs2)),
+        (0x23, 3) => (true, 8, false, Target::Int(rs2)),
+        (0x27, 2) => (true, 4, false, Target::Float(rs2)),
+        (0x27, 3) => (true, 8, false, Target::Float(rs2)),
+        _ => return None,
+    };
+    Some(Access { store, width, signed, target, len: 4 })
+}
+
+unsafe fn get_target(frame: *const TrapFrame, target: Target) -> usize {
+    match target {
+        Target::Int(0) => 0,
+        Target::Int(r) => (*frame).regs[r],
+        Target::Float(r) => (*frame).fregs[r],
+    }
+}
+
+unsafe fn set_target(frame: *mut TrapFrame, target: Target, val: usize) {
+    match target {
+        Target::Int(0) => {},
+        Target::Int(r) => (*frame).regs[r] = val,
+        Target::Float(r) => (*frame).fregs[r] = val,
+    }
+}
+
+pub unsafe fn emulate(frame: *mut TrapFrame, epc: usize, addr: usize) -> Option<usize> {
+    let access = decode(fetch(frame, epc)?)?;
+    if access.store {
+        let val = get_target(frame, access.target);
+        for i in 0..access.width {
+            write_byte(frame, addr + i, (val >> (i * 8)) as u8)?;
+        }
+    } else {
+        let mut val = 0usize;
+        for i in 0..access.width {
+            val |= (read_byte(frame, addr + i)? as usize) << (i * 8);
+        }
+        if access.width < 8 {
+            let shift = 64 - access.width * 8;
+            val = if access.signed {
+                ((val << shift) as isize >> shift) as usize
+            } else if let Target::Float(_) = access.target {
+                val | !0usize << (access.width * 8)
+            } else {
+                val
+            };
+        }
+        set_target(frame, access.target, val);
+    }
+    Some(epc + access.len)
+}
@@ -0,0 +1,101 @@
+use crate::{fdt::{prop_str, Fdt, FdtEvent},
+            page::PAGE_SIZE,
+            paging::PTE_PBMT_IO,
+            spinlock::SpinLock};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const MAX_REGIONS: usize = 16;
+
+const SVPBMT_UNKNOWN: usize = 0;
+const SVPBMT_ABSENT: usize = 1;
+const SVPBMT_PRESENT: usize = 2;
+
+#[derive(Copy, Clone)]
+pub struct MmioRegion {
+    pub name: &'static str,
+    pub base: usize,
+    pub size: usize,
+    pub io: bool,
+}
+
+pub enum MmioError {
+    Misaligned,
+    Overlap,
+    Full,
+}
+
+static REGIONS: SpinLock<[Option<MmioRegion>; MAX_REGIONS]> = SpinLock::new("mmio", [None; MAX_REGIONS]);
+static SVPBMT: AtomicUsize = AtomicUsize::new(SVPBMT_UNKNOWN);
+
+pub fn register(name: &'static str, base: usize, size: usize) -> Result<(), MmioError> {
+    insert(name, base, size, true)
+}
+
+pub fn register_memory(name: &'static str, base: usize, size: usize) -> Result<(), MmioError> {
+    insert(name, base, size, false)
+}
+
+fn insert(name: &'static str, base: usize, size: usize, io: bool) -> Result<(), MmioError> {
+    let size = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    if base % PAGE_SIZE != 0 || size == 0 || base.checked_add(size).is_none() {
+        return Err(MmioError::Misaligned);
+    }
+    let mut regions = REGIONS.lock();
+    if regions.iter().flatten().any(|r| base < r.base + r.size && r.base < base + size) {
+        return Err(MmioError::Overlap);
+    }
+    match regions.iter_mut().find(|r| r.is_none()) {
+        Some(slot) => {
+            *slot = Some(MmioRegion { name, base, size, io });
+            Ok(())
+        },
+        None => Err(MmioError::Full),
+    }
+}
+
+pub fn unregister(base: usize) {
+    let mut regions = REGIONS.lock();
+    if let Some(slot) = regions.iter_mut().find(|r| r.map_or(false, |r| r.base == base)) {
+        *slot = None;
+    }
+}
+
+pub fn find(base: usize, len: usize) -> Option<MmioRegion> {
+    let end = base.checked_add(len)?;
+    REGIONS.lock().iter().flatten().find(|r| base >= r.base && end <= r.base + r.size).copied()
+}
+
+fn detect_svpbmt() -> bool {
+    let mut found = false;
+    if let Some(fdt) = Fdt::boot() {
+        fdt.walk(|event| {
+            if let FdtEvent::Prop(name, value) = event {
+                found |= match name {
+                    "riscv,isa" => prop_str(value).split('_').any(|ext| ext == "svpbmt"),
+                    "riscv,isa-extensions" => value.split(|&b| b == 0).any(|ext| ext == b"svpbmt"),
+                    _ => false,
+                };
+            }
+        });
+    }
+    found
+}
+
+pub fn device_bits() -> i64 {
+    let svpbmt = match SVPBMT.load(Ordering::Relaxed) {
+        SVPBMT_UNKNOWN => {
+            let svpbmt = if detect_svpbmt() { SVPBMT_PRESENT } else { SVPBMT_ABSENT };
+            SVPBMT.store(svpbmt, Ordering::Relaxed);
+            svpbmt
+        },
+        svpbmt => svpbmt,
+    };
+    if svpbmt == SVPBMT_PRESENT { PTE_PBMT_IO } else { 0 }
+}
+
+pub fn page_bits(paddr: usize) -> i64 {
+    match find(paddr, 1) {
+        Some(region) if !region.io => 0,
+        _ => device_bits(),
+    }
+}
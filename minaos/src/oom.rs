@@ -0,0 +1,92 @@
+use crate::{acct,
+            kasan,
+            lifecycle,
+            signal::{force_signal, SIGKILL},
+            slab,
+            spinlock::SpinLock};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub const OOM_FAIL: usize = 0;
+pub const OOM_RECLAIM: usize = 1;
+pub const OOM_KILL: usize = 2;
+
+pub const MAX_RECLAIMERS: usize = 8;
+
+pub type Reclaimer = fn() -> usize;
+
+static POLICY: AtomicUsize = AtomicUsize::new(OOM_RECLAIM);
+static KILL_PENDING: AtomicBool = AtomicBool::new(false);
+static EVENTS: AtomicUsize = AtomicUsize::new(0);
+static KILLS: AtomicUsize = AtomicUsize::new(0);
+static RECLAIMERS: SpinLock<[Option<Reclaimer>; MAX_RECLAIMERS]> = SpinLock::new("oom", [None; MAX_RECLAIMERS]);
+
+pub fn policy() -> usize {
+    POLICY.load(Ordering::SeqCst)
+}
+
+pub fn set_policy(policy: usize) -> bool {
+    if policy > OOM_KILL {
+        return false;
+    }
+    POLICY.store(policy, Ordering::SeqCst);
+    true
+}
+
+pub fn policy_name(policy: usize) -> &'static str {
+    match policy {
+        OOM_FAIL => "fail",
+        OOM_RECLAIM => "reclaim",
+        _ => "kill",
+    }
+}
+
+pub fn register_reclaimer(f: Reclaimer) -> bool {
+    let mut reclaimers = RECLAIMERS.lock();
+    match reclaimers.iter_mut().find(|r| r.is_none()) {
+        Some(slot) => {
+            *slot = Some(f);
+            true
+        },
+        None => false,
+    }
+}
+
+pub fn reclaim() -> usize {
+    let reclaimers = *RECLAIMERS.lock();
+    kasan::drain() + slab::reclaim() + reclaimers.iter().flatten().map(|f| f()).sum::<usize>()
+}
+
+pub fn out_of_memory(size: usize) -> bool {
+    EVENTS.fetch_add(1, Ordering::SeqCst);
+    match policy() {
+        OOM_RECLAIM => reclaim() > 0,
+        OOM_KILL => {
+            if !KILL_PENDING.swap(true, Ordering::SeqCst) {
+                klog!("oom: {}-byte allocation failed, killing largest process", size);
+            }
+            reclaim() > 0
+        },
+        _ => false,
+    }
+}
+
+pub fn reap() {
+    if !KILL_PENDING.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let init = lifecycle::init_pid();
+    let victim = acct::snapshot().into_iter().filter(|p| p.pid != init && p.vsize > 0).max_by_key(|p| p.vsize);
+    if let Some(p) = victim {
+        klog!("oom: killed pid {} ({} bytes mapped)", p.pid, p.vsize);
+        KILLS.fetch_add(1, Ordering::SeqCst);
+        force_signal(p.pid, SIGKILL);
+    }
+}
+
+pub fn events() -> usize {
+    EVENTS.load(Ordering::SeqCst)
+}
+
+pub fn kills() -> usize {
+    KILLS.load(Ordering::SeqCst)
+}
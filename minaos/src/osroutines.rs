@@ -1,6 +1,6 @@
-use crate::{block, block::setup_block_device, page::PAGE_SIZE};
+use crate::{block, block::setup_block_device, mmio, page::PAGE_SIZE};
 use crate::rng::setup_entropy_device;
-use crate::{gpu, gpu::setup_gpu_device};
+use crate::{fbcon, gpu, gpu::setup_gpu_device};
 use crate::{input, input::setup_input_device};
 use core::men::size_of;
 
@@ -242,6 +242,13 @@ pub fn probe() {
                             let idx = (addr - MMIO_IO_START) >> 12;
                             unsafe {
                                 IO_DEVICES[idx] = Some(IoDevice::new_with(DeviceTypes::Gpu));
+                                if let Some(dev) = gpu::GPU_DEVICES[idx].as_ref() {
+                                    let size = dev.get_width() as usize * dev.get_height() as usize * size_of::<gpu::Pixel>();
+                                    if mmio::register_memory("framebuffer", dev.get_framebuffer() as usize, size).is_err() {
+                                        println!("unable to expose the framebuffer to userspace");
+                                    }
+                                }
+                                fbcon::attach(idx);
                             }
                             println!("setup succeeded.");
                         }
@@ -0,0 +1,308 @@
+use crate::{hart::hart_id,
+            lock::Mutex,
+            page::{dealloc, zalloc, Entry, Table, PAGE_SIZE},
+            smp::{self, IPI_TLB_FLUSH}};
+use alloc::collections::BTreeMap;
+
+pub const PTE_VALID: i64 = 1 << 0;
+pub const PTE_READ: i64 = 1 << 1;
+pub const PTE_WRITE: i64 = 1 << 2;
+pub const PTE_EXEC: i64 = 1 << 3;
+pub const PTE_USER: i64 = 1 << 4;
+pub const PTE_GLOBAL: i64 = 1 << 5;
+pub const PTE_ACCESSED: i64 = 1 << 6;
+pub const PTE_DIRTY: i64 = 1 << 7;
+pub const PTE_PERMS: i64 = PTE_READ | PTE_WRITE | PTE_EXEC | PTE_USER | PTE_GLOBAL;
+pub const PTE_PBMT_NC: i64 = 1 << 61;
+pub const PTE_PBMT_IO: i64 = 2 << 61;
+pub const PTE_PBMT: i64 = 3 << 61;
+const PTE_PPN: i64 = 0x003f_ffff_ffff_fc00;
+
+pub const LEVELS: usize = 3;
+pub const ENTRIES: usize = 512;
+pub const MEGA_PAGE_SIZE: usize = PAGE_SIZE << 9;
+pub const MAX_VADDR: usize = 1 << 38;
+
+pub const ASID_KERNEL: u16 = 0;
+pub const MAX_ASID: u16 = 0xffff;
+
+pub enum MapError {
+    Misaligned,
+    OutOfRange,
+    InvalidPermissions,
+    AlreadyMapped,
+    OutOfMemory,
+}
+
+struct Asids {
+    next: u16,
+    owners: BTreeMap<u16, u16>,
+    by_pid: BTreeMap<u16, u16>,
+}
+
+static mut ASIDS: Option<Asids> = None;
+static mut ASID_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        ASIDS.replace(Asids {
+            next: ASID_KERNEL + 1,
+            owners: BTreeMap::new(),
+            by_pid: BTreeMap::new(),
+        });
+    }
+}
+
+fn with_asids<T, F: FnOnce(&mut Asids) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        ASID_LOCK.spin_lock();
+        if let Some(mut asids) = ASIDS.take() {
+            ret = Some(f(&mut asids));
+            ASIDS.replace(asids);
+        }
+        ASID_LOCK.unlock();
+    }
+    ret
+}
+
+fn vpn(vaddr: usize, level: usize) -> usize {
+    (vaddr >> (12 + 9 * level)) & (ENTRIES - 1)
+}
+
+fn entry_paddr(entry: &Entry) -> usize {
+    ((entry.get_entry() & PTE_PPN) << 2) as usize
+}
+
+fn leaf(paddr: usize, perms: i64) -> i64 {
+    ((paddr as i64 >> 2) & PTE_PPN) | perms | PTE_VALID | PTE_ACCESSED | PTE_DIRTY
+}
+
+fn valid_perms(perms: i64) -> bool {
+    perms & (PTE_READ | PTE_WRITE | PTE_EXEC) != 0 && perms & (PTE_READ | PTE_WRITE) != PTE_WRITE
+        && perms & !(PTE_PERMS | PTE_PBMT) == 0
+}
+
+fn canonical(vaddr: usize) -> bool {
+    vaddr < MAX_VADDR / 2 || vaddr >= !(MAX_VADDR / 2 - 1)
+}
+
+unsafe fn walk(table: &Table, vaddr: usize) -> Option<(*mut Entry, usize)> {
+    if !canonical(vaddr) {
+        return None;
+    }
+    let mut v = &table.entries[vpn(vaddr, LEVELS - 1)] as *const Entry as *mut Entry;
+    for level in (0..LEVELS).rev() {
+        if !(*v).is_valid() {
+            return None;
+        }
+        if (*v).is_leaf() {
+            return Some((v, level));
+        }
+        if level == 0 {
+            return None;
+        }
+        v = (entry_paddr(&*v) as *mut Entry).add(vpn(vaddr, level - 1));
+    }
+    None
+}
+
+fn level_size(level: usize) -> usize {
+    PAGE_SIZE << (9 * level)
+}
+
+pub fn map_level(table: &mut Table, vaddr: usize, paddr: usize, perms: i64, level: usize) -> Result<(), MapError> {
+    if level >= LEVELS - 1 || vaddr % level_size(level) != 0 || paddr % level_size(level) != 0 {
+        return Err(MapError::Misaligned);
+    }
+    if !canonical(vaddr) {
+        return Err(MapError::OutOfRange);
+    }
+    if !valid_perms(perms) {
+        return Err(MapError::InvalidPermissions);
+    }
+    unsafe {
+        let mut v = &mut table.entries[vpn(vaddr, LEVELS - 1)] as *mut Entry;
+        for l in (level + 1..LEVELS).rev() {
+            if !(*v).is_valid() {
+                let page = zalloc(1);
+                if page.is_null() {
+                    return Err(MapError::OutOfMemory);
+                }
+                (*v).set_entry((page as i64 >> 2) | PTE_VALID);
+            } else if (*v).is_leaf() {
+                return Err(MapError::AlreadyMapped);
+            }
+            v = (entry_paddr(&*v) as *mut Entry).add(vpn(vaddr, l - 1));
+        }
+        if (*v).is_valid() {
+            return Err(MapError::AlreadyMapped);
+        }
+        (*v).set_entry(leaf(paddr, perms));
+    }
+    Ok(())
+}
+
+pub fn map(table: &mut Table, vaddr: usize, paddr: usize, perms: i64) -> Result<(), MapError> {
+    map_level(table, vaddr, paddr, perms, 0)
+}
+
+pub fn map_range(table: &mut Table, vaddr: usize, paddr: usize, len: usize, perms: i64) -> Result<(), MapError> {
+    let mut off = 0;
+    while off < len {
+        let (va, pa) = (vaddr + off, paddr + off);
+        if (va | pa) % MEGA_PAGE_SIZE == 0 && len - off >= MEGA_PAGE_SIZE {
+            match map_level(table, va, pa, perms, 1) {
+                Ok(()) => {
+                    off += MEGA_PAGE_SIZE;
+                    continue;
+                },
+                Err(MapError::AlreadyMapped) => {},
+                Err(e) => return Err(e),
+            }
+        }
+        map(table, va, pa, perms)?;
+        off += PAGE_SIZE;
+    }
+    Ok(())
+}
+
+unsafe fn split(v: *mut Entry, level: usize) -> bool {
+    let page = zalloc(1) as *mut Entry;
+    if page.is_null() {
+        return false;
+    }
+    let base = entry_paddr(&*v);
+    let bits = (*v).get_entry() & (0x3ff | PTE_PBMT);
+    for i in 0..ENTRIES {
+        (*page.add(i)).set_entry((((base + i * level_size(level - 1)) as i64 >> 2) & PTE_PPN) | bits);
+    }
+    (*v).set_entry((page as i64 >> 2) | PTE_VALID);
+    true
+}
+
+unsafe fn walk_page(table: &mut Table, vaddr: usize) -> Option<*mut Entry> {
+    loop {
+        let (v, level) = walk(table, vaddr)?;
+        if level == 0 {
+            return Some(v);
+        }
+        if !split(v, level) {
+            return None;
+        }
+    }
+}
+
+pub fn unmap(table: &mut Table, vaddr: usize) -> Option<usize> {
+    unsafe {
+        let v = walk_page(table, vaddr)?;
+        let paddr = entry_paddr(&*v);
+        (*v).set_entry(0);
+        Some(paddr)
+    }
+}
+
+pub fn protect(table: &mut Table, vaddr: usize, perms: i64) -> Result<(), MapError> {
+    if !valid_perms(perms) {
+        return Err(MapError::InvalidPermissions);
+    }
+    unsafe {
+        match walk_page(table, vaddr) {
+            Some(v) => {
+                (*v).set_entry(leaf(entry_paddr(&*v), perms | (*v).get_entry() & PTE_PBMT));
+                Ok(())
+            },
+            None => Err(MapError::OutOfRange),
+        }
+    }
+}
+
+pub fn lookup(table: &Table, vaddr: usize) -> Option<(usize, i64)> {
+    unsafe {
+        let (v, level) = walk(table, vaddr)?;
+        let size = level_size(level);
+        let paddr = entry_paddr(&*v) + (vaddr & (size - 1) & !(PAGE_SIZE - 1));
+        Some((paddr, (*v).get_entry() & PTE_PERMS))
+    }
+}
+
+pub fn translate(table: &Table, vaddr: usize) -> Option<usize> {
+    lookup(table, vaddr).map(|(paddr, _)| paddr + (vaddr & (PAGE_SIZE - 1)))
+}
+
+pub fn free_tables(table: &mut Table) {
+    unsafe {
+        free_level(table, LEVELS - 1);
+    }
+}
+
+unsafe fn free_level(table: &mut Table, level: usize) {
+    for entry in table.entries.iter_mut() {
+        if entry.is_valid() && !entry.is_leaf() {
+            let next = entry_paddr(entry) as *mut Table;
+            if level > 1 {
+                free_level(&mut *next, level - 1);
+            }
+            dealloc(next as *mut u8);
+            entry.set_entry(0);
+        }
+    }
+}
+
+pub fn alloc_asid(pid: u16) -> Option<u16> {
+    let asid = with_asids(|asids| {
+        if let Some(&asid) = asids.by_pid.get(&pid) {
+            return Some(asid);
+        }
+        if asids.owners.len() >= MAX_ASID as usize {
+            return None;
+        }
+        let mut asid = asids.next;
+        while asid == ASID_KERNEL || asids.owners.contains_key(&asid) {
+            asid = asid.wrapping_add(1);
+        }
+        asids.next = asid.wrapping_add(1);
+        asids.owners.insert(asid, pid);
+        asids.by_pid.insert(pid, asid);
+        Some(asid)
+    }).flatten()?;
+    flush_asid(asid);
+    Some(asid)
+}
+
+pub fn asid_of(pid: u16) -> Option<u16> {
+    with_asids(|asids| asids.by_pid.get(&pid).copied()).flatten()
+}
+
+pub fn release(pid: u16) {
+    with_asids(|asids| {
+        if let Some(asid) = asids.by_pid.remove(&pid) {
+            asids.owners.remove(&asid);
+        }
+    });
+}
+
+pub fn asids_in_use() -> usize {
+    with_asids(|asids| asids.owners.len()).unwrap_or(0)
+}
+
+pub fn flush_page(asid: u16, vaddr: usize) {
+    unsafe {
+        asm!("sfence.vma {}, {}", in(reg) vaddr, in(reg) asid as usize);
+    }
+}
+
+pub fn flush_asid(asid: u16) {
+    unsafe {
+        asm!("sfence.vma zero, {}", in(reg) asid as usize);
+    }
+    if smp::num_harts() > 1 {
+        smp::broadcast_ipi(hart_id(), IPI_TLB_FLUSH);
+    }
+}
+
+pub fn flush_all() {
+    unsafe {
+        asm!("sfence.vma");
+    }
+}
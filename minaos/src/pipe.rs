@@ -0,0 +1,203 @@
+use crate::{lock::Mutex,
+            poll::{self, POLLERR, POLLHUP, POLLIN, POLLOUT},
+            waitqueue::WaitQueue};
+use alloc::collections::{BTreeMap, VecDeque};
+
+pub const PIPE_BUFFER_SIZE: usize = 4096;
+
+pub enum PipeResult {
+    Done(usize),
+    Blocked,
+    Broken,
+}
+
+pub struct Pipe {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+    read_wait: WaitQueue,
+    write_wait: WaitQueue,
+}
+
+impl Pipe {
+    fn new() -> Self {
+        Pipe {
+            buffer: VecDeque::with_capacity(PIPE_BUFFER_SIZE),
+            readers: 1,
+            writers: 1,
+            read_wait: WaitQueue::new("pipe read"),
+            write_wait: WaitQueue::new("pipe write"),
+        }
+    }
+}
+
+static mut PIPES: Option<BTreeMap<usize, Pipe>> = None;
+static mut PIPE_LOCK: Mutex = Mutex::new();
+static mut NEXT_PIPE: usize = 1;
+
+pub fn init() {
+    unsafe {
+        PIPES.replace(BTreeMap::new());
+    }
+}
+
+fn with_pipes<T, F: FnOnce(&mut BTreeMap<usize, Pipe>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        PIPE_LOCK.spin_lock();
+        if let Some(mut pipes) = PIPES.take() {
+            ret = Some(f(&mut pipes));
+            PIPES.replace(pipes);
+        }
+        PIPE_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn create() -> Option<usize> {
+    with_pipes(|pipes| {
+        let id = unsafe {
+            let id = NEXT_PIPE;
+            NEXT_PIPE += 1;
+            id
+        };
+        pipes.insert(id, Pipe::new());
+        id
+    })
+}
+
+pub fn read(pid: u16, id: usize, buffer: *mut u8, size: usize) -> PipeResult {
+    with_pipes(|pipes| {
+        let pipe = match pipes.get_mut(&id) {
+            Some(pipe) => pipe,
+            None => return PipeResult::Broken,
+        };
+        if pipe.buffer.is_empty() {
+            if pipe.writers == 0 {
+                return PipeResult::Done(0);
+            }
+            pipe.read_wait.sleep(pid);
+            return PipeResult::Blocked;
+        }
+        let mut read = 0;
+        while read < size {
+            match pipe.buffer.pop_front() {
+                Some(c) => unsafe {
+                    buffer.add(read).write(c);
+                    read += 1;
+                },
+                None => break,
+            }
+        }
+        pipe.write_wait.wake_all();
+        poll::notify();
+        PipeResult::Done(read)
+    }).unwrap_or(PipeResult::Broken)
+}
+
+pub fn write(pid: u16, id: usize, buffer: *const u8, size: usize) -> PipeResult {
+    write_inner(Some(pid), id, buffer, size)
+}
+
+pub fn try_write(id: usize, buffer: *const u8, size: usize) -> PipeResult {
+    write_inner(None, id, buffer, size)
+}
+
+fn write_inner(pid: Option<u16>, id: usize, buffer: *const u8, size: usize) -> PipeResult {
+    with_pipes(|pipes| {
+        let pipe = match pipes.get_mut(&id) {
+            Some(pipe) => pipe,
+            None => return PipeResult::Broken,
+        };
+        if pipe.readers == 0 {
+            return PipeResult::Broken;
+        }
+        let space = PIPE_BUFFER_SIZE - pipe.buffer.len();
+        if space == 0 && size > 0 {
+            if let Some(pid) = pid {
+                pipe.write_wait.sleep(pid);
+            }
+            return PipeResult::Blocked;
+        }
+        let count = size.min(space);
+        for i in 0..count {
+            pipe.buffer.push_back(unsafe { buffer.add(i).read() });
+        }
+        pipe.read_wait.wake_all();
+        poll::notify();
+        PipeResult::Done(count)
+    }).unwrap_or(PipeResult::Broken)
+}
+
+pub fn add_reader(id: usize) {
+    with_pipes(|pipes| {
+        if let Some(pipe) = pipes.get_mut(&id) {
+            pipe.readers += 1;
+        }
+    });
+}
+
+pub fn add_writer(id: usize) {
+    with_pipes(|pipes| {
+        if let Some(pipe) = pipes.get_mut(&id) {
+            pipe.writers += 1;
+        }
+    });
+}
+
+fn release_if_unused(pipes: &mut BTreeMap<usize, Pipe>, id: usize) {
+    if pipes.get(&id).map_or(false, |p| p.readers == 0 && p.writers == 0) {
+        pipes.remove(&id);
+    }
+}
+
+pub fn close_reader(id: usize) {
+    with_pipes(|pipes| {
+        if let Some(pipe) = pipes.get_mut(&id) {
+            pipe.readers = pipe.readers.saturating_sub(1);
+            if pipe.readers == 0 {
+                pipe.write_wait.wake_all();
+                poll::notify();
+            }
+        }
+        release_if_unused(pipes, id);
+    });
+}
+
+pub fn close_writer(id: usize) {
+    with_pipes(|pipes| {
+        if let Some(pipe) = pipes.get_mut(&id) {
+            pipe.writers = pipe.writers.saturating_sub(1);
+            if pipe.writers == 0 {
+                pipe.read_wait.wake_all();
+                poll::notify();
+            }
+        }
+        release_if_unused(pipes, id);
+    });
+}
+
+pub fn poll_read(id: usize) -> i16 {
+    with_pipes(|pipes| match pipes.get(&id) {
+        Some(pipe) => {
+            let mut events = 0;
+            if !pipe.buffer.is_empty() {
+                events |= POLLIN;
+            }
+            if pipe.writers == 0 {
+                events |= POLLHUP;
+            }
+            events
+        },
+        None => POLLERR,
+    }).unwrap_or(POLLERR)
+}
+
+pub fn poll_write(id: usize) -> i16 {
+    with_pipes(|pipes| match pipes.get(&id) {
+        Some(pipe) if pipe.readers == 0 => POLLERR,
+        Some(pipe) if pipe.buffer.len() < PIPE_BUFFER_SIZE => POLLOUT,
+        Some(_) => 0,
+        None => POLLERR,
+    }).unwrap_or(POLLERR)
+}
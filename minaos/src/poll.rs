@@ -0,0 +1,133 @@
+use crate::{fd,
+            lock::Mutex,
+            time::{self, nanos_to_ticks},
+            timer::{self, TimerCallback},
+            waitqueue::WaitQueue};
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub const POLLIN: i16 = 0x001;
+pub const POLLPRI: i16 = 0x002;
+pub const POLLOUT: i16 = 0x004;
+pub const POLLERR: i16 = 0x008;
+pub const POLLHUP: i16 = 0x010;
+pub const POLLNVAL: i16 = 0x020;
+
+pub const MAX_POLL_FDS: usize = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+pub enum PollResult {
+    Ready(usize),
+    Blocked,
+}
+
+struct Pending {
+    fds: usize,
+    deadline: Option<u64>,
+    timer: Option<u64>,
+}
+
+static SEQ: AtomicU64 = AtomicU64::new(0);
+static POLL_WAIT: WaitQueue = WaitQueue::new("poll");
+
+static mut PENDING: Option<BTreeMap<u16, Pending>> = None;
+static mut POLL_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        PENDING.replace(BTreeMap::new());
+    }
+}
+
+fn with_pending<T, F: FnOnce(&mut BTreeMap<u16, Pending>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        POLL_LOCK.spin_lock();
+        if let Some(mut pending) = PENDING.take() {
+            ret = Some(f(&mut pending));
+            PENDING.replace(pending);
+        }
+        POLL_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn notify() {
+    SEQ.fetch_add(1, Ordering::SeqCst);
+    POLL_WAIT.wake_all();
+}
+
+fn scan(pid: u16, fds: &mut [PollFd]) -> usize {
+    let mut ready = 0;
+    for p in fds.iter_mut() {
+        p.revents = if p.fd < 0 {
+            0
+        } else {
+            fd::poll(pid, p.fd as usize) & (p.events | POLLERR | POLLHUP | POLLNVAL)
+        };
+        if p.revents != 0 {
+            ready += 1;
+        }
+    }
+    ready
+}
+
+pub fn poll(pid: u16, token: usize, fds: &mut [PollFd], timeout_ns: Option<u64>) -> PollResult {
+    loop {
+        let seq = SEQ.load(Ordering::SeqCst);
+        let ready = scan(pid, fds);
+        if ready > 0 || timeout_ns == Some(0) {
+            release(pid);
+            return PollResult::Ready(ready);
+        }
+        let now = time::ticks();
+        let deadline = with_pending(|pending| {
+            let entry = pending.entry(pid).or_insert(Pending {
+                fds: token,
+                deadline: None,
+                timer: None,
+            });
+            if entry.fds != token || (entry.deadline.is_none() && entry.timer.is_none()) {
+                if let Some(id) = entry.timer.take() {
+                    timer::cancel_timer(id);
+                }
+                entry.fds = token;
+                entry.deadline = timeout_ns.map(|ns| now + nanos_to_ticks(ns));
+            }
+            entry.deadline
+        }).flatten();
+        if let Some(deadline) = deadline {
+            if now >= deadline {
+                release(pid);
+                return PollResult::Ready(0);
+            }
+        }
+        if POLL_WAIT.sleep_on(pid, || SEQ.load(Ordering::SeqCst) != seq) {
+            continue;
+        }
+        if let Some(deadline) = deadline {
+            with_pending(|pending| {
+                if let Some(entry) = pending.get_mut(&pid) {
+                    if entry.timer.is_none() {
+                        entry.timer = Some(timer::add_timer_at(deadline, TimerCallback::Wake(pid)));
+                    }
+                }
+            });
+        }
+        return PollResult::Blocked;
+    }
+}
+
+pub fn release(pid: u16) {
+    if let Some(Some(id)) = with_pending(|pending| pending.remove(&pid).and_then(|entry| entry.timer)) {
+        timer::cancel_timer(id);
+    }
+    POLL_WAIT.remove(pid);
+}
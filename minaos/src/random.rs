@@ -0,0 +1,159 @@
+use crate::{spinlock::SpinLock,
+            time,
+            waitqueue::WaitQueue};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub const GRND_NONBLOCK: usize = 1;
+pub const GRND_RANDOM: usize = 2;
+
+pub const SEED_BITS: usize = 256;
+pub const BLOCK_BYTES: usize = 64;
+
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+struct Pool {
+    key: [u32; 8],
+    input: [u32; 8],
+    cursor: usize,
+    counter: u64,
+    credited: usize,
+}
+
+static POOL: SpinLock<Pool> = SpinLock::new("random", Pool {
+    key: [0; 8],
+    input: [0; 8],
+    cursor: 0,
+    counter: 0,
+    credited: 0,
+});
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static SEED_WAIT: WaitQueue = WaitQueue::new("random_seed");
+
+fn quarter(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+fn chacha20(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut init = [0u32; 16];
+    init[..4].copy_from_slice(&CONSTANTS);
+    init[4..12].copy_from_slice(key);
+    init[12] = counter as u32;
+    init[13] = (counter >> 32) as u32;
+    let mut s = init;
+    for _ in 0..10 {
+        quarter(&mut s, 0, 4, 8, 12);
+        quarter(&mut s, 1, 5, 9, 13);
+        quarter(&mut s, 2, 6, 10, 14);
+        quarter(&mut s, 3, 7, 11, 15);
+        quarter(&mut s, 0, 5, 10, 15);
+        quarter(&mut s, 1, 6, 11, 12);
+        quarter(&mut s, 2, 7, 8, 13);
+        quarter(&mut s, 3, 4, 9, 14);
+    }
+    for (word, orig) in s.iter_mut().zip(init.iter()) {
+        *word = word.wrapping_add(*orig);
+    }
+    s
+}
+
+impl Pool {
+    fn mix(&mut self, word: u32) {
+        let i = self.cursor % self.input.len();
+        self.input[i] = self.input[i].rotate_left(7) ^ word;
+        self.cursor = self.cursor.wrapping_add(1);
+    }
+
+    fn reseed(&mut self) {
+        let mut key = self.key;
+        for (k, input) in key.iter_mut().zip(self.input.iter()) {
+            *k ^= *input;
+        }
+        let block = chacha20(&key, self.counter);
+        self.key.copy_from_slice(&block[..8]);
+        self.input = [0; 8];
+        self.counter = self.counter.wrapping_add(1);
+    }
+
+    fn credit(&mut self, bits: usize) -> bool {
+        self.credited = self.credited.saturating_add(bits);
+        if self.credited >= SEED_BITS && !SEEDED.load(Ordering::SeqCst) {
+            self.reseed();
+            SEEDED.store(true, Ordering::SeqCst);
+            return true;
+        }
+        false
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(BLOCK_BYTES) {
+            let block = chacha20(&self.key, self.counter);
+            self.counter = self.counter.wrapping_add(1);
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = (block[i / 4] >> (8 * (i % 4))) as u8;
+            }
+        }
+        let next = chacha20(&self.key, self.counter);
+        self.counter = self.counter.wrapping_add(1);
+        self.key.copy_from_slice(&next[..8]);
+    }
+}
+
+pub fn init() {
+    let mut pool = POOL.lock();
+    let now = time::ticks();
+    let wall = time::wall_nanos();
+    pool.mix(now as u32);
+    pool.mix((now >> 32) as u32);
+    pool.mix(wall as u32);
+    pool.mix((wall >> 32) as u32);
+    pool.reseed();
+}
+
+pub fn add_entropy(data: &[u8], bits: usize) {
+    let seeded = {
+        let mut pool = POOL.lock();
+        for chunk in data.chunks(4) {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            pool.mix(u32::from_le_bytes(word));
+        }
+        if SEEDED.load(Ordering::SeqCst) {
+            pool.reseed();
+        }
+        pool.credit(bits)
+    };
+    if seeded {
+        SEED_WAIT.wake_all();
+    }
+}
+
+pub fn add_interrupt_entropy(irq: usize) {
+    let now = time::ticks();
+    let seeded = {
+        let mut pool = POOL.lock();
+        pool.mix(now as u32 ^ ((irq as u32) << 24));
+        pool.credit(1)
+    };
+    if seeded {
+        SEED_WAIT.wake_all();
+    }
+}
+
+pub fn is_seeded() -> bool {
+    SEEDED.load(Ordering::SeqCst)
+}
+
+pub fn fill(buf: &mut [u8]) {
+    POOL.lock().fill(buf);
+}
+
+pub fn wait_seeded(pid: u16) -> bool {
+    SEED_WAIT.sleep_on(pid, is_seeded)
+}
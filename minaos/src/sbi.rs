@@ -0,0 +1,215 @@
+use crate::{smp::CLINT_MSIP,
+            trap::MMIO_MTIMECMP};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub const SBI_SUCCESS: isize = 0;
+pub const SBI_ERR_NOT_SUPPORTED: isize = -2;
+
+pub const LEGACY_SET_TIMER: usize = 0x00;
+pub const LEGACY_CONSOLE_PUTCHAR: usize = 0x01;
+pub const LEGACY_CLEAR_IPI: usize = 0x03;
+pub const LEGACY_SEND_IPI: usize = 0x04;
+pub const LEGACY_SHUTDOWN: usize = 0x08;
+
+pub const EXT_BASE: usize = 0x10;
+pub const EXT_TIME: usize = 0x5449_4D45;
+pub const EXT_IPI: usize = 0x0073_5049;
+pub const EXT_HSM: usize = 0x0048_534D;
+pub const EXT_SRST: usize = 0x5352_5354;
+
+pub const BASE_GET_SPEC_VERSION: usize = 0;
+pub const BASE_PROBE_EXTENSION: usize = 3;
+
+pub const HSM_HART_START: usize = 0;
+pub const HSM_HART_STOP: usize = 1;
+pub const HSM_HART_GET_STATUS: usize = 2;
+
+pub const HSM_STARTED: usize = 0;
+pub const HSM_STOPPED: usize = 1;
+
+pub const RESET_SHUTDOWN: usize = 0;
+pub const RESET_COLD_REBOOT: usize = 1;
+pub const RESET_WARM_REBOOT: usize = 2;
+
+pub const RESET_REASON_NONE: usize = 0;
+pub const RESET_REASON_FAILURE: usize = 1;
+
+pub const SIFIVE_TEST: *mut u32 = 0x0010_0000 as *mut u32;
+pub const TEST_PASS: u32 = 0x5555;
+pub const TEST_FAIL: u32 = 0x3333;
+pub const TEST_RESET: u32 = 0x7777;
+
+#[derive(Copy, Clone)]
+pub struct SbiRet {
+    pub error: isize,
+    pub value: usize,
+}
+
+static SUPERVISOR: AtomicBool = AtomicBool::new(false);
+static SPEC_VERSION: AtomicUsize = AtomicUsize::new(0);
+static EXTENSIONS: AtomicUsize = AtomicUsize::new(0);
+
+const HAS_TIME: usize = 1 << 0;
+const HAS_IPI: usize = 1 << 1;
+const HAS_HSM: usize = 1 << 2;
+const HAS_SRST: usize = 1 << 3;
+
+extern "C" {
+    fn _start_hart();
+}
+
+unsafe fn ecall(ext: usize, fid: usize, a0: usize, a1: usize, a2: usize) -> SbiRet {
+    let error: isize;
+    let value: usize;
+    asm!("ecall",
+         inlateout("a0") a0 => error,
+         inlateout("a1") a1 => value,
+         in("a2") a2,
+         in("a6") fid,
+         in("a7") ext);
+    SbiRet { error, value }
+}
+
+unsafe fn legacy(ext: usize, a0: usize) -> isize {
+    ecall(ext, 0, a0, 0, 0).error
+}
+
+pub fn init(supervisor: bool) {
+    SUPERVISOR.store(supervisor, Ordering::SeqCst);
+    if !supervisor {
+        return;
+    }
+    let version = unsafe { ecall(EXT_BASE, BASE_GET_SPEC_VERSION, 0, 0, 0) };
+    if version.error != SBI_SUCCESS {
+        return;
+    }
+    SPEC_VERSION.store(version.value, Ordering::SeqCst);
+    let mut extensions = 0;
+    for (ext, bit) in [(EXT_TIME, HAS_TIME), (EXT_IPI, HAS_IPI), (EXT_HSM, HAS_HSM), (EXT_SRST, HAS_SRST)].iter() {
+        if probe(*ext) {
+            extensions |= bit;
+        }
+    }
+    EXTENSIONS.store(extensions, Ordering::SeqCst);
+}
+
+pub fn is_supervisor() -> bool {
+    SUPERVISOR.load(Ordering::SeqCst)
+}
+
+pub fn spec_version() -> (usize, usize) {
+    let version = SPEC_VERSION.load(Ordering::SeqCst);
+    ((version >> 24) & 0x7f, version & 0xff_ffff)
+}
+
+pub fn probe(ext: usize) -> bool {
+    let ret = unsafe { ecall(EXT_BASE, BASE_PROBE_EXTENSION, ext, 0, 0) };
+    ret.error == SBI_SUCCESS && ret.value != 0
+}
+
+fn has(ext: usize) -> bool {
+    EXTENSIONS.load(Ordering::SeqCst) & ext != 0
+}
+
+pub fn set_timer(hart: usize, deadline: u64) {
+    unsafe {
+        if !is_supervisor() {
+            MMIO_MTIMECMP.add(hart).write_volatile(deadline);
+        } else if has(HAS_TIME) {
+            ecall(EXT_TIME, 0, deadline as usize, 0, 0);
+        } else {
+            legacy(LEGACY_SET_TIMER, deadline as usize);
+        }
+    }
+}
+
+pub fn send_ipi(hart: usize) {
+    unsafe {
+        if !is_supervisor() {
+            CLINT_MSIP.add(hart).write_volatile(1);
+        } else if has(HAS_IPI) {
+            ecall(EXT_IPI, 0, 1, hart, 0);
+        } else {
+            let mask = 1usize << hart;
+            legacy(LEGACY_SEND_IPI, &mask as *const usize as usize);
+        }
+    }
+}
+
+pub fn clear_ipi(hart: usize) {
+    unsafe {
+        if !is_supervisor() {
+            CLINT_MSIP.add(hart).write_volatile(0);
+        } else if has(HAS_IPI) {
+            asm!("csrc sip, {}", in(reg) 1usize << 1);
+        } else {
+            legacy(LEGACY_CLEAR_IPI, 0);
+        }
+    }
+}
+
+pub fn hart_start(hart: usize, opaque: usize) -> Result<(), isize> {
+    if !is_supervisor() {
+        send_ipi(hart);
+        return Ok(());
+    }
+    if !has(HAS_HSM) {
+        return Err(SBI_ERR_NOT_SUPPORTED);
+    }
+    let ret = unsafe { ecall(EXT_HSM, HSM_HART_START, hart, _start_hart as usize, opaque) };
+    match ret.error {
+        SBI_SUCCESS => Ok(()),
+        error => Err(error),
+    }
+}
+
+pub fn hart_stop() -> ! {
+    unsafe {
+        if is_supervisor() && has(HAS_HSM) {
+            ecall(EXT_HSM, HSM_HART_STOP, 0, 0, 0);
+        }
+        loop {
+            asm!("wfi");
+        }
+    }
+}
+
+pub fn hart_status(hart: usize) -> Option<usize> {
+    if !is_supervisor() || !has(HAS_HSM) {
+        return None;
+    }
+    let ret = unsafe { ecall(EXT_HSM, HSM_HART_GET_STATUS, hart, 0, 0) };
+    if ret.error == SBI_SUCCESS {
+        Some(ret.value)
+    } else {
+        None
+    }
+}
+
+pub fn system_reset(kind: usize, reason: usize) -> ! {
+    unsafe {
+        if !is_supervisor() {
+            let code = match (kind, reason) {
+                (RESET_SHUTDOWN, RESET_REASON_NONE) => TEST_PASS,
+                (RESET_SHUTDOWN, _) => TEST_FAIL | (1 << 16),
+                _ => TEST_RESET,
+            };
+            SIFIVE_TEST.write_volatile(code);
+        } else if has(HAS_SRST) {
+            ecall(EXT_SRST, 0, kind, reason, 0);
+        } else if kind == RESET_SHUTDOWN {
+            legacy(LEGACY_SHUTDOWN, 0);
+        }
+        loop {
+            asm!("wfi");
+        }
+    }
+}
+
+pub fn shutdown() -> ! {
+    system_reset(RESET_SHUTDOWN, RESET_REASON_NONE)
+}
+
+pub fn reboot() -> ! {
+    system_reset(RESET_COLD_REBOOT, RESET_REASON_NONE)
+}
@@ -0,0 +1,417 @@
+use crate::{hart::{hart_id, is_running_elsewhere, online_mask, set_current_pid, MAX_HARTS, NO_PID},
+            lock::Mutex,
+            process::{Process, ProcessState, PROCESS_LIST, PROCESS_LIST_MUTEX},
+            smp::{self, IPI_RESCHEDULE},
+            trap::MMIO_MTIME};
+use alloc::{collections::{BTreeMap, VecDeque}, vec::Vec};
+
+pub const NUM_LEVELS: usize = 8;
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+pub const NICE_DEFAULT: i8 = 0;
+pub const BOOST_INTERVAL: usize = 32;
+pub const MAX_TIMESLICE: u16 = 8;
+
+pub const PRIO_PROCESS: usize = 0;
+
+pub const SCHED_OTHER: usize = 0;
+pub const SCHED_FIFO: usize = 1;
+pub const RT_PRIO_MIN: u8 = 1;
+pub const RT_PRIO_MAX: u8 = 99;
+
+pub const ALL_HARTS: usize = (1 << MAX_HARTS) - 1;
+
+pub enum AffinityError {
+    EmptyMask,
+    NoProcess,
+}
+
+pub enum PolicyError {
+    InvalidPolicy,
+    InvalidPriority,
+}
+
+pub fn nice_to_level(nice: i8) -> usize {
+    (nice - NICE_MIN) as usize * NUM_LEVELS / (NICE_MAX - NICE_MIN + 1) as usize
+}
+
+pub fn nice_to_timeslice(nice: i8) -> u16 {
+    if nice >= NICE_DEFAULT {
+        1
+    } else {
+        (1 + (-nice) as u16 / 5).min(MAX_TIMESLICE)
+    }
+}
+
+pub struct RunQueues {
+    queues: [[VecDeque<u16>; NUM_LEVELS]; MAX_HARTS],
+    owner: BTreeMap<u16, usize>,
+    nice: BTreeMap<u16, i8>,
+    affinity: BTreeMap<u16, usize>,
+    rt: VecDeque<u16>,
+    rt_prio: BTreeMap<u16, u8>,
+    inherited: BTreeMap<u16, Vec<(usize, u8)>>,
+    picks: [usize; MAX_HARTS],
+}
+
+impl RunQueues {
+    pub fn new() -> Self {
+        RunQueues {
+            queues: Default::default(),
+            owner: BTreeMap::new(),
+            nice: BTreeMap::new(),
+            affinity: BTreeMap::new(),
+            rt: VecDeque::new(),
+            rt_prio: BTreeMap::new(),
+            inherited: BTreeMap::new(),
+            picks: [0; MAX_HARTS],
+        }
+    }
+
+    pub fn len(&self, hart: usize) -> usize {
+        self.queues[hart].iter().map(|q| q.len()).sum()
+    }
+
+    fn least_loaded(&self, online: usize, mask: usize) -> usize {
+        let allowed = if online & mask != 0 { online & mask } else { online };
+        (0..MAX_HARTS).filter(|h| allowed & (1 << h) != 0).min_by_key(|&h| self.len(h)).unwrap_or(0)
+    }
+
+    fn busiest(&self, online: usize, except: usize) -> Option<usize> {
+        (0..MAX_HARTS)
+            .filter(|&h| h != except && online & (1 << h) != 0)
+            .max_by_key(|&h| self.len(h))
+            .filter(|&h| self.len(h) > 1)
+    }
+
+    pub fn affinity(&self, pid: u16) -> usize {
+        self.affinity.get(&pid).copied().unwrap_or(ALL_HARTS)
+    }
+
+    pub fn set_affinity(&mut self, pid: u16, mask: usize, online: usize) {
+        if mask & ALL_HARTS == ALL_HARTS {
+            self.affinity.remove(&pid);
+        } else {
+            self.affinity.insert(pid, mask);
+        }
+        if let Some(&hart) = self.owner.get(&pid) {
+            if mask & (1 << hart) == 0 {
+                let target = self.least_loaded(online, mask);
+                self.enqueue(pid, target);
+            }
+        }
+    }
+
+    pub fn nice(&self, pid: u16) -> i8 {
+        self.nice.get(&pid).copied().unwrap_or(NICE_DEFAULT)
+    }
+
+    pub fn set_nice(&mut self, pid: u16, nice: i8) {
+        self.nice.insert(pid, nice.max(NICE_MIN).min(NICE_MAX));
+        if let Some(&hart) = self.owner.get(&pid) {
+            self.enqueue(pid, hart);
+        }
+    }
+
+    pub fn rt_priority(&self, pid: u16) -> u8 {
+        let base = self.rt_prio.get(&pid).copied().unwrap_or(0);
+        self.inherited.get(&pid).into_iter().flatten().map(|(_, prio)| *prio).fold(base, u8::max)
+    }
+
+    pub fn set_rt_priority(&mut self, pid: u16, prio: u8) {
+        if prio == 0 {
+            self.rt_prio.remove(&pid);
+        } else {
+            self.rt_prio.insert(pid, prio);
+        }
+        if let Some(&hart) = self.owner.get(&pid) {
+            self.enqueue(pid, hart);
+        }
+    }
+
+    pub fn inherit(&mut self, pid: u16, key: usize, prio: u8) {
+        let boosts = self.inherited.entry(pid).or_insert_with(Vec::new);
+        match boosts.iter_mut().find(|(k, _)| *k == key) {
+            Some(boost) => boost.1 = boost.1.max(prio),
+            None => boosts.push((key, prio)),
+        }
+        if let Some(&hart) = self.owner.get(&pid) {
+            self.enqueue(pid, hart);
+        }
+    }
+
+    pub fn disinherit(&mut self, pid: u16, key: usize) {
+        if let Some(boosts) = self.inherited.get_mut(&pid) {
+            boosts.retain(|(k, _)| *k != key);
+            if boosts.is_empty() {
+                self.inherited.remove(&pid);
+            }
+        }
+        if let Some(&hart) = self.owner.get(&pid) {
+            self.enqueue(pid, hart);
+        }
+    }
+
+    pub fn enqueue(&mut self, pid: u16, hart: usize) {
+        self.dequeue(pid);
+        if self.rt_priority(pid) > 0 {
+            self.rt.push_back(pid);
+        } else {
+            let level = nice_to_level(self.nice(pid));
+            self.queues[hart][level].push_back(pid);
+        }
+        self.owner.insert(pid, hart);
+    }
+
+    pub fn dequeue(&mut self, pid: u16) {
+        if let Some(hart) = self.owner.remove(&pid) {
+            for q in self.queues[hart].iter_mut() {
+                q.retain(|&p| p != pid);
+            }
+            self.rt.retain(|&p| p != pid);
+        }
+    }
+
+    fn requeue_rt(&mut self, pid: u16) {
+        if let Some(idx) = self.rt.iter().position(|&p| p == pid) {
+            self.rt.remove(idx);
+            self.rt.push_back(pid);
+        }
+    }
+
+    fn sync(&mut self, pl: &VecDeque<Process>, online: usize) {
+        for prc in pl.iter() {
+            if !self.owner.contains_key(&prc.pid) {
+                let hart = self.least_loaded(online, self.affinity(prc.pid));
+                self.enqueue(prc.pid, hart);
+            }
+        }
+        let stale: Vec<u16> = self.owner.keys().filter(|pid| !pl.iter().any(|p| p.pid == **pid)).copied().collect();
+        for pid in stale {
+            self.dequeue(pid);
+            self.nice.remove(&pid);
+            self.affinity.remove(&pid);
+            self.rt_prio.remove(&pid);
+            self.inherited.remove(&pid);
+        }
+    }
+}
+
+static mut RUN_QUEUES: Option<RunQueues> = None;
+static mut RUN_QUEUE_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        RUN_QUEUES.replace(RunQueues::new());
+    }
+}
+
+fn runnable(prc: &mut Process, now: usize) -> bool {
+    if let ProcessState::Sleeping = prc.state {
+        if prc.sleep_until <= now {
+            prc.state = ProcessState::Running;
+        }
+    }
+    if let ProcessState::Running = prc.state {
+        true
+    } else {
+        false
+    }
+}
+
+fn pick_level(rq: &mut RunQueues, pl: &mut VecDeque<Process>, hart: usize, from: usize, level: usize, now: usize) -> Option<u16> {
+    let affinity = &rq.affinity;
+    let queue = &mut rq.queues[from][level];
+    for _ in 0..queue.len() {
+        let pid = queue.pop_front()?;
+        queue.push_back(pid);
+        if is_running_elsewhere(hart, pid) || affinity.get(&pid).map_or(false, |mask| mask & (1 << hart) == 0) {
+            continue;
+        }
+        if let Some(prc) = pl.iter_mut().find(|p| p.pid == pid) {
+            if runnable(prc, now) {
+                return Some(pid);
+            }
+        }
+    }
+    None
+}
+
+fn pick_rt(rq: &RunQueues, pl: &mut VecDeque<Process>, hart: usize, now: usize) -> Option<u16> {
+    let mut best: Option<(u16, u8)> = None;
+    for &pid in rq.rt.iter() {
+        let prio = rq.rt_priority(pid);
+        if best.map_or(false, |(_, p)| p >= prio) {
+            continue;
+        }
+        if is_running_elsewhere(hart, pid) || rq.affinity(pid) & (1 << hart) == 0 {
+            continue;
+        }
+        if let Some(prc) = pl.iter_mut().find(|p| p.pid == pid) {
+            if runnable(prc, now) {
+                best = Some((pid, prio));
+            }
+        }
+    }
+    best.map(|(pid, _)| pid)
+}
+
+fn pick(rq: &mut RunQueues, pl: &mut VecDeque<Process>, hart: usize, from: usize, now: usize) -> Option<u16> {
+    if let Some(pid) = pick_rt(rq, pl, hart, now) {
+        return Some(pid);
+    }
+    rq.picks[hart] += 1;
+    if rq.picks[hart] % BOOST_INTERVAL == 0 {
+        for level in (0..NUM_LEVELS).rev() {
+            if let Some(pid) = pick_level(rq, pl, hart, from, level, now) {
+                return Some(pid);
+            }
+        }
+        return None;
+    }
+    for level in 0..NUM_LEVELS {
+        if let Some(pid) = pick_level(rq, pl, hart, from, level, now) {
+            return Some(pid);
+        }
+    }
+    None
+}
+
+pub fn schedule(hart: usize) -> usize {
+    let mut frame_addr: usize = 0;
+    let mut pid = NO_PID;
+    unsafe {
+        PROCESS_LIST_MUTEX.spin_lock();
+        RUN_QUEUE_LOCK.spin_lock();
+        if let Some(mut pl) = PROCESS_LIST.take() {
+            if let Some(mut rq) = RUN_QUEUES.take() {
+                let now = MMIO_MTIME.read_volatile() as usize;
+                let online = online_mask() | 1 << hart;
+                rq.sync(&pl, online);
+                let mut chosen = pick(&mut rq, &mut pl, hart, hart, now);
+                if chosen.is_none() {
+                    if let Some(victim) = rq.busiest(online, hart) {
+                        chosen = pick(&mut rq, &mut pl, hart, victim, now);
+                        if let Some(stolen) = chosen {
+                            if rq.rt_priority(stolen) == 0 {
+                                rq.enqueue(stolen, hart);
+                            }
+                        }
+                    }
+                }
+                if let Some(p) = chosen {
+                    if let Some(prc) = pl.iter_mut().find(|prc| prc.pid == p) {
+                        (*prc.frame).hartid = hart;
+                        frame_addr = prc.frame as usize;
+                        pid = p;
+                    }
+                }
+                RUN_QUEUES.replace(rq);
+            }
+            PROCESS_LIST.replace(pl);
+        }
+        set_current_pid(hart, pid);
+        RUN_QUEUE_LOCK.unlock();
+        PROCESS_LIST_MUTEX.unlock();
+    }
+    frame_addr
+}
+
+fn with_run_queues<T, F: FnOnce(&mut RunQueues) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        RUN_QUEUE_LOCK.spin_lock();
+        if let Some(mut rq) = RUN_QUEUES.take() {
+            ret = Some(f(&mut rq));
+            RUN_QUEUES.replace(rq);
+        }
+        RUN_QUEUE_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn get_nice(pid: u16) -> i8 {
+    with_run_queues(|rq| rq.nice(pid)).unwrap_or(NICE_DEFAULT)
+}
+
+pub fn set_nice(pid: u16, nice: i8) {
+    with_run_queues(|rq| rq.set_nice(pid, nice));
+}
+
+pub fn nice(pid: u16, inc: i8) -> i8 {
+    with_run_queues(|rq| {
+        let nice = rq.nice(pid).saturating_add(inc).max(NICE_MIN).min(NICE_MAX);
+        rq.set_nice(pid, nice);
+        nice
+    }).unwrap_or(NICE_DEFAULT)
+}
+
+pub fn timeslice(pid: u16) -> u16 {
+    with_run_queues(|rq| {
+        if rq.rt_priority(pid) > 0 {
+            MAX_TIMESLICE
+        } else {
+            nice_to_timeslice(rq.nice(pid))
+        }
+    }).unwrap_or(1)
+}
+
+pub fn get_scheduler(pid: u16) -> (usize, u8) {
+    with_run_queues(|rq| match rq.rt_prio.get(&pid) {
+        Some(&prio) => (SCHED_FIFO, prio),
+        None => (SCHED_OTHER, 0),
+    }).unwrap_or((SCHED_OTHER, 0))
+}
+
+pub fn set_scheduler(pid: u16, policy: usize, prio: usize) -> Result<(), PolicyError> {
+    let prio = match policy {
+        SCHED_OTHER if prio == 0 => 0,
+        SCHED_FIFO if prio >= RT_PRIO_MIN as usize && prio <= RT_PRIO_MAX as usize => prio as u8,
+        SCHED_OTHER | SCHED_FIFO => return Err(PolicyError::InvalidPriority),
+        _ => return Err(PolicyError::InvalidPolicy),
+    };
+    with_run_queues(|rq| rq.set_rt_priority(pid, prio));
+    preempt();
+    Ok(())
+}
+
+pub fn rt_priority(pid: u16) -> u8 {
+    with_run_queues(|rq| rq.rt_priority(pid)).unwrap_or(0)
+}
+
+pub fn inherit(pid: u16, key: usize, prio: u8) {
+    with_run_queues(|rq| rq.inherit(pid, key, prio));
+}
+
+pub fn disinherit(pid: u16, key: usize) {
+    with_run_queues(|rq| rq.disinherit(pid, key));
+}
+
+pub fn yield_now(pid: u16) {
+    with_run_queues(|rq| rq.requeue_rt(pid));
+}
+
+pub fn preempt() {
+    let hart = hart_id();
+    smp::broadcast_ipi(hart, IPI_RESCHEDULE);
+    smp::send_ipi(hart, IPI_RESCHEDULE);
+}
+
+pub fn get_affinity(pid: u16) -> usize {
+    with_run_queues(|rq| rq.affinity(pid)).unwrap_or(ALL_HARTS)
+}
+
+pub fn set_affinity(pid: u16, mask: usize) -> Result<(), AffinityError> {
+    let online = online_mask();
+    if mask & online == 0 {
+        return Err(AffinityError::EmptyMask);
+    }
+    with_run_queues(|rq| rq.set_affinity(pid, mask, online)).ok_or(AffinityError::NoProcess)
+}
+
+pub fn pin(pid: u16, hart: usize) -> Result<(), AffinityError> {
+    if hart >= MAX_HARTS {
+        return Err(AffinityError::EmptyMask);
+    }
+    set_affinity(pid, 1 << hart)
+}
@@ -0,0 +1,26 @@
+use crate::page::{dealloc, PAGE_SIZE};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(cfg!(debug_assertions));
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub unsafe fn zero(ptr: *mut u8, len: usize) {
+    if is_enabled() && !ptr.is_null() {
+        ptr.write_bytes(0, len);
+    }
+}
+
+pub unsafe fn free_pages(ptr: *mut u8, pages: usize) {
+    if ptr.is_null() {
+        return;
+    }
+    zero(ptr, pages * PAGE_SIZE);
+    dealloc(ptr);
+}
@@ -0,0 +1,218 @@
+use crate::{lifecycle,
+            lock::Mutex,
+            signal::{self, SIGCONT, SIGHUP, SIGTTIN}};
+use alloc::{collections::BTreeMap, vec::Vec};
+
+pub const NO_SESSION: u16 = 0;
+
+pub enum JobError {
+    NoProcess,
+    Permission,
+    InvalidGroup,
+    NotTty,
+}
+
+#[derive(Copy, Clone)]
+struct Membership {
+    pgid: u16,
+    sid: u16,
+}
+
+struct Tty {
+    session: u16,
+    foreground: u16,
+}
+
+struct Sessions {
+    members: BTreeMap<u16, Membership>,
+    tty: Tty,
+}
+
+impl Sessions {
+    fn get(&mut self, pid: u16) -> Membership {
+        *self.members.entry(pid).or_insert(Membership { pgid: pid, sid: pid })
+    }
+
+    fn group_exists(&self, pgid: u16, sid: u16) -> bool {
+        self.members.values().any(|m| m.pgid == pgid && m.sid == sid)
+    }
+
+    fn group(&self, pgid: u16) -> Vec<u16> {
+        self.members.iter().filter(|(_, m)| m.pgid == pgid).map(|(pid, _)| *pid).collect()
+    }
+}
+
+static mut SESSIONS: Option<Sessions> = None;
+static mut SESSION_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        SESSIONS.replace(Sessions {
+            members: BTreeMap::new(),
+            tty: Tty {
+                session: NO_SESSION,
+                foreground: 0,
+            },
+        });
+    }
+}
+
+fn with_sessions<T, F: FnOnce(&mut Sessions) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        SESSION_LOCK.spin_lock();
+        if let Some(mut sessions) = SESSIONS.take() {
+            ret = Some(f(&mut sessions));
+            SESSIONS.replace(sessions);
+        }
+        SESSION_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn fork(parent: u16, child: u16) {
+    with_sessions(|s| {
+        let m = s.get(parent);
+        s.members.insert(child, m);
+    });
+}
+
+pub fn release(pid: u16) {
+    let hangup = with_sessions(|s| {
+        s.members.remove(&pid);
+        if s.tty.session != pid {
+            return Vec::new();
+        }
+        let foreground = s.tty.foreground;
+        s.tty.session = NO_SESSION;
+        s.tty.foreground = 0;
+        s.group(foreground)
+    }).unwrap_or_default();
+    for member in hangup {
+        let _ = signal::send_signal(member, SIGHUP);
+        let _ = signal::send_signal(member, SIGCONT);
+    }
+}
+
+pub fn getpgid(pid: u16) -> u16 {
+    with_sessions(|s| s.get(pid).pgid).unwrap_or(pid)
+}
+
+pub fn getsid(pid: u16) -> u16 {
+    with_sessions(|s| s.get(pid).sid).unwrap_or(pid)
+}
+
+pub fn setpgid(caller: u16, pid: u16, pgid: u16) -> Result<(), JobError> {
+    let pid = if pid == 0 { caller } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    if pid != caller && lifecycle::parent_of(pid) != caller {
+        return Err(JobError::NoProcess);
+    }
+    with_sessions(|s| {
+        let me = s.get(caller);
+        let target = s.get(pid);
+        if target.sid != me.sid || target.sid == pid {
+            return Err(JobError::Permission);
+        }
+        if pgid != pid && !s.group_exists(pgid, me.sid) {
+            return Err(JobError::Permission);
+        }
+        s.members.insert(pid, Membership { pgid, sid: target.sid });
+        Ok(())
+    }).unwrap_or(Err(JobError::NoProcess))
+}
+
+pub fn setsid(pid: u16) -> Result<u16, JobError> {
+    with_sessions(|s| {
+        s.get(pid);
+        if s.members.iter().any(|(p, m)| *p != pid && m.pgid == pid) {
+            return Err(JobError::Permission);
+        }
+        s.members.insert(pid, Membership { pgid: pid, sid: pid });
+        Ok(pid)
+    }).unwrap_or(Err(JobError::NoProcess))
+}
+
+pub fn set_controlling_tty(pid: u16) -> Result<(), JobError> {
+    with_sessions(|s| {
+        let me = s.get(pid);
+        if me.sid != pid {
+            return Err(JobError::Permission);
+        }
+        if s.tty.session != NO_SESSION && s.tty.session != pid {
+            return Err(JobError::Permission);
+        }
+        s.tty.session = pid;
+        s.tty.foreground = me.pgid;
+        Ok(())
+    }).unwrap_or(Err(JobError::NoProcess))
+}
+
+pub fn tcgetpgrp(pid: u16) -> Result<u16, JobError> {
+    with_sessions(|s| {
+        if s.tty.session == NO_SESSION || s.get(pid).sid != s.tty.session {
+            return Err(JobError::NotTty);
+        }
+        Ok(s.tty.foreground)
+    }).unwrap_or(Err(JobError::NoProcess))
+}
+
+pub fn tcsetpgrp(pid: u16, pgid: u16) -> Result<(), JobError> {
+    with_sessions(|s| {
+        let me = s.get(pid);
+        if s.tty.session == NO_SESSION || me.sid != s.tty.session {
+            return Err(JobError::NotTty);
+        }
+        if !s.group_exists(pgid, me.sid) {
+            return Err(JobError::InvalidGroup);
+        }
+        s.tty.foreground = pgid;
+        Ok(())
+    }).unwrap_or(Err(JobError::NoProcess))
+}
+
+pub fn members(pgid: u16) -> Vec<u16> {
+    with_sessions(|s| s.group(pgid)).unwrap_or_default()
+}
+
+pub fn kill_group(pgid: u16, sig: usize) -> Result<(), JobError> {
+    let members = members(pgid);
+    if members.is_empty() {
+        return Err(JobError::InvalidGroup);
+    }
+    for member in members {
+        let _ = signal::send_signal(member, sig);
+    }
+    Ok(())
+}
+
+pub fn tty_signal(sig: usize) {
+    let foreground = with_sessions(|s| {
+        if s.tty.session == NO_SESSION {
+            None
+        } else {
+            Some(s.tty.foreground)
+        }
+    }).flatten();
+    if let Some(pgid) = foreground {
+        let _ = kill_group(pgid, sig);
+    }
+}
+
+pub fn may_read_tty(pid: u16) -> bool {
+    let background = with_sessions(|s| {
+        let me = s.get(pid);
+        if s.tty.session != NO_SESSION && me.sid == s.tty.session && me.pgid != s.tty.foreground {
+            Some(me.pgid)
+        } else {
+            None
+        }
+    }).flatten();
+    match background {
+        Some(pgid) => {
+            let _ = kill_group(pgid, SIGTTIN);
+            false
+        },
+        None => true,
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{acct,
+            buffer::Buffer,
+            console,
+            coredump,
+            elf,
+            fd::STDIN_FILENO,
+            fs::{FileSystem, S_IFDIR},
+            irqstat,
+            kasan,
+            klog,
+            kthread,
+            memmap,
+            lifecycle,
+            oom::{self, OOM_FAIL, OOM_KILL, OOM_RECLAIM},
+            page::print_page_allocations,
+            sbi,
+            scrub,
+            slab,
+            strace,
+            syscall::syscall_read,
+            xmodem};
+use alloc::{string::String, vec::Vec};
+
+pub const DEFAULT_BDEV: usize = 8;
+pub const MAX_LINE: usize = 256;
+pub const PROMPT: &str = "mina> ";
+
+static mut CURRENT_BDEV: usize = DEFAULT_BDEV;
+
+pub fn spawn_if_no_init() -> bool {
+    if lifecycle::init_pid() != lifecycle::NO_PARENT {
+        return false;
+    }
+    if FileSystem::is_mounted(DEFAULT_BDEV) && lifecycle::start_init(DEFAULT_BDEV).is_ok() {
+        return false;
+    }
+    let _ = kthread::spawn("shell", shell_proc);
+    true
+}
+
+#[no_mangle]
+extern "C" fn kinit_userspace() {
+    spawn_if_no_init();
+}
+
+fn read_line(line: &mut String) -> bool {
+    let mut c = 0u8;
+    line.clear();
+    loop {
+        if syscall_read(STDIN_FILENO, &mut c as *mut u8, 1) != 1 {
+            return false;
+        }
+        match c {
+            10 | 13 => return true,
+            8 | 127 => {
+                line.pop();
+            },
+            0x20..=0x7e => {
+                if line.len() < MAX_LINE {
+                    line.push(c as char);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+fn shell_proc() {
+    let mut line = String::with_capacity(MAX_LINE);
+    console::set_canonical(true);
+    println!("MinaOS kernel shell. Type 'help' for a list of commands.");
+    loop {
+        print!("{}", PROMPT);
+        if !read_line(&mut line) {
+            continue;
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            continue;
+        }
+        match args[0] {
+            "help" => help(),
+            "ls" => ls(args.get(1).copied().unwrap_or("/")),
+            "cat" => {
+                for path in &args[1..] {
+                    cat(path);
+                }
+            },
+            "ps" => ps(),
+            "free" => print_page_allocations(),
+            "memmap" => memmap::print(),
+            "mount" => mount(&args[1..]),
+            "run" => run(&args[1..]),
+            "rx" => rx(&args[1..]),
+            "dmesg" => klog::dump(),
+            "irqs" => irqstat::dump(),
+            "slabs" => slab::print_stats(),
+            "meminfo" => slab::print_heap(),
+            "oom" => oom_policy(&args[1..]),
+            "wipe" => wipe_frees(&args[1..]),
+            "asan" => sanitizer(&args[1..]),
+            "poweroff" => sbi::shutdown(),
+            "reboot" => sbi::reboot(),
+            "strace" => strace(&args[1..]),
+            "core" => core_dumps(&args[1..]),
+            cmd => println!("{}: command not found", cmd),
+        }
+    }
+}
+
+fn help() {
+    println!("help          show this message");
+    println!("ls [dir]      list files");
+    println!("cat <file>..  print files");
+    println!("ps            list processes");
+    println!("free          show page allocations");
+    println!("memmap        show RAM and reserved memory regions");
+    println!("mount [bdev]  mount a block device or list mounts");
+    println!("run <elf> ..  start a user program");
+    println!("rx <file>     receive a file over XMODEM");
+    println!("dmesg         show the kernel log");
+    println!("irqs          show interrupt counts");
+    println!("slabs         show slab allocator statistics");
+    println!("meminfo       show heap usage");
+    println!("oom [policy]  show or set the OOM policy");
+    println!("wipe [on|off] zero freed pages and poison freed heap objects");
+    println!("asan [on|off] redzone and quarantine heap allocations");
+    println!("poweroff      shut the machine down");
+    println!("reboot        reset the machine");
+    println!("strace <pid>  toggle syscall tracing to the kernel log");
+    println!("core [on|off] write cores of crashed processes to /core");
+}
+
+fn current_bdev() -> Option<usize> {
+    let bdev = unsafe { CURRENT_BDEV };
+    if FileSystem::is_mounted(bdev) {
+        Some(bdev)
+    } else {
+        println!("no filesystem mounted");
+        None
+    }
+}
+
+fn ls(dir: &str) {
+    let bdev = match current_bdev() {
+        Some(bdev) => bdev,
+        None => return,
+    };
+    let prefix = if dir.ends_with('/') { String::from(dir) } else { format!("{}/", dir) };
+    let mut last = String::new();
+    for path in FileSystem::paths(bdev) {
+        let rest = match path.strip_prefix(prefix.as_str()) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let child = match rest.find('/') {
+            Some(slash) => &rest[..=slash],
+            None => rest,
+        };
+        if child != last {
+            println!("{}", child);
+            last = String::from(child);
+        }
+    }
+}
+
+fn cat(path: &str) {
+    let bdev = match current_bdev() {
+        Some(bdev) => bdev,
+        None => return,
+    };
+    let inode = match FileSystem::open(bdev, path) {
+        Ok(inode) => inode,
+        Err(_) => {
+            println!("cat: {}: no such file", path);
+            return;
+        },
+    };
+    if inode.mode & S_IFDIR != 0 {
+        println!("cat: {}: is a directory", path);
+        return;
+    }
+    let mut buffer = Buffer::new(inode.size as usize);
+    let bytes = FileSystem::read(bdev, &inode, buffer.get_mut(), inode.size, 0);
+    for i in 0..bytes as usize {
+        print!("{}", buffer[i] as char);
+    }
+    println!();
+}
+
+fn ps() {
+    println!("  PID  PPID STATE    NICE   UTIME(ms)   STIME(ms)      VSIZE NAME");
+    for p in acct::snapshot() {
+        let state = match p.state {
+            acct::STATE_RUNNING => "running",
+            acct::STATE_SLEEPING => "sleeping",
+            acct::STATE_WAITING => "waiting",
+            acct::STATE_ZOMBIE => "zombie",
+            _ => "dead",
+        };
+        println!("{:>5} {:>5} {:<8} {:>4} {:>11} {:>11} {:>10} {}",
+                 p.pid,
+                 p.ppid,
+                 state,
+                 p.nice,
+                 p.utime_ns / 1_000_000,
+                 p.stime_ns / 1_000_000,
+                 p.vsize,
+                 kthread::name(p.pid).unwrap_or("-"));
+    }
+}
+
+fn mount(args: &[&str]) {
+    match args.first() {
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(bdev) if bdev >= 1 && bdev <= 8 => {
+                FileSystem::init(bdev);
+                unsafe {
+                    CURRENT_BDEV = bdev;
+                }
+            },
+            _ => println!("mount: invalid block device {}", arg),
+        },
+        None => {
+            for bdev in 1..=8 {
+                if FileSystem::is_mounted(bdev) {
+                    println!("bdev {} on / type minix3", bdev);
+                }
+            }
+        },
+    }
+}
+
+fn run(args: &[&str]) {
+    let bdev = match current_bdev() {
+        Some(bdev) => bdev,
+        None => return,
+    };
+    match args.first() {
+        Some(path) => match elf::exec(bdev, path, args, &[]) {
+            Ok(pid) => println!("started pid {}", pid),
+            Err(e) => println!("run: {}: {:?}", path, e),
+        },
+        None => println!("usage: run <elf> [args..]"),
+    }
+}
+
+fn rx(args: &[&str]) {
+    let bdev = match current_bdev() {
+        Some(bdev) => bdev,
+        None => return,
+    };
+    match args.first() {
+        Some(path) => {
+            println!("rx: waiting for XMODEM sender...");
+            match xmodem::receive_file(bdev, path) {
+                Ok(bytes) => println!("rx: wrote {} bytes to {}", bytes, path),
+                Err(_) => println!("rx: transfer failed"),
+            }
+        },
+        None => println!("usage: rx <file>"),
+    }
+}
+
+fn strace(args: &[&str]) {
+    match args.first().and_then(|arg| arg.parse::<u16>().ok()) {
+        Some(pid) => {
+            if strace::sink(pid).is_some() {
+                strace::set_trace(pid, None);
+                println!("strace: stopped tracing {}", pid);
+            } else {
+                strace::set_trace(pid, Some(strace::TraceSink::Klog));
+                println!("strace: tracing {}", pid);
+            }
+        },
+        None => println!("usage: strace <pid>"),
+    }
+}
+
+fn core_dumps(args: &[&str]) {
+    match args.first() {
+        Some(&"on") => coredump::set_enabled(true),
+        Some(&"off") => coredump::set_enabled(false),
+        Some(_) => {
+            println!("usage: core [on|off]");
+            return;
+        },
+        None => {},
+    }
+    println!("core dumps: {}", if coredump::is_enabled() { "on" } else { "off" });
+}
+
+fn oom_policy(args: &[&str]) {
+    let policy = match args.first() {
+        Some(&"fail") => OOM_FAIL,
+        Some(&"reclaim") => OOM_RECLAIM,
+        Some(&"kill") => OOM_KILL,
+        Some(_) => {
+            println!("usage: oom [fail|reclaim|kill]");
+            return;
+        },
+        None => oom::policy(),
+    };
+    oom::set_policy(policy);
+    println!("oom policy: {}", oom::policy_name(policy));
+}
+
+fn wipe_frees(args: &[&str]) {
+    match args.first() {
+        Some(&"on") => scrub::set_enabled(true),
+        Some(&"off") => scrub::set_enabled(false),
+        Some(_) => {
+            println!("usage: wipe [on|off]");
+            return;
+        },
+        None => {},
+    }
+    println!("wipe on free: {}", if scrub::is_enabled() { "on" } else { "off" });
+}
+
+fn sanitizer(args: &[&str]) {
+    match args.first() {
+        Some(&"on") => kasan::set_enabled(true),
+        Some(&"off") => kasan::set_enabled(false),
+        Some(_) => {
+            println!("usage: asan [on|off]");
+            return;
+        },
+        None => {},
+    }
+    let (objects, bytes) = kasan::quarantined();
+    println!("kasan: {}, {} reports, {} objects ({} bytes) quarantined",
+             if kasan::is_enabled() { "on" } else { "off" },
+             kasan::reports(),
+             objects,
+             bytes);
+}
@@ -0,0 +1,134 @@
+use crate::{lock::Mutex,
+            page::{zalloc, PAGE_SIZE},
+            scrub};
+use alloc::{collections::BTreeMap, string::String};
+
+pub const O_CREAT: usize = 0o100;
+pub const O_EXCL: usize = 0o200;
+
+pub const MAX_SHM_SIZE: usize = 16 * 1024 * 1024;
+
+pub enum ShmError {
+    NotFound,
+    Exists,
+    InvalidSize,
+    OutOfMemory,
+}
+
+struct Segment {
+    name: String,
+    base: *mut u8,
+    size: usize,
+    refs: usize,
+    unlinked: bool,
+}
+
+static mut SEGMENTS: Option<BTreeMap<usize, Segment>> = None;
+static mut SHM_LOCK: Mutex = Mutex::new();
+static mut NEXT_SEGMENT: usize = 1;
+
+pub fn init() {
+    unsafe {
+        SEGMENTS.replace(BTreeMap::new());
+    }
+}
+
+fn with_segments<T, F: FnOnce(&mut BTreeMap<usize, Segment>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        SHM_LOCK.spin_lock();
+        if let Some(mut segments) = SEGMENTS.take() {
+            ret = Some(f(&mut segments));
+            SEGMENTS.replace(segments);
+        }
+        SHM_LOCK.unlock();
+    }
+    ret
+}
+
+fn release_if_unused(segments: &mut BTreeMap<usize, Segment>, id: usize) {
+    if segments.get(&id).map_or(false, |s| s.refs == 0 && s.unlinked) {
+        if let Some(segment) = segments.remove(&id) {
+            unsafe {
+                scrub::free_pages(segment.base, (segment.size + PAGE_SIZE - 1) / PAGE_SIZE);
+            }
+        }
+    }
+}
+
+pub fn open(name: &str, flags: usize, size: usize) -> Result<usize, ShmError> {
+    with_segments(|segments| {
+        let existing = segments.iter().find(|(_, s)| !s.unlinked && s.name == name).map(|(id, _)| *id);
+        if let Some(id) = existing {
+            if flags & O_CREAT != 0 && flags & O_EXCL != 0 {
+                return Err(ShmError::Exists);
+            }
+            if let Some(segment) = segments.get_mut(&id) {
+                segment.refs += 1;
+            }
+            return Ok(id);
+        }
+        if flags & O_CREAT == 0 {
+            return Err(ShmError::NotFound);
+        }
+        if size == 0 || size > MAX_SHM_SIZE {
+            return Err(ShmError::InvalidSize);
+        }
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let base = zalloc(pages);
+        if base.is_null() {
+            return Err(ShmError::OutOfMemory);
+        }
+        let id = unsafe {
+            let id = NEXT_SEGMENT;
+            NEXT_SEGMENT += 1;
+            id
+        };
+        segments.insert(id, Segment {
+            name: String::from(name),
+            base,
+            size: pages * PAGE_SIZE,
+            refs: 1,
+            unlinked: false,
+        });
+        Ok(id)
+    }).unwrap_or(Err(ShmError::NotFound))
+}
+
+pub fn unlink(name: &str) -> Result<(), ShmError> {
+    with_segments(|segments| {
+        let id = segments.iter().find(|(_, s)| !s.unlinked && s.name == name).map(|(id, _)| *id).ok_or(ShmError::NotFound)?;
+        if let Some(segment) = segments.get_mut(&id) {
+            segment.unlinked = true;
+        }
+        release_if_unused(segments, id);
+        Ok(())
+    }).unwrap_or(Err(ShmError::NotFound))
+}
+
+pub fn add_ref(id: usize) {
+    with_segments(|segments| {
+        if let Some(segment) = segments.get_mut(&id) {
+            segment.refs += 1;
+        }
+    });
+}
+
+pub fn drop_ref(id: usize) {
+    with_segments(|segments| {
+        if let Some(segment) = segments.get_mut(&id) {
+            segment.refs = segment.refs.saturating_sub(1);
+        }
+        release_if_unused(segments, id);
+    });
+}
+
+pub fn page(id: usize, offset: usize) -> Option<usize> {
+    with_segments(|segments| {
+        segments.get(&id).filter(|s| offset < s.size).map(|s| s.base as usize + (offset & !(PAGE_SIZE - 1)))
+    }).flatten()
+}
+
+pub fn size(id: usize) -> usize {
+    with_segments(|segments| segments.get(&id).map_or(0, |s| s.size)).unwrap_or(0)
+}
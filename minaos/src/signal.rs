@@ -0,0 +1,382 @@
+use crate::{coredump,
+            cpu::{Registers, TrapFrame},
+            lifecycle::{self, WCOREFLAG},
+            lock::Mutex,
+            process::{set_running, set_waiting},
+            timer};
+use alloc::{collections::BTreeMap, vec::Vec};
+
+pub const NSIG: usize = 32;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGWINCH: usize = 28;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Core,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub restorer: usize,
+    pub mask: u64,
+}
+
+impl SigAction {
+    pub const fn default() -> Self {
+        SigAction {
+            handler: SIG_DFL,
+            flags: 0,
+            restorer: 0,
+            mask: 0,
+        }
+    }
+}
+
+pub enum SignalError {
+    InvalidSignal,
+    NoProcess,
+    Uncatchable,
+}
+
+struct SavedContext {
+    regs: [usize; 32],
+    pc: usize,
+    blocked: u64,
+}
+
+pub struct SignalState {
+    pending: u64,
+    blocked: u64,
+    stopped: bool,
+    actions: [SigAction; NSIG],
+    saved: Vec<SavedContext>,
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        SignalState {
+            pending: 0,
+            blocked: 0,
+            stopped: false,
+            actions: [SigAction::default(); NSIG],
+            saved: Vec::new(),
+        }
+    }
+
+    fn next_deliverable(&self) -> Option<usize> {
+        let ready = self.pending & !self.blocked;
+        if ready == 0 {
+            None
+        } else {
+            Some(ready.trailing_zeros() as usize)
+        }
+    }
+}
+
+static mut SIGNAL_TABLES: Option<BTreeMap<u16, SignalState>> = None;
+static mut SIGNAL_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        SIGNAL_TABLES.replace(BTreeMap::new());
+    }
+}
+
+fn with_state<T, F: FnOnce(&mut SignalState) -> T>(pid: u16, f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        SIGNAL_LOCK.spin_lock();
+        if let Some(mut tables) = SIGNAL_TABLES.take() {
+            ret = tables.get_mut(&pid).map(f);
+            SIGNAL_TABLES.replace(tables);
+        }
+        SIGNAL_LOCK.unlock();
+    }
+    ret
+}
+
+fn insert_state(pid: u16, state: SignalState) {
+    unsafe {
+        SIGNAL_LOCK.spin_lock();
+        if let Some(mut tables) = SIGNAL_TABLES.take() {
+            tables.entry(pid).or_insert(state);
+            SIGNAL_TABLES.replace(tables);
+        }
+        SIGNAL_LOCK.unlock();
+    }
+}
+
+pub fn attach(pid: u16) {
+    insert_state(pid, SignalState::new());
+}
+
+pub fn fork(parent: u16, child: u16) {
+    let mut state = SignalState::new();
+    if let Some((actions, blocked)) = with_state(parent, |parent| (parent.actions, parent.blocked)) {
+        state.actions = actions;
+        state.blocked = blocked;
+    }
+    insert_state(child, state);
+}
+
+fn sig_bit(sig: usize) -> u64 {
+    1 << sig
+}
+
+fn unblockable() -> u64 {
+    sig_bit(SIGKILL) | sig_bit(SIGSTOP)
+}
+
+pub fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV => DefaultAction::Core,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+pub fn release(pid: u16) {
+    unsafe {
+        SIGNAL_LOCK.spin_lock();
+        if let Some(mut tables) = SIGNAL_TABLES.take() {
+            tables.remove(&pid);
+            SIGNAL_TABLES.replace(tables);
+        }
+        SIGNAL_LOCK.unlock();
+    }
+}
+
+pub fn terminate(pid: u16, sig: usize) {
+    println!("Process {} terminated by signal {}", pid, sig);
+    lifecycle::exit(pid, sig as i32);
+}
+
+pub fn reset_handlers(pid: u16) {
+    with_state(pid, |state| {
+        for act in state.actions.iter_mut() {
+            if act.handler != SIG_IGN {
+                *act = SigAction::default();
+            }
+        }
+        state.saved.clear();
+    });
+}
+
+pub fn send_signal(pid: u16, sig: usize) -> Result<(), SignalError> {
+    if sig == 0 || sig >= NSIG {
+        return Err(SignalError::InvalidSignal);
+    }
+    let wake = with_state(pid, |state| {
+        if sig == SIGCONT && state.stopped {
+            state.stopped = false;
+            state.pending &= !(sig_bit(SIGSTOP) | sig_bit(SIGTSTP) | sig_bit(SIGTTIN) | sig_bit(SIGTTOU));
+            return true;
+        }
+        if state.actions[sig].handler == SIG_IGN && sig != SIGKILL && sig != SIGSTOP {
+            return false;
+        }
+        state.pending |= sig_bit(sig);
+        if sig == SIGKILL {
+            state.stopped = false;
+        }
+        sig == SIGKILL || state.pending & !state.blocked != 0
+    }).ok_or(SignalError::NoProcess)?;
+    if wake {
+        timer::interrupt_sleep(pid);
+        set_running(pid);
+    }
+    Ok(())
+}
+
+pub fn force_signal(pid: u16, sig: usize) {
+    with_state(pid, |state| {
+        if state.blocked & sig_bit(sig) != 0 || state.actions[sig].handler == SIG_IGN {
+            state.blocked &= !sig_bit(sig);
+            state.actions[sig] = SigAction::default();
+        }
+        state.pending |= sig_bit(sig);
+    });
+}
+
+pub fn sigaction(pid: u16, sig: usize, act: Option<SigAction>) -> Result<SigAction, SignalError> {
+    if sig == 0 || sig >= NSIG {
+        return Err(SignalError::InvalidSignal);
+    }
+    if act.is_some() && (sig == SIGKILL || sig == SIGSTOP) {
+        return Err(SignalError::Uncatchable);
+    }
+    with_state(pid, |state| {
+        let old = state.actions[sig];
+        if let Some(act) = act {
+            state.actions[sig] = act;
+            if act.handler == SIG_IGN {
+                state.pending &= !sig_bit(sig);
+            }
+        }
+        old
+    }).ok_or(SignalError::NoProcess)
+}
+
+pub fn sigprocmask(pid: u16, how: usize, set: Option<u64>) -> Result<u64, SignalError> {
+    with_state(pid, |state| {
+        let old = state.blocked;
+        if let Some(set) = set {
+            state.blocked = match how {
+                SIG_BLOCK => state.blocked | set,
+                SIG_UNBLOCK => state.blocked & !set,
+                SIG_SETMASK => set,
+                _ => return Err(SignalError::InvalidSignal),
+            } & !unblockable();
+        }
+        Ok(old)
+    }).unwrap_or(Err(SignalError::NoProcess))
+}
+
+pub unsafe fn sigreturn(frame: *mut TrapFrame) -> bool {
+    let pid = (*frame).pid as u16;
+    let saved = with_state(pid, |state| {
+        let ctx = state.saved.pop()?;
+        state.blocked = ctx.blocked;
+        Some(ctx)
+    }).flatten();
+    match saved {
+        Some(ctx) => {
+            (*frame).regs = ctx.regs;
+            (*frame).pc = ctx.pc;
+            true
+        },
+        None => false,
+    }
+}
+
+enum Delivery {
+    None,
+    Terminate(usize),
+    Core(usize),
+    Stop,
+    Handler(usize, SigAction),
+}
+
+pub unsafe fn deliver_signals(frame: *mut TrapFrame) -> bool {
+    let pid = (*frame).pid as u16;
+    loop {
+        let delivery = with_state(pid, |state| {
+            if state.pending & sig_bit(SIGKILL) != 0 {
+                state.pending &= !sig_bit(SIGKILL);
+                state.stopped = false;
+                return Delivery::Terminate(SIGKILL);
+            }
+            if state.stopped {
+                return Delivery::Stop;
+            }
+            let sig = match state.next_deliverable() {
+                Some(sig) => sig,
+                None => return Delivery::None,
+            };
+            state.pending &= !sig_bit(sig);
+            let act = state.actions[sig];
+            match act.handler {
+                SIG_IGN => Delivery::None,
+                SIG_DFL => match default_action(sig) {
+                    DefaultAction::Terminate => Delivery::Terminate(sig),
+                    DefaultAction::Core => Delivery::Core(sig),
+                    DefaultAction::Stop => {
+                        state.stopped = true;
+                        Delivery::Stop
+                    },
+                    DefaultAction::Ignore | DefaultAction::Continue => Delivery::None,
+                },
+                _ => {
+                    state.saved.push(SavedContext {
+                        regs: (*frame).regs,
+                        pc: (*frame).pc,
+                        blocked: state.blocked,
+                    });
+                    state.blocked |= act.mask;
+                    if act.flags & SA_NODEFER == 0 {
+                        state.blocked |= sig_bit(sig);
+                    }
+                    state.blocked &= !unblockable();
+                    if act.flags & SA_RESETHAND != 0 {
+                        state.actions[sig] = SigAction::default();
+                    }
+                    Delivery::Handler(sig, act)
+                },
+            }
+        }).unwrap_or(Delivery::None);
+
+        match delivery {
+            Delivery::None => {
+                let more = with_state(pid, |state| state.next_deliverable().is_some()).unwrap_or(false);
+                if !more {
+                    return true;
+                }
+            },
+            Delivery::Terminate(sig) => {
+                terminate(pid, sig);
+                return false;
+            },
+            Delivery::Core(sig) => {
+                if coredump::dump(frame, sig) {
+                    println!("Process {} terminated by signal {} (core dumped)", pid, sig);
+                    lifecycle::exit(pid, sig as i32 | WCOREFLAG);
+                } else {
+                    terminate(pid, sig);
+                }
+                return false;
+            },
+            Delivery::Stop => {
+                set_waiting(pid);
+                return false;
+            },
+            Delivery::Handler(sig, act) => {
+                (*frame).regs[Registers::A0 as usize] = sig;
+                (*frame).regs[Registers::Ra as usize] = act.restorer;
+                (*frame).regs[Registers::Sp as usize] = ((*frame).regs[Registers::Sp as usize] - 128) & !0xf;
+                (*frame).pc = act.handler;
+                return true;
+            },
+        }
+    }
+}
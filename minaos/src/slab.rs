@@ -0,0 +1,489 @@
+use crate::{kasan,
+            oom,
+            page::{zalloc, PAGE_SIZE},
+            scrub,
+            spinlock::SpinLock};
+use alloc::vec::Vec;
+use core::{alloc::{GlobalAlloc, Layout},
+           mem::size_of,
+           panic::Location,
+           ptr::null_mut,
+           sync::atomic::{AtomicUsize, Ordering}};
+
+pub const SLAB_MAGIC: u32 = 0x51ab_51ab;
+pub const LARGE_MAGIC: u32 = 0x1a59_e000;
+pub const MIN_OBJECT: usize = 16;
+pub const NUM_CLASSES: usize = 7;
+pub const MAX_OBJECT: usize = MIN_OBJECT << (NUM_CLASSES - 1);
+pub const LARGE_OFFSET: usize = 64;
+
+pub const POISON_FREE: u8 = 0x6b;
+pub const POISON_ALLOC: u8 = 0xa5;
+pub const POISONED: usize = 0x6b6b_f4ee_6b6b_f4ee;
+
+#[repr(C)]
+struct SlabHeader {
+    magic: u32,
+    class: u16,
+    in_use: u16,
+    free: *mut FreeObject,
+    next: *mut SlabHeader,
+    prev: *mut SlabHeader,
+}
+
+#[repr(C)]
+struct LargeHeader {
+    magic: u32,
+    pages: u32,
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+    poisoned: usize,
+}
+
+#[derive(Copy, Clone)]
+pub struct SlabStats {
+    pub size: usize,
+    pub slabs: usize,
+    pub active: usize,
+    pub allocs: usize,
+    pub frees: usize,
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct HeapStats {
+    pub allocated: usize,
+    pub free: usize,
+    pub pages: usize,
+    pub allocs: usize,
+    pub frees: usize,
+    pub failures: usize,
+    pub high_water: usize,
+}
+
+struct Cache {
+    slabs: *mut SlabHeader,
+    stats: SlabStats,
+}
+
+unsafe impl Send for Cache {}
+
+const fn cache(class: usize) -> SpinLock<Cache> {
+    SpinLock::new("slab", Cache {
+        slabs: null_mut(),
+        stats: SlabStats {
+            size: MIN_OBJECT << class,
+            slabs: 0,
+            active: 0,
+            allocs: 0,
+            frees: 0,
+        },
+    })
+}
+
+static CACHES: [SpinLock<Cache>; NUM_CLASSES] = [cache(0), cache(1), cache(2), cache(3), cache(4), cache(5), cache(6)];
+static LARGE: SpinLock<SlabStats> = SpinLock::new("slab large", SlabStats {
+    size: 0,
+    slabs: 0,
+    active: 0,
+    allocs: 0,
+    frees: 0,
+});
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static HIGH_WATER: AtomicUsize = AtomicUsize::new(0);
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+fn account_alloc(bytes: usize) {
+    let now = ALLOCATED.fetch_add(bytes, Ordering::SeqCst) + bytes;
+    HIGH_WATER.fetch_max(now, Ordering::SeqCst);
+}
+
+fn account_free(bytes: usize) {
+    ALLOCATED.fetch_sub(bytes, Ordering::SeqCst);
+}
+
+fn class_of(size: usize) -> Option<usize> {
+    (0..NUM_CLASSES).find(|&class| size <= MIN_OBJECT << class)
+}
+
+fn first_object(size: usize) -> usize {
+    (size_of::<SlabHeader>() + size - 1) / size * size
+}
+
+fn objects_per_slab(size: usize) -> usize {
+    (PAGE_SIZE - first_object(size)) / size
+}
+
+fn slab_of(ptr: *mut u8) -> *mut SlabHeader {
+    (ptr as usize & !(PAGE_SIZE - 1)) as *mut SlabHeader
+}
+
+unsafe fn poison_free(obj: *mut u8, size: usize) {
+    let poisoned = if scrub::is_enabled() {
+        obj.add(size_of::<FreeObject>()).write_bytes(POISON_FREE, size - size_of::<FreeObject>());
+        POISONED
+    } else {
+        0
+    };
+    (*(obj as *mut FreeObject)).poisoned = poisoned;
+}
+
+unsafe fn check_poison(obj: *mut u8, size: usize) {
+    if (*(obj as *mut FreeObject)).poisoned != POISONED {
+        return;
+    }
+    for i in size_of::<FreeObject>()..size {
+        if obj.add(i).read() != POISON_FREE {
+            println!("slab: {}-byte object {:p} modified after free at offset {}", size, obj, i);
+            break;
+        }
+    }
+    if scrub::is_enabled() {
+        obj.write_bytes(POISON_ALLOC, size);
+    }
+}
+
+unsafe fn new_slab(class: usize) -> *mut SlabHeader {
+    let page = zalloc(1);
+    if page.is_null() {
+        return null_mut();
+    }
+    let size = MIN_OBJECT << class;
+    let slab = page as *mut SlabHeader;
+    let mut free = null_mut();
+    for i in (0..objects_per_slab(size)).rev() {
+        let obj = page.add(first_object(size) + i * size);
+        poison_free(obj, size);
+        let obj = obj as *mut FreeObject;
+        (*obj).next = free;
+        free = obj;
+    }
+    slab.write(SlabHeader {
+        magic: SLAB_MAGIC,
+        class: class as u16,
+        in_use: 0,
+        free,
+        next: null_mut(),
+        prev: null_mut(),
+    });
+    slab
+}
+
+unsafe fn unlink(cache: &mut Cache, slab: *mut SlabHeader) {
+    if (*slab).prev.is_null() {
+        cache.slabs = (*slab).next;
+    } else {
+        (*(*slab).prev).next = (*slab).next;
+    }
+    if !(*slab).next.is_null() {
+        (*(*slab).next).prev = (*slab).prev;
+    }
+    (*slab).next = null_mut();
+    (*slab).prev = null_mut();
+}
+
+unsafe fn push(cache: &mut Cache, slab: *mut SlabHeader) {
+    (*slab).next = cache.slabs;
+    (*slab).prev = null_mut();
+    if !cache.slabs.is_null() {
+        (*cache.slabs).prev = slab;
+    }
+    cache.slabs = slab;
+}
+
+unsafe fn alloc_small(class: usize) -> *mut u8 {
+    let mut cache = CACHES[class].lock();
+    let size = cache.stats.size;
+    let mut slab = cache.slabs;
+    if slab.is_null() {
+        slab = new_slab(class);
+        if slab.is_null() {
+            return null_mut();
+        }
+        push(&mut cache, slab);
+        cache.stats.slabs += 1;
+    }
+    let obj = (*slab).free;
+    (*slab).free = (*obj).next;
+    (*slab).in_use += 1;
+    if (*slab).free.is_null() {
+        unlink(&mut cache, slab);
+    }
+    cache.stats.active += 1;
+    cache.stats.allocs += 1;
+    account_alloc(size);
+    check_poison(obj as *mut u8, size);
+    obj as *mut u8
+}
+
+unsafe fn free_small(slab: *mut SlabHeader, ptr: *mut u8) {
+    let class = (*slab).class as usize;
+    let mut cache = CACHES[class].lock();
+    let size = cache.stats.size;
+    let offset = ptr as usize - slab as usize;
+    if offset < first_object(size) || (offset - first_object(size)) % size != 0 {
+        println!("slab: bad free of {:p} in {}-byte cache", ptr, size);
+        return;
+    }
+    let was_full = (*slab).free.is_null();
+    poison_free(ptr, size);
+    let obj = ptr as *mut FreeObject;
+    (*obj).next = (*slab).free;
+    (*slab).free = obj;
+    (*slab).in_use -= 1;
+    cache.stats.active -= 1;
+    cache.stats.frees += 1;
+    account_free(size);
+    if was_full {
+        push(&mut cache, slab);
+    }
+    let only_slab = cache.slabs == slab && (*slab).next.is_null();
+    if (*slab).in_use == 0 && !only_slab {
+        unlink(&mut cache, slab);
+        (*slab).magic = 0;
+        scrub::free_pages(slab as *mut u8, 1);
+        cache.stats.slabs -= 1;
+    }
+}
+
+unsafe fn alloc_large(size: usize) -> *mut u8 {
+    let pages = (size + LARGE_OFFSET + PAGE_SIZE - 1) / PAGE_SIZE;
+    let page = zalloc(pages);
+    if page.is_null() {
+        return null_mut();
+    }
+    (page as *mut LargeHeader).write(LargeHeader {
+        magic: LARGE_MAGIC,
+        pages: pages as u32,
+    });
+    let mut large = LARGE.lock();
+    large.slabs += pages;
+    large.active += 1;
+    large.allocs += 1;
+    account_alloc(pages * PAGE_SIZE);
+    page.add(LARGE_OFFSET)
+}
+
+unsafe fn free_large(header: *mut LargeHeader) {
+    let pages = (*header).pages as usize;
+    (*header).magic = 0;
+    scrub::free_pages(header as *mut u8, pages);
+    let mut large = LARGE.lock();
+    large.slabs -= pages;
+    large.active -= 1;
+    large.frees += 1;
+    account_free(pages * PAGE_SIZE);
+}
+
+fn try_alloc(size: usize) -> *mut u8 {
+    unsafe {
+        match class_of(size.max(1)) {
+            Some(class) => alloc_small(class),
+            None => alloc_large(size),
+        }
+    }
+}
+
+pub fn alloc_raw(size: usize) -> *mut u8 {
+    let mut ptr = try_alloc(size);
+    if ptr.is_null() && oom::out_of_memory(size) {
+        ptr = try_alloc(size);
+    }
+    if ptr.is_null() {
+        FAILURES.fetch_add(1, Ordering::SeqCst);
+    }
+    ptr
+}
+
+#[track_caller]
+pub fn kmalloc(size: usize) -> *mut u8 {
+    if kasan::is_enabled() {
+        return kasan::alloc(size, Location::caller());
+    }
+    alloc_raw(size)
+}
+
+#[track_caller]
+pub fn kzalloc(size: usize) -> *mut u8 {
+    let ptr = kmalloc(size);
+    if !ptr.is_null() {
+        unsafe {
+            ptr.write_bytes(0, size);
+        }
+    }
+    ptr
+}
+
+#[track_caller]
+pub fn kfree(ptr: *mut u8) {
+    if ptr.is_null() {
+        return;
+    }
+    if kasan::owns(ptr) {
+        kasan::free(ptr, Location::caller());
+        return;
+    }
+    free_raw(ptr);
+}
+
+pub fn free_raw(ptr: *mut u8) {
+    unsafe {
+        let slab = slab_of(ptr);
+        if ptr as usize - slab as usize == LARGE_OFFSET && (*(slab as *mut LargeHeader)).magic == LARGE_MAGIC {
+            free_large(slab as *mut LargeHeader);
+        } else if (*slab).magic == SLAB_MAGIC {
+            free_small(slab, ptr);
+        } else {
+            println!("slab: free of unknown pointer {:p}", ptr);
+        }
+    }
+}
+
+pub fn usable_size(ptr: *mut u8) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+    if kasan::owns(ptr) {
+        return kasan::size_of_object(ptr);
+    }
+    unsafe {
+        let slab = slab_of(ptr);
+        if ptr as usize - slab as usize == LARGE_OFFSET && (*(slab as *mut LargeHeader)).magic == LARGE_MAGIC {
+            (*(slab as *mut LargeHeader)).pages as usize * PAGE_SIZE - LARGE_OFFSET
+        } else if (*slab).magic == SLAB_MAGIC {
+            MIN_OBJECT << (*slab).class
+        } else {
+            0
+        }
+    }
+}
+
+#[track_caller]
+pub fn krealloc(ptr: *mut u8, new_size: usize) -> *mut u8 {
+    if ptr.is_null() {
+        return kmalloc(new_size);
+    }
+    if new_size == 0 {
+        kfree(ptr);
+        return null_mut();
+    }
+    let old_size = usable_size(ptr);
+    if old_size == 0 {
+        println!("slab: realloc of unknown pointer {:p}", ptr);
+        return null_mut();
+    }
+    let shrinks_class = new_size <= MAX_OBJECT && old_size > MAX_OBJECT || new_size <= old_size / 2 && old_size > MIN_OBJECT;
+    if new_size <= old_size && !shrinks_class && !kasan::owns(ptr) {
+        return ptr;
+    }
+    let new = kmalloc(new_size);
+    if !new.is_null() {
+        unsafe {
+            new.copy_from_nonoverlapping(ptr, old_size.min(new_size));
+        }
+        kfree(ptr);
+    }
+    new
+}
+
+pub fn reclaim() -> usize {
+    let mut freed = 0;
+    for cache in CACHES.iter() {
+        let mut cache = cache.lock();
+        let mut slab = cache.slabs;
+        while !slab.is_null() {
+            let next = unsafe { (*slab).next };
+            if unsafe { (*slab).in_use } == 0 {
+                unsafe {
+                    unlink(&mut cache, slab);
+                    (*slab).magic = 0;
+                    scrub::free_pages(slab as *mut u8, 1);
+                }
+                cache.stats.slabs -= 1;
+                freed += 1;
+            }
+            slab = next;
+        }
+    }
+    freed
+}
+
+pub fn heap_stats() -> HeapStats {
+    let mut ret = HeapStats {
+        allocated: ALLOCATED.load(Ordering::SeqCst),
+        failures: FAILURES.load(Ordering::SeqCst),
+        high_water: HIGH_WATER.load(Ordering::SeqCst),
+        ..HeapStats::default()
+    };
+    for s in stats() {
+        ret.pages += s.slabs;
+        if s.size != 0 {
+            ret.free += (s.slabs * objects_per_slab(s.size) - s.active) * s.size;
+        }
+        ret.allocs += s.allocs;
+        ret.frees += s.frees;
+    }
+    ret
+}
+
+pub fn stats() -> Vec<SlabStats> {
+    let mut ret: Vec<SlabStats> = CACHES.iter().map(|cache| cache.lock().stats).collect();
+    ret.push(*LARGE.lock());
+    ret
+}
+
+pub fn print_stats() {
+    println!("  SIZE  SLABS  ACTIVE     ALLOCS      FREES");
+    for s in stats() {
+        if s.size == 0 {
+            println!(" large {:>6} {:>7} {:>10} {:>10}", s.slabs, s.active, s.allocs, s.frees);
+        } else {
+            println!("{:>6} {:>6} {:>7} {:>10} {:>10}", s.size, s.slabs, s.active, s.allocs, s.frees);
+        }
+    }
+}
+
+pub fn print_heap() {
+    let h = heap_stats();
+    println!("allocated   {:>10} bytes", h.allocated);
+    println!("free        {:>10} bytes", h.free);
+    println!("high water  {:>10} bytes", h.high_water);
+    println!("pages       {:>10}", h.pages);
+    println!("allocs      {:>10}", h.allocs);
+    println!("frees       {:>10}", h.frees);
+    println!("failures    {:>10}", h.failures);
+    println!("oom events  {:>10} (policy {}, {} killed)", oom::events(), oom::policy_name(oom::policy()), oom::kills());
+}
+
+pub struct SlabAllocator;
+
+unsafe impl GlobalAlloc for SlabAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = layout.size().max(layout.align());
+        if size > MAX_OBJECT && layout.align() > LARGE_OFFSET {
+            return null_mut();
+        }
+        if layout.align() > MIN_OBJECT {
+            return alloc_raw(size);
+        }
+        kmalloc(size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        kfree(ptr);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.align() > MIN_OBJECT {
+            let new = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
+            if !new.is_null() {
+                new.copy_from_nonoverlapping(ptr, layout.size().min(new_size));
+                kfree(ptr);
+            }
+            return new;
+        }
+        krealloc(ptr, new_size)
+    }
+}
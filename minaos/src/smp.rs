@@ -0,0 +1,74 @@
+use crate::{fdt::Fdt,
+            hart::{init_hart, online_mask, MAX_HARTS},
+            sbi,
+            trap::schedule_next_context_switch};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+pub const CLINT_MSIP: *mut u32 = 0x0200_0000 as *mut u32;
+
+pub const IPI_RESCHEDULE: usize = 1 << 0;
+pub const IPI_TLB_FLUSH: usize = 1 << 1;
+pub const IPI_HALT: usize = 1 << 2;
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static PENDING_IPIS: [AtomicUsize; MAX_HARTS] = [ZERO; MAX_HARTS];
+static HARTS_RELEASED: AtomicBool = AtomicBool::new(false);
+static NUM_HARTS: AtomicUsize = AtomicUsize::new(1);
+
+pub fn num_harts() -> usize {
+    NUM_HARTS.load(Ordering::SeqCst)
+}
+
+pub fn send_ipi(hart: usize, kind: usize) {
+    if hart >= num_harts() {
+        return;
+    }
+    PENDING_IPIS[hart].fetch_or(kind, Ordering::SeqCst);
+    sbi::send_ipi(hart);
+}
+
+pub fn broadcast_ipi(from: usize, kind: usize) {
+    for hart in 0..num_harts() {
+        if hart != from && online_mask() & (1 << hart) != 0 {
+            send_ipi(hart, kind);
+        }
+    }
+}
+
+pub fn take_ipis(hart: usize) -> usize {
+    sbi::clear_ipi(hart);
+    PENDING_IPIS[hart].swap(0, Ordering::SeqCst)
+}
+
+pub fn boot_harts() -> usize {
+    let count = Fdt::boot().map_or(1, |fdt| fdt.count_harts()).max(1).min(MAX_HARTS);
+    NUM_HARTS.store(count, Ordering::SeqCst);
+    init_hart(0);
+    HARTS_RELEASED.store(true, Ordering::SeqCst);
+    for hart in 1..count {
+        if sbi::hart_start(hart, 0).is_err() {
+            println!("smp: unable to start hart {}", hart);
+        }
+    }
+    count
+}
+
+pub fn secondary_entry(hart: usize) -> bool {
+    if hart >= MAX_HARTS {
+        return false;
+    }
+    while !HARTS_RELEASED.load(Ordering::SeqCst) {
+        core::hint::spin_loop();
+    }
+    if hart >= num_harts() {
+        return false;
+    }
+    sbi::clear_ipi(hart);
+    init_hart(hart);
+    schedule_next_context_switch(hart, 1);
+    unsafe {
+        asm!("csrw mie, {}", in(reg) (1usize << 3) | (1 << 7) | (1 << 11));
+        asm!("csrs mstatus, {}", in(reg) 1usize << 3);
+    }
+    true
+}
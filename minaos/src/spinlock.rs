@@ -0,0 +1,94 @@
+use crate::{hart::hart_id,
+            interrupt::{self, InterruptState}};
+use core::{cell::UnsafeCell,
+           ops::{Deref, DerefMut},
+           sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+pub const NO_OWNER: usize = usize::MAX;
+pub const DEADLOCK_SPINS: usize = 100_000_000;
+
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    owner: AtomicUsize,
+    name: &'static str,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    state: InterruptState,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(name: &'static str, data: T) -> Self {
+        SpinLock {
+            locked: AtomicBool::new(false),
+            owner: AtomicUsize::new(NO_OWNER),
+            name,
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let state = interrupt::disable();
+        let hart = hart_id();
+        if cfg!(debug_assertions) && self.owner.load(Ordering::Relaxed) == hart {
+            panic!("Recursive lock of {} on hart {}", self.name, hart);
+        }
+        let mut spins = 0;
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            while self.locked.load(Ordering::Relaxed) {
+                spins += 1;
+                if cfg!(debug_assertions) && spins == DEADLOCK_SPINS {
+                    println!("Possible deadlock: hart {} waiting for {} held by hart {}", hart, self.name, self.owner.load(Ordering::Relaxed));
+                }
+                core::hint::spin_loop();
+            }
+        }
+        self.owner.store(hart, Ordering::Relaxed);
+        SpinLockGuard { lock: self, state }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let state = interrupt::disable();
+        if self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+            self.owner.store(hart_id(), Ordering::Relaxed);
+            Some(SpinLockGuard { lock: self, state })
+        } else {
+            interrupt::restore(state);
+            None
+        }
+    }
+}
+
+impl<'a, T> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        self.lock.locked.store(false, Ordering::Release);
+        interrupt::restore(self.state);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{cpu::{Registers, TrapFrame},
+            fd::{self, Descriptor},
+            klog,
+            lock::Mutex,
+            pipe,
+            syscall::*};
+use alloc::{collections::BTreeMap, format, string::String};
+use core::fmt::Write;
+
+#[derive(Copy, Clone)]
+pub enum TraceSink {
+    Klog,
+    Fd(u16, usize),
+}
+
+#[derive(Copy, Clone)]
+enum Arg {
+    Int,
+    Hex,
+    Str,
+    Fd,
+}
+
+pub struct TraceEntry {
+    line: String,
+    sink: TraceSink,
+}
+
+static mut TRACED: Option<BTreeMap<u16, TraceSink>> = None;
+static mut TRACE_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        TRACED.replace(BTreeMap::new());
+    }
+}
+
+fn with_traced<T, F: FnOnce(&mut BTreeMap<u16, TraceSink>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        TRACE_LOCK.spin_lock();
+        if let Some(mut traced) = TRACED.take() {
+            ret = Some(f(&mut traced));
+            TRACED.replace(traced);
+        }
+        TRACE_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn set_trace(pid: u16, sink: Option<TraceSink>) {
+    with_traced(|traced| match sink {
+        Some(sink) => traced.insert(pid, sink),
+        None => traced.remove(&pid),
+    });
+}
+
+pub fn sink(pid: u16) -> Option<TraceSink> {
+    with_traced(|traced| traced.get(&pid).copied()).flatten()
+}
+
+pub fn release(pid: u16) {
+    set_trace(pid, None);
+}
+
+fn describe(nr: usize) -> Option<(&'static str, &'static [Arg])> {
+    Some(match nr {
+        SYS_IOCTL => ("ioctl", &[Arg::Fd, Arg::Hex, Arg::Hex]),
+        SYS_OPENAT => ("openat", &[Arg::Int, Arg::Str, Arg::Hex]),
+        SYS_CLOSE => ("close", &[Arg::Fd]),
+        SYS_PIPE2 => ("pipe2", &[Arg::Hex, Arg::Hex]),
+        SYS_LSEEK => ("lseek", &[Arg::Fd, Arg::Int, Arg::Int]),
+        SYS_READ => ("read", &[Arg::Fd, Arg::Hex, Arg::Int]),
+        SYS_WRITE => ("write", &[Arg::Fd, Arg::Hex, Arg::Int]),
+        SYS_SENDFILE => ("sendfile", &[Arg::Fd, Arg::Fd, Arg::Hex, Arg::Int]),
+        SYS_PPOLL => ("ppoll", &[Arg::Hex, Arg::Int, Arg::Hex, Arg::Hex]),
+        SYS_EXIT => ("exit", &[Arg::Int]),
+        SYS_EXIT_GROUP => ("exit_group", &[Arg::Int]),
+        SYS_FUTEX => ("futex", &[Arg::Hex, Arg::Int, Arg::Int, Arg::Hex]),
+        SYS_NANOSLEEP => ("nanosleep", &[Arg::Hex, Arg::Hex]),
+        SYS_CLOCK_GETTIME => ("clock_gettime", &[Arg::Int, Arg::Hex]),
+        SYS_PTRACE => ("ptrace", &[Arg::Hex, Arg::Int, Arg::Hex, Arg::Hex]),
+        SYS_SCHED_SETPARAM => ("sched_setparam", &[Arg::Int, Arg::Hex]),
+        SYS_SCHED_SETSCHEDULER => ("sched_setscheduler", &[Arg::Int, Arg::Int, Arg::Hex]),
+        SYS_SCHED_GETSCHEDULER => ("sched_getscheduler", &[Arg::Int]),
+        SYS_SCHED_GETPARAM => ("sched_getparam", &[Arg::Int, Arg::Hex]),
+        SYS_SCHED_SETAFFINITY => ("sched_setaffinity", &[Arg::Int, Arg::Int, Arg::Hex]),
+        SYS_SCHED_GETAFFINITY => ("sched_getaffinity", &[Arg::Int, Arg::Int, Arg::Hex]),
+        SYS_SCHED_YIELD => ("sched_yield", &[]),
+        SYS_SCHED_GET_PRIORITY_MAX => ("sched_get_priority_max", &[Arg::Int]),
+        SYS_SCHED_GET_PRIORITY_MIN => ("sched_get_priority_min", &[Arg::Int]),
+        SYS_KILL => ("kill", &[Arg::Int, Arg::Int]),
+        SYS_SIGACTION => ("rt_sigaction", &[Arg::Int, Arg::Hex, Arg::Hex]),
+        SYS_SIGPROCMASK => ("rt_sigprocmask", &[Arg::Int, Arg::Hex, Arg::Hex]),
+        SYS_SIGRETURN => ("rt_sigreturn", &[]),
+        SYS_SETPRIORITY => ("setpriority", &[Arg::Int, Arg::Int, Arg::Int]),
+        SYS_GETPRIORITY => ("getpriority", &[Arg::Int, Arg::Int]),
+        SYS_SETPGID => ("setpgid", &[Arg::Int, Arg::Int]),
+        SYS_GETPGID => ("getpgid", &[Arg::Int]),
+        SYS_GETSID => ("getsid", &[Arg::Int]),
+        SYS_SETSID => ("setsid", &[]),
+        SYS_GETPID => ("getpid", &[]),
+        SYS_GETPPID => ("getppid", &[]),
+        SYS_GETUID => ("getuid", &[]),
+        SYS_GETEUID => ("geteuid", &[]),
+        SYS_GETGID => ("getgid", &[]),
+        SYS_GETEGID => ("getegid", &[]),
+        SYS_SETUID => ("setuid", &[Arg::Int]),
+        SYS_SETGID => ("setgid", &[Arg::Int]),
+        SYS_SHM_OPEN => ("shm_open", &[Arg::Str, Arg::Hex, Arg::Int]),
+        SYS_SHM_UNLINK => ("shm_unlink", &[Arg::Str]),
+        SYS_PROCINFO => ("procinfo", &[Arg::Hex, Arg::Int]),
+        SYS_TRACE => ("trace", &[Arg::Int, Arg::Int, Arg::Fd]),
+        SYS_MMAP_DEVICE => ("mmap_device", &[Arg::Hex, Arg::Int, Arg::Hex]),
+        SYS_BRK => ("brk", &[Arg::Hex]),
+        SYS_MUNMAP => ("munmap", &[Arg::Hex, Arg::Int]),
+        SYS_CLONE => ("clone", &[Arg::Hex]),
+        SYS_EXECVE => ("execve", &[Arg::Str, Arg::Hex, Arg::Hex]),
+        SYS_MMAP => ("mmap", &[Arg::Hex, Arg::Int, Arg::Hex, Arg::Hex, Arg::Fd, Arg::Int]),
+        SYS_WAIT4 => ("wait4", &[Arg::Int, Arg::Hex, Arg::Hex, Arg::Hex]),
+        SYS_GETRANDOM => ("getrandom", &[Arg::Hex, Arg::Int, Arg::Hex]),
+        _ => return None,
+    })
+}
+
+const ARG_REGS: [Registers; 6] = [Registers::A0, Registers::A1, Registers::A2, Registers::A3, Registers::A4, Registers::A5];
+
+pub unsafe fn enter(frame: *const TrapFrame) -> Option<TraceEntry> {
+    let pid = (*frame).pid as u16;
+    let sink = sink(pid)?;
+    let nr = (*frame).regs[Registers::A7 as usize];
+    let (mut line, args) = match describe(nr) {
+        Some((name, args)) => (format!("[{}] {}(", pid, name), args),
+        None => (format!("[{}] syscall_{}(", pid, nr), &[Arg::Hex, Arg::Hex, Arg::Hex][..]),
+    };
+    for (i, kind) in args.iter().enumerate() {
+        if i > 0 {
+            line.push_str(", ");
+        }
+        let value = (*frame).regs[ARG_REGS[i] as usize];
+        let _ = match kind {
+            Arg::Int => write!(line, "{}", value as isize),
+            Arg::Hex => write!(line, "0x{:x}", value),
+            Arg::Fd => write!(line, "{}", value),
+            Arg::Str => match user_string(frame, value) {
+                Some(s) => write!(line, "\"{}\"", s),
+                None => write!(line, "0x{:x}", value),
+            },
+        };
+    }
+    line.push(')');
+    Some(TraceEntry { line, sink })
+}
+
+pub fn exit(entry: TraceEntry, result: Option<usize>) {
+    let line = match result {
+        Some(ret) if ret as isize >= -4096 && (ret as isize) < 0 => format!("{} = {}", entry.line, ret as isize),
+        Some(ret) if ret > 0xffff => format!("{} = 0x{:x}", entry.line, ret),
+        Some(ret) => format!("{} = {}", entry.line, ret),
+        None => format!("{} = ?", entry.line),
+    };
+    match entry.sink {
+        TraceSink::Klog => klog!("{}", line),
+        TraceSink::Fd(owner, tracefd) => match fd::get(owner, tracefd) {
+            Some(Descriptor::Console) => println!("{}", line),
+            Some(Descriptor::PipeWrite(id)) => {
+                let mut out = line;
+                out.push('\n');
+                let _ = pipe::try_write(id, out.as_ptr(), out.len());
+            },
+            _ => {},
+        },
+    }
+}
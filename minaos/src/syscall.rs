@@ -0,0 +1,904 @@
+use crate::{acct::{self, ProcInfo},
+            block,
+            cpu::{Registers, TrapFrame},
+            cred,
+            elf::ROOT_BDEV,
+            fd::{self, Descriptor, IoResult},
+            file,
+            futex::{self, FutexKey, FUTEX_PRIVATE_FLAG, FUTEX_WAIT, FUTEX_WAKE},
+            hart::online_mask,
+            hwbreak::{self, TriggerHit},
+            kthread,
+            lifecycle::{self, WaitResult},
+            mmio,
+            page::{Table, PAGE_SIZE},
+            paging,
+            pipe,
+            poll::{self, PollFd, PollResult, MAX_POLL_FDS},
+            process::{get_by_pid, ProcessState, PROCESS_LIST, PROCESS_LIST_MUTEX, STACK_ADDR},
+            random::{self, GRND_NONBLOCK},
+            sched::{self, NICE_MAX, NICE_MIN, PRIO_PROCESS, RT_PRIO_MAX, RT_PRIO_MIN, SCHED_FIFO, SCHED_OTHER},
+            session,
+            shm,
+            signal::{self, SigAction},
+            strace::{self, TraceSink},
+            time::{self, TimeSpec},
+            timer,
+            uaccess::{copy_to_user, read_user, user_span, write_user, UaccessError},
+            vma::{self, Vma, VmaBacking, VMA_EXEC, VMA_MMAP, VMA_READ, VMA_WRITE}};
+use alloc::{string::String, vec::Vec};
+use core::mem::size_of;
+
+pub const SYS_IOCTL: usize = 29;
+pub const SYS_OPENAT: usize = 56;
+pub const SYS_CLOSE: usize = 57;
+pub const SYS_PIPE2: usize = 59;
+pub const SYS_LSEEK: usize = 62;
+pub const SYS_READ: usize = 63;
+pub const SYS_WRITE: usize = 64;
+pub const SYS_SENDFILE: usize = 71;
+pub const SYS_PPOLL: usize = 73;
+pub const SYS_EXIT: usize = 93;
+pub const SYS_EXIT_GROUP: usize = 94;
+pub const SYS_FUTEX: usize = 98;
+pub const SYS_NANOSLEEP: usize = 101;
+pub const SYS_CLOCK_GETTIME: usize = 113;
+pub const SYS_PTRACE: usize = 117;
+pub const SYS_SCHED_SETPARAM: usize = 118;
+pub const SYS_SCHED_SETSCHEDULER: usize = 119;
+pub const SYS_SCHED_GETSCHEDULER: usize = 120;
+pub const SYS_SCHED_GETPARAM: usize = 121;
+pub const SYS_SCHED_SETAFFINITY: usize = 122;
+pub const SYS_SCHED_GETAFFINITY: usize = 123;
+pub const SYS_SCHED_YIELD: usize = 124;
+pub const SYS_SCHED_GET_PRIORITY_MAX: usize = 125;
+pub const SYS_SCHED_GET_PRIORITY_MIN: usize = 126;
+pub const SYS_KILL: usize = 129;
+pub const SYS_SIGACTION: usize = 134;
+pub const SYS_SIGPROCMASK: usize = 135;
+pub const SYS_SIGRETURN: usize = 139;
+pub const SYS_SETPRIORITY: usize = 140;
+pub const SYS_GETPRIORITY: usize = 141;
+pub const SYS_SETGID: usize = 144;
+pub const SYS_SETUID: usize = 146;
+pub const SYS_SETPGID: usize = 154;
+pub const SYS_GETPGID: usize = 155;
+pub const SYS_GETSID: usize = 156;
+pub const SYS_SETSID: usize = 157;
+pub const SYS_GETPID: usize = 172;
+pub const SYS_GETPPID: usize = 173;
+pub const SYS_GETUID: usize = 174;
+pub const SYS_GETEUID: usize = 175;
+pub const SYS_GETGID: usize = 176;
+pub const SYS_GETEGID: usize = 177;
+pub const SYS_BLOCK_READ: usize = 180;
+pub const SYS_BLOCK_WRITE: usize = 181;
+pub const SYS_SHM_OPEN: usize = 182;
+pub const SYS_SHM_UNLINK: usize = 183;
+pub const SYS_PARK: usize = 184;
+pub const SYS_PROCINFO: usize = 185;
+pub const SYS_TRACE: usize = 186;
+pub const SYS_MMAP_DEVICE: usize = 187;
+pub const SYS_BRK: usize = 214;
+pub const SYS_MUNMAP: usize = 215;
+pub const SYS_CLONE: usize = 220;
+pub const SYS_EXECVE: usize = 221;
+pub const SYS_MMAP: usize = 222;
+pub const SYS_WAIT4: usize = 260;
+pub const SYS_GETRANDOM: usize = 278;
+
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+pub const EINTR: usize = 4;
+
+pub const TRACE_OFF: usize = 0;
+pub const TRACE_KLOG: usize = 1;
+pub const TRACE_FD: usize = 2;
+
+pub const MAX_USER_STRING: usize = 256;
+pub const MAX_USER_ARGS: usize = 64;
+pub const MAX_GETRANDOM: usize = PAGE_SIZE;
+
+pub unsafe fn user_to_phys(frame: *const TrapFrame, vaddr: usize) -> Option<usize> {
+    if (*frame).satp >> 60 == 0 {
+        return Some(vaddr);
+    }
+    let p = get_by_pid((*frame).pid as u16);
+    if p.is_null() {
+        return None;
+    }
+    let table = ((*p).mmu_table as *const Table).as_ref()?;
+    paging::translate(table, vaddr)
+}
+
+pub unsafe fn user_string(frame: *const TrapFrame, vaddr: usize) -> Option<String> {
+    let pid = (*frame).pid as u16;
+    let mut ret = String::new();
+    for i in 0..MAX_USER_STRING {
+        let c = read_user::<u8>(pid, vaddr.checked_add(i)?)?;
+        if c == 0 {
+            return Some(ret);
+        }
+        ret.push(c as char);
+    }
+    None
+}
+
+pub unsafe fn user_string_array(frame: *const TrapFrame, vaddr: usize) -> Option<Vec<String>> {
+    let mut ret = Vec::new();
+    if vaddr == 0 {
+        return Some(ret);
+    }
+    for i in 0..MAX_USER_ARGS {
+        let ptr = read_user::<usize>((*frame).pid as u16, vaddr.checked_add(i * size_of::<usize>())?)?;
+        if ptr == 0 {
+            return Some(ret);
+        }
+        ret.push(user_string(frame, ptr)?);
+    }
+    None
+}
+
+fn all_pids() -> Vec<u16> {
+    let mut pids = Vec::new();
+    unsafe {
+        PROCESS_LIST_MUTEX.spin_lock();
+        if let Some(pl) = PROCESS_LIST.take() {
+            pids.extend(pl.iter().map(|p| p.pid));
+            PROCESS_LIST.replace(pl);
+        }
+        PROCESS_LIST_MUTEX.unlock();
+    }
+    pids
+}
+
+unsafe fn finish_io(mepc: usize, frame: *mut TrapFrame, result: IoResult) {
+    match result {
+        IoResult::Done(n) => (*frame).regs[Registers::A0 as usize] = n,
+        IoResult::Blocked => (*frame).pc = mepc,
+        IoResult::Pending => {},
+        IoResult::Error => (*frame).regs[Registers::A0 as usize] = -1isize as usize,
+    }
+}
+
+pub unsafe fn do_syscall(mepc: usize, frame: *mut TrapFrame) {
+    let pid = (*frame).pid as u16;
+    let trace = strace::enter(frame);
+    dispatch(mepc, frame);
+    if let Some(entry) = trace {
+        let p = get_by_pid(pid);
+        let result = if p.is_null() || (*(*p).frame).pc == mepc {
+            None
+        } else if let ProcessState::Waiting = (*p).state {
+            None
+        } else {
+            Some((*(*p).frame).regs[Registers::A0 as usize])
+        };
+        strace::exit(entry, result);
+    }
+}
+
+unsafe fn dispatch(mepc: usize, frame: *mut TrapFrame) {
+    let syscall_number = (*frame).regs[Registers::A7 as usize];
+    (*frame).pc = mepc + 4;
+    match syscall_number {
+        SYS_READ | SYS_WRITE => {
+            let pid = (*frame).pid as u16;
+            let fd = (*frame).regs[Registers::A0 as usize];
+            let size = (*frame).regs[Registers::A2 as usize];
+            if syscall_number == SYS_READ && matches!(fd::get(pid, fd), Some(Descriptor::Console)) {
+                finish_io(mepc, frame, fd::read_console(pid, (*frame).regs[Registers::A1 as usize], size));
+                return;
+            }
+            let (buffer, size) = match user_span(pid, (*frame).regs[Registers::A1 as usize], size, syscall_number == SYS_READ) {
+                Some(span) => span,
+                None => {
+                    (*frame).regs[Registers::A0 as usize] = -1isize as usize;
+                    return;
+                },
+            };
+            let result = if syscall_number == SYS_READ {
+                fd::read(pid, fd, buffer, size)
+            } else {
+                fd::write(pid, fd, buffer as *const u8, size)
+            };
+            finish_io(mepc, frame, result);
+        },
+        SYS_IOCTL => {
+            let fd = (*frame).regs[Registers::A0 as usize];
+            let request = (*frame).regs[Registers::A1 as usize];
+            let arg = (*frame).regs[Registers::A2 as usize];
+            finish_io(mepc, frame, fd::ioctl(frame, fd, request, arg));
+        },
+        SYS_OPENAT => {
+            let pid = (*frame).pid as u16;
+            let id = user_string(frame, (*frame).regs[Registers::A1 as usize]).and_then(|path| file::open(pid, ROOT_BDEV, &path).ok());
+            (*frame).regs[Registers::A0 as usize] = match id {
+                Some(id) => match fd::alloc(pid, Descriptor::File(id)) {
+                    Ok(fd) => fd,
+                    Err(_) => {
+                        file::close(id);
+                        -1isize as usize
+                    },
+                },
+                None => -1isize as usize,
+            };
+        },
+        SYS_LSEEK => {
+            let pid = (*frame).pid as u16;
+            let fd = (*frame).regs[Registers::A0 as usize];
+            let offset = (*frame).regs[Registers::A1 as usize] as isize;
+            let whence = (*frame).regs[Registers::A2 as usize];
+            (*frame).regs[Registers::A0 as usize] = match fd::get(pid, fd) {
+                Some(Descriptor::File(id)) => file::lseek(id, offset, whence).unwrap_or(-1isize as usize),
+                _ => -1isize as usize,
+            };
+        },
+        SYS_CLOSE => {
+            let pid = (*frame).pid as u16;
+            let fd = (*frame).regs[Registers::A0 as usize];
+            (*frame).regs[Registers::A0 as usize] = match fd::close(pid, fd) {
+                Ok(()) => 0,
+                Err(_) => -1isize as usize,
+            };
+        },
+        SYS_PIPE2 => {
+            let pid = (*frame).pid as u16;
+            let fds = (*frame).regs[Registers::A0 as usize];
+            let valid = user_span(pid, fds, 2 * size_of::<i32>(), true).is_some();
+            (*frame).regs[Registers::A0 as usize] = match (valid, pipe::create()) {
+                (true, Some(id)) => match fd::alloc(pid, Descriptor::PipeRead(id)) {
+                    Ok(rfd) => match fd::alloc(pid, Descriptor::PipeWrite(id)) {
+                        Ok(wfd) if write_user(pid, fds, [rfd as i32, wfd as i32]) => 0,
+                        Ok(wfd) => {
+                            let _ = fd::close(pid, rfd);
+                            let _ = fd::close(pid, wfd);
+                            -1isize as usize
+                        },
+                        Err(_) => {
+                            let _ = fd::close(pid, rfd);
+                            pipe::close_writer(id);
+                            -1isize as usize
+                        },
+                    },
+                    Err(_) => {
+                        pipe::close_reader(id);
+                        pipe::close_writer(id);
+                        -1isize as usize
+                    },
+                },
+                (_, Some(id)) => {
+                    pipe::close_reader(id);
+                    pipe::close_writer(id);
+                    -1isize as usize
+                },
+                _ => -1isize as usize,
+            };
+        },
+        SYS_SENDFILE => {
+            let pid = (*frame).pid as u16;
+            let out_fd = (*frame).regs[Registers::A0 as usize];
+            let in_fd = (*frame).regs[Registers::A1 as usize];
+            let offset = (*frame).regs[Registers::A2 as usize];
+            let count = (*frame).regs[Registers::A3 as usize];
+            let offset_ptr = match offset {
+                0 => Some(None),
+                vaddr => match user_span(pid, vaddr, size_of::<i64>(), true) {
+                    Some((p, len)) if len == size_of::<i64>() => Some(Some(p as *mut i64)),
+                    _ => None,
+                },
+            };
+            let result = match (fd::get(pid, in_fd), fd::get(pid, out_fd), offset_ptr) {
+                (Some(Descriptor::File(id)), Some(out), Some(offset_ptr)) => file::sendfile(pid, id, out, offset_ptr, count),
+                _ => IoResult::Error,
+            };
+            finish_io(mepc, frame, result);
+        },
+        SYS_PPOLL => {
+            let pid = (*frame).pid as u16;
+            let fds_ptr = (*frame).regs[Registers::A0 as usize];
+            let nfds = (*frame).regs[Registers::A1 as usize];
+            let timeout = (*frame).regs[Registers::A2 as usize];
+            let timeout_ns = if timeout == 0 {
+                Some(None)
+            } else {
+                read_user::<TimeSpec>(pid, timeout).and_then(|t| t.to_nanos()).map(Some)
+            };
+            let mut fds = Vec::with_capacity(nfds.min(MAX_POLL_FDS));
+            for i in 0..nfds.min(MAX_POLL_FDS) {
+                match read_user::<PollFd>(pid, fds_ptr + i * size_of::<PollFd>()) {
+                    Some(p) => fds.push(p),
+                    None => break,
+                }
+            }
+            let timeout_ns = match timeout_ns {
+                Some(timeout_ns) if nfds <= MAX_POLL_FDS && fds.len() == nfds => timeout_ns,
+                _ => {
+                    (*frame).regs[Registers::A0 as usize] = -1isize as usize;
+                    return;
+                },
+            };
+            match poll::poll(pid, fds_ptr, &mut fds, timeout_ns) {
+                PollResult::Ready(count) => {
+                    for (i, p) in fds.iter().enumerate() {
+                        write_user(pid, fds_ptr + i * size_of::<PollFd>(), *p);
+                    }
+                    (*frame).regs[Registers::A0 as usize] = count;
+                },
+                PollResult::Blocked => (*frame).pc = mepc,
+            }
+        },
+        SYS_FUTEX => {
+            let pid = (*frame).pid as u16;
+            let uaddr = (*frame).regs[Registers::A0 as usize];
+            let op = (*frame).regs[Registers::A1 as usize];
+            let val = (*frame).regs[Registers::A2 as usize];
+            let timeout = (*frame).regs[Registers::A3 as usize];
+            let phys = if uaddr % 4 == 0 { user_span(pid, uaddr, size_of::<u32>(), false).map(|(p, _)| p as usize) } else { None };
+            let key = phys.map(|p| if op & FUTEX_PRIVATE_FLAG != 0 { FutexKey::Private(pid, uaddr) } else { FutexKey::Shared(p) });
+            (*frame).regs[Registers::A0 as usize] = match (op & !FUTEX_PRIVATE_FLAG, key, phys) {
+                (FUTEX_WAIT, Some(key), Some(p)) => {
+                    let timeout_ns = if timeout == 0 {
+                        Some(None)
+                    } else {
+                        read_user::<TimeSpec>(pid, timeout).and_then(|t| t.to_nanos()).map(Some)
+                    };
+                    match timeout_ns {
+                        Some(timeout_ns) if futex::wait(pid, key, p as *const u32, val as u32, timeout_ns).is_ok() => 0,
+                        _ => -1isize as usize,
+                    }
+                },
+                (FUTEX_WAKE, Some(key), _) => futex::wake(key, val),
+                _ => -1isize as usize,
+            };
+        },
+        SYS_NANOSLEEP => {
+            let req = (*frame).regs[Registers::A0 as usize];
+            let rem = (*frame).regs[Registers::A1 as usize];
+            let pid = (*frame).pid as u16;
+            let ns = read_user::<TimeSpec>(pid, req).and_then(|t| t.to_nanos());
+            (*frame).regs[Registers::A0 as usize] = match ns {
+                Some(ns) => {
+                    if ns > 0 {
+                        timer::sleep(pid, ns, rem);
+                    }
+                    0
+                },
+                None => -1isize as usize,
+            };
+        },
+        SYS_CLOCK_GETTIME => {
+            let clock_id = (*frame).regs[Registers::A0 as usize];
+            let tp = (*frame).regs[Registers::A1 as usize];
+            (*frame).regs[Registers::A0 as usize] = match time::clock_gettime(clock_id) {
+                Some(ts) if write_user((*frame).pid as u16, tp, ts) => 0,
+                _ => -1isize as usize,
+            };
+        },
+        SYS_KILL => {
+            let pid = (*frame).pid as u16;
+            let target = (*frame).regs[Registers::A0 as usize] as isize;
+            let sig = (*frame).regs[Registers::A1 as usize];
+            let targets = match target {
+                0 => session::members(session::getpgid(pid)),
+                -1 => all_pids().into_iter().filter(|p| *p != pid).collect(),
+                t if t < 0 => session::members((-t) as u16),
+                t if !get_by_pid(t as u16).is_null() => vec![t as u16],
+                _ => Vec::new(),
+            };
+            let permitted: Vec<u16> = targets.into_iter().filter(|t| cred::can_signal(pid, *t)).collect();
+            let sent = if sig == 0 {
+                permitted.len()
+            } else {
+                permitted.iter().filter(|t| signal::send_signal(**t, sig).is_ok()).count()
+            };
+            (*frame).regs[Registers::A0 as usize] = if sent > 0 { 0 } else { -1isize as usize };
+        },
+        SYS_GETPID => {
+            (*frame).regs[Registers::A0 as usize] = (*frame).pid;
+        },
+        SYS_GETPPID => {
+            (*frame).regs[Registers::A0 as usize] = lifecycle::parent_of((*frame).pid as u16) as usize;
+        },
+        SYS_GETUID => {
+            (*frame).regs[Registers::A0 as usize] = cred::get((*frame).pid as u16).uid as usize;
+        },
+        SYS_GETEUID => {
+            (*frame).regs[Registers::A0 as usize] = cred::get((*frame).pid as u16).euid as usize;
+        },
+        SYS_GETGID => {
+            (*frame).regs[Registers::A0 as usize] = cred::get((*frame).pid as u16).gid as usize;
+        },
+        SYS_GETEGID => {
+            (*frame).regs[Registers::A0 as usize] = cred::get((*frame).pid as u16).egid as usize;
+        },
+        SYS_SETUID => {
+            let uid = (*frame).regs[Registers::A0 as usize] as u32;
+            (*frame).regs[Registers::A0 as usize] = match cred::setuid((*frame).pid as u16, uid) {
+                Ok(()) => 0,
+                Err(_) => -1isize as usize,
+            };
+        },
+        SYS_SETGID => {
+            let gid = (*frame).regs[Registers::A0 as usize] as u32;
+            (*frame).regs[Registers::A0 as usize] = match cred::setgid((*frame).pid as u16, gid) {
+                Ok(()) => 0,
+                Err(_) => -1isize as usize,
+            };
+        },
+        SYS_SETPGID => {
+            let pid = (*frame).regs[Registers::A0 as usize] as u16;
+            let pgid = (*frame).regs[Registers::A1 as usize] as u16;
+            (*frame).regs[Registers::A0 as usize] = match session::setpgid((*frame).pid as u16, pid, pgid) {
+                Ok(()) => 0,
+                Err(_) => -1isize as usize,
+            };
+        },
+        SYS_GETPGID | SYS_GETSID => {
+            let pid = match (*frame).regs[Registers::A0 as usize] as u16 {
+                0 => (*frame).pid as u16,
+                pid => pid,
+            };
+            (*frame).regs[Registers::A0 as usize] = if get_by_pid(pid).is_null() {
+                -1isize as usize
+            } else if syscall_number == SYS_GETPGID {
+                session::getpgid(pid) as usize
+            } else {
+                session::getsid(pid) as usize
+            };
+        },
+        SYS_SETSID => {
+            (*frame).regs[Registers::A0 as usize] = match session::setsid((*frame).pid as u16) {
+                Ok(sid) => sid as usize,
+                Err(_) => -1isize as usize,
+            };
+        },
+        SYS_SIGACTION => {
+            let pid = (*frame).pid as u16;
+            let sig = (*frame).regs[Registers::A0 as usize];
+            let act_ptr = (*frame).regs[Registers::A1 as usize];
+            let old_ptr = (*frame).regs[Registers::A2 as usize];
+            let act = if act_ptr != 0 {
+                read_user::<SigAction>(pid, act_ptr)
+            } else {
+                None
+            };
+            (*frame).regs[Registers::A0 as usize] = match signal::sigaction(pid, sig, act) {
+                Ok(old) => {
+                    if old_ptr != 0 {
+                        write_user(pid, old_ptr, old);
+                    }
+                    0
+                },
+                Err(_) => -1isize as usize,
+            };
+        },
+        SYS_SIGPROCMASK => {
+            let pid = (*frame).pid as u16;
+            let how = (*frame).regs[Registers::A0 as usize];
+            let set_ptr = (*frame).regs[Registers::A1 as usize];
+            let old_ptr = (*frame).regs[Registers::A2 as usize];
+            let set = if set_ptr != 0 {
+                read_user::<u64>(pid, set_ptr)
+            } else {
+                None
+            };
+            (*frame).regs[Registers::A0 as usize] = match signal::sigprocmask(pid, how, set) {
+                Ok(old) => {
+                    if old_ptr != 0 {
+                        write_user(pid, old_ptr, old);
+                    }
+                    0
+                },
+                Err(_) => -1isize as usize,
+            };
+        },
+        SYS_PROCINFO => {
+            let buf = (*frame).regs[Registers::A0 as usize];
+            let max = (*frame).regs[Registers::A1 as usize];
+            let pid = (*frame).pid as u16;
+            let procs = acct::snapshot();
+            let ok = procs.iter().take(max).enumerate().all(|(i, info)| write_user(pid, buf + i * size_of::<ProcInfo>(), *info));
+            (*frame).regs[Registers::A0 as usize] = if ok { procs.len() } else { -1isize as usize };
+        },
+        SYS_GETRANDOM => {
+            let pid = (*frame).pid as u16;
+            let buf = (*frame).regs[Registers::A0 as usize];
+            let len = (*frame).regs[Registers::A1 as usize].min(MAX_GETRANDOM);
+            let flags = (*frame).regs[Registers::A2 as usize];
+            if !random::is_seeded() {
+                if flags & GRND_NONBLOCK != 0 {
+                    (*frame).regs[Registers::A0 as usize] = -1isize as usize;
+                    return;
+                }
+                if !random::wait_seeded(pid) {
+                    (*frame).pc = mepc;
+                    return;
+                }
+            }
+            let mut bytes = vec![0u8; len];
+            random::fill(&mut bytes);
+            (*frame).regs[Registers::A0 as usize] = match copy_to_user(pid, buf, &bytes) {
+                Ok(()) => len,
+                Err(UaccessError::Fault) => -1isize as usize,
+            };
+        },
+        SYS_TRACE => {
+            let pid = (*frame).pid as u16;
+            let target = match (*frame).regs[Registers::A0 as usize] as u16 {
+                0 => pid,
+                target => target,
+            };
+            let mode = (*frame).regs[Registers::A1 as usize];
+            let trace_fd = (*frame).regs[Registers::A2 as usize];
+            let sink = match mode {
+                TRACE_OFF => Some(None),
+                TRACE_KLOG => Some(Some(TraceSink::Klog)),
+                TRACE_FD if fd::get(pid, trace_fd).is_some() => Some(Some(TraceSink::Fd(pid, trace_fd))),
+                _ => None,
+            };
+            (*frame).regs[Registers::A0 as usize] = match sink {
+                Some(sink) if !get_by_pid(target).is_null() && cred::can_signal(pid, target) => {
+                    strace::set_trace(target, sink);
+                    0
+                },
+                _ => -1isize as usize,
+            };
+        },
+        SYS_PARK => {
+            kthread::park_pid((*frame).pid as u16);
+        },
+        SYS_SIGRETURN => {
+            if !signal::sigreturn(frame) {
+                signal::force_signal((*frame).pid as u16, signal::SIGSEGV);
+            }
+        },
+        SYS_SETPRIORITY | SYS_GETPRIORITY => {
+            let which = (*frame).regs[Registers::A0 as usize];
+            let who = match (*frame).regs[Registers::A1 as usize] as u16 {
+                0 => (*frame).pid as u16,
+                pid => pid,
+            };
+            (*frame).regs[Registers::A0 as usize] = if which != PRIO_PROCESS || get_by_pid(who).is_null() {
+                -1isize as usize
+            } else if syscall_number == SYS_SETPRIORITY {
+                let prio = (*frame).regs[Registers::A2 as usize] as isize;
+                sched::set_nice(who, prio.max(NICE_MIN as isize).min(NICE_MAX as isize) as i8);
+                0
+            } else {
+                (20 - sched::get_nice(who) as isize) as usize
+            };
+        },
+        SYS_SCHED_SETSCHEDULER | SYS_SCHED_SETPARAM => {
+            let caller = (*frame).pid as u16;
+            let pid = match (*frame).regs[Registers::A0 as usize] as u16 {
+                0 => caller,
+                pid => pid,
+            };
+            let (policy, param) = if syscall_number == SYS_SCHED_SETSCHEDULER {
+                ((*frame).regs[Registers::A1 as usize], (*frame).regs[Registers::A2 as usize])
+            } else {
+                (sched::get_scheduler(pid).0, (*frame).regs[Registers::A1 as usize])
+            };
+            let prio = read_user::<i32>(caller, param).map(|p| p as usize);
+            let permitted = cred::can_signal(caller, pid) && (policy != SCHED_FIFO || cred::get(caller).is_root());
+            (*frame).regs[Registers::A0 as usize] = match prio {
+                Some(prio) if permitted && !get_by_pid(pid).is_null() => match sched::set_scheduler(pid, policy, prio) {
+                    Ok(()) => 0,
+                    Err(_) => -1isize as usize,
+                },
+                _ => -1isize as usize,
+            };
+        },
+        SYS_SCHED_GETSCHEDULER | SYS_SCHED_GETPARAM => {
+            let pid = match (*frame).regs[Registers::A0 as usize] as u16 {
+                0 => (*frame).pid as u16,
+                pid => pid,
+            };
+            let (policy, prio) = sched::get_scheduler(pid);
+            (*frame).regs[Registers::A0 as usize] = if get_by_pid(pid).is_null() {
+                -1isize as usize
+            } else if syscall_number == SYS_SCHED_GETSCHEDULER {
+                policy
+            } else {
+                match write_user((*frame).pid as u16, (*frame).regs[Registers::A1 as usize], prio as i32) {
+                    true => 0,
+                    false => -1isize as usize,
+                }
+            };
+        },
+        SYS_SCHED_YIELD => {
+            sched::yield_now((*frame).pid as u16);
+            (*frame).regs[Registers::A0 as usize] = 0;
+        },
+        SYS_SCHED_GET_PRIORITY_MAX | SYS_SCHED_GET_PRIORITY_MIN => {
+            let max = syscall_number == SYS_SCHED_GET_PRIORITY_MAX;
+            (*frame).regs[Registers::A0 as usize] = match (*frame).regs[Registers::A0 as usize] {
+                SCHED_FIFO if max => RT_PRIO_MAX as usize,
+                SCHED_FIFO => RT_PRIO_MIN as usize,
+                SCHED_OTHER => 0,
+                _ => -1isize as usize,
+            };
+        },
+        SYS_SCHED_SETAFFINITY | SYS_SCHED_GETAFFINITY => {
+            let pid = match (*frame).regs[Registers::A0 as usize] as u16 {
+                0 => (*frame).pid as u16,
+                pid => pid,
+            };
+            let len = (*frame).regs[Registers::A1 as usize];
+            let caller = (*frame).pid as u16;
+            let mask_ptr = (*frame).regs[Registers::A2 as usize];
+            (*frame).regs[Registers::A0 as usize] = if get_by_pid(pid).is_null() || len < size_of::<usize>() {
+                -1isize as usize
+            } else if syscall_number == SYS_SCHED_SETAFFINITY {
+                match read_user::<usize>(caller, mask_ptr).map(|mask| sched::set_affinity(pid, mask)) {
+                    Some(Ok(())) => 0,
+                    _ => -1isize as usize,
+                }
+            } else if write_user(caller, mask_ptr, sched::get_affinity(pid) & online_mask()) {
+                size_of::<usize>()
+            } else {
+                -1isize as usize
+            };
+        },
+        SYS_SHM_OPEN => {
+            let pid = (*frame).pid as u16;
+            let flags = (*frame).regs[Registers::A1 as usize];
+            let size = (*frame).regs[Registers::A2 as usize];
+            let id = user_string(frame, (*frame).regs[Registers::A0 as usize]).and_then(|name| shm::open(&name, flags, size).ok());
+            (*frame).regs[Registers::A0 as usize] = match id {
+                Some(id) => match fd::alloc(pid, Descriptor::Shm(id)) {
+                    Ok(fd) => fd,
+                    Err(_) => {
+                        shm::drop_ref(id);
+                        -1isize as usize
+                    },
+                },
+                None => -1isize as usize,
+            };
+        },
+        SYS_SHM_UNLINK => {
+            let ok = user_string(frame, (*frame).regs[Registers::A0 as usize]).map_or(false, |name| shm::unlink(&name).is_ok());
+            (*frame).regs[Registers::A0 as usize] = if ok { 0 } else { -1isize as usize };
+        },
+        SYS_MMAP => {
+            let pid = (*frame).pid as u16;
+            let hint = (*frame).regs[Registers::A0 as usize];
+            let len = match (*frame).regs[Registers::A1 as usize].checked_add(PAGE_SIZE - 1) {
+                Some(len) => len & !(PAGE_SIZE - 1),
+                None => {
+                    (*frame).regs[Registers::A0 as usize] = -1isize as usize;
+                    return;
+                },
+            };
+            let prot = (*frame).regs[Registers::A2 as usize];
+            let flags = (*frame).regs[Registers::A3 as usize];
+            let fd = (*frame).regs[Registers::A4 as usize];
+            let offset = (*frame).regs[Registers::A5 as usize];
+            let backing = if flags & MAP_ANONYMOUS != 0 {
+                Some(VmaBacking::Anonymous)
+            } else {
+                match (flags & MAP_SHARED, fd::get(pid, fd)) {
+                    (MAP_SHARED, Some(Descriptor::Shm(id))) if offset % PAGE_SIZE == 0 && offset + len <= shm::size(id) => {
+                        Some(VmaBacking::Shared { id, offset })
+                    },
+                    _ => None,
+                }
+            };
+            let start = if flags & MAP_FIXED != 0 {
+                Some(hint).filter(|h| vma::find_free(pid, *h, len, STACK_ADDR) == Some(*h))
+            } else {
+                vma::find_free(pid, hint, len, STACK_ADDR)
+            };
+            let mut vma_flags = VMA_MMAP;
+            if prot & PROT_READ != 0 {
+                vma_flags |= VMA_READ;
+            }
+            if prot & PROT_WRITE != 0 {
+                vma_flags |= VMA_WRITE;
+            }
+            if prot & PROT_EXEC != 0 {
+                vma_flags |= VMA_EXEC;
+            }
+            (*frame).regs[Registers::A0 as usize] = match (backing, start) {
+                (Some(backing), Some(start)) if len > 0 => {
+                    let added = vma::add_vma(pid, Vma {
+                        start,
+                        end: start + len,
+                        flags: vma_flags,
+                        backing,
+                    }).is_ok();
+                    match backing {
+                        VmaBacking::Shared { id, .. } if added => shm::add_ref(id),
+                        _ => {},
+                    }
+                    if added { start } else { -1isize as usize }
+                },
+                _ => -1isize as usize,
+            };
+        },
+        SYS_MMAP_DEVICE => {
+            let pid = (*frame).pid as u16;
+            let paddr = (*frame).regs[Registers::A0 as usize];
+            let len = match (*frame).regs[Registers::A1 as usize].checked_add(PAGE_SIZE - 1) {
+                Some(len) => len & !(PAGE_SIZE - 1),
+                None => {
+                    (*frame).regs[Registers::A0 as usize] = -1isize as usize;
+                    return;
+                },
+            };
+            let prot = (*frame).regs[Registers::A2 as usize];
+            let mut vma_flags = VMA_MMAP;
+            if prot & PROT_READ != 0 {
+                vma_flags |= VMA_READ;
+            }
+            if prot & PROT_WRITE != 0 {
+                vma_flags |= VMA_WRITE;
+            }
+            let start = match mmio::find(paddr, len) {
+                Some(_) if len > 0 && paddr % PAGE_SIZE == 0 && prot & PROT_EXEC == 0 && cred::get(pid).is_root() => {
+                    vma::find_free(pid, 0, len, STACK_ADDR)
+                },
+                _ => None,
+            };
+            (*frame).regs[Registers::A0 as usize] = match start {
+                Some(start) if vma::add_vma(pid, Vma {
+                    start,
+                    end: start + len,
+                    flags: vma_flags,
+                    backing: VmaBacking::Device { paddr },
+                }).is_ok() => start,
+                _ => -1isize as usize,
+            };
+        },
+        SYS_BRK => {
+            let pid = (*frame).pid as u16;
+            let requested = (*frame).regs[Registers::A0 as usize];
+            let p = get_by_pid(pid);
+            (*frame).regs[Registers::A0 as usize] = if p.is_null() {
+                -1isize as usize
+            } else {
+                if requested != 0 && vma::set_brk(pid, requested).is_ok() {
+                    (*p).brk = requested;
+                }
+                (*p).brk
+            };
+        },
+        SYS_MUNMAP => {
+            let pid = (*frame).pid as u16;
+            let addr = (*frame).regs[Registers::A0 as usize];
+            (*frame).regs[Registers::A0 as usize] = match vma::unmap(pid, addr) {
+                Some(_) => 0,
+                None => -1isize as usize,
+            };
+        },
+        SYS_EXIT | SYS_EXIT_GROUP => {
+            let code = (*frame).regs[Registers::A0 as usize] as i32;
+            lifecycle::exit((*frame).pid as u16, (code & 0xff) << 8);
+        },
+        SYS_CLONE => {
+            (*frame).regs[Registers::A0 as usize] = match lifecycle::fork(frame) {
+                Ok(pid) => pid as usize,
+                Err(_) => -1isize as usize,
+            };
+        },
+        SYS_EXECVE => {
+            let pid = (*frame).pid as u16;
+            let path = user_string(frame, (*frame).regs[Registers::A0 as usize]);
+            let argv = user_string_array(frame, (*frame).regs[Registers::A1 as usize]);
+            let envp = user_string_array(frame, (*frame).regs[Registers::A2 as usize]);
+            if let (Some(path), Some(argv), Some(envp)) = (path, argv, envp) {
+                let argv: Vec<&str> = argv.iter().map(|s| s.as_str()).collect();
+                let envp: Vec<&str> = envp.iter().map(|s| s.as_str()).collect();
+                if lifecycle::exec(pid, &path, &argv, &envp).is_ok() {
+                    return;
+                }
+            }
+            (*frame).regs[Registers::A0 as usize] = -1isize as usize;
+        },
+        SYS_WAIT4 => {
+            let pid = (*frame).pid as u16;
+            let target = (*frame).regs[Registers::A0 as usize] as isize;
+            let status_ptr = (*frame).regs[Registers::A1 as usize];
+            let options = (*frame).regs[Registers::A2 as usize];
+            (*frame).regs[Registers::A0 as usize] = match lifecycle::wait(pid, target, options) {
+                WaitResult::Reaped(child, status) => {
+                    if status_ptr != 0 {
+                        write_user(pid, status_ptr, status);
+                    }
+                    child as usize
+                },
+                WaitResult::NotYet => 0,
+                WaitResult::NoChildren => -1isize as usize,
+                WaitResult::Blocked => {
+                    (*frame).pc = mepc;
+                    return;
+                },
+            };
+        },
+        SYS_PTRACE => {
+            let owner = (*frame).pid as u16;
+            let request = (*frame).regs[Registers::A0 as usize];
+            let pid = (*frame).regs[Registers::A1 as usize] as u16;
+            let addr = (*frame).regs[Registers::A2 as usize];
+            let data = (*frame).regs[Registers::A3 as usize];
+            (*frame).regs[Registers::A0 as usize] = match request {
+                hwbreak::PTRACE_SETTRIGGER => match hwbreak::set_trigger(owner, pid, addr, data) {
+                    Ok(slot) => slot,
+                    Err(_) => -1isize as usize,
+                },
+                hwbreak::PTRACE_CLEARTRIGGER => match hwbreak::clear_trigger(owner, pid, data) {
+                    Ok(()) => 0,
+                    Err(_) => -1isize as usize,
+                },
+                hwbreak::PTRACE_CONT => match hwbreak::resume(owner, pid) {
+                    Ok(()) => 0,
+                    Err(_) => -1isize as usize,
+                },
+                hwbreak::PTRACE_GETHIT => match user_span(owner, addr, size_of::<TriggerHit>(), true) {
+                    Some(_) => match hwbreak::wait_hit(owner) {
+                        Some(hit) if write_user(owner, addr, hit) => hit.pid,
+                        Some(_) => -1isize as usize,
+                        None => {
+                            (*frame).pc = mepc;
+                            return;
+                        },
+                    },
+                    None => -1isize as usize,
+                },
+                _ => -1isize as usize,
+            };
+        },
+        SYS_BLOCK_READ | SYS_BLOCK_WRITE => {
+            let dev = (*frame).regs[Registers::A0 as usize];
+            let size = (*frame).regs[Registers::A2 as usize] as u32;
+            let offset = (*frame).regs[Registers::A3 as usize] as u64;
+            let pid = (*frame).pid as u16;
+            match user_span(pid, (*frame).regs[Registers::A1 as usize], size as usize, syscall_number == SYS_BLOCK_READ) {
+                Some((buffer, len)) if len == size as usize && syscall_number == SYS_BLOCK_READ => {
+                    block::process_read(pid, dev, buffer, size, offset);
+                },
+                Some((buffer, len)) if len == size as usize => {
+                    block::process_write(pid, dev, buffer, size, offset);
+                },
+                _ => {
+                    (*frame).regs[Registers::A0 as usize] = -1isize as usize;
+                },
+            }
+        },
+        _ => {
+            println!("Unknown syscall number {}", syscall_number);
+        },
+    }
+}
+
+extern "C" {
+    fn make_syscall(sysno: usize, arg0: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize) -> usize;
+}
+
+fn do_make_syscall(sysno: usize, arg0: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize, arg5: usize) -> usize {
+    unsafe { make_syscall(sysno, arg0, arg1, arg2, arg3, arg4, arg5) }
+}
+
+pub fn syscall_read(fd: usize, buffer: *mut u8, size: usize) -> usize {
+    do_make_syscall(SYS_READ, fd, buffer as usize, size, 0, 0, 0)
+}
+
+pub fn syscall_block_read(dev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
+    do_make_syscall(SYS_BLOCK_READ, dev, buffer as usize, size as usize, offset as usize, 0, 0) as u8
+}
+
+pub fn syscall_block_write(dev: usize, buffer: *mut u8, size: u32, offset: u32) -> u8 {
+    do_make_syscall(SYS_BLOCK_WRITE, dev, buffer as usize, size as usize, offset as usize, 0, 0) as u8
+}
+
+pub fn syscall_park() {
+    do_make_syscall(SYS_PARK, 0, 0, 0, 0, 0, 0);
+}
@@ -0,0 +1,94 @@
+use crate::trap::MMIO_MTIME;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub const TIMER_FREQ: u64 = 10_000_000;
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+pub const NSEC_PER_TICK: u64 = NSEC_PER_SEC / TIMER_FREQ;
+
+pub const GOLDFISH_RTC_BASE: usize = 0x0010_1000;
+pub const RTC_TIME_LOW: usize = 0x00;
+pub const RTC_TIME_HIGH: usize = 0x04;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+pub const CLOCK_BOOTTIME: usize = 7;
+
+static WALL_OFFSET_NS: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl TimeSpec {
+    pub fn from_nanos(ns: u64) -> Self {
+        TimeSpec {
+            tv_sec: (ns / NSEC_PER_SEC) as i64,
+            tv_nsec: (ns % NSEC_PER_SEC) as i64,
+        }
+    }
+
+    pub fn to_nanos(&self) -> Option<u64> {
+        if self.tv_sec < 0 || self.tv_nsec < 0 || self.tv_nsec >= NSEC_PER_SEC as i64 {
+            return None;
+        }
+        (self.tv_sec as u64).checked_mul(NSEC_PER_SEC)?.checked_add(self.tv_nsec as u64)
+    }
+}
+
+pub fn ticks() -> u64 {
+    unsafe { MMIO_MTIME.read_volatile() }
+}
+
+pub fn ticks_to_nanos(ticks: u64) -> u64 {
+    ticks.saturating_mul(NSEC_PER_TICK)
+}
+
+pub fn nanos_to_ticks(ns: u64) -> u64 {
+    (ns + NSEC_PER_TICK - 1) / NSEC_PER_TICK
+}
+
+pub fn monotonic_nanos() -> u64 {
+    ticks_to_nanos(ticks())
+}
+
+fn read_rtc() -> u64 {
+    let ptr = GOLDFISH_RTC_BASE as *const u32;
+    unsafe {
+        let low = ptr.add(RTC_TIME_LOW / 4).read_volatile() as u64;
+        let high = ptr.add(RTC_TIME_HIGH / 4).read_volatile() as u64;
+        high << 32 | low
+    }
+}
+
+pub fn init() {
+    sync_wall_clock();
+}
+
+pub fn sync_wall_clock() {
+    let rtc = read_rtc();
+    let mono = monotonic_nanos();
+    WALL_OFFSET_NS.store(rtc.saturating_sub(mono), Ordering::SeqCst);
+}
+
+pub fn set_wall_nanos(ns: u64) {
+    WALL_OFFSET_NS.store(ns.saturating_sub(monotonic_nanos()), Ordering::SeqCst);
+}
+
+pub fn wall_nanos() -> u64 {
+    WALL_OFFSET_NS.load(Ordering::SeqCst) + monotonic_nanos()
+}
+
+pub fn wall_secs() -> u64 {
+    wall_nanos() / NSEC_PER_SEC
+}
+
+pub fn clock_gettime(clock_id: usize) -> Option<TimeSpec> {
+    match clock_id {
+        CLOCK_REALTIME => Some(TimeSpec::from_nanos(wall_nanos())),
+        CLOCK_MONOTONIC | CLOCK_BOOTTIME => Some(TimeSpec::from_nanos(monotonic_nanos())),
+        _ => None,
+    }
+}
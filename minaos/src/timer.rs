@@ -0,0 +1,208 @@
+use crate::{cpu::Registers,
+            hart::MAX_HARTS,
+            lock::Mutex,
+            process::{get_by_pid, set_running, set_waiting},
+            sbi,
+            spinlock::SpinLock,
+            syscall::EINTR,
+            time::{self, nanos_to_ticks, ticks_to_nanos, TimeSpec},
+            uaccess::write_user};
+use alloc::vec::Vec;
+
+pub const WHEEL_SIZE: usize = 256;
+pub const WHEEL_GRANULARITY: u64 = time::TIMER_FREQ / 1000;
+
+#[derive(Copy, Clone)]
+pub enum TimerCallback {
+    Wake(u16),
+    Call(fn(usize), usize),
+}
+
+#[derive(Copy, Clone)]
+struct Timer {
+    id: u64,
+    expires: u64,
+    callback: TimerCallback,
+}
+
+pub struct TimerWheel {
+    slots: Vec<Vec<Timer>>,
+    next_id: u64,
+    last_run: u64,
+}
+
+impl TimerWheel {
+    pub fn new(now: u64) -> Self {
+        let mut slots = Vec::with_capacity(WHEEL_SIZE);
+        for _ in 0..WHEEL_SIZE {
+            slots.push(Vec::new());
+        }
+        TimerWheel {
+            slots,
+            next_id: 1,
+            last_run: now / WHEEL_GRANULARITY,
+        }
+    }
+
+    fn slot_of(expires: u64) -> usize {
+        (expires / WHEEL_GRANULARITY) as usize % WHEEL_SIZE
+    }
+
+    pub fn add(&mut self, expires: u64, callback: TimerCallback) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.slots[Self::slot_of(expires)].push(Timer { id, expires, callback });
+        id
+    }
+
+    pub fn cancel(&mut self, id: u64) -> bool {
+        for slot in self.slots.iter_mut() {
+            if let Some(idx) = slot.iter().position(|t| t.id == id) {
+                slot.swap_remove(idx);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn expire(&mut self, now: u64, fired: &mut Vec<TimerCallback>) {
+        let current = now / WHEEL_GRANULARITY;
+        let steps = core::cmp::min(current.saturating_sub(self.last_run) + 1, WHEEL_SIZE as u64);
+        for i in 0..steps {
+            let slot = &mut self.slots[(self.last_run + i) as usize % WHEEL_SIZE];
+            let mut j = 0;
+            while j < slot.len() {
+                if slot[j].expires <= now {
+                    fired.push(slot.swap_remove(j).callback);
+                } else {
+                    j += 1;
+                }
+            }
+        }
+        self.last_run = current;
+    }
+
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.slots.iter().flat_map(|slot| slot.iter().map(|t| t.expires)).min()
+    }
+}
+
+struct Sleeper {
+    pid: u16,
+    timer: u64,
+    deadline: u64,
+    rem: usize,
+}
+
+static mut TIMER_WHEEL: Option<TimerWheel> = None;
+static mut TIMER_LOCK: Mutex = Mutex::new();
+static mut SWITCH_DEADLINE: [u64; MAX_HARTS] = [u64::MAX; MAX_HARTS];
+static SLEEPERS: SpinLock<Vec<Sleeper>> = SpinLock::new("sleep", Vec::new());
+
+pub fn init() {
+    unsafe {
+        TIMER_WHEEL.replace(TimerWheel::new(time::ticks()));
+    }
+}
+
+fn with_wheel<T, F: FnOnce(&mut TimerWheel) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        TIMER_LOCK.spin_lock();
+        if let Some(mut wheel) = TIMER_WHEEL.take() {
+            ret = Some(f(&mut wheel));
+            TIMER_WHEEL.replace(wheel);
+        }
+        TIMER_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn add_timer_at(expires: u64, callback: TimerCallback) -> u64 {
+    let id = with_wheel(|wheel| wheel.add(expires, callback)).unwrap_or(0);
+    program(crate::hart::hart_id());
+    id
+}
+
+pub fn add_timer(delay_ns: u64, callback: TimerCallback) -> u64 {
+    add_timer_at(time::ticks() + nanos_to_ticks(delay_ns), callback)
+}
+
+pub fn schedule_callback(delay_ns: u64, func: fn(usize), arg: usize) -> u64 {
+    add_timer(delay_ns, TimerCallback::Call(func, arg))
+}
+
+pub fn cancel_timer(id: u64) -> bool {
+    with_wheel(|wheel| wheel.cancel(id)).unwrap_or(false)
+}
+
+pub fn sleep(pid: u16, delay_ns: u64, rem: usize) {
+    let deadline = time::ticks() + nanos_to_ticks(delay_ns);
+    let mut sleepers = SLEEPERS.lock();
+    let timer = add_timer_at(deadline, TimerCallback::Call(wake_sleeper, pid as usize));
+    sleepers.push(Sleeper { pid, timer, deadline, rem });
+    set_waiting(pid);
+}
+
+fn take_sleeper(pid: u16) -> Option<Sleeper> {
+    let mut sleepers = SLEEPERS.lock();
+    let idx = sleepers.iter().position(|s| s.pid == pid)?;
+    Some(sleepers.swap_remove(idx))
+}
+
+fn wake_sleeper(arg: usize) {
+    let pid = arg as u16;
+    if take_sleeper(pid).is_some() {
+        set_running(pid);
+    }
+}
+
+pub fn interrupt_sleep(pid: u16) {
+    if let Some(sleeper) = take_sleeper(pid) {
+        cancel_timer(sleeper.timer);
+        unsafe {
+            if sleeper.rem != 0 {
+                let left = ticks_to_nanos(sleeper.deadline.saturating_sub(time::ticks()));
+                write_user(pid, sleeper.rem, TimeSpec::from_nanos(left));
+            }
+            let p = get_by_pid(pid);
+            if !p.is_null() {
+                (*(*p).frame).regs[Registers::A0 as usize] = -(EINTR as isize) as usize;
+            }
+        }
+    }
+}
+
+pub fn release(pid: u16) {
+    if let Some(sleeper) = take_sleeper(pid) {
+        cancel_timer(sleeper.timer);
+    }
+}
+
+pub fn run_expired() {
+    let mut fired = Vec::new();
+    with_wheel(|wheel| wheel.expire(time::ticks(), &mut fired));
+    for callback in fired {
+        match callback {
+            TimerCallback::Wake(pid) => set_running(pid),
+            TimerCallback::Call(func, arg) => func(arg),
+        }
+    }
+}
+
+pub fn set_switch_deadline(hart: usize, deadline: u64) {
+    unsafe {
+        SWITCH_DEADLINE[hart] = deadline;
+    }
+    program(hart);
+}
+
+pub fn quantum_expired(hart: usize) -> bool {
+    unsafe { SWITCH_DEADLINE[hart] <= time::ticks() }
+}
+
+pub fn program(hart: usize) {
+    let next_timer = with_wheel(|wheel| wheel.next_expiry()).flatten().unwrap_or(u64::MAX);
+    let deadline = unsafe { core::cmp::min(SWITCH_DEADLINE[hart], next_timer) };
+    sbi::set_timer(hart, deadline);
+}
@@ -1,14 +1,50 @@
-use crate::{cpu::{TrapFrame, CONTEXT_SWITCH_TIME},
+use crate::{acct,
+    backtrace,
+    cpu::{Registers, TrapFrame, CONTEXT_SWITCH_TIME},
+    gdbstub,
+    hwbreak,
+    idle,
+    irqstat,
+    kstack,
+    misaligned,
+    oom,
     plic,
-    process::delete_process,
+    random,
     rust_switch_to_user,
-    sched::schedule,
-    syscall::do_syscall};
+    sched::{self, schedule},
+    signal::{deliver_signals, force_signal, SIGBUS, SIGILL, SIGSEGV},
+    smp::{self, IPI_HALT, IPI_RESCHEDULE, IPI_TLB_FLUSH},
+    syscall::do_syscall,
+    timer,
+    vma::{handle_page_fault, FaultResult}};
+
+unsafe fn switch_to_next(hart: usize) {
+    acct::leave_kernel(hart);
+    oom::reap();
+    let mut frame = schedule(hart);
+    while frame != 0 && !deliver_signals(frame as *mut TrapFrame) {
+        frame = schedule(hart);
+    }
+    if frame != 0 {
+        let pid = (*(frame as *mut TrapFrame)).pid as u16;
+        idle::leave(hart);
+        schedule_next_context_switch(hart, sched::timeslice(pid));
+        hwbreak::load(pid);
+        rust_switch_to_user(frame);
+    } else {
+        schedule_next_context_switch(hart, 1);
+        let idle_frame = idle::enter(hart);
+        if !idle_frame.is_null() {
+            rust_switch_to_user(idle_frame as usize);
+        }
+    }
+}
 
 #[no_mangle]
 
 extern "C" fn m_trap(epc: usize,
                     tval: usize,
+                    cause: usize,
                     hart: usize,
                     _status: usize,
                     frame: *mut TrapFrame)
@@ -23,21 +59,46 @@ extern "C" fn m_trap(epc: usize,
     };
 
     let cause_num = cause & 0xfff;
+    irqstat::record_trap(hart, is_async, cause_num);
+    acct::enter_kernel(hart, unsafe { (*frame).pid } as u16);
+    if let Some(overflow) = unsafe { kstack::check(frame) } {
+        kstack::report(overflow);
+    }
     let mut return_pc = epc;
     if is_async {
         match cause_num {
-            3 => {
-                println!("Machine software interrupt CPU #{}", hart);
+            3 => unsafe {
+                let ipis = smp::take_ipis(hart);
+                if ipis & IPI_TLB_FLUSH != 0 {
+                    asm!("sfence.vma");
+                }
+                if ipis & IPI_HALT != 0 {
+                    loop {
+                        asm!("wfi");
+                    }
+                }
+                if ipis & IPI_RESCHEDULE != 0 {
+                    switch_to_next(hart);
+                }
             }
-            7 => {
-                let new_frame = schedule();
-                schedule_next_context_switch(1);
-                if new_frame != 0 {
-                    rust_switch_to_user(new_frame);
+            7 => unsafe {
+                timer::run_expired();
+                if timer::quantum_expired(hart) {
+                    switch_to_next(hart);
+                } else {
+                    timer::program(hart);
                 }
             }
             11 => {
+                random::add_interrupt_entropy(hart);
                 plic::handle_interrupt();
+                if gdbstub::take_request() {
+                    return_pc = gdbstub::handle_exception(frame, epc, gdbstub::SIGINT);
+                } else if idle::is_idle(hart) {
+                    unsafe {
+                        switch_to_next(hart);
+                    }
+                }
             }
             _ => {
                 panic!("Unhandled async trap CPU#{} -> {}\n", hart, cause_num);
@@ -46,69 +107,97 @@ extern "C" fn m_trap(epc: usize,
     }
     else {
         match cause_num {
+            4 | 6 => unsafe {
+                match misaligned::emulate(frame, epc, tval) {
+                    Some(next) => {
+                        return_pc = next;
+                    }
+                    None => {
+                        early_println!("Misaligned access CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
+
+                        force_signal((*frame).pid as u16, SIGBUS);
+                        switch_to_next(hart);
+                    }
+                }
+            }
+            0 => unsafe {
+                early_println!("Misaligned access CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
+
+                force_signal((*frame).pid as u16, SIGBUS);
+                switch_to_next(hart);
+            }
+            1 | 5 => unsafe {
+                if let Some(overflow) = kstack::guard_hit(tval) {
+                    kstack::report(overflow);
+                }
+                early_println!("Access fault CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
+
+                force_signal((*frame).pid as u16, SIGSEGV);
+                switch_to_next(hart);
+            }
             2 => unsafe {
-                println!("Illegal instruction CPU#{} -> 0x{:08x}: 0x{:08x}\n", hart, epc, tval);
-                
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1),
-                rust_switch_to_user(frame);
+                early_println!("Illegal instruction CPU#{} -> 0x{:08x}: 0x{:08x}\n", hart, epc, tval);
+
+                force_signal((*frame).pid as u16, SIGILL);
+                switch_to_next(hart);
             }
-            3 => {
-                println!("Breakpoint\n\n");
-                return_pc += 2;
+            3 => unsafe {
+                if hwbreak::handle_hit((*frame).pid as u16, epc, tval) {
+                    switch_to_next(hart);
+                } else if gdbstub::is_enabled() {
+                    return_pc = gdbstub::handle_exception(frame, epc, gdbstub::SIGTRAP);
+                } else {
+                    println!("Breakpoint\n\n");
+                    return_pc += 2;
+                }
             }
             7 => unsafe {
-                println!("Error with pid {}, at PC 0x{:08x}, mepc 0x{:08x}", (*frame).pid, (*frame).pc, epc);
-             
-                delete_process((*frame).pid as u16); 
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
+                if let Some(overflow) = kstack::guard_hit(tval) {
+                    kstack::report(overflow);
+                }
+                early_println!("Error with pid {}, at PC 0x{:08x}, mepc 0x{:08x}", (*frame).pid, (*frame).pc, epc);
+
+                force_signal((*frame).pid as u16, SIGSEGV);
+                switch_to_next(hart);
             }
             8 | 9 | 11 => unsafe {
                 do_syscall(return_pc, frame);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
-            }
-            12 => unsafe {
-                println!("Instruction page fault CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
-
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
+                switch_to_next(hart);
             }
-            13 => unsafe {
-                println!("Load page fault CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
+            12 | 13 | 15 => unsafe {
+                match handle_page_fault(frame, tval, cause_num) {
+                    FaultResult::Resolved => {},
+                    FaultResult::Pending => {
+                        switch_to_next(hart);
+                    }
+                    FaultResult::Invalid => {
+                        let kind = match cause_num {
+                            12 => "Instruction",
+                            13 => "Load",
+                            _ => "Store",
+                        };
+                        early_println!("{} page fault CPU#{} -> 0x{:08x}: 0x{:08x}", kind, hart, epc, tval);
 
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
-            }
-            15 => unsafe {
-                println!("Store page fault CPU#{} -> 0x{:08x}: 0x{:08x}", hart, epc, tval);
-
-                delete_process((*frame).pid as u16);
-                let frame = schedule();
-                schedule_next_context_switch(1);
-                rust_switch_to_user(frame);
+                        force_signal((*frame).pid as u16, SIGSEGV);
+                        switch_to_next(hart);
+                    }
+                }
             }
-            _ => {
+            _ => unsafe {
+                backtrace::print_frame(epc, (*frame).regs[Registers::Ra as usize], (*frame).regs[Registers::S0 as usize], (*frame).regs[Registers::Sp as usize]);
                 panic!("Unhandled sync trap {}. CPU#{} -> 0x{:08x}: 0x{:08x}\n", cause_num, hart, epc, tval);
             }
         }
     };
+    acct::leave_kernel(hart);
     return_pc
 }
 
 pub const MMIO_MTIMECMP: *mut u64 = 0x0200_4000usize as *mut u64;
 pub const MMIO_MTIME: *const u64 = 0x0200_BFF8 as *const u64;
 
-pub fn schedule_next_context_switch(qm: u16) {
+pub fn schedule_next_context_switch(hart: usize, qm: u16) {
     unsafe {
-        MMIO_MTIMECMP.write_volatile(MMIO_MTIME.read_volatile().wrapping_add(CONTEXT_SWITCH_TIME * qm as u64));
+        timer::set_switch_deadline(hart, MMIO_MTIME.read_volatile().wrapping_add(CONTEXT_SWITCH_TIME * qm as u64));
     }
 }
\ No newline at end of file
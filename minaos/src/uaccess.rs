@@ -0,0 +1,80 @@
+use crate::{page::{Table, PAGE_SIZE},
+            paging::{self, PTE_READ, PTE_USER, PTE_WRITE},
+            process::get_by_pid,
+            vma::{self, CAUSE_LOAD_PAGE_FAULT, CAUSE_STORE_PAGE_FAULT}};
+use core::{mem::{size_of, MaybeUninit},
+           slice};
+
+pub enum UaccessError {
+    Fault,
+}
+
+unsafe fn user_page(pid: u16, vaddr: usize, write: bool) -> Option<usize> {
+    let p = get_by_pid(pid);
+    if p.is_null() {
+        return None;
+    }
+    if (*(*p).frame).satp >> 60 == 0 {
+        return Some(vaddr);
+    }
+    let table = ((*p).mmu_table as *const Table).as_ref()?;
+    let cause = if write { CAUSE_STORE_PAGE_FAULT } else { CAUSE_LOAD_PAGE_FAULT };
+    let need = PTE_USER | if write { PTE_WRITE } else { PTE_READ };
+    match paging::lookup(table, vaddr) {
+        Some((_, perms)) if perms & need != need => return None,
+        Some(_) if !vma::find_vma(pid, vaddr).map_or(false, |v| v.allows(cause)) => return None,
+        Some(_) => {},
+        None if !vma::fault_in(pid, vaddr, write) => return None,
+        None => {},
+    }
+    paging::translate(table, vaddr)
+}
+
+unsafe fn copy_user(pid: u16, vaddr: usize, len: usize, write: bool, mut f: impl FnMut(*mut u8, usize, usize)) -> Result<(), UaccessError> {
+    let end = vaddr.checked_add(len).ok_or(UaccessError::Fault)?;
+    let mut addr = vaddr;
+    while addr < end {
+        let chunk = (PAGE_SIZE - addr % PAGE_SIZE).min(end - addr);
+        let phys = user_page(pid, addr, write).ok_or(UaccessError::Fault)?;
+        f(phys as *mut u8, addr - vaddr, chunk);
+        addr += chunk;
+    }
+    Ok(())
+}
+
+pub unsafe fn copy_from_user(pid: u16, dst: &mut [u8], vaddr: usize) -> Result<(), UaccessError> {
+    copy_user(pid, vaddr, dst.len(), false, |phys, off, len| {
+        dst[off..off + len].copy_from_slice(slice::from_raw_parts(phys, len));
+    })
+}
+
+pub unsafe fn copy_to_user(pid: u16, vaddr: usize, src: &[u8]) -> Result<(), UaccessError> {
+    copy_user(pid, vaddr, src.len(), true, |phys, off, len| {
+        slice::from_raw_parts_mut(phys, len).copy_from_slice(&src[off..off + len]);
+    })
+}
+
+pub unsafe fn read_user<T: Copy>(pid: u16, vaddr: usize) -> Option<T> {
+    let mut val = MaybeUninit::<T>::uninit();
+    let bytes = slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>());
+    copy_from_user(pid, bytes, vaddr).ok()?;
+    Some(val.assume_init())
+}
+
+pub unsafe fn write_user<T: Copy>(pid: u16, vaddr: usize, val: T) -> bool {
+    let bytes = slice::from_raw_parts(&val as *const T as *const u8, size_of::<T>());
+    copy_to_user(pid, vaddr, bytes).is_ok()
+}
+
+pub unsafe fn user_span(pid: u16, vaddr: usize, len: usize, write: bool) -> Option<(*mut u8, usize)> {
+    let start = user_page(pid, vaddr, write)?;
+    let end = vaddr.checked_add(len)?;
+    let mut contiguous = (PAGE_SIZE - vaddr % PAGE_SIZE).min(len);
+    while vaddr + contiguous < end {
+        match user_page(pid, vaddr + contiguous, write) {
+            Some(phys) if phys == start + contiguous => contiguous += PAGE_SIZE.min(end - vaddr - contiguous),
+            _ => break,
+        }
+    }
+    Some((start as *mut u8, contiguous))
+}
@@ -1,5 +1,44 @@
 use core::{convert::TryInto, fmt::{Error, Write}};
-use crate::console::push_stdin;
+use crate::ansi::AnsiSink;
+use crate::console::{push_stdin, stdin_space, ECHO};
+use crate::{gdbstub, irqstat};
+
+pub const UART_RBR: usize = 0;
+pub const UART_THR: usize = 0;
+pub const UART_IER: usize = 1;
+pub const UART_FCR: usize = 2;
+pub const UART_LCR: usize = 3;
+pub const UART_MCR: usize = 4;
+pub const UART_LSR: usize = 5;
+pub const UART_MSR: usize = 6;
+
+pub const UART_FCR_ENABLE: u8 = 1 << 0;
+pub const UART_FCR_CLEAR_RX: u8 = 1 << 1;
+pub const UART_FCR_CLEAR_TX: u8 = 1 << 2;
+
+pub const UART_MCR_DTR: u8 = 1 << 0;
+pub const UART_MCR_RTS: u8 = 1 << 1;
+
+pub const UART_LSR_DR: u8 = 1 << 0;
+pub const UART_LSR_THRE: u8 = 1 << 5;
+
+pub const UART_MSR_CTS: u8 = 1 << 4;
+
+pub const RTS_LOW_WATER: usize = 64;
+pub const RTS_HIGH_WATER: usize = 256;
+pub const CTS_WAIT_SPINS: usize = 100_000;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum FifoTrigger {
+    One = 0,
+    Four = 1,
+    Eight = 2,
+    Fourteen = 3,
+}
+
+static mut FLOW_CONTROL: bool = false;
+static mut THROTTLED: bool = false;
+static mut CTS_STALLED: bool = false;
 
 pub struct Uart {
     base_address: usize,
@@ -14,6 +53,12 @@ impl Write for Uart {
     }
 }
 
+impl AnsiSink for Uart {
+    fn put(&mut self, c: u8) {
+        Uart::put(self, c);
+    }
+}
+
 impl Uart {
     pub fn new(base_address: usize) -> Self {
         Uart { base_address }
@@ -36,23 +81,75 @@ impl Uart {
             ptr.add(1).write_volatile(divisor_most);
 
             ptr.add(3).write_volatile(lcr);
+            ptr.add(UART_MCR).write_volatile(UART_MCR_DTR | UART_MCR_RTS);
         }
     }
 
+    pub fn set_fifo(&mut self, enabled: bool, trigger: FifoTrigger) {
+        let ptr = self.base_address as *mut u8;
+        let fcr = if enabled {
+            UART_FCR_ENABLE | UART_FCR_CLEAR_RX | UART_FCR_CLEAR_TX | (trigger as u8) << 6
+        } else {
+            0
+        };
+        unsafe {
+            ptr.add(UART_FCR).write_volatile(fcr);
+        }
+    }
+
+    pub fn set_flow_control(&mut self, enabled: bool) {
+        let ptr = self.base_address as *mut u8;
+        unsafe {
+            ptr.add(UART_MCR).write_volatile(UART_MCR_DTR | UART_MCR_RTS);
+            FLOW_CONTROL = enabled;
+            THROTTLED = false;
+            CTS_STALLED = false;
+        }
+    }
+
+    pub fn set_rts(&mut self, on: bool) {
+        let ptr = self.base_address as *mut u8;
+        unsafe {
+            let mcr = ptr.add(UART_MCR).read_volatile();
+            if on {
+                ptr.add(UART_MCR).write_volatile(mcr | UART_MCR_RTS);
+            } else {
+                ptr.add(UART_MCR).write_volatile(mcr & !UART_MCR_RTS);
+            }
+        }
+    }
+
+    pub fn cts(&self) -> bool {
+        let ptr = self.base_address as *mut u8;
+        unsafe { ptr.add(UART_MSR).read_volatile() & UART_MSR_CTS != 0 }
+    }
+
+    fn tx_ready(&self) -> bool {
+        let ptr = self.base_address as *mut u8;
+        unsafe { ptr.add(UART_LSR).read_volatile() & UART_LSR_THRE != 0 && self.cts() }
+    }
+
     pub fn put(&mut self, c: u8) {
         let ptr = self.base_address as *mut u8;
         unsafe {
-            ptr.add(0).write_volatile(c);
+            if FLOW_CONTROL {
+                let spins = if CTS_STALLED { 1 } else { CTS_WAIT_SPINS };
+                CTS_STALLED = !(0..spins).any(|_| self.tx_ready());
+                if CTS_STALLED {
+                    return;
+                }
+            }
+            ptr.add(UART_THR).write_volatile(c);
         }
     }
 
     pub fn get(&mut self) -> Option<u8> {
         let ptr = self.base_address as *mut u8;
         unsafe {
-            if ptr.add(5).read_volatile() & 1 == 0 {
+            if ptr.add(UART_LSR).read_volatile() & UART_LSR_DR == 0 {
                 None
             } else {
-                Some(ptr.add(0).read_volatile())
+                Some(ptr.add(UART_RBR).read_volatile())
             }
         }
     }
@@ -61,8 +158,19 @@ impl Uart {
 pub fn handle_interrupt() {
     let mut my_uart = Uart::new(0x1000_0000);
 
-    if let Some(c) = my_uart.get() {
+    irqstat::record_source(irqstat::UART_SOURCE);
+    while let Some(c) = my_uart.get() {
+        gdbstub::check_magic(c);
         push_stdin(c);
+        unsafe {
+            if FLOW_CONTROL && !THROTTLED && stdin_space() < RTS_LOW_WATER {
+                my_uart.set_rts(false);
+                THROTTLED = true;
+            }
+            if !ECHO {
+                continue;
+            }
+        }
         match c {
             8 => {
                 print!("{} {}", 8 as char, 8 as char);
@@ -75,4 +183,13 @@ pub fn handle_interrupt() {
             },
         }
     }
-}
\ No newline at end of file
+}
+
+pub fn unthrottle() {
+    unsafe {
+        if THROTTLED && stdin_space() >= RTS_HIGH_WATER {
+            Uart::new(0x1000_0000).set_rts(true);
+            THROTTLED = false;
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::{cpu::TrapFrame,
+            fs::FileSystem,
+            kthread,
+            lock::Mutex,
+            mmio,
+            page::{dealloc, zalloc, Table, PAGE_SIZE},
+            paging::{self, MapError, PTE_EXEC, PTE_READ, PTE_USER, PTE_WRITE},
+            process::{get_by_pid, set_running, set_waiting},
+            scrub,
+            shm};
+use alloc::{collections::BTreeMap, vec::Vec};
+
+pub const VMA_READ: usize = 1 << 0;
+pub const VMA_WRITE: usize = 1 << 1;
+pub const VMA_EXEC: usize = 1 << 2;
+pub const VMA_GROWSDOWN: usize = 1 << 3;
+pub const VMA_HEAP: usize = 1 << 4;
+pub const VMA_MMAP: usize = 1 << 5;
+
+pub const DEFAULT_STACK_RLIMIT: usize = 8 * 1024 * 1024;
+pub const STACK_GROWTH_GAP: usize = 64 * 1024;
+
+pub const MMAP_BASE: usize = 0x8000_0000;
+
+pub const CAUSE_INSTRUCTION_PAGE_FAULT: usize = 12;
+pub const CAUSE_LOAD_PAGE_FAULT: usize = 13;
+pub const CAUSE_STORE_PAGE_FAULT: usize = 15;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum VmaBacking {
+    Anonymous,
+    File { bdev: usize, inode: u32, offset: u32, size: u32 },
+    Shared { id: usize, offset: usize },
+    Device { paddr: usize },
+}
+
+#[derive(Copy, Clone)]
+pub struct Vma {
+    pub start: usize,
+    pub end: usize,
+    pub flags: usize,
+    pub backing: VmaBacking,
+}
+
+pub enum VmaError {
+    Overlap,
+    InvalidRange,
+    NoProcess,
+    LimitExceeded,
+}
+
+pub enum FaultResult {
+    Resolved,
+    Pending,
+    Invalid,
+}
+
+impl Vma {
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        start < self.end && self.start < end
+    }
+
+    pub fn allows(&self, cause: usize) -> bool {
+        match cause {
+            CAUSE_INSTRUCTION_PAGE_FAULT => self.flags & VMA_EXEC != 0,
+            CAUSE_LOAD_PAGE_FAULT => self.flags & VMA_READ != 0,
+            CAUSE_STORE_PAGE_FAULT => self.flags & VMA_WRITE != 0,
+            _ => false,
+        }
+    }
+
+    pub fn entry_bits(&self) -> i64 {
+        Vma::entry_bits_for(self.flags)
+    }
+
+    pub fn entry_bits_for(flags: usize) -> i64 {
+        let mut bits = PTE_USER;
+        if flags & VMA_READ != 0 {
+            bits |= PTE_READ;
+        }
+        if flags & VMA_WRITE != 0 {
+            bits |= PTE_READ | PTE_WRITE;
+        }
+        if flags & VMA_EXEC != 0 {
+            bits |= PTE_EXEC;
+        }
+        bits
+    }
+}
+
+pub struct AddressSpace {
+    pub vmas: Vec<Vma>,
+    pub stack_limit: usize,
+    pub heap_start: usize,
+}
+
+impl AddressSpace {
+    pub fn new() -> Self {
+        AddressSpace {
+            vmas: Vec::new(),
+            stack_limit: DEFAULT_STACK_RLIMIT,
+            heap_start: 0,
+        }
+    }
+
+    fn resize_heap(&mut self, new_end: usize) -> Result<usize, VmaError> {
+        let heap_start = self.heap_start;
+        if heap_start == 0 || new_end < heap_start {
+            return Err(VmaError::InvalidRange);
+        }
+        let idx = self.vmas.iter().position(|v| v.flags & VMA_HEAP != 0);
+        let old_end = idx.map_or(heap_start, |i| self.vmas[i].end);
+        if new_end > old_end && self.vmas.iter().any(|v| v.flags & VMA_HEAP == 0 && v.overlaps(heap_start, new_end)) {
+            return Err(VmaError::Overlap);
+        }
+        match idx {
+            Some(i) if new_end == heap_start => {
+                self.vmas.remove(i);
+            },
+            Some(i) => self.vmas[i].end = new_end,
+            None if new_end > heap_start => self.vmas.push(Vma {
+                start: heap_start,
+                end: new_end,
+                flags: VMA_READ | VMA_WRITE | VMA_HEAP,
+                backing: VmaBacking::Anonymous,
+            }),
+            None => {},
+        }
+        Ok(old_end)
+    }
+
+    fn grow_stack(&mut self, addr: usize) -> Option<Vma> {
+        let vaddr = addr & !(PAGE_SIZE - 1);
+        let idx = self.vmas.iter().position(|v| {
+            v.flags & VMA_GROWSDOWN != 0 && addr < v.start && addr + STACK_GROWTH_GAP >= v.start
+        })?;
+        let stack = self.vmas[idx];
+        if stack.end - vaddr > self.stack_limit {
+            return None;
+        }
+        if self.vmas.iter().any(|v| v.start != stack.start && v.overlaps(vaddr, stack.start)) {
+            return None;
+        }
+        self.vmas[idx].start = vaddr;
+        Some(self.vmas[idx])
+    }
+}
+
+static mut VMA_TABLES: Option<BTreeMap<u16, AddressSpace>> = None;
+static mut VMA_LOCK: Mutex = Mutex::new();
+
+pub fn init() {
+    unsafe {
+        VMA_TABLES.replace(BTreeMap::new());
+    }
+}
+
+fn with_tables<T, F: FnOnce(&mut BTreeMap<u16, AddressSpace>) -> T>(f: F) -> Option<T> {
+    let mut ret = None;
+    unsafe {
+        VMA_LOCK.spin_lock();
+        if let Some(mut tables) = VMA_TABLES.take() {
+            ret = Some(f(&mut tables));
+            VMA_TABLES.replace(tables);
+        }
+        VMA_LOCK.unlock();
+    }
+    ret
+}
+
+pub fn add_vma(pid: u16, vma: Vma) -> Result<(), VmaError> {
+    if vma.start >= vma.end || vma.start % PAGE_SIZE != 0 || vma.end % PAGE_SIZE != 0 {
+        return Err(VmaError::InvalidRange);
+    }
+    with_tables(|tables| {
+        let space = tables.entry(pid).or_insert_with(AddressSpace::new);
+        if space.vmas.iter().any(|v| v.overlaps(vma.start, vma.end)) {
+            return Err(VmaError::Overlap);
+        }
+        space.vmas.push(vma);
+        Ok(())
+    }).unwrap_or(Err(VmaError::NoProcess))
+}
+
+pub fn remove_vma(pid: u16, start: usize) -> Option<Vma> {
+    with_tables(|tables| {
+        let space = tables.get_mut(&pid)?;
+        let idx = space.vmas.iter().position(|v| v.start == start)?;
+        Some(space.vmas.remove(idx))
+    }).flatten()
+}
+
+pub fn find_vma(pid: u16, addr: usize) -> Option<Vma> {
+    with_tables(|tables| tables.get(&pid).and_then(|space| space.vmas.iter().find(|v| v.contains(addr)).copied())).flatten()
+}
+
+pub fn vmas(pid: u16) -> Vec<Vma> {
+    with_tables(|tables| tables.get(&pid).map(|space| space.vmas.clone()).unwrap_or_default()).unwrap_or_default()
+}
+
+pub fn set_stack_limit(pid: u16, limit: usize) -> Result<(), VmaError> {
+    with_tables(|tables| {
+        let space = tables.entry(pid).or_insert_with(AddressSpace::new);
+        let current = space.vmas.iter().find(|v| v.flags & VMA_GROWSDOWN != 0).map_or(0, |v| v.end - v.start);
+        if limit < current {
+            return Err(VmaError::LimitExceeded);
+        }
+        space.stack_limit = limit;
+        Ok(())
+    }).unwrap_or(Err(VmaError::NoProcess))
+}
+
+pub fn set_heap_start(pid: u16, start: usize) {
+    with_tables(|tables| tables.entry(pid).or_insert_with(AddressSpace::new).heap_start = start);
+}
+
+pub fn heap_start(pid: u16) -> usize {
+    with_tables(|tables| tables.get(&pid).map_or(0, |space| space.heap_start)).unwrap_or(0)
+}
+
+pub fn set_brk(pid: u16, brk: usize) -> Result<(), VmaError> {
+    let new_end = (brk + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let old_end = with_tables(|tables| {
+        tables.get_mut(&pid).ok_or(VmaError::NoProcess).and_then(|space| space.resize_heap(new_end))
+    }).unwrap_or(Err(VmaError::NoProcess))?;
+    if new_end < old_end {
+        unsafe {
+            unmap_range(pid, new_end, old_end, true);
+        }
+    }
+    Ok(())
+}
+
+pub fn stack_limit(pid: u16) -> usize {
+    with_tables(|tables| tables.get(&pid).map_or(DEFAULT_STACK_RLIMIT, |space| space.stack_limit)).unwrap_or(DEFAULT_STACK_RLIMIT)
+}
+
+fn grow_stack(pid: u16, addr: usize) -> Option<Vma> {
+    with_tables(|tables| tables.get_mut(&pid).and_then(|space| space.grow_stack(addr))).flatten()
+}
+
+pub fn release(pid: u16) {
+    let space = with_tables(|tables| tables.remove(&pid)).flatten();
+    if let Some(space) = space {
+        for v in space.vmas.iter() {
+            match v.backing {
+                VmaBacking::Shared { id, .. } => shm::drop_ref(id),
+                VmaBacking::Anonymous if v.flags & (VMA_HEAP | VMA_MMAP) != 0 => unsafe {
+                    unmap_range(pid, v.start, v.end, true);
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+pub fn find_free(pid: u16, hint: usize, len: usize, limit: usize) -> Option<usize> {
+    with_tables(|tables| {
+        let empty = Vec::new();
+        let vmas = tables.get(&pid).map_or(&empty, |space| &space.vmas);
+        let free = |start: usize| start.checked_add(len).map_or(false, |end| end <= limit && !vmas.iter().any(|v| v.overlaps(start, end)));
+        if hint != 0 && hint % PAGE_SIZE == 0 && free(hint) {
+            return Some(hint);
+        }
+        let mut start = MMAP_BASE;
+        while start.checked_add(len).map_or(false, |end| end <= limit) {
+            match vmas.iter().filter(|v| v.overlaps(start, start + len)).map(|v| v.end).max() {
+                Some(end) => start = (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+                None => return Some(start),
+            }
+        }
+        None
+    }).flatten()
+}
+
+pub unsafe fn unmap_range(pid: u16, start: usize, end: usize, free: bool) {
+    let p = get_by_pid(pid);
+    if p.is_null() || (*p).mmu_table.is_null() {
+        return;
+    }
+    let table = &mut *((*p).mmu_table as *mut Table);
+    let mut vaddr = start;
+    while vaddr < end {
+        if let Some(paddr) = paging::unmap(table, vaddr) {
+            if free {
+                scrub::free_pages(paddr as *mut u8, 1);
+            }
+        }
+        vaddr += PAGE_SIZE;
+    }
+    match paging::asid_of(pid) {
+        Some(asid) => paging::flush_asid(asid),
+        None => paging::flush_all(),
+    }
+}
+
+pub fn unmap(pid: u16, start: usize) -> Option<Vma> {
+    let vma = with_tables(|tables| {
+        let space = tables.get_mut(&pid)?;
+        let idx = space.vmas.iter().position(|v| v.start == start && v.flags & VMA_MMAP != 0)?;
+        Some(space.vmas.remove(idx))
+    }).flatten()?;
+    unsafe {
+        unmap_range(pid, vma.start, vma.end, vma.backing == VmaBacking::Anonymous);
+    }
+    if let VmaBacking::Shared { id, .. } = vma.backing {
+        shm::drop_ref(id);
+    }
+    Some(vma)
+}
+
+unsafe fn map_page(pid: u16, vaddr: usize, paddr: usize, bits: i64) -> Result<(), MapError> {
+    let p = get_by_pid(pid);
+    if p.is_null() || (*p).mmu_table.is_null() {
+        return Err(MapError::OutOfRange);
+    }
+    let table = &mut *((*p).mmu_table as *mut Table);
+    paging::map(table, vaddr, paddr, bits)?;
+    match paging::asid_of(pid) {
+        Some(asid) => paging::flush_page(asid, vaddr),
+        None => paging::flush_all(),
+    }
+    Ok(())
+}
+
+fn file_fault(pid: u16, vaddr: usize, vma: Vma) {
+    if let VmaBacking::File { bdev, inode, offset, size } = vma.backing {
+        let page = zalloc(1);
+        if page.is_null() {
+            set_running(pid);
+            return;
+        }
+        let page_offset = (vaddr - vma.start) as u32;
+        if page_offset < size {
+            let to_read = core::cmp::min(PAGE_SIZE as u32, size - page_offset);
+            if let Some(ino) = FileSystem::get_inode(bdev, inode) {
+                FileSystem::read(bdev, &ino, page, to_read, offset + page_offset);
+            }
+        }
+        if unsafe { map_page(pid, vaddr, page as usize, vma.entry_bits()) }.is_err() {
+            dealloc(page);
+        }
+    }
+    set_running(pid);
+}
+
+unsafe fn resolve_fault(pid: u16, addr: usize, vma: Vma) -> FaultResult {
+    let vaddr = addr & !(PAGE_SIZE - 1);
+    match vma.backing {
+        VmaBacking::Anonymous => {
+            let page = zalloc(1);
+            if page.is_null() {
+                return FaultResult::Invalid;
+            }
+            match map_page(pid, vaddr, page as usize, vma.entry_bits()) {
+                Ok(()) => FaultResult::Resolved,
+                Err(MapError::AlreadyMapped) => {
+                    dealloc(page);
+                    FaultResult::Resolved
+                },
+                Err(_) => {
+                    dealloc(page);
+                    FaultResult::Invalid
+                },
+            }
+        },
+        VmaBacking::Shared { id, offset } => {
+            match shm::page(id, offset + (vaddr - vma.start)) {
+                Some(page) => match map_page(pid, vaddr, page, vma.entry_bits()) {
+                    Ok(()) | Err(MapError::AlreadyMapped) => FaultResult::Resolved,
+                    Err(_) => FaultResult::Invalid,
+                },
+                _ => FaultResult::Invalid,
+            }
+        },
+        VmaBacking::Device { paddr } => {
+            let paddr = paddr + (vaddr - vma.start);
+            match map_page(pid, vaddr, paddr, vma.entry_bits() | mmio::page_bits(paddr)) {
+                Ok(()) | Err(MapError::AlreadyMapped) => FaultResult::Resolved,
+                Err(_) => FaultResult::Invalid,
+            }
+        },
+        VmaBacking::File { .. } => {
+            set_waiting(pid);
+            let _ = kthread::spawn("page fault", move || file_fault(pid, vaddr, vma));
+            FaultResult::Pending
+        },
+    }
+}
+
+fn fault_vma(pid: u16, addr: usize, cause: usize) -> Option<Vma> {
+    match find_vma(pid, addr) {
+        Some(vma) if vma.allows(cause) => Some(vma),
+        Some(_) => None,
+        None => grow_stack(pid, addr).filter(|vma| vma.allows(cause)),
+    }
+}
+
+pub unsafe fn handle_page_fault(frame: *mut TrapFrame, addr: usize, cause: usize) -> FaultResult {
+    let pid = (*frame).pid as u16;
+    match fault_vma(pid, addr, cause) {
+        Some(vma) => resolve_fault(pid, addr, vma),
+        None => FaultResult::Invalid,
+    }
+}
+
+pub unsafe fn fault_in(pid: u16, addr: usize, write: bool) -> bool {
+    let cause = if write { CAUSE_STORE_PAGE_FAULT } else { CAUSE_LOAD_PAGE_FAULT };
+    match fault_vma(pid, addr, cause) {
+        Some(Vma { backing: VmaBacking::File { .. }, .. }) | None => false,
+        Some(vma) => match resolve_fault(pid, addr, vma) {
+            FaultResult::Resolved => true,
+            _ => false,
+        },
+    }
+}
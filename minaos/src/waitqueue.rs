@@ -0,0 +1,74 @@
+use crate::{process::{set_running, set_waiting},
+            spinlock::SpinLock};
+use alloc::collections::VecDeque;
+
+pub struct WaitQueue {
+    waiters: SpinLock<Option<VecDeque<u16>>>,
+}
+
+impl WaitQueue {
+    pub const fn new(name: &'static str) -> Self {
+        WaitQueue { waiters: SpinLock::new(name, None) }
+    }
+
+    fn enqueue(waiters: &mut Option<VecDeque<u16>>, pid: u16) {
+        let q = waiters.get_or_insert_with(VecDeque::new);
+        if !q.contains(&pid) {
+            q.push_back(pid);
+        }
+        set_waiting(pid);
+    }
+
+    pub fn sleep(&self, pid: u16) {
+        let mut waiters = self.waiters.lock();
+        Self::enqueue(&mut waiters, pid);
+    }
+
+    pub fn sleep_on<F: FnOnce() -> bool>(&self, pid: u16, condition: F) -> bool {
+        let mut waiters = self.waiters.lock();
+        if condition() {
+            return true;
+        }
+        Self::enqueue(&mut waiters, pid);
+        false
+    }
+
+    pub fn wake_one(&self) -> Option<u16> {
+        let pid = self.waiters.lock().as_mut().and_then(|q| q.pop_front());
+        if let Some(pid) = pid {
+            set_running(pid);
+        }
+        pid
+    }
+
+    pub fn wake_all(&self) -> usize {
+        let woken: VecDeque<u16> = self.waiters.lock().take().unwrap_or_default();
+        for pid in woken.iter() {
+            set_running(*pid);
+        }
+        woken.len()
+    }
+
+    pub fn wake(&self, pid: u16) -> bool {
+        let found = self.remove(pid);
+        if found {
+            set_running(pid);
+        }
+        found
+    }
+
+    pub fn remove(&self, pid: u16) -> bool {
+        let mut waiters = self.waiters.lock();
+        if let Some(q) = waiters.as_mut() {
+            if let Some(idx) = q.iter().position(|&p| p == pid) {
+                q.remove(idx);
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().as_ref().map_or(true, |q| q.is_empty())
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{console,
+            fs::{FileSystem, FsError},
+            process::add_kernel_process_args,
+            trap::MMIO_MTIME,
+            uart::Uart};
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+pub const EOT: u8 = 0x04;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CAN: u8 = 0x18;
+pub const CRC_MODE: u8 = b'C';
+pub const SUB: u8 = 0x1a;
+
+pub const TIMER_FREQ: u64 = 10_000_000;
+pub const BYTE_TIMEOUT: u64 = TIMER_FREQ;
+pub const START_TIMEOUT: u64 = 3 * TIMER_FREQ;
+pub const MAX_RETRIES: usize = 10;
+pub const MAX_FILE_SIZE: usize = 4 * 1024 * 1024;
+
+pub enum XmodemError {
+    Timeout,
+    Cancelled,
+    TooManyErrors,
+    TooLarge,
+    Fs(FsError),
+}
+
+fn now() -> u64 {
+    unsafe { MMIO_MTIME.read_volatile() }
+}
+
+fn send(c: u8) {
+    Uart::new(0x1000_0000).put(c);
+}
+
+fn recv_timeout(ticks: u64) -> Option<u8> {
+    let deadline = now() + ticks;
+    while now() < deadline {
+        if let Some(c) = console::try_pop_stdin() {
+            return Some(c);
+        }
+    }
+    None
+}
+
+fn flush_input() {
+    while recv_timeout(BYTE_TIMEOUT / 10).is_some() {}
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn recv_packet(len: usize, crc_mode: bool, packet: &mut [u8; 1024]) -> Option<u8> {
+    let blk = recv_timeout(BYTE_TIMEOUT)?;
+    let blk_inv = recv_timeout(BYTE_TIMEOUT)?;
+    for i in 0..len {
+        packet[i] = recv_timeout(BYTE_TIMEOUT)?;
+    }
+    let valid = if crc_mode {
+        let hi = recv_timeout(BYTE_TIMEOUT)? as u16;
+        let lo = recv_timeout(BYTE_TIMEOUT)? as u16;
+        crc16(&packet[..len]) == (hi << 8 | lo)
+    } else {
+        checksum(&packet[..len]) == recv_timeout(BYTE_TIMEOUT)?
+    };
+    if valid && blk == !blk_inv {
+        Some(blk)
+    } else {
+        None
+    }
+}
+
+pub fn receive() -> Result<Vec<u8>, XmodemError> {
+    let mut data = Vec::new();
+    let mut packet = [0u8; 1024];
+    let mut expected = 1u8;
+    let mut errors = 0;
+    let mut crc_mode = true;
+    let mut started = false;
+
+    loop {
+        if !started {
+            if errors == MAX_RETRIES / 2 {
+                crc_mode = false;
+            }
+            send(if crc_mode { CRC_MODE } else { NAK });
+        }
+        let header = match recv_timeout(if started { BYTE_TIMEOUT * 10 } else { START_TIMEOUT }) {
+            Some(c) => c,
+            None => {
+                errors += 1;
+                if errors >= MAX_RETRIES {
+                    send(CAN);
+                    return Err(XmodemError::Timeout);
+                }
+                if started {
+                    send(NAK);
+                }
+                continue;
+            },
+        };
+        let len = match header {
+            SOH => 128,
+            STX => 1024,
+            EOT => {
+                send(ACK);
+                while data.last() == Some(&SUB) {
+                    data.pop();
+                }
+                return Ok(data);
+            },
+            CAN => return Err(XmodemError::Cancelled),
+            _ => {
+                flush_input();
+                continue;
+            },
+        };
+        started = true;
+        match recv_packet(len, crc_mode, &mut packet) {
+            Some(blk) if blk == expected => {
+                if data.len() + len > MAX_FILE_SIZE {
+                    send(CAN);
+                    send(CAN);
+                    return Err(XmodemError::TooLarge);
+                }
+                data.extend_from_slice(&packet[..len]);
+                expected = expected.wrapping_add(1);
+                errors = 0;
+                send(ACK);
+            },
+            Some(blk) if blk == expected.wrapping_sub(1) => {
+                send(ACK);
+            },
+            _ => {
+                errors += 1;
+                if errors >= MAX_RETRIES {
+                    send(CAN);
+                    send(CAN);
+                    return Err(XmodemError::TooManyErrors);
+                }
+                flush_input();
+                send(NAK);
+            },
+        }
+    }
+}
+
+pub fn receive_file(bdev: usize, path: &str) -> Result<u32, XmodemError> {
+    let (inode_num, mut inode) = FileSystem::create(bdev, path).map_err(XmodemError::Fs)?;
+    let canonical = unsafe { console::CANONICAL };
+    console::set_echo(false);
+    console::set_canonical(false);
+    let ret = receive();
+    console::set_canonical(canonical);
+    console::set_echo(true);
+    let data = ret?;
+    inode.size = 0;
+    Ok(FileSystem::write(bdev, inode_num, &mut inode, data.as_ptr(), data.len() as u32, 0))
+}
+
+struct ProcArgs {
+    pub dev: usize,
+    pub path: String,
+}
+
+fn receive_proc(args_addr: usize) {
+    let args = unsafe { Box::from_raw(args_addr as *mut ProcArgs) };
+    match receive_file(args.dev, &args.path) {
+        Ok(bytes) => klog!("xmodem: wrote {} bytes to {}", bytes, args.path),
+        Err(XmodemError::Timeout) => println!("xmodem: timed out"),
+        Err(XmodemError::Cancelled) => println!("xmodem: cancelled by sender"),
+        Err(XmodemError::TooManyErrors) => println!("xmodem: too many errors"),
+        Err(XmodemError::TooLarge) => println!("xmodem: file too large"),
+        Err(XmodemError::Fs(_)) => println!("xmodem: cannot create {}", args.path),
+    }
+}
+
+pub fn spawn_receive(dev: usize, path: &str) {
+    let args = ProcArgs {
+        dev,
+        path: String::from(path),
+    };
+    let boxed_args = Box::new(args);
+    let _ = add_kernel_process_args(receive_proc, Box::into_raw(boxed_args) as usize);
+}
@@ -0,0 +1,73 @@
+#!/usr/bin/env python3
+# Post-link step: fill the kernel's .ksyms section with a sorted symbol table.
+#
+#   NM=riscv64-unknown-elf-nm tools/ksyms.py target/riscv64gc-unknown-none-elf/debug/minaos
+#
+# Layout (little endian, matches backtrace.rs):
+#   u32 magic, u32 count
+#   count * { u64 addr, u32 name_off, u32 name_len }   name_off is from the start of .ksyms
+#   names, not terminated
+
+import os
+import struct
+import subprocess
+import sys
+
+KSYMS_MAGIC = 0x4b53594d
+HEADER = struct.Struct("<II")
+ENTRY = struct.Struct("<QII")
+
+
+def find_section(image, name):
+    if image[:4] != b"\x7fELF" or image[4] != 2 or image[5] != 1:
+        sys.exit("ksyms: not a little endian ELF64 image")
+    shoff, = struct.unpack_from("<Q", image, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", image, 0x3a)
+    sections = [struct.unpack_from("<IIQQQQ", image, shoff + i * shentsize) for i in range(shnum)]
+    strtab = sections[shstrndx][4]
+    for sh_name, _, _, _, offset, size in sections:
+        end = image.index(b"\0", strtab + sh_name)
+        if image[strtab + sh_name:end].decode() == name:
+            return offset, size
+    sys.exit("ksyms: image has no {} section".format(name))
+
+
+def text_symbols(path):
+    nm = os.environ.get("NM", "nm")
+    out = subprocess.run([nm, "-C", "-n", "--defined-only", path], check=True, stdout=subprocess.PIPE).stdout
+    symbols = []
+    for line in out.decode(errors="replace").splitlines():
+        parts = line.split(" ", 2)
+        if len(parts) == 3 and parts[1] in ("T", "t", "W", "w"):
+            symbols.append((int(parts[0], 16), parts[2]))
+    return symbols
+
+
+def build_table(symbols, size):
+    names = bytearray()
+    entries = []
+    names_start = HEADER.size + len(symbols) * ENTRY.size
+    for addr, name in symbols:
+        raw = name.encode()
+        entries.append(ENTRY.pack(addr, names_start + len(names), len(raw)))
+        names += raw
+    table = HEADER.pack(KSYMS_MAGIC, len(symbols)) + b"".join(entries) + bytes(names)
+    if len(table) > size:
+        sys.exit("ksyms: table is {} bytes, .ksyms holds {}".format(len(table), size))
+    return table + bytes(size - len(table))
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit("usage: ksyms.py <kernel elf>")
+    path = sys.argv[1]
+    with open(path, "rb") as f:
+        image = bytearray(f.read())
+    offset, size = find_section(image, ".ksyms")
+    image[offset:offset + size] = build_table(text_symbols(path), size)
+    with open(path, "wb") as f:
+        f.write(image)
+
+
+if __name__ == "__main__":
+    main()